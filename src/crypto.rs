@@ -0,0 +1,206 @@
+//! Block ciphers, CMAC and checksums of the DESFire and NTAG 424 DNA secure messaging
+//!
+//! The cards encrypt with AES-128 or 2-key triple DES (single DES being triple DES with equal
+//! halves) in CBC mode, and MAC with CMAC (NIST SP 800-38B). Unlike plain CMAC, the chaining value
+//! carries over between commands, so both take an IV.
+
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockDecrypt, BlockEncrypt, KeyInit};
+use aes::Aes128;
+use des::TdesEde2;
+
+/// Largest block size of the ciphers, the one of AES
+pub const MAX_BLOCK_SIZE: usize = 16;
+
+/// A block or IV, of which the first [`Cipher::block_size`] bytes are used
+pub type Block = [u8; MAX_BLOCK_SIZE];
+
+const CRC32_PRESET: u32 = 0xFFFF_FFFF;
+const CRC32_POLYNOMIAL: u32 = 0xEDB8_8320;
+const CRC16_PRESET: u16 = 0x6363;
+const CRC16_POLYNOMIAL: u16 = 0x8408;
+
+/// A keyed block cipher
+///
+/// The key schedule is kept inline, there is no allocator to box the larger AES one.
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone)]
+pub enum Cipher {
+    Aes(Aes128),
+    Tdes(TdesEde2),
+}
+
+impl Cipher {
+    pub fn aes(key: &[u8; 16]) -> Self {
+        Self::Aes(Aes128::new(GenericArray::from_slice(key)))
+    }
+
+    /// 2-key triple DES, a single DES key is repeated
+    pub fn tdes(key: &[u8; 16]) -> Self {
+        Self::Tdes(TdesEde2::new(GenericArray::from_slice(key)))
+    }
+
+    pub fn block_size(&self) -> usize {
+        match self {
+            Self::Aes(_) => 16,
+            Self::Tdes(_) => 8,
+        }
+    }
+
+    pub fn encrypt_block(&self, block: &mut [u8]) {
+        match self {
+            Self::Aes(cipher) => cipher.encrypt_block(GenericArray::from_mut_slice(block)),
+            Self::Tdes(cipher) => cipher.encrypt_block(GenericArray::from_mut_slice(block)),
+        }
+    }
+
+    pub fn decrypt_block(&self, block: &mut [u8]) {
+        match self {
+            Self::Aes(cipher) => cipher.decrypt_block(GenericArray::from_mut_slice(block)),
+            Self::Tdes(cipher) => cipher.decrypt_block(GenericArray::from_mut_slice(block)),
+        }
+    }
+
+    /// Encrypt whole blocks in CBC mode, leaving the last cipher block in `iv`
+    pub fn encrypt_cbc(&self, iv: &mut Block, data: &mut [u8]) {
+        let size = self.block_size();
+        for block in data.chunks_exact_mut(size) {
+            xor(block, &iv[..size]);
+            self.encrypt_block(block);
+            iv[..size].copy_from_slice(block);
+        }
+    }
+
+    /// Decrypt whole blocks in CBC mode, leaving the last cipher block in `iv`
+    pub fn decrypt_cbc(&self, iv: &mut Block, data: &mut [u8]) {
+        let size = self.block_size();
+        let mut cipher = [0u8; MAX_BLOCK_SIZE];
+        for block in data.chunks_exact_mut(size) {
+            cipher[..size].copy_from_slice(block);
+            self.decrypt_block(block);
+            xor(block, &iv[..size]);
+            iv[..size].copy_from_slice(&cipher[..size]);
+        }
+    }
+
+    /// CBC "send mode" of the DESFire legacy authentication, deciphering instead of enciphering
+    pub fn decrypt_send(&self, iv: &mut Block, data: &mut [u8]) {
+        let size = self.block_size();
+        for block in data.chunks_exact_mut(size) {
+            xor(block, &iv[..size]);
+            self.decrypt_block(block);
+            iv[..size].copy_from_slice(block);
+        }
+    }
+
+    /// CMAC of `data`, chained to `iv`, a zero IV gives the standard CMAC
+    pub fn cmac(&self, iv: &Block, data: &[u8]) -> Block {
+        self.cmac_parts(iv, &[data])
+    }
+
+    /// CMAC of the concatenation of `parts`, chained to `iv`
+    pub fn cmac_parts(&self, iv: &Block, parts: &[&[u8]]) -> Block {
+        let size = self.block_size();
+        let (k1, k2) = self.cmac_subkeys();
+
+        // the last block is always processed with a subkey, an empty message is one padded block
+        let len: usize = parts.iter().map(|part| part.len()).sum();
+        let last_start = match len % size {
+            0 => len.saturating_sub(size),
+            tail => len - tail,
+        };
+
+        let mut mac = *iv;
+        let mut block = [0u8; MAX_BLOCK_SIZE];
+        let mut filled = 0;
+        for (i, &byte) in parts.iter().flat_map(|part| part.iter()).enumerate() {
+            block[filled] = byte;
+            filled += 1;
+            if filled == size && i < last_start {
+                xor(&mut mac[..size], &block[..size]);
+                self.encrypt_block(&mut mac[..size]);
+                filled = 0;
+            }
+        }
+        if filled == size {
+            xor(&mut block[..size], &k1[..size]);
+        } else {
+            block[filled] = 0x80;
+            block[filled + 1..].fill(0x00);
+            xor(&mut block[..size], &k2[..size]);
+        }
+        xor(&mut mac[..size], &block[..size]);
+        self.encrypt_block(&mut mac[..size]);
+        mac
+    }
+
+    fn cmac_subkeys(&self) -> (Block, Block) {
+        let size = self.block_size();
+        let mut l = [0u8; MAX_BLOCK_SIZE];
+        self.encrypt_block(&mut l[..size]);
+        let k1 = double(&l, size);
+        let k2 = double(&k1, size);
+        (k1, k2)
+    }
+}
+
+/// Multiply by x in GF(2^n), the subkey derivation of CMAC
+fn double(block: &Block, size: usize) -> Block {
+    let rb = match size {
+        16 => 0x87,
+        _ => 0x1B,
+    };
+    let mut doubled = [0u8; MAX_BLOCK_SIZE];
+    for i in 0..size {
+        let carry = match i + 1 < size {
+            true => block[i + 1] >> 7,
+            false => 0,
+        };
+        doubled[i] = block[i] << 1 | carry;
+    }
+    if block[0] & 0x80 != 0 {
+        doubled[size - 1] ^= rb;
+    }
+    doubled
+}
+
+pub(crate) fn xor(target: &mut [u8], other: &[u8]) {
+    for (target, other) in target.iter_mut().zip(other) {
+        *target ^= other;
+    }
+}
+
+/// Truncated MAC of the EV2 secure messaging and of SUN messages, the odd bytes of the CMAC
+pub fn truncate_mac(mac: &Block) -> [u8; 8] {
+    core::array::from_fn(|i| mac[2 * i + 1])
+}
+
+/// Compare MACs in constant time
+pub(crate) fn matches(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// CRC-32 of the DESFire EV1 secure messaging, the IEEE 802.3 CRC without the final inversion
+pub fn crc32(data: &[u8]) -> u32 {
+    crc32_update(CRC32_PRESET, data)
+}
+
+/// Continue a [`crc32`] with more data
+pub fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    data.iter().fold(crc, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| match crc & 1 {
+            0 => crc >> 1,
+            _ => crc >> 1 ^ CRC32_POLYNOMIAL,
+        })
+    })
+}
+
+/// CRC-16 of the DESFire legacy secure messaging, the one of ISO/IEC 14443-3 Type A
+pub fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(CRC16_PRESET, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u16, |crc, _| match crc & 1 {
+            0 => crc >> 1,
+            _ => crc >> 1 ^ CRC16_POLYNOMIAL,
+        })
+    })
+}
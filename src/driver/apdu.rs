@@ -0,0 +1,326 @@
+//! ISO/IEC 7816-4 APDUs, exchanged with ISO/IEC 14443-4 (ISO-DEP) cards
+//!
+//! A command APDU is a 4 byte header (`CLA INS P1 P2`), optionally followed by the command data
+//! and the expected length of the response data (`Le`). Short APDUs carry up to 255 bytes of
+//! data and expect up to 256 bytes, extended APDUs up to 65535 and 65536 bytes. The response
+//! APDU is the response data followed by the status word `SW1 SW2`.
+//!
+//! The PN532 handles the ISO-DEP framing and chaining towards the card, data longer than a frame
+//! is chained between host and PN532, see [`Reader::in_data_exchange_chained`].
+
+use crate::driver::protocol::{Interface, MAX_FRAME_DATA};
+use crate::driver::{ReadError, Reader};
+use core::fmt;
+use embedded_hal_async::delay::DelayUs;
+
+/// Longest command data of a short APDU
+pub const MAX_SHORT_DATA: usize = 255;
+/// Longest expected response data of a short APDU
+pub const MAX_SHORT_LE: usize = 256;
+/// Longest command data of an extended APDU
+pub const MAX_EXTENDED_DATA: usize = 65535;
+/// Longest expected response data of an extended APDU
+pub const MAX_EXTENDED_LE: usize = 65536;
+
+/// Longest command data [`Reader::transmit`] sends, the APDU is encoded on the stack
+pub const MAX_TRANSMIT_DATA: usize = 2 * MAX_FRAME_DATA;
+/// Header, extended `Lc` and extended `Le`
+const MAX_OVERHEAD: usize = 4 + 3 + 2;
+
+/// INS of GET RESPONSE
+const INS_GET_RESPONSE: u8 = 0xC0;
+
+/// A command APDU
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Command<'a> {
+    pub cla: u8,
+    pub ins: u8,
+    pub p1: u8,
+    pub p2: u8,
+    pub data: &'a [u8],
+    /// Expected length of the response data, `None` if no data is expected
+    pub le: Option<usize>,
+}
+
+impl<'a> Command<'a> {
+    /// A command without data, not expecting any data back
+    pub const fn new(cla: u8, ins: u8, p1: u8, p2: u8) -> Self {
+        Self {
+            cla,
+            ins,
+            p1,
+            p2,
+            data: &[],
+            le: None,
+        }
+    }
+
+    pub const fn with_data(mut self, data: &'a [u8]) -> Self {
+        self.data = data;
+        self
+    }
+
+    /// Expect up to `le` bytes of response data, from 1 to [`MAX_EXTENDED_LE`]
+    pub const fn with_le(mut self, le: usize) -> Self {
+        self.le = Some(le);
+        self
+    }
+
+    /// Whether the data or `Le` need the extended length fields
+    pub fn is_extended(&self) -> bool {
+        self.data.len() > MAX_SHORT_DATA || matches!(self.le, Some(le) if le > MAX_SHORT_LE)
+    }
+
+    /// Length of the encoded APDU
+    pub fn encoded_len(&self) -> usize {
+        let lc = match (self.data.len(), self.is_extended()) {
+            (0, _) => 0,
+            (_, false) => 1,
+            (_, true) => 3,
+        };
+        let le = match (self.le, self.is_extended()) {
+            (None, _) => 0,
+            (Some(_), false) => 1,
+            // the leading zero byte is shared with Lc
+            (Some(_), true) if !self.data.is_empty() => 2,
+            (Some(_), true) => 3,
+        };
+        4 + lc + self.data.len() + le
+    }
+
+    /// Encode the APDU into `buf`, choosing the short form whenever possible
+    pub fn encode<'b>(&self, buf: &'b mut [u8]) -> Result<&'b [u8], EncodeError> {
+        if self.data.len() > MAX_EXTENDED_DATA
+            || matches!(self.le, Some(le) if le == 0 || le > MAX_EXTENDED_LE)
+        {
+            return Err(EncodeError::InvalidLength);
+        }
+        let len = self.encoded_len();
+        let buf = buf.get_mut(..len).ok_or(EncodeError::BufferTooSmall)?;
+
+        buf[..4].copy_from_slice(&[self.cla, self.ins, self.p1, self.p2]);
+        let mut offset = 4;
+        let extended = self.is_extended();
+        if extended {
+            buf[offset] = 0x00;
+            offset += 1;
+        }
+        if !self.data.is_empty() {
+            if extended {
+                buf[offset..offset + 2].copy_from_slice(&(self.data.len() as u16).to_be_bytes());
+                offset += 2;
+            } else {
+                buf[offset] = self.data.len() as u8;
+                offset += 1;
+            }
+            buf[offset..offset + self.data.len()].copy_from_slice(self.data);
+            offset += self.data.len();
+        }
+        // the largest Le is encoded as zero
+        match (self.le, extended) {
+            (None, _) => {}
+            (Some(le), false) => buf[offset] = le as u8,
+            (Some(le), true) => buf[offset..].copy_from_slice(&(le as u16).to_be_bytes()),
+        }
+        Ok(buf)
+    }
+}
+
+/// Error of [`Command::encode`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum EncodeError {
+    /// The data is too long, or `Le` is zero or too large, even for an extended APDU
+    InvalidLength,
+    BufferTooSmall,
+}
+
+/// Status word `SW1 SW2`, ending every response APDU
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StatusWord(pub u16);
+
+impl StatusWord {
+    pub const SUCCESS: Self = Self(0x9000);
+    /// End of file reached before reading `Le` bytes
+    pub const END_OF_FILE: Self = Self(0x6282);
+    pub const WRONG_LENGTH: Self = Self(0x6700);
+    pub const SECURITY_STATUS_NOT_SATISFIED: Self = Self(0x6982);
+    pub const CONDITIONS_NOT_SATISFIED: Self = Self(0x6985);
+    /// Command not allowed, no current elementary file
+    pub const NO_CURRENT_EF: Self = Self(0x6986);
+    /// File or application not found
+    pub const NOT_FOUND: Self = Self(0x6A82);
+    pub const WRONG_P1P2: Self = Self(0x6B00);
+    pub const INS_NOT_SUPPORTED: Self = Self(0x6D00);
+    pub const CLA_NOT_SUPPORTED: Self = Self(0x6E00);
+
+    pub const fn from_bytes(sw1: u8, sw2: u8) -> Self {
+        Self(u16::from_be_bytes([sw1, sw2]))
+    }
+
+    pub const fn sw1(self) -> u8 {
+        (self.0 >> 8) as u8
+    }
+
+    pub const fn sw2(self) -> u8 {
+        self.0 as u8
+    }
+
+    pub const fn is_success(self) -> bool {
+        self.0 == Self::SUCCESS.0
+    }
+
+    /// Response data left to fetch with GET RESPONSE (`61xx`)
+    pub const fn bytes_available(self) -> Option<usize> {
+        match self.sw1() {
+            0x61 => Some(short_le(self.sw2())),
+            _ => None,
+        }
+    }
+
+    /// Wrong `Le`, the command has to be repeated with the exact `Le` given (`6Cxx`)
+    pub const fn exact_le(self) -> Option<usize> {
+        match self.sw1() {
+            0x6C => Some(short_le(self.sw2())),
+            _ => None,
+        }
+    }
+}
+
+/// Length of a short `Le`, or of `SW2` of `61xx` and `6Cxx`, where zero stands for 256
+const fn short_le(le: u8) -> usize {
+    match le {
+        0 => MAX_SHORT_LE,
+        le => le as usize,
+    }
+}
+
+impl fmt::Debug for StatusWord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "StatusWord({:04X})", self.0)
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for StatusWord {
+    fn format(&self, fmt: defmt::Formatter) {
+        defmt::write!(fmt, "StatusWord({=u16:04X})", self.0)
+    }
+}
+
+/// A response APDU
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Response<'b> {
+    pub data: &'b [u8],
+    pub sw: StatusWord,
+}
+
+impl<'b> Response<'b> {
+    /// Split the status word off, `None` if the response is shorter than that
+    pub fn parse(response: &'b [u8]) -> Option<Self> {
+        let (data, sw) = response.split_at(response.len().checked_sub(2)?);
+        Some(Self {
+            data,
+            sw: StatusWord::from_bytes(sw[0], sw[1]),
+        })
+    }
+
+    /// The response data, if the status word reports success
+    pub fn check(self) -> Result<&'b [u8], StatusWord> {
+        match self.sw.is_success() {
+            true => Ok(self.data),
+            false => Err(self.sw),
+        }
+    }
+}
+
+/// Error of [`Reader::transmit`]
+#[derive(Debug)]
+pub enum ApduError<E> {
+    Reader(ReadError<E>),
+    Encode(EncodeError),
+    /// The card answered without a status word
+    MissingStatusWord,
+}
+
+#[cfg(feature = "defmt")]
+impl<E> defmt::Format for ApduError<E> {
+    fn format(&self, fmt: defmt::Formatter) {
+        match self {
+            Self::Reader(err) => defmt::write!(fmt, "{}", err),
+            Self::Encode(err) => defmt::write!(fmt, "Can't encode APDU: {}", err),
+            Self::MissingStatusWord => defmt::write!(fmt, "Missing status word"),
+        }
+    }
+}
+
+impl<E> From<ReadError<E>> for ApduError<E> {
+    fn from(value: ReadError<E>) -> Self {
+        Self::Reader(value)
+    }
+}
+
+impl<E> From<EncodeError> for ApduError<E> {
+    fn from(value: EncodeError) -> Self {
+        Self::Encode(value)
+    }
+}
+
+impl<I, T> Reader<I, T>
+where
+    I: Interface,
+    T: DelayUs,
+{
+    /// Send `command` to the ISO-DEP card with target number `tg`, and receive the response into
+    /// `buf`
+    ///
+    /// A `6Cxx` status word repeats the command once with the exact `Le`, `61xx` fetches the
+    /// remaining data with GET RESPONSE. Any other status word is returned as is.
+    pub async fn transmit<'b>(
+        &mut self,
+        tg: u8,
+        command: &Command<'_>,
+        buf: &'b mut [u8],
+    ) -> Result<Response<'b>, ApduError<I::Error>> {
+        let (mut len, mut sw) = self.transmit_once(tg, command, buf).await?;
+        if let Some(le) = sw.exact_le() {
+            debug!("Repeating command with Le {}", le);
+            (len, sw) = self.transmit_once(tg, &command.with_le(le), buf).await?;
+        }
+
+        while let Some(available) = sw.bytes_available() {
+            debug!("Fetching {} more bytes", available);
+            let get_response = Command::new(0x00, INS_GET_RESPONSE, 0x00, 0x00).with_le(available);
+            let (received, next) = self
+                .transmit_once(tg, &get_response, &mut buf[len..])
+                .await?;
+            len += received;
+            sw = next;
+        }
+
+        let buf: &'b [u8] = buf;
+        Ok(Response {
+            data: &buf[..len],
+            sw,
+        })
+    }
+
+    /// Exchange a single APDU, returning the length of the response data in `buf`
+    async fn transmit_once(
+        &mut self,
+        tg: u8,
+        command: &Command<'_>,
+        buf: &mut [u8],
+    ) -> Result<(usize, StatusWord), ApduError<I::Error>> {
+        let mut apdu = [0u8; MAX_TRANSMIT_DATA + MAX_OVERHEAD];
+        let apdu = command.encode(&mut apdu)?;
+        trace!("APDU: {:02X}", apdu);
+
+        let len = self.in_data_exchange_chained(tg, apdu, buf).await?;
+        let response = Response::parse(&buf[..len]).ok_or(ApduError::MissingStatusWord)?;
+        debug!("Status word: {}", response.sw);
+        Ok((response.data.len(), response.sw))
+    }
+}
@@ -0,0 +1,225 @@
+//! Telling apart the kinds of type A cards
+//!
+//! The SAK narrows the card down to a family, which is then probed with `GET_VERSION`: the
+//! NTAG21x and Ultralight EV1 answer it as a Type 2 Tag command, DESFire cards as a native
+//! command wrapped in ISO-DEP. See NXP AN10833 (MIFARE type identification procedure).
+
+use crate::driver::protocol::Interface;
+use crate::driver::requests::NTAGCommand;
+use crate::driver::{IsoTypeATarget, ReadError, Reader, TargetInfo};
+use embedded_hal_async::delay::DelayUs;
+
+/// First step of the Ultralight C three pass authentication
+const ULTRALIGHT_C_AUTHENTICATE: [u8; 2] = [0x1A, 0x00];
+/// DESFire native `GetVersion`
+const DESFIRE_GET_VERSION: u8 = 0x60;
+/// First byte of an answer continued in more frames, sent by DESFire and the Ultralight C
+const ADDITIONAL_FRAME: u8 = 0xAF;
+
+/// Generation of a Mifare DESFire
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DesFireVersion {
+    Ev1,
+    Ev2,
+    Ev3,
+    /// Any other major version, like the original DESFire (`0x00`)
+    Other(u8),
+}
+
+impl DesFireVersion {
+    pub const fn from_major(major: u8) -> Self {
+        match major {
+            0x01 => Self::Ev1,
+            0x12 | 0x22 => Self::Ev2,
+            0x30..=0x3F => Self::Ev3,
+            major => Self::Other(major),
+        }
+    }
+}
+
+/// Kind of a card, as found by [`Reader::classify`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CardKind {
+    Ntag213,
+    Ntag215,
+    Ntag216,
+    /// Mifare Ultralight (MF0ICU1)
+    Ultralight,
+    /// Mifare Ultralight C (MF0ICU2)
+    UltralightC,
+    /// Mifare Ultralight EV1 (MF0UL11 or MF0UL21)
+    UltralightEv1 {
+        user_memory: u16,
+    },
+    MifareMini,
+    MifareClassic1K,
+    MifareClassic4K,
+    DesFire {
+        version: DesFireVersion,
+        /// Free memory for applications, in bytes
+        memory: u32,
+    },
+    /// Any other ISO/IEC 14443-4 compliant card
+    Iso14443_4,
+    Unknown,
+}
+
+impl CardKind {
+    /// Parse the answer to the Type 2 Tag `GET_VERSION`, `None` for unknown products
+    pub fn from_type2_version(version: &[u8]) -> Option<Self> {
+        let &[0x00, 0x04, product, _subtype, _major, _minor, storage, 0x03] = version else {
+            return None;
+        };
+        Some(match (product, storage) {
+            (0x04, 0x0F) => Self::Ntag213,
+            (0x04, 0x11) => Self::Ntag215,
+            (0x04, 0x13) => Self::Ntag216,
+            (0x03, 0x0B) => Self::UltralightEv1 { user_memory: 48 },
+            (0x03, 0x0E) => Self::UltralightEv1 { user_memory: 128 },
+            _ => return None,
+        })
+    }
+
+    /// Parse the hardware related part of the DESFire `GetVersion`, `None` for other products
+    pub fn from_desfire_version(hardware: &[u8]) -> Option<Self> {
+        let &[0x04, kind, _subtype, major, _minor, storage, _protocol] = hardware else {
+            return None;
+        };
+        // 0x81 is a DESFire implemented on a SmartMX
+        if kind & 0x7F != 0x01 {
+            return None;
+        }
+        // the size is 2^(storage / 2), rounded down if the lowest bit is set
+        Some(Self::DesFire {
+            version: DesFireVersion::from_major(major),
+            memory: 1u32.checked_shl(storage as u32 >> 1)?,
+        })
+    }
+
+    /// Size of the memory for user data in bytes, `None` if not known
+    ///
+    /// For Mifare Classic this is the total memory, including manufacturer block and sector
+    /// trailers.
+    pub fn memory_size(&self) -> Option<usize> {
+        Some(match self {
+            Self::Ntag213 => 144,
+            Self::Ntag215 => 504,
+            Self::Ntag216 => 888,
+            Self::Ultralight => 48,
+            Self::UltralightC => 144,
+            Self::UltralightEv1 { user_memory } => *user_memory as usize,
+            Self::MifareMini => 320,
+            Self::MifareClassic1K => 1024,
+            Self::MifareClassic4K => 4096,
+            Self::DesFire { memory, .. } => *memory as usize,
+            Self::Iso14443_4 | Self::Unknown => return None,
+        })
+    }
+
+    /// Whether the card reads page ranges with `FAST_READ`
+    pub fn supports_fast_read(&self) -> bool {
+        matches!(
+            self,
+            Self::Ntag213 | Self::Ntag215 | Self::Ntag216 | Self::UltralightEv1 { .. }
+        )
+    }
+
+    /// Whether the card speaks ISO/IEC 14443-4, exchanging APDUs
+    pub fn is_iso_dep(&self) -> bool {
+        matches!(self, Self::DesFire { .. } | Self::Iso14443_4)
+    }
+
+    /// Whether the card is a NFC Forum Type 2 Tag, read and written in pages of 4 bytes
+    pub fn is_type2(&self) -> bool {
+        matches!(
+            self,
+            Self::Ntag213
+                | Self::Ntag215
+                | Self::Ntag216
+                | Self::Ultralight
+                | Self::UltralightC
+                | Self::UltralightEv1 { .. }
+        )
+    }
+}
+
+impl<I, T> Reader<I, T>
+where
+    I: Interface,
+    T: DelayUs,
+{
+    /// Find out the kind of an activated card
+    ///
+    /// This exchanges commands with the card, which might be left selected or reactivated
+    /// afterwards. Cards other than 106 kbps type A are [`CardKind::Unknown`].
+    pub async fn classify(&mut self, target: &TargetInfo) -> Result<CardKind, ReadError<I::Error>> {
+        let TargetInfo::IsoTypeA(target) = target else {
+            return Ok(CardKind::Unknown);
+        };
+
+        match target.sak {
+            0x00 => self.classify_type2(target.tg).await,
+            0x09 => Ok(CardKind::MifareMini),
+            // 0x28 and 0x38 are emulations on SmartMX cards, also supporting ISO/IEC 14443-4
+            0x08 | 0x88 | 0x28 => Ok(CardKind::MifareClassic1K),
+            0x18 | 0x38 => Ok(CardKind::MifareClassic4K),
+            _ if target.supports_iso14443_4() => self.classify_iso_dep(target).await,
+            _ => Ok(CardKind::Unknown),
+        }
+    }
+
+    async fn classify_type2(&mut self, tg: u8) -> Result<CardKind, ReadError<I::Error>> {
+        // `InDataExchange` takes 0x60 for a Mifare Classic authentication, so go through
+        // `InCommunicateThru`, which needs the target to be selected
+        self.in_select(tg).await?;
+        let version = self
+            .in_communicate_thru(&[NTAGCommand::GetVersion as u8])
+            .await
+            .map(CardKind::from_type2_version);
+        match version {
+            Ok(Some(kind)) => return Ok(kind),
+            Ok(None) => return Ok(CardKind::Unknown),
+            Err(ReadError::Status(_)) => {}
+            Err(err) => return Err(err),
+        }
+
+        // the original Ultralight doesn't know GET_VERSION and went back to idle, which the
+        // selection wakes it up from
+        self.in_select(tg).await?;
+        let authenticates = self
+            .in_communicate_thru(&ULTRALIGHT_C_AUTHENTICATE)
+            .await
+            .map(|answer| answer.first() == Some(&ADDITIONAL_FRAME));
+        let kind = match authenticates {
+            Ok(true) => CardKind::UltralightC,
+            Ok(false) | Err(ReadError::Status(_)) => CardKind::Ultralight,
+            Err(err) => return Err(err),
+        };
+
+        // leave the authentication, or the idle state
+        self.in_select(tg).await?;
+        Ok(kind)
+    }
+
+    async fn classify_iso_dep(
+        &mut self,
+        target: &IsoTypeATarget,
+    ) -> Result<CardKind, ReadError<I::Error>> {
+        let hardware = self
+            .in_data_exchange(target.tg, &[DESFIRE_GET_VERSION])
+            .await
+            .map(|answer| match answer.split_first() {
+                Some((&ADDITIONAL_FRAME, hardware)) => CardKind::from_desfire_version(hardware),
+                _ => None,
+            });
+
+        // the remaining frames of the version are dropped by the next command
+        match hardware {
+            Ok(Some(kind)) => Ok(kind),
+            Ok(None) | Err(ReadError::Status(_)) => Ok(CardKind::Iso14443_4),
+            Err(err) => Err(err),
+        }
+    }
+}
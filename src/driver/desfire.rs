@@ -0,0 +1,707 @@
+//! Mifare DESFire EV1/EV2 native commands and secure messaging
+//!
+//! Native commands are wrapped in ISO 7816-4 APDUs with CLA `0x90`, the DESFire status is the
+//! `SW2` of a `91xx` status word. Answers longer than a frame continue with the additional frame
+//! status `0xAF`.
+//!
+//! Authenticating with a key of the selected application establishes a session key. Afterwards
+//! the card MACs or encrypts its answers, depending on the communication settings of the file.
+//! Sessions of `AuthenticateISO` and `AuthenticateAES` use the EV1 secure messaging, where the
+//! IV chains all commands and answers with a CMAC. Sessions of the legacy `Authenticate` use the
+//! original DESFire secure messaging, a 4 byte MAC and CRC-16.
+//!
+//! The random number of the reader has to come from a proper source of randomness, like the RNG
+//! of the MCU.
+
+use crate::crypto::{self, Block, Cipher, MAX_BLOCK_SIZE};
+use crate::driver::apdu::{ApduError, Command, StatusWord, MAX_SHORT_LE};
+use crate::driver::protocol::Interface;
+use crate::driver::requests::DesFireCommand;
+use crate::driver::{CardKind, ReadError, Reader};
+use embedded_hal_async::delay::DelayUs;
+
+/// Application ID of the card level, the PICC
+pub const PICC_AID: u32 = 0x000000;
+
+const CLA: u8 = 0x90;
+/// `SW1` of the status words answered to native commands
+const SW1_DESFIRE: u8 = 0x91;
+/// Bytes of the EV1 CMAC sent by the card
+const CMAC_LEN: usize = 8;
+/// Bytes of the legacy MAC sent by the card
+const LEGACY_MAC_LEN: usize = 4;
+
+/// Status of a DESFire, answered to every native command
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Status {
+    /// Nothing to commit
+    NoChanges,
+    OutOfEeprom,
+    IllegalCommand,
+    /// CRC or MAC of the command don't match
+    IntegrityError,
+    NoSuchKey,
+    LengthError,
+    PermissionDenied,
+    ParameterError,
+    ApplicationNotFound,
+    ApplicationIntegrityError,
+    AuthenticationError,
+    BoundaryError,
+    PiccIntegrityError,
+    CommandAborted,
+    PiccDisabled,
+    CountError,
+    DuplicateError,
+    EepromError,
+    FileNotFound,
+    FileIntegrityError,
+    /// Status code not listed in the datasheet
+    Unknown(u8),
+}
+
+impl Status {
+    pub(super) const OK: u8 = 0x00;
+    pub(super) const ADDITIONAL_FRAME: u8 = 0xAF;
+
+    /// Error of a status code, `None` for success and additional frames
+    pub const fn from_code(code: u8) -> Option<Self> {
+        Some(match code {
+            Self::OK | Self::ADDITIONAL_FRAME => return None,
+            0x0C => Self::NoChanges,
+            0x0E => Self::OutOfEeprom,
+            0x1C => Self::IllegalCommand,
+            0x1E => Self::IntegrityError,
+            0x40 => Self::NoSuchKey,
+            0x7E => Self::LengthError,
+            0x9D => Self::PermissionDenied,
+            0x9E => Self::ParameterError,
+            0xA0 => Self::ApplicationNotFound,
+            0xA1 => Self::ApplicationIntegrityError,
+            0xAE => Self::AuthenticationError,
+            0xBE => Self::BoundaryError,
+            0xC1 => Self::PiccIntegrityError,
+            0xCA => Self::CommandAborted,
+            0xCD => Self::PiccDisabled,
+            0xCE => Self::CountError,
+            0xDE => Self::DuplicateError,
+            0xEE => Self::EepromError,
+            0xF0 => Self::FileNotFound,
+            0xF1 => Self::FileIntegrityError,
+            code => Self::Unknown(code),
+        })
+    }
+}
+
+/// A key of an application, or of the card
+///
+/// DES keys keep their version in the parity bits, which the cipher ignores.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Key {
+    Des([u8; 8]),
+    /// 2-key triple DES, a key with equal halves acts as single DES
+    Tdes([u8; 16]),
+    Aes([u8; 16]),
+}
+
+impl Key {
+    /// Key 0 of a card as shipped
+    pub const DEFAULT_DES: Self = Self::Des([0x00; 8]);
+
+    pub fn cipher(&self) -> Cipher {
+        match self {
+            Self::Des(key) => {
+                let mut tdes = [0u8; 16];
+                tdes[..8].copy_from_slice(key);
+                tdes[8..].copy_from_slice(key);
+                Cipher::tdes(&tdes)
+            }
+            Self::Tdes(key) => Cipher::tdes(key),
+            Self::Aes(key) => Cipher::aes(key),
+        }
+    }
+
+    /// Whether the key acts as single DES
+    pub fn is_single_des(&self) -> bool {
+        match self {
+            Self::Des(_) => true,
+            Self::Tdes(key) => key[..8]
+                .iter()
+                .zip(&key[8..])
+                .all(|(a, b)| (a ^ b) & 0xFE == 0),
+            Self::Aes(_) => false,
+        }
+    }
+
+    /// The session key made of the random numbers of reader and card
+    pub fn session_key(&self, rnd_a: &[u8], rnd_b: &[u8]) -> Self {
+        let mut key = [0u8; 16];
+        key[0..4].copy_from_slice(&rnd_a[0..4]);
+        key[4..8].copy_from_slice(&rnd_b[0..4]);
+        match self {
+            Self::Aes(_) => {
+                key[8..12].copy_from_slice(&rnd_a[12..16]);
+                key[12..16].copy_from_slice(&rnd_b[12..16]);
+                Self::Aes(key)
+            }
+            _ if self.is_single_des() => Self::Des(key[..8].try_into().unwrap()),
+            _ => {
+                key[8..12].copy_from_slice(&rnd_a[4..8]);
+                key[12..16].copy_from_slice(&rnd_b[4..8]);
+                Self::Tdes(key)
+            }
+        }
+    }
+}
+
+/// Communication settings of a file, how the card protects the data it answers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CommMode {
+    Plain,
+    /// Followed by a MAC
+    Mac,
+    /// Encrypted, with a CRC
+    Full,
+}
+
+/// Answer to `GetVersion`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Version {
+    /// Vendor, type, subtype, major and minor version, storage size and protocol
+    pub hardware: [u8; 7],
+    /// Same layout as `hardware`
+    pub software: [u8; 7],
+    pub uid: [u8; 7],
+    pub batch: [u8; 5],
+    /// Calendar week of production, BCD
+    pub week: u8,
+    /// Year of production, BCD
+    pub year: u8,
+}
+
+impl Version {
+    pub fn parse(version: &[u8; 28]) -> Self {
+        let field = |start: usize| version[start..start + 7].try_into().unwrap();
+        Self {
+            hardware: field(0),
+            software: field(7),
+            uid: field(14),
+            batch: version[21..26].try_into().unwrap(),
+            week: version[26],
+            year: version[27],
+        }
+    }
+
+    pub fn kind(&self) -> Option<CardKind> {
+        CardKind::from_desfire_version(&self.hardware)
+    }
+}
+
+#[derive(Debug)]
+pub enum DesFireError<E> {
+    Apdu(ApduError<E>),
+    /// The card reported an error, which also ends the authentication
+    Status(Status),
+    /// The card answered with a status word of ISO 7816-4 instead of a DESFire status
+    StatusWord(StatusWord),
+    /// The card doesn't know the key, or answered the wrong random number
+    Authentication,
+    /// The MAC or CRC of the answer doesn't match
+    Integrity,
+    /// The answer is shorter or longer than expected
+    InvalidResponse,
+    /// The authentication command doesn't support the key
+    UnsupportedKey,
+    BufferTooSmall,
+}
+
+#[cfg(feature = "defmt")]
+impl<E> defmt::Format for DesFireError<E> {
+    fn format(&self, fmt: defmt::Formatter) {
+        match self {
+            Self::Apdu(err) => defmt::write!(fmt, "{}", err),
+            Self::Status(status) => defmt::write!(fmt, "DESFire status: {}", status),
+            Self::StatusWord(sw) => defmt::write!(fmt, "Status word: {}", sw),
+            Self::Authentication => defmt::write!(fmt, "Authentication failed"),
+            Self::Integrity => defmt::write!(fmt, "Integrity error"),
+            Self::InvalidResponse => defmt::write!(fmt, "Invalid response"),
+            Self::UnsupportedKey => defmt::write!(fmt, "Unsupported key"),
+            Self::BufferTooSmall => defmt::write!(fmt, "Buffer too small"),
+        }
+    }
+}
+
+impl<E> From<ApduError<E>> for DesFireError<E> {
+    fn from(value: ApduError<E>) -> Self {
+        Self::Apdu(value)
+    }
+}
+
+impl<E> From<ReadError<E>> for DesFireError<E> {
+    fn from(value: ReadError<E>) -> Self {
+        Self::Apdu(value.into())
+    }
+}
+
+/// Secure messaging of a session
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Scheme {
+    /// Established by the legacy `Authenticate`
+    Legacy,
+    /// Established by `AuthenticateISO` or `AuthenticateAES`
+    Ev1,
+}
+
+struct Session {
+    cipher: Cipher,
+    scheme: Scheme,
+    iv: Block,
+    key_no: u8,
+}
+
+/// A DESFire card, and the session with it
+///
+/// Any error reported by the card ends the session.
+pub struct DesFire<'r, I, T>
+where
+    I: Interface,
+    T: DelayUs,
+{
+    reader: &'r mut Reader<I, T>,
+    tg: u8,
+    session: Option<Session>,
+}
+
+impl<I, T> Reader<I, T>
+where
+    I: Interface,
+    T: DelayUs,
+{
+    /// Talk to the DESFire with target number `tg`
+    pub fn desfire(&mut self, tg: u8) -> DesFire<'_, I, T> {
+        DesFire {
+            reader: self,
+            tg,
+            session: None,
+        }
+    }
+}
+
+impl<'r, I, T> DesFire<'r, I, T>
+where
+    I: Interface,
+    T: DelayUs,
+{
+    /// The key number of the current session
+    pub fn authenticated_key(&self) -> Option<u8> {
+        self.session.as_ref().map(|session| session.key_no)
+    }
+
+    pub async fn get_version(&mut self) -> Result<Version, DesFireError<I::Error>> {
+        let mut buf = [0u8; 28 + 2 * MAX_BLOCK_SIZE];
+        let version = self
+            .command(DesFireCommand::GetVersion, &[], CommMode::Plain, &mut buf)
+            .await?;
+        let version = version
+            .try_into()
+            .map_err(|_| DesFireError::InvalidResponse)?;
+        Ok(Version::parse(version))
+    }
+
+    /// Read the IDs of the applications into `aids`, returning how many there are
+    pub async fn get_application_ids(
+        &mut self,
+        aids: &mut [u32],
+    ) -> Result<usize, DesFireError<I::Error>> {
+        let mut buf = [0u8; 28 * 3 + 2 * MAX_BLOCK_SIZE];
+        let ids = self
+            .command(
+                DesFireCommand::GetApplicationIds,
+                &[],
+                CommMode::Plain,
+                &mut buf,
+            )
+            .await?;
+        if ids.len() % 3 != 0 {
+            return Err(DesFireError::InvalidResponse);
+        }
+        let count = ids.len() / 3;
+        let aids = aids.get_mut(..count).ok_or(DesFireError::BufferTooSmall)?;
+        for (aid, id) in aids.iter_mut().zip(ids.chunks(3)) {
+            *aid = u32::from_le_bytes([id[0], id[1], id[2], 0]);
+        }
+        Ok(count)
+    }
+
+    /// Select an application, or the card level with [`PICC_AID`], ending the session
+    pub async fn select_application(&mut self, aid: u32) -> Result<(), DesFireError<I::Error>> {
+        self.session = None;
+        let aid = aid.to_le_bytes();
+        let mut buf = [0u8; 2];
+        self.exchange(DesFireCommand::SelectApplication, &aid[..3], &mut buf)
+            .await?;
+        Ok(())
+    }
+
+    /// Read the numbers of the files of the selected application into `ids`, returning how many
+    /// there are
+    pub async fn get_file_ids(&mut self, ids: &mut [u8]) -> Result<usize, DesFireError<I::Error>> {
+        let mut buf = [0u8; 32 + 2 * MAX_BLOCK_SIZE];
+        let files = self
+            .command(DesFireCommand::GetFileIds, &[], CommMode::Plain, &mut buf)
+            .await?;
+        let ids = ids
+            .get_mut(..files.len())
+            .ok_or(DesFireError::BufferTooSmall)?;
+        ids.copy_from_slice(files);
+        Ok(ids.len())
+    }
+
+    /// Read `len` bytes of a standard or backup data file, starting at `offset`
+    ///
+    /// A `len` of zero reads up to the end of the file. `comm` has to match the communication
+    /// settings of the file. `buf` needs room for two blocks and the status word more than the
+    /// data.
+    pub async fn read_data<'b>(
+        &mut self,
+        file: u8,
+        offset: u32,
+        len: u32,
+        comm: CommMode,
+        buf: &'b mut [u8],
+    ) -> Result<&'b [u8], DesFireError<I::Error>> {
+        let offset = offset.to_le_bytes();
+        let length = len.to_le_bytes();
+        let header = [
+            file, offset[0], offset[1], offset[2], length[0], length[1], length[2],
+        ];
+        let data = self
+            .command(DesFireCommand::ReadData, &header, comm, buf)
+            .await?;
+        match len {
+            0 => Ok(data),
+            len if data.len() == len as usize => Ok(data),
+            _ => Err(DesFireError::InvalidResponse),
+        }
+    }
+
+    /// Read the value of a value file
+    pub async fn get_value(
+        &mut self,
+        file: u8,
+        comm: CommMode,
+    ) -> Result<i32, DesFireError<I::Error>> {
+        let mut buf = [0u8; 4 + 2 * MAX_BLOCK_SIZE];
+        let value = self
+            .command(DesFireCommand::GetValue, &[file], comm, &mut buf)
+            .await?;
+        let value = value
+            .try_into()
+            .map_err(|_| DesFireError::InvalidResponse)?;
+        Ok(i32::from_le_bytes(value))
+    }
+
+    /// Authenticate with `key_no` of the selected application, using `AuthenticateISO` for DES
+    /// keys and `AuthenticateAES` for AES keys
+    ///
+    /// `rnd_a` is the random number of the reader, only the first 8 bytes are used for DES keys.
+    pub async fn authenticate(
+        &mut self,
+        key_no: u8,
+        key: &Key,
+        rnd_a: &[u8; 16],
+    ) -> Result<(), DesFireError<I::Error>> {
+        self.session = None;
+        let command = match key {
+            Key::Aes(_) => DesFireCommand::AuthenticateAes,
+            _ => DesFireCommand::AuthenticateIso,
+        };
+        let cipher = key.cipher();
+        let size = cipher.block_size();
+        let rnd_a = &rnd_a[..size];
+
+        let mut buf = [0u8; 2 * MAX_BLOCK_SIZE + 2];
+        let mut rnd_b = [0u8; MAX_BLOCK_SIZE];
+        let mut iv = [0u8; MAX_BLOCK_SIZE];
+        let encrypted = self.begin_authentication(command, key_no, &mut buf).await?;
+        let rnd_b = rnd_b.get_mut(..size).filter(|_| encrypted.len() == size);
+        let rnd_b = rnd_b.ok_or(DesFireError::InvalidResponse)?;
+        rnd_b.copy_from_slice(encrypted);
+        cipher.decrypt_cbc(&mut iv, rnd_b);
+
+        // RndA and RndB rotated left by a byte, chained to the encrypted RndB
+        let mut token = [0u8; 2 * MAX_BLOCK_SIZE];
+        let token = &mut token[..2 * size];
+        token[..size].copy_from_slice(rnd_a);
+        token[size..].copy_from_slice(rnd_b);
+        token[size..].rotate_left(1);
+        cipher.encrypt_cbc(&mut iv, token);
+
+        let answer = self.exchange(DesFireCommand::AdditionalFrame, token, &mut buf);
+        let answer = answer.await.map_err(authentication_error)?;
+        let rnd_a_rotated = &mut buf[..answer.min(size)];
+        if answer != size {
+            return Err(DesFireError::InvalidResponse);
+        }
+        cipher.decrypt_cbc(&mut iv, rnd_a_rotated);
+        rnd_a_rotated.rotate_right(1);
+        if rnd_a_rotated != rnd_a {
+            return Err(DesFireError::Authentication);
+        }
+
+        debug!("Authenticated with key {}", key_no);
+        self.session = Some(Session {
+            cipher: key.session_key(rnd_a, rnd_b).cipher(),
+            scheme: Scheme::Ev1,
+            iv: [0u8; MAX_BLOCK_SIZE],
+            key_no,
+        });
+        Ok(())
+    }
+
+    /// Authenticate with `key_no` of the selected application, using the legacy `Authenticate`
+    /// of the original DESFire
+    ///
+    /// Only DES keys are supported, `rnd_a` is the random number of the reader.
+    pub async fn authenticate_legacy(
+        &mut self,
+        key_no: u8,
+        key: &Key,
+        rnd_a: &[u8; 8],
+    ) -> Result<(), DesFireError<I::Error>> {
+        self.session = None;
+        if matches!(key, Key::Aes(_)) {
+            return Err(DesFireError::UnsupportedKey);
+        }
+        let cipher = key.cipher();
+
+        let mut buf = [0u8; 16 + 2];
+        let encrypted = self
+            .begin_authentication(DesFireCommand::Authenticate, key_no, &mut buf)
+            .await?;
+        let mut rnd_b: [u8; 8] = encrypted
+            .try_into()
+            .map_err(|_| DesFireError::InvalidResponse)?;
+        cipher.decrypt_block(&mut rnd_b);
+
+        // the reader deciphers what it sends, and the card enciphers
+        let mut token = [0u8; 16];
+        token[..8].copy_from_slice(rnd_a);
+        token[8..].copy_from_slice(&rnd_b);
+        token[8..].rotate_left(1);
+        cipher.decrypt_send(&mut [0u8; MAX_BLOCK_SIZE], &mut token);
+
+        let answer = self.exchange(DesFireCommand::AdditionalFrame, &token, &mut buf);
+        let answer = answer.await.map_err(authentication_error)?;
+        let mut rnd_a_rotated: [u8; 8] = buf[..answer]
+            .try_into()
+            .map_err(|_| DesFireError::InvalidResponse)?;
+        cipher.decrypt_block(&mut rnd_a_rotated);
+        rnd_a_rotated.rotate_right(1);
+        if &rnd_a_rotated != rnd_a {
+            return Err(DesFireError::Authentication);
+        }
+
+        debug!("Authenticated with key {} (legacy)", key_no);
+        self.session = Some(Session {
+            cipher: key.session_key(rnd_a, &rnd_b).cipher(),
+            scheme: Scheme::Legacy,
+            iv: [0u8; MAX_BLOCK_SIZE],
+            key_no,
+        });
+        Ok(())
+    }
+
+    /// First step of an authentication, returning the encrypted RndB
+    async fn begin_authentication<'b>(
+        &mut self,
+        command: DesFireCommand,
+        key_no: u8,
+        buf: &'b mut [u8],
+    ) -> Result<&'b [u8], DesFireError<I::Error>> {
+        let (len, status) = self.transmit(command as u8, &[key_no], buf).await?;
+        match status {
+            Status::ADDITIONAL_FRAME => Ok(&buf[..len]),
+            Status::OK => Err(DesFireError::InvalidResponse),
+            code => Err(authentication_error(self.error(code))),
+        }
+    }
+
+    /// Send a command with the secure messaging of the session, returning the plain answer
+    async fn command<'b>(
+        &mut self,
+        command: DesFireCommand,
+        data: &[u8],
+        comm: CommMode,
+        buf: &'b mut [u8],
+    ) -> Result<&'b [u8], DesFireError<I::Error>> {
+        // the command is sent in plain, but advances the IV
+        if let Some(session) = self.session.as_mut().filter(|s| s.scheme == Scheme::Ev1) {
+            let size = session.cipher.block_size();
+            let mac = session
+                .cipher
+                .cmac_parts(&session.iv, &[&[command as u8], data]);
+            session.iv[..size].copy_from_slice(&mac[..size]);
+        }
+
+        let len = self.exchange(command, data, buf).await?;
+        let Some(session) = self.session.as_mut() else {
+            return Ok(&buf[..len]);
+        };
+        let len = match session.scheme {
+            Scheme::Ev1 => session.verify_ev1(comm, &mut buf[..len]),
+            Scheme::Legacy => session.verify_legacy(comm, &mut buf[..len]),
+        };
+        match len {
+            Some(len) => Ok(&buf[..len]),
+            None => {
+                warn!("Integrity check of the answer failed");
+                self.session = None;
+                Err(DesFireError::Integrity)
+            }
+        }
+    }
+
+    /// Send a command, collecting the answer of all frames in `buf`
+    async fn exchange(
+        &mut self,
+        command: DesFireCommand,
+        data: &[u8],
+        buf: &mut [u8],
+    ) -> Result<usize, DesFireError<I::Error>> {
+        let (mut len, mut status) = self.transmit(command as u8, data, buf).await?;
+        while status == Status::ADDITIONAL_FRAME {
+            trace!("Additional frame, {} bytes so far", len);
+            let (received, next) = self
+                .transmit(DesFireCommand::AdditionalFrame as u8, &[], &mut buf[len..])
+                .await?;
+            len += received;
+            status = next;
+        }
+        match status {
+            Status::OK => Ok(len),
+            code => Err(self.error(code)),
+        }
+    }
+
+    /// Send a single frame, returning the length of the answer and the status code
+    async fn transmit(
+        &mut self,
+        command: u8,
+        data: &[u8],
+        buf: &mut [u8],
+    ) -> Result<(usize, u8), DesFireError<I::Error>> {
+        transmit(self.reader, self.tg, command, data, buf).await
+    }
+
+    /// The error of a status code, ending the session
+    fn error(&mut self, code: u8) -> DesFireError<I::Error> {
+        self.session = None;
+        match Status::from_code(code) {
+            Some(status) => DesFireError::Status(status),
+            None => DesFireError::InvalidResponse,
+        }
+    }
+}
+
+/// Send a single frame of a native command, returning the length of the answer and the status
+/// code
+pub(super) async fn transmit<I: Interface, T: DelayUs>(
+    reader: &mut Reader<I, T>,
+    tg: u8,
+    command: u8,
+    data: &[u8],
+    buf: &mut [u8],
+) -> Result<(usize, u8), DesFireError<I::Error>> {
+    let apdu = Command::new(CLA, command, 0x00, 0x00)
+        .with_data(data)
+        .with_le(MAX_SHORT_LE);
+    let response = reader.transmit(tg, &apdu, buf).await?;
+    match response.sw.sw1() {
+        SW1_DESFIRE => Ok((response.data.len(), response.sw.sw2())),
+        _ => Err(DesFireError::StatusWord(response.sw)),
+    }
+}
+
+/// A card answering the token with an error doesn't know the key
+pub(super) fn authentication_error<E>(err: DesFireError<E>) -> DesFireError<E> {
+    match err {
+        DesFireError::Status(Status::AuthenticationError) => DesFireError::Authentication,
+        err => err,
+    }
+}
+
+impl Session {
+    /// Check and strip the CMAC, or decrypt the answer, returning the length of the data
+    fn verify_ev1(&mut self, comm: CommMode, answer: &mut [u8]) -> Option<usize> {
+        let size = self.cipher.block_size();
+        match comm {
+            CommMode::Plain | CommMode::Mac => {
+                let len = answer.len().checked_sub(CMAC_LEN)?;
+                let mut received = [0u8; CMAC_LEN];
+                received.copy_from_slice(&answer[len..]);
+                // the CMAC covers the data and the status, which takes the place of the CMAC
+                answer[len] = Status::OK;
+                let mac = self.cipher.cmac(&self.iv, &answer[..len + 1]);
+                self.iv[..size].copy_from_slice(&mac[..size]);
+                crypto::matches(&mac[..CMAC_LEN], &received).then_some(len)
+            }
+            CommMode::Full => {
+                if answer.is_empty() || !answer.chunks_exact(size).remainder().is_empty() {
+                    return None;
+                }
+                self.cipher.decrypt_cbc(&mut self.iv, answer);
+                // data, CRC-32 of data and status, zero padding
+                (0..=answer.len() - 4).rev().find(|&len| {
+                    let crc = crypto::crc32_update(crypto::crc32(&answer[..len]), &[Status::OK]);
+                    answer[len..len + 4] == crc.to_le_bytes()
+                        && answer[len + 4..].iter().all(|&b| b == 0)
+                        && answer.len() - len - 4 < size
+                })
+            }
+        }
+    }
+
+    /// Check and strip the MAC, or decrypt the answer, returning the length of the data
+    fn verify_legacy(&mut self, comm: CommMode, answer: &mut [u8]) -> Option<usize> {
+        let size = self.cipher.block_size();
+        match comm {
+            CommMode::Plain => Some(answer.len()),
+            CommMode::Mac => {
+                let len = answer.len().checked_sub(LEGACY_MAC_LEN)?;
+                let mac = legacy_mac(&self.cipher, &answer[..len]);
+                crypto::matches(&mac[..LEGACY_MAC_LEN], &answer[len..]).then_some(len)
+            }
+            CommMode::Full => {
+                if answer.is_empty() || !answer.chunks_exact(size).remainder().is_empty() {
+                    return None;
+                }
+                self.cipher.decrypt_cbc(&mut [0u8; MAX_BLOCK_SIZE], answer);
+                // data, CRC-16 of the data, zero padding. The CRC over data and CRC is zero, so
+                // the shortest match is the data
+                let min = answer.len().saturating_sub(size + 1);
+                (min..=answer.len() - 2).find(|&len| {
+                    answer[len..len + 2] == crypto::crc16(&answer[..len]).to_le_bytes()
+                        && answer[len + 2..].iter().all(|&b| b == 0)
+                        && answer.len() - len - 2 < size
+                })
+            }
+        }
+    }
+}
+
+/// MAC of the legacy secure messaging: the start of the last block of the data, zero padded and
+/// encrypted in CBC mode
+pub fn legacy_mac(cipher: &Cipher, data: &[u8]) -> Block {
+    let size = cipher.block_size();
+    let mut mac = [0u8; MAX_BLOCK_SIZE];
+    for chunk in data.chunks(size) {
+        crypto::xor(&mut mac[..chunk.len()], chunk);
+        cipher.encrypt_block(&mut mac[..size]);
+    }
+    mac
+}
@@ -0,0 +1,222 @@
+//! Incremental parser for frames sent by the PN532, including extended information frames
+//!
+//! The parser is a pure state machine, fed one byte at a time. It scans for the start code, so
+//! it tolerates any amount of preamble or garbage in front of a frame, and picks up the next
+//! frame after a corrupted one.
+
+/// Kind of a successfully parsed frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Frame {
+    /// ACK frame
+    Ack,
+    /// NACK frame
+    Nack,
+    /// Application level error frame (6.2.1.5)
+    Error,
+    /// Normal information frame, its TFI and data are available from [`Parser::data`]
+    Information,
+}
+
+/// Reason a frame was dropped
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FrameError {
+    /// Length checksum mismatch
+    LengthChecksum,
+    /// Data checksum mismatch
+    DataChecksum,
+    /// Frame doesn't fit into the parser buffer
+    TooLong,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    /// Looking for the start code, remembering if the last byte was `0x00`
+    Scan {
+        zero: bool,
+    },
+    Len,
+    Lcs {
+        len: u8,
+    },
+    /// Extended frame, `LEN` and `LCS` were `0xFF`
+    ExtLenM,
+    ExtLenL {
+        m: u8,
+    },
+    ExtLcs {
+        len: u16,
+    },
+    Data,
+    Dcs,
+}
+
+/// Incremental frame parser, holding up to `N` bytes of frame data (TFI included)
+pub struct Parser<const N: usize> {
+    state: State,
+    data: [u8; N],
+    len: usize,
+    pos: usize,
+    sum: u8,
+}
+
+impl<const N: usize> Default for Parser<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Parser<N> {
+    pub const fn new() -> Self {
+        Self {
+            state: State::Scan { zero: false },
+            data: [0u8; N],
+            len: 0,
+            pos: 0,
+            sum: 0,
+        }
+    }
+
+    /// Drop any partially parsed frame and start scanning again
+    pub fn reset(&mut self) {
+        self.state = State::Scan { zero: false };
+        self.len = 0;
+        self.pos = 0;
+        self.sum = 0;
+    }
+
+    /// Feed the next byte
+    ///
+    /// Returns `None` until a frame is complete, or has been dropped. In both cases the parser
+    /// continues scanning for the next frame with the following byte.
+    pub fn push(&mut self, byte: u8) -> Option<Result<Frame, FrameError>> {
+        match self.state {
+            State::Scan { zero } => {
+                self.state = match (zero, byte) {
+                    (true, 0xFF) => State::Len,
+                    (_, 0x00) => State::Scan { zero: true },
+                    _ => State::Scan { zero: false },
+                };
+                None
+            }
+            State::Len => {
+                self.state = State::Lcs { len: byte };
+                None
+            }
+            State::Lcs { len } => match (len, byte) {
+                (0x00, 0xFF) => self.finish(Ok(Frame::Ack)),
+                (0xFF, 0x00) => self.finish(Ok(Frame::Nack)),
+                (0xFF, 0xFF) => {
+                    self.state = State::ExtLenM;
+                    None
+                }
+                _ if len.wrapping_add(byte) != 0 => self.resync(byte),
+                _ => self.start_data(len as usize),
+            },
+            State::ExtLenM => {
+                self.state = State::ExtLenL { m: byte };
+                None
+            }
+            State::ExtLenL { m } => {
+                self.state = State::ExtLcs {
+                    len: u16::from_be_bytes([m, byte]),
+                };
+                None
+            }
+            State::ExtLcs { len } => {
+                let [m, l] = len.to_be_bytes();
+                match m.wrapping_add(l).wrapping_add(byte) {
+                    0 => self.start_data(len as usize),
+                    _ => self.resync(byte),
+                }
+            }
+            State::Data => {
+                self.data[self.pos] = byte;
+                self.pos += 1;
+                self.sum = self.sum.wrapping_add(byte);
+                if self.pos == self.len {
+                    self.state = State::Dcs;
+                }
+                None
+            }
+            State::Dcs => {
+                if self.sum.wrapping_add(byte) != 0 {
+                    return self.finish(Err(FrameError::DataChecksum));
+                }
+                match self.data() {
+                    [0x7F] => self.finish(Ok(Frame::Error)),
+                    _ => self.finish(Ok(Frame::Information)),
+                }
+            }
+        }
+    }
+
+    /// Feed a slice of bytes, stopping at the first completed or dropped frame
+    ///
+    /// Returns the number of bytes consumed, together with the outcome.
+    pub fn feed(&mut self, bytes: &[u8]) -> (usize, Option<Result<Frame, FrameError>>) {
+        for (n, &b) in bytes.iter().enumerate() {
+            if let Some(result) = self.push(b) {
+                return (n + 1, Some(result));
+            }
+        }
+        (bytes.len(), None)
+    }
+
+    /// Minimum number of bytes still needed before the current frame is complete, up to and
+    /// including its postamble
+    ///
+    /// Reading exactly this many bytes never reads past the end of a frame, so a byte stream is
+    /// left at a frame boundary.
+    pub fn needed(&self) -> usize {
+        match self.state {
+            State::Scan { zero: true } => 4,
+            State::Scan { zero: false } => 5,
+            State::Len => 3,
+            State::Lcs { .. } => 2,
+            State::ExtLenM => 5,
+            State::ExtLenL { .. } => 4,
+            State::ExtLcs { len } => len as usize + 3,
+            State::Data => self.len - self.pos + 2,
+            State::Dcs => 2,
+        }
+    }
+
+    /// TFI and data of the last information frame
+    pub fn data(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+
+    fn start_data(&mut self, len: usize) -> Option<Result<Frame, FrameError>> {
+        if len > N {
+            return self.finish(Err(FrameError::TooLong));
+        }
+        self.len = len;
+        self.pos = 0;
+        self.sum = 0;
+        self.state = match len {
+            0 => State::Dcs,
+            _ => State::Data,
+        };
+        None
+    }
+
+    /// Drop a frame with a broken length
+    ///
+    /// The start code might have been part of the payload of a broken frame, so keep scanning
+    /// right here instead of skipping anything.
+    fn resync(&mut self, byte: u8) -> Option<Result<Frame, FrameError>> {
+        self.reset();
+        self.push(byte);
+        Some(Err(FrameError::LengthChecksum))
+    }
+
+    fn finish(&mut self, result: Result<Frame, FrameError>) -> Option<Result<Frame, FrameError>> {
+        self.state = State::Scan { zero: false };
+        if !matches!(result, Ok(Frame::Information) | Ok(Frame::Error)) {
+            self.len = 0;
+        }
+        Some(result)
+    }
+}
@@ -1,36 +1,136 @@
-use crate::driver::{protocol::Error, Interface};
+use crate::driver::protocol::{Error, MAX_FRAME_LEN};
+use crate::driver::{Interface, Irq, NoIrq};
+use embassy_futures::yield_now;
 use embedded_hal_async::i2c::Operation;
 
 const ADDRESS: u8 = 0x24;
 
-pub struct I2c<I>(pub I)
+/// Bytes read to learn the length of a frame, the header of an extended frame with preamble
+const HEADER_LEN: usize = 8;
+
+/// I2C interface, with an optional IRQ line
+///
+/// The PN532 prefixes every read with the status byte, and starts over with the frame on every
+/// read. So the header of the frame is read first, then the frame again, if the header announces
+/// more data. The frame is handed out in chunks from an internal buffer.
+pub struct I2c<I, Q = NoIrq>
 where
-    I: embedded_hal_async::i2c::I2c;
+    I: embedded_hal_async::i2c::I2c,
+    Q: Irq,
+{
+    bus: I,
+    irq: Q,
+    frame: [u8; MAX_FRAME_LEN],
+    /// Bytes of `frame` read from the PN532
+    len: usize,
+    /// Position of the next byte to be handed out of `frame`, `None` if it wasn't read yet
+    position: Option<usize>,
+}
 
-impl<I> Interface for I2c<I>
+impl<I> I2c<I>
 where
     I: embedded_hal_async::i2c::I2c,
 {
-    type Error = I::Error;
+    /// Create an interface without IRQ line, polling for readiness
+    pub fn new(bus: I) -> Self {
+        Self::with_irq(bus, NoIrq)
+    }
+}
 
-    async fn send(&mut self, data: &[u8]) -> Result<(), Error<Self::Error>> {
-        self.0.write(ADDRESS, &data).await.map_err(Error::Transport)
+impl<I, Q> I2c<I, Q>
+where
+    I: embedded_hal_async::i2c::I2c,
+    Q: Irq,
+{
+    /// Create an interface waiting for the IRQ line before polling the status
+    pub fn with_irq(bus: I, irq: Q) -> Self {
+        Self {
+            bus,
+            irq,
+            frame: [0u8; MAX_FRAME_LEN],
+            len: 0,
+            position: None,
+        }
     }
 
-    async fn receive(&mut self, buf: &mut [u8]) -> Result<(), Error<Self::Error>> {
-        self.0
+    /// Read the status byte and the first `len` bytes of the frame
+    async fn read(&mut self, len: usize) -> Result<(), Error<I::Error>> {
+        self.bus
             .transaction(
                 ADDRESS,
-                &mut [Operation::Read(&mut [0u8]), Operation::Read(buf)],
+                &mut [
+                    Operation::Read(&mut [0u8]),
+                    Operation::Read(&mut self.frame[..len]),
+                ],
             )
             .await
+            .map_err(Error::Transport)?;
+        self.len = len;
+        Ok(())
+    }
+}
+
+/// Length of the frame starting with `header`, preamble and postamble included
+///
+/// `None` if there is no start code in `header`.
+fn frame_len(header: &[u8]) -> Option<usize> {
+    let start = header.windows(2).position(|code| code == [0x00, 0xFF])? + 2;
+    let len = match header[start..] {
+        // ACK and NACK frame
+        [0x00, 0xFF, ..] | [0xFF, 0x00, ..] => start + 3,
+        // extended information frame: LENM, LENL, LCS, data, DCS and postamble
+        [0xFF, 0xFF, m, l, ..] => start + 7 + u16::from_be_bytes([m, l]) as usize,
+        // LEN, LCS, data, DCS and postamble
+        [len, _, ..] => start + 4 + len as usize,
+        _ => return None,
+    };
+    Some(len.min(MAX_FRAME_LEN))
+}
+
+impl<I, Q> Interface for I2c<I, Q>
+where
+    I: embedded_hal_async::i2c::I2c,
+    Q: Irq,
+{
+    type Error = I::Error;
+
+    async fn send(&mut self, data: &[u8]) -> Result<(), Error<Self::Error>> {
+        self.position = None;
+        self.bus
+            .write(ADDRESS, &data)
+            .await
             .map_err(Error::Transport)
     }
 
+    async fn receive(&mut self, buf: &mut [u8]) -> Result<(), Error<Self::Error>> {
+        let position = match self.position {
+            Some(position) => position,
+            None => {
+                self.read(HEADER_LEN).await?;
+                let len = frame_len(&self.frame[..HEADER_LEN]).unwrap_or(MAX_FRAME_LEN);
+                if len > HEADER_LEN {
+                    self.read(len).await?;
+                }
+                0
+            }
+        };
+
+        let end = (position + buf.len()).min(self.len);
+        let len = end - position;
+        buf.fill(0);
+        buf[..len].copy_from_slice(&self.frame[position..end]);
+        self.position = Some(end);
+
+        Ok(())
+    }
+
     async fn wait_for_ready(&mut self) -> Result<(), Error<Self::Error>> {
+        self.position = None;
+        self.irq.wait_for_ready().await;
+
         let mut buf = [0u8; 1];
         loop {
-            self.0
+            self.bus
                 .read(ADDRESS, &mut buf)
                 .await
                 .map_err(Error::Transport)?;
@@ -39,6 +139,9 @@ where
                 // we are ready
                 break;
             }
+
+            // give a pending timeout the chance to cancel us
+            yield_now().await;
         }
 
         Ok(())
@@ -0,0 +1,27 @@
+use embedded_hal_async::digital::Wait;
+
+/// Readiness signal of the PN532, through its `P70_IRQ` line
+///
+/// Waiting for the signal lets the MCU sleep, instead of polling the status of the PN532.
+pub trait Irq {
+    /// Wait for the PN532 to signal that it is ready
+    async fn wait_for_ready(&mut self);
+}
+
+/// No IRQ line connected, readiness is only polled
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoIrq;
+
+impl Irq for NoIrq {
+    async fn wait_for_ready(&mut self) {}
+}
+
+impl<P: Wait> Irq for P {
+    async fn wait_for_ready(&mut self) {
+        // The PN532 pulls the line low for as long as it is ready. Waiting for the level, instead of
+        // the falling edge, doesn't miss a response which got ready before we started waiting.
+        if self.wait_for_low().await.is_err() {
+            warn!("Failed to wait for IRQ, falling back to polling");
+        }
+    }
+}
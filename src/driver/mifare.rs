@@ -0,0 +1,577 @@
+//! Mifare Classic sector authentication, block and value block access
+//!
+//! The memory is organized in blocks of 16 bytes, grouped into sectors of 4 blocks (16 blocks for
+//! the upper sectors of the 4K). The last block of each sector, the sector trailer, holds the
+//! keys A and B and the access bits. A sector has to be authenticated with one of its keys before
+//! its blocks can be accessed. The PN532 handles the Crypto1 encryption.
+
+use crate::driver::protocol::Interface;
+use crate::driver::requests::MifareCommand;
+use crate::driver::{CardUid, Error, ReadError, Reader, Status};
+use embedded_hal_async::delay::DelayUs;
+
+/// Bytes in a block
+pub const BLOCK_SIZE: usize = 16;
+
+/// The sector a block belongs to
+pub const fn sector_of(block: u8) -> u8 {
+    match block {
+        0..=127 => block / 4,
+        _ => 32 + (block - 128) / 16,
+    }
+}
+
+/// The first block of a sector
+pub const fn first_block(sector: u8) -> u8 {
+    match sector {
+        0..=31 => sector * 4,
+        _ => 128 + (sector - 32) * 16,
+    }
+}
+
+/// The sector trailer of a sector
+pub const fn trailer_block(sector: u8) -> u8 {
+    match sector {
+        0..=31 => first_block(sector) + 3,
+        _ => first_block(sector) + 15,
+    }
+}
+
+/// Key used to authenticate a sector
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum KeyType {
+    A,
+    B,
+}
+
+impl KeyType {
+    const fn command(self) -> MifareCommand {
+        match self {
+            Self::A => MifareCommand::AuthenticationWithKeyA,
+            Self::B => MifareCommand::AuthenticationWithKeyB,
+        }
+    }
+}
+
+/// A 48 bit sector key, and which of the two keys of the sector it is
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Key {
+    pub key_type: KeyType,
+    pub key: [u8; 6],
+}
+
+impl Key {
+    /// Key A of cards in transport configuration
+    pub const DEFAULT_A: Self = Self {
+        key_type: KeyType::A,
+        key: [0xFF; 6],
+    };
+}
+
+/// Value block: a signed 32 bit value, stored three times, and the address of a block
+///
+/// The address is free to use, commonly for the address of a backup block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ValueBlock {
+    pub value: i32,
+    pub address: u8,
+}
+
+impl ValueBlock {
+    /// Decode a block, `None` if it isn't a valid value block
+    ///
+    /// The value is stored as is, inverted and as is again, the address as is, inverted, as is
+    /// and inverted.
+    pub fn decode(block: &[u8; BLOCK_SIZE]) -> Option<Self> {
+        let value = |offset: usize| {
+            u32::from_le_bytes([
+                block[offset],
+                block[offset + 1],
+                block[offset + 2],
+                block[offset + 3],
+            ])
+        };
+        let (value, inverted, copy) = (value(0), value(4), value(8));
+        let [address, address_inverted, address_copy, address_inverted_copy] =
+            [block[12], block[13], block[14], block[15]];
+
+        let valid = value == copy
+            && value == !inverted
+            && address == address_copy
+            && address == !address_inverted
+            && address == !address_inverted_copy;
+        valid.then_some(Self {
+            value: value as i32,
+            address,
+        })
+    }
+
+    pub fn encode(&self) -> [u8; BLOCK_SIZE] {
+        let value = self.value.to_le_bytes();
+        let inverted = (!self.value).to_le_bytes();
+
+        let mut block = [0u8; BLOCK_SIZE];
+        block[0..4].copy_from_slice(&value);
+        block[4..8].copy_from_slice(&inverted);
+        block[8..12].copy_from_slice(&value);
+        block[12..16].copy_from_slice(&[self.address, !self.address, self.address, !self.address]);
+        block
+    }
+}
+
+/// Who is granted an operation by the access conditions
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Permission {
+    Never,
+    KeyA,
+    KeyB,
+    KeyAOrB,
+}
+
+impl Permission {
+    /// Whether a sector authenticated with `key_type` is granted the operation
+    pub const fn allows(self, key_type: KeyType) -> bool {
+        matches!(
+            (self, key_type),
+            (Self::KeyAOrB, _) | (Self::KeyA, KeyType::A) | (Self::KeyB, KeyType::B)
+        )
+    }
+
+    /// Drop key B, for sectors where key B is readable and can't be used for authentication
+    const fn without_key_b(self) -> Self {
+        match self {
+            Self::KeyB => Self::Never,
+            Self::KeyAOrB => Self::KeyA,
+            permission => permission,
+        }
+    }
+}
+
+/// Access condition bits `C1`, `C2` and `C3` of a block, or group of blocks
+///
+/// All combinations are valid, their meaning differs between data blocks and the sector trailer.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AccessCondition {
+    pub c1: bool,
+    pub c2: bool,
+    pub c3: bool,
+}
+
+impl AccessCondition {
+    /// The bits as `C1 C2 C3`, the way the datasheets list them
+    pub const fn bits(self) -> u8 {
+        (self.c1 as u8) << 2 | (self.c2 as u8) << 1 | self.c3 as u8
+    }
+
+    pub const fn from_bits(bits: u8) -> Self {
+        Self {
+            c1: bits & 0b100 != 0,
+            c2: bits & 0b010 != 0,
+            c3: bits & 0b001 != 0,
+        }
+    }
+}
+
+/// What the access conditions grant for a data block
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DataBlockAccess {
+    pub read: Permission,
+    pub write: Permission,
+    pub increment: Permission,
+    /// Decrement, transfer and restore
+    pub decrement: Permission,
+}
+
+/// What the access conditions grant for the sector trailer, key A can never be read
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TrailerAccess {
+    pub key_a_write: Permission,
+    pub access_bits_read: Permission,
+    pub access_bits_write: Permission,
+    pub key_b_read: Permission,
+    pub key_b_write: Permission,
+}
+
+/// Access conditions of a block
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BlockAccess {
+    Data(DataBlockAccess),
+    Trailer(TrailerAccess),
+}
+
+/// Access bits of a sector: the conditions of the 3 data block groups and the sector trailer
+///
+/// In sectors of 4 blocks a group is a single block, in the sectors of 16 blocks of the 4K it is
+/// 5 blocks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AccessBits {
+    /// Data block groups 0 to 2, then the sector trailer
+    pub conditions: [AccessCondition; 4],
+}
+
+impl AccessBits {
+    /// Transport configuration: data blocks are open with either key, key A manages the trailer
+    pub const TRANSPORT: Self = Self {
+        conditions: [
+            AccessCondition::from_bits(0b000),
+            AccessCondition::from_bits(0b000),
+            AccessCondition::from_bits(0b000),
+            AccessCondition::from_bits(0b001),
+        ],
+    };
+
+    /// Decode bytes 6 to 8 of a sector trailer, `None` if the inverted copy doesn't match
+    pub fn decode(bytes: [u8; 3]) -> Option<Self> {
+        let [b6, b7, b8] = bytes;
+        let (c1, c1_inverted) = (b7 >> 4, b6 & 0x0F);
+        let (c2, c2_inverted) = (b8 & 0x0F, b6 >> 4);
+        let (c3, c3_inverted) = (b8 >> 4, b7 & 0x0F);
+        if c1 != !c1_inverted & 0x0F || c2 != !c2_inverted & 0x0F || c3 != !c3_inverted & 0x0F {
+            return None;
+        }
+
+        let mut conditions = [AccessCondition::default(); 4];
+        for (group, condition) in conditions.iter_mut().enumerate() {
+            *condition = AccessCondition {
+                c1: c1 >> group & 1 != 0,
+                c2: c2 >> group & 1 != 0,
+                c3: c3 >> group & 1 != 0,
+            };
+        }
+        Some(Self { conditions })
+    }
+
+    /// Encode as bytes 6 to 8 of a sector trailer, always with a matching inverted copy
+    pub fn encode(&self) -> [u8; 3] {
+        let (mut c1, mut c2, mut c3) = (0u8, 0u8, 0u8);
+        for (group, condition) in self.conditions.iter().enumerate() {
+            c1 |= (condition.c1 as u8) << group;
+            c2 |= (condition.c2 as u8) << group;
+            c3 |= (condition.c3 as u8) << group;
+        }
+        [
+            (!c2 & 0x0F) << 4 | (!c1 & 0x0F),
+            c1 << 4 | (!c3 & 0x0F),
+            c3 << 4 | c2,
+        ]
+    }
+
+    /// Whether key B can be read, then it can't be used for authentication
+    pub fn key_b_readable(&self) -> bool {
+        matches!(self.conditions[3].bits(), 0b000..=0b010)
+    }
+
+    /// What the conditions grant for `block`
+    pub fn access(&self, block: u8) -> BlockAccess {
+        match group_of(block) {
+            3 => BlockAccess::Trailer(self.trailer_access()),
+            group => BlockAccess::Data(self.data_access(group)),
+        }
+    }
+
+    /// What the conditions grant for the data blocks of `group` (0 to 2)
+    pub fn data_access(&self, group: usize) -> DataBlockAccess {
+        use Permission::*;
+
+        let (read, write, increment, decrement) = match self.conditions[group].bits() {
+            0b000 => (KeyAOrB, KeyAOrB, KeyAOrB, KeyAOrB),
+            0b010 => (KeyAOrB, Never, Never, Never),
+            0b100 => (KeyAOrB, KeyB, Never, Never),
+            0b110 => (KeyAOrB, KeyB, KeyB, KeyAOrB),
+            0b001 => (KeyAOrB, Never, Never, KeyAOrB),
+            0b011 => (KeyB, KeyB, Never, Never),
+            0b101 => (KeyB, Never, Never, Never),
+            _ => (Never, Never, Never, Never),
+        };
+        let access = DataBlockAccess {
+            read,
+            write,
+            increment,
+            decrement,
+        };
+        match self.key_b_readable() {
+            true => DataBlockAccess {
+                read: read.without_key_b(),
+                write: write.without_key_b(),
+                increment: increment.without_key_b(),
+                decrement: decrement.without_key_b(),
+            },
+            false => access,
+        }
+    }
+
+    /// What the conditions grant for the sector trailer
+    pub fn trailer_access(&self) -> TrailerAccess {
+        use Permission::*;
+
+        let (key_a_write, access_bits_read, access_bits_write, key_b_read, key_b_write) =
+            match self.conditions[3].bits() {
+                0b000 => (KeyA, KeyA, Never, KeyA, KeyA),
+                0b010 => (Never, KeyA, Never, KeyA, Never),
+                0b100 => (KeyB, KeyAOrB, Never, Never, KeyB),
+                0b110 => (Never, KeyAOrB, Never, Never, Never),
+                0b001 => (KeyA, KeyA, KeyA, KeyA, KeyA),
+                0b011 => (KeyB, KeyAOrB, KeyB, Never, KeyB),
+                0b101 => (Never, KeyAOrB, KeyB, Never, Never),
+                _ => (Never, KeyAOrB, Never, Never, Never),
+            };
+        TrailerAccess {
+            key_a_write,
+            access_bits_read,
+            access_bits_write,
+            key_b_read,
+            key_b_write,
+        }
+    }
+}
+
+/// Group of the access conditions a block belongs to, 3 for the sector trailer
+pub const fn group_of(block: u8) -> usize {
+    match block {
+        0..=127 => block as usize % 4,
+        _ => match (block as usize - 128) % 16 {
+            15 => 3,
+            offset => offset / 5,
+        },
+    }
+}
+
+/// Sector trailer: keys, access bits and the general purpose byte
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SectorTrailer {
+    pub key_a: [u8; 6],
+    pub access_bits: AccessBits,
+    /// General purpose byte, free to use (e.g. the MAD version in sector 0)
+    pub gpb: u8,
+    pub key_b: [u8; 6],
+}
+
+impl SectorTrailer {
+    /// Decode a sector trailer, `None` if the access bits are inconsistent
+    ///
+    /// Key A always reads as zeros, key B too if it isn't readable.
+    pub fn decode(block: &[u8; BLOCK_SIZE]) -> Option<Self> {
+        Some(Self {
+            key_a: block[0..6].try_into().unwrap(),
+            access_bits: AccessBits::decode([block[6], block[7], block[8]])?,
+            gpb: block[9],
+            key_b: block[10..16].try_into().unwrap(),
+        })
+    }
+
+    pub fn encode(&self) -> [u8; BLOCK_SIZE] {
+        let mut block = [0u8; BLOCK_SIZE];
+        block[0..6].copy_from_slice(&self.key_a);
+        block[6..9].copy_from_slice(&self.access_bits.encode());
+        block[9] = self.gpb;
+        block[10..16].copy_from_slice(&self.key_b);
+        block
+    }
+}
+
+/// Error of the Mifare Classic operations
+#[derive(Debug)]
+pub enum ClassicError<E> {
+    Reader(ReadError<E>),
+    /// The card rejected the key
+    Authentication(KeyType),
+    /// The block doesn't hold a valid value block
+    InvalidValueBlock,
+    /// The access bits of a sector trailer are inconsistent, writing them would lock the sector
+    InvalidAccessBits,
+}
+
+#[cfg(feature = "defmt")]
+impl<E> defmt::Format for ClassicError<E> {
+    fn format(&self, fmt: defmt::Formatter) {
+        match self {
+            Self::Reader(err) => defmt::write!(fmt, "{}", err),
+            Self::Authentication(key_type) => {
+                defmt::write!(fmt, "Authentication with key {} failed", key_type)
+            }
+            Self::InvalidValueBlock => defmt::write!(fmt, "Invalid value block"),
+            Self::InvalidAccessBits => defmt::write!(fmt, "Invalid access bits"),
+        }
+    }
+}
+
+impl<E> From<ReadError<E>> for ClassicError<E> {
+    fn from(value: ReadError<E>) -> Self {
+        Self::Reader(value)
+    }
+}
+
+impl<I, T> Reader<I, T>
+where
+    I: Interface,
+    T: DelayUs,
+{
+    /// Authenticate the sector of `block` with `key`, on the card with target number `tg`
+    ///
+    /// The last 4 bytes of `uid` are used for cards with a 7 byte UID. A failed authentication
+    /// leaves the card unauthenticated, it has to be activated again before the next attempt.
+    pub async fn mifare_authenticate(
+        &mut self,
+        tg: u8,
+        block: u8,
+        key: &Key,
+        uid: &CardUid,
+    ) -> Result<(), ClassicError<I::Error>> {
+        let uid = uid.as_bytes();
+        let uid = &uid[uid.len().saturating_sub(4)..];
+
+        let mut command = [0u8; 12];
+        command[0] = key.key_type.command() as u8;
+        command[1] = block;
+        command[2..8].copy_from_slice(&key.key);
+        command[8..8 + uid.len()].copy_from_slice(uid);
+
+        debug!("Authenticate block {} with key {}", block, key.key_type);
+        match self.in_data_exchange(tg, &command[..8 + uid.len()]).await {
+            Ok(_) => Ok(()),
+            Err(ReadError::Status(Status::MifareAuthentication)) => {
+                Err(ClassicError::Authentication(key.key_type))
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Read a block of an authenticated sector
+    ///
+    /// Key A always reads as zeros, key B too if the access bits don't allow reading it.
+    pub async fn mifare_read(
+        &mut self,
+        tg: u8,
+        block: u8,
+    ) -> Result<[u8; BLOCK_SIZE], ReadError<I::Error>> {
+        self.in_data_exchange(tg, &[MifareCommand::Read as u8, block])
+            .await?
+            .try_into()
+            .map_err(|_| ReadError::Reader(Error::Decoder))
+    }
+
+    /// Write a block of an authenticated sector
+    ///
+    /// Sector trailers with inconsistent access bits are refused, the sector would be unusable.
+    pub async fn mifare_write(
+        &mut self,
+        tg: u8,
+        block: u8,
+        data: &[u8; BLOCK_SIZE],
+    ) -> Result<(), ClassicError<I::Error>> {
+        if group_of(block) == 3 && SectorTrailer::decode(data).is_none() {
+            return Err(ClassicError::InvalidAccessBits);
+        }
+
+        let mut command = [0u8; 2 + BLOCK_SIZE];
+        command[0] = MifareCommand::Write as u8;
+        command[1] = block;
+        command[2..].copy_from_slice(data);
+        self.in_data_exchange(tg, &command).await?;
+        Ok(())
+    }
+
+    /// Read the sector trailer of an authenticated sector
+    pub async fn mifare_read_trailer(
+        &mut self,
+        tg: u8,
+        sector: u8,
+    ) -> Result<SectorTrailer, ClassicError<I::Error>> {
+        let data = self.mifare_read(tg, trailer_block(sector)).await?;
+        SectorTrailer::decode(&data).ok_or(ClassicError::InvalidAccessBits)
+    }
+
+    /// Write the sector trailer of an authenticated sector
+    ///
+    /// Check the access bits grant what is needed later on, the new keys and access bits apply
+    /// right away.
+    pub async fn mifare_write_trailer(
+        &mut self,
+        tg: u8,
+        sector: u8,
+        trailer: &SectorTrailer,
+    ) -> Result<(), ClassicError<I::Error>> {
+        self.mifare_write(tg, trailer_block(sector), &trailer.encode())
+            .await
+    }
+
+    /// Read and decode a value block
+    pub async fn mifare_read_value(
+        &mut self,
+        tg: u8,
+        block: u8,
+    ) -> Result<ValueBlock, ClassicError<I::Error>> {
+        let data = self.mifare_read(tg, block).await?;
+        ValueBlock::decode(&data).ok_or(ClassicError::InvalidValueBlock)
+    }
+
+    /// Format `block` as value block
+    pub async fn mifare_write_value(
+        &mut self,
+        tg: u8,
+        block: u8,
+        value: &ValueBlock,
+    ) -> Result<(), ClassicError<I::Error>> {
+        self.mifare_write(tg, block, &value.encode()).await
+    }
+
+    /// Add `delta` to the value block `block`, keeping the result in the transfer buffer
+    ///
+    /// The block itself is only changed by [`Self::mifare_transfer`].
+    pub async fn mifare_increment(
+        &mut self,
+        tg: u8,
+        block: u8,
+        delta: u32,
+    ) -> Result<(), ReadError<I::Error>> {
+        self.mifare_value_operation(tg, MifareCommand::Increment, block, delta)
+            .await
+    }
+
+    /// Subtract `delta` from the value block `block`, keeping the result in the transfer buffer
+    pub async fn mifare_decrement(
+        &mut self,
+        tg: u8,
+        block: u8,
+        delta: u32,
+    ) -> Result<(), ReadError<I::Error>> {
+        self.mifare_value_operation(tg, MifareCommand::Decrement, block, delta)
+            .await
+    }
+
+    /// Copy the value block `block` into the transfer buffer, to be transferred to another block
+    pub async fn mifare_restore(&mut self, tg: u8, block: u8) -> Result<(), ReadError<I::Error>> {
+        self.mifare_value_operation(tg, MifareCommand::Restore, block, 0)
+            .await
+    }
+
+    /// Write the transfer buffer to the value block `block`
+    pub async fn mifare_transfer(&mut self, tg: u8, block: u8) -> Result<(), ReadError<I::Error>> {
+        self.in_data_exchange(tg, &[MifareCommand::Transfer as u8, block])
+            .await?;
+        Ok(())
+    }
+
+    async fn mifare_value_operation(
+        &mut self,
+        tg: u8,
+        command: MifareCommand,
+        block: u8,
+        operand: u32,
+    ) -> Result<(), ReadError<I::Error>> {
+        let [a, b, c, d] = operand.to_le_bytes();
+        self.in_data_exchange(tg, &[command as u8, block, a, b, c, d])
+            .await?;
+        Ok(())
+    }
+}
@@ -1,34 +1,65 @@
-use defmt::{debug, trace, write, Format, Formatter};
-
+pub mod apdu;
+mod classify;
+pub mod desfire;
+pub mod frame;
 mod i2c;
+mod irq;
+pub mod mifare;
+pub mod ntag;
+pub mod ntag424;
 pub mod protocol;
 pub mod requests;
+#[cfg(feature = "simulator")]
+pub mod simulator;
 mod spi;
-
-use crate::driver::protocol::{Interface, Protocol};
-use crate::driver::requests::{CardType, Command, NTAGCommand, SAMMode};
+mod status;
+mod target;
+pub mod trace;
+mod uart;
+
+use crate::driver::protocol::{Interface, Protocol, MAX_FRAME_DATA, MAX_FRAME_LEN};
+use crate::driver::requests::{BaudRate, CardType, Command, NTAGCommand, SAMMode};
+pub use classify::{CardKind, DesFireVersion};
+use embedded_hal_async::delay::DelayUs;
 pub use i2c::I2c;
+pub use irq::{Irq, NoIrq};
+pub use protocol::{LinkStats, RetryPolicy};
 pub use spi::Spi;
-
+pub use status::Status;
+pub use target::{
+    Bytes, CardUid, FeliCaTarget, IsoTypeATarget, IsoTypeBTarget, JewelTarget, TargetInfo, Targets,
+    MAX_TARGETS,
+};
+pub use uart::{Uart, UartError};
+
+/// Longest data of a single `InDataExchange`, in both directions
+const MAX_EXCHANGE_DATA: usize = MAX_FRAME_DATA - 3;
+/// More information bit of `InDataExchange`, in the target number and the status byte
+const MORE_INFORMATION: u8 = 0x40;
+
+#[derive(Debug)]
 pub enum Error<E> {
     Protocol(protocol::Error<E>),
     InvalidResponse,
     Decoder,
 }
 
-impl<E> Format for Error<E> {
-    fn format(&self, fmt: Formatter) {
+#[cfg(feature = "defmt")]
+impl<E> defmt::Format for Error<E> {
+    fn format(&self, fmt: defmt::Formatter) {
         match self {
-            Self::Protocol(err) => write!(fmt, "Protocol error: {}", err),
-            Self::InvalidResponse => write!(fmt, "Invalid response"),
-            Self::Decoder => write!(fmt, "Decoder error"),
+            Self::Protocol(err) => defmt::write!(fmt, "Protocol error: {}", err),
+            Self::InvalidResponse => defmt::write!(fmt, "Invalid response"),
+            Self::Decoder => defmt::write!(fmt, "Decoder error"),
         }
     }
 }
 
+#[derive(Debug)]
 pub enum ReadError<E> {
     Reader(Error<E>),
-    ReadError,
+    /// The PN532 reported an error while communicating with the target
+    Status(Status),
 }
 
 impl<E> From<Error<E>> for ReadError<E> {
@@ -37,32 +68,58 @@ impl<E> From<Error<E>> for ReadError<E> {
     }
 }
 
-impl<E> Format for ReadError<E> {
-    fn format(&self, fmt: Formatter) {
+#[cfg(feature = "defmt")]
+impl<E> defmt::Format for ReadError<E> {
+    fn format(&self, fmt: defmt::Formatter) {
         match self {
-            Self::Reader(err) => write!(fmt, "Protocol error: {}", err),
-            Self::ReadError => write!(fmt, "Read error"),
+            Self::Reader(err) => defmt::write!(fmt, "Protocol error: {}", err),
+            Self::Status(status) => defmt::write!(fmt, "Status: {}", status),
         }
     }
 }
 
-pub struct Reader<I>
+pub struct Reader<I, T>
 where
     I: Interface,
+    T: DelayUs,
 {
-    protocol: Protocol<I, 200>,
+    protocol: Protocol<I, T, MAX_FRAME_LEN>,
+    /// Target number of the target known to be selected, `None` after anything which might have
+    /// deselected it or sent it back to idle
+    selected: Option<u8>,
 }
 
-impl<I> Reader<I>
+impl<I, T> Reader<I, T>
 where
     I: Interface,
+    T: DelayUs,
 {
-    pub fn new(interface: I) -> Self {
+    pub fn new(interface: I, delay: T) -> Self {
         Self {
-            protocol: Protocol::new(interface),
+            protocol: Protocol::new(interface, delay),
+            selected: None,
         }
     }
 
+    /// Set how to recover from transient errors, see [`RetryPolicy`] for the defaults
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.set_retry_policy(policy);
+        self
+    }
+
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.protocol.set_retry_policy(policy);
+    }
+
+    /// Counters of the link quality, since creation or the last reset
+    pub fn link_stats(&self) -> LinkStats {
+        *self.protocol.stats()
+    }
+
+    pub fn reset_link_stats(&mut self) {
+        self.protocol.reset_stats();
+    }
+
     pub async fn get_firmware_version(&mut self) -> Result<FirmwareVersion, Error<I::Error>> {
         self.request(Request::<0>::get_firmware_version().borrow())
             .await
@@ -77,31 +134,285 @@ where
             .await
     }
 
-    pub async fn read_ntag(&mut self, page: u8) -> Result<[u8; 16], ReadError<I::Error>> {
-        self.request(Request::<0>::ntag_read(page).borrow())
+    /// Change the baud rate of the HSU interface
+    ///
+    /// The PN532 switches to the new baud rate once the response has been acknowledged, the host
+    /// has to reconfigure its UART afterwards.
+    pub async fn set_serial_baud_rate(&mut self, rate: BaudRate) -> Result<(), Error<I::Error>> {
+        self.request::<()>(Request::<0>::set_serial_baud_rate(rate).borrow())
+            .await?;
+        self.protocol.send_ack().await.map_err(Error::Protocol)
+    }
+
+    /// Read 4 pages, starting at `page`, from the NTAG with target number `tg`
+    pub async fn read_ntag(&mut self, tg: u8, page: u8) -> Result<[u8; 16], ReadError<I::Error>> {
+        self.target_request(tg, Request::<0>::ntag_read(tg, page).borrow())
+            .await
+    }
+
+    /// Write a single page to the NTAG with target number `tg`
+    pub async fn write_ntag(
+        &mut self,
+        tg: u8,
+        page: u8,
+        data: [u8; 4],
+    ) -> Result<(), ReadError<I::Error>> {
+        self.target_request(tg, Request::<0>::ntag_write(tg, page, data).borrow())
             .await
-            .map_err(ReadError::Reader)
-            .and_then(|d| match d {
-                DataReadResult::Err => Err(ReadError::ReadError),
-                DataReadResult::Ok(data) => Ok(data),
+    }
+
+    /// Write a single page with `COMPATIBILITY_WRITE`, only the first 4 bytes of `data` are
+    /// written
+    ///
+    /// This is the Mifare Classic write command, for readers that don't support the NTAG `WRITE`.
+    pub async fn compatibility_write_ntag(
+        &mut self,
+        tg: u8,
+        page: u8,
+        data: [u8; 16],
+    ) -> Result<(), ReadError<I::Error>> {
+        let request = Request::<0>::ntag_compatibility_write(tg, page, data);
+        self.target_request(tg, request.borrow()).await
+    }
+
+    /// Send a request to target `tg`, answered by a status byte and `D`
+    ///
+    /// Like [`Self::exchange`], the target is selected once the request succeeded.
+    async fn target_request<D: Decode>(
+        &mut self,
+        tg: u8,
+        request: BorrowedRequest<'_>,
+    ) -> Result<D, ReadError<I::Error>> {
+        self.selected = None;
+        let response = self
+            .request::<Result<D, Status>>(request)
+            .await?
+            .map_err(ReadError::Status)?;
+        self.selected = Some(tg);
+        Ok(response)
+    }
+
+    /// Exchange data with target `tg`, returning its answer
+    ///
+    /// The PN532 handles the protocol of the target, like ISO/IEC 14443-4 framing and chaining.
+    pub async fn in_data_exchange(
+        &mut self,
+        tg: u8,
+        data: &[u8],
+    ) -> Result<&[u8], ReadError<I::Error>> {
+        let mut request = [0u8; MAX_FRAME_DATA];
+        if data.len() > MAX_EXCHANGE_DATA {
+            return Err(Error::Protocol(protocol::Error::TooMuchData).into());
+        }
+        request[0] = tg;
+        request[1..1 + data.len()].copy_from_slice(data);
+
+        self.exchange(
+            Command::InDataExchange,
+            &request[..1 + data.len()],
+            Some(tg),
+        )
+        .await
+    }
+
+    /// Exchange data with target `tg` like [`Self::in_data_exchange`], without the frame size limit
+    ///
+    /// Longer data is sent in parts, flagged by the MI bit in the target number. A longer answer
+    /// is flagged by the MI bit in the status and fetched in parts. Returns the length of the
+    /// answer written to `buf`.
+    pub async fn in_data_exchange_chained(
+        &mut self,
+        tg: u8,
+        data: &[u8],
+        buf: &mut [u8],
+    ) -> Result<usize, ReadError<I::Error>> {
+        let mut request = [0u8; MAX_FRAME_DATA];
+        let mut parts = data.chunks(MAX_EXCHANGE_DATA);
+        let mut part = parts.next().unwrap_or_default();
+        for next in parts {
+            request[0] = tg | MORE_INFORMATION;
+            request[1..1 + part.len()].copy_from_slice(part);
+            self.exchange(
+                Command::InDataExchange,
+                &request[..1 + part.len()],
+                Some(tg),
+            )
+            .await?;
+            part = next;
+        }
+
+        request[0] = tg;
+        request[1..1 + part.len()].copy_from_slice(part);
+        self.selected = None;
+        let mut response = self
+            .raw_request(BorrowedRequest {
+                command: Command::InDataExchange,
+                data: &request[..1 + part.len()],
             })
+            .await?;
+
+        let mut len = 0;
+        loop {
+            let (&status, answer) = response.split_first().ok_or(Error::Decoder)?;
+            Status::check(status).map_err(ReadError::Status)?;
+            let Some(target) = buf.get_mut(len..len + answer.len()) else {
+                return Err(Error::Protocol(protocol::Error::BufferUnderflow).into());
+            };
+            target.copy_from_slice(answer);
+            len += answer.len();
+
+            if status & MORE_INFORMATION == 0 {
+                self.selected = Some(tg);
+                return Ok(len);
+            }
+            debug!("Fetching more data, {} bytes so far", len);
+            response = self
+                .raw_request(BorrowedRequest {
+                    command: Command::InDataExchange,
+                    data: &[tg],
+                })
+                .await?;
+        }
+    }
+
+    /// Exchange raw data with the selected target, returning its answer
+    ///
+    /// Other than [`Self::in_data_exchange`], the PN532 doesn't handle any protocol, except for
+    /// the CRC and parity.
+    pub async fn in_communicate_thru(&mut self, data: &[u8]) -> Result<&[u8], ReadError<I::Error>> {
+        let selected = self.selected;
+        self.exchange(Command::InCommunicateThru, data, selected)
+            .await
+    }
+
+    /// Send a command answered by a status byte and data, returning the data
+    ///
+    /// `selected` is the target selected once the command succeeded. A failed command might have
+    /// sent the target back to idle, so none is known to be selected then.
+    async fn exchange(
+        &mut self,
+        command: Command,
+        data: &[u8],
+        selected: Option<u8>,
+    ) -> Result<&[u8], ReadError<I::Error>> {
+        self.selected = None;
+        let timeout_ms = command.default_timeout_ms();
+        let request = BorrowedRequest { command, data };
+        let data = Self::protocol_request(&mut self.protocol, request, timeout_ms).await?;
+
+        let (&status, data) = data.split_first().ok_or(Error::Decoder)?;
+        Status::check(status).map_err(ReadError::Status)?;
+        self.selected = selected;
+        Ok(data)
     }
 
+    /// Look for a target of `card_type` in the field, and activate it
     pub async fn read_passive_target(
         &mut self,
         card_type: CardType,
-    ) -> Result<Option<CardUid>, Error<I::Error>> {
-        self.request(Request::<0>::in_list_passive_target(card_type).borrow())
-            .await
+    ) -> Result<Option<TargetInfo>, Error<I::Error>> {
+        let targets = self.list_targets(1, card_type).await?;
+        Ok(targets.first().copied())
+    }
+
+    /// Look for up to [`MAX_TARGETS`] targets of `card_type` in the field, and activate them
+    ///
+    /// More than one target means several cards are held to the reader at the same time.
+    pub async fn list_passive_targets(
+        &mut self,
+        card_type: CardType,
+    ) -> Result<Targets, Error<I::Error>> {
+        self.list_targets(MAX_TARGETS as u8, card_type).await
+    }
+
+    async fn list_targets(
+        &mut self,
+        max_tg: u8,
+        card_type: CardType,
+    ) -> Result<Targets, Error<I::Error>> {
+        let request = Request::<0>::in_list_passive_target(max_tg, card_type);
+        self.selected = None;
+        let data = self.raw_request(request.borrow()).await?;
+
+        Targets::parse(card_type, data).ok_or(Error::Decoder)
+    }
+
+    /// Select target `tg`, the target of any following `InCommunicateThru`
+    ///
+    /// The currently selected target gets deselected. Selecting a target reactivates it, which
+    /// wakes it up from idle, but also ends any authentication, like the `PWD_AUTH` of an NTAG.
+    pub async fn in_select(&mut self, tg: u8) -> Result<(), ReadError<I::Error>> {
+        self.exchange(Command::InSelect, &[tg], Some(tg)).await?;
+        Ok(())
+    }
+
+    /// Select target `tg` like [`Self::in_select`], unless it is still selected
+    pub(crate) async fn select(&mut self, tg: u8) -> Result<(), ReadError<I::Error>> {
+        match self.selected == Some(tg) {
+            true => Ok(()),
+            false => self.in_select(tg).await,
+        }
+    }
+
+    /// Deselect target `tg`, keeping its information, or all targets for `tg` 0
+    pub async fn in_deselect(&mut self, tg: u8) -> Result<(), ReadError<I::Error>> {
+        self.exchange(Command::InDeselect, &[tg], None).await?;
+        Ok(())
+    }
+
+    /// Release target `tg`, dropping its information, or all targets for `tg` 0
+    pub async fn in_release(&mut self, tg: u8) -> Result<(), ReadError<I::Error>> {
+        self.exchange(Command::InRelease, &[tg], None).await?;
+        Ok(())
     }
 
+    /// Send a request, using the default timeout of the command
     pub async fn request<D>(&mut self, request: BorrowedRequest<'_>) -> Result<D, Error<I::Error>>
     where
         D: Decode,
     {
-        let (response, data) = self
-            .protocol
-            .request(request.command as u8, &request.data, D::LEN as u8)
+        let timeout_ms = request.command.default_timeout_ms();
+        self.request_with_timeout(request, timeout_ms).await
+    }
+
+    pub async fn request_with_timeout<D>(
+        &mut self,
+        request: BorrowedRequest<'_>,
+        timeout_ms: u32,
+    ) -> Result<D, Error<I::Error>>
+    where
+        D: Decode,
+    {
+        let data = self.raw_request_with_timeout(request, timeout_ms).await?;
+
+        D::decode(data).map_err(|_| Error::Decoder)
+    }
+
+    /// Send a request, using the default timeout of the command, returning the response data
+    async fn raw_request(
+        &mut self,
+        request: BorrowedRequest<'_>,
+    ) -> Result<&[u8], Error<I::Error>> {
+        let timeout_ms = request.command.default_timeout_ms();
+        self.raw_request_with_timeout(request, timeout_ms).await
+    }
+
+    async fn raw_request_with_timeout(
+        &mut self,
+        request: BorrowedRequest<'_>,
+        timeout_ms: u32,
+    ) -> Result<&[u8], Error<I::Error>> {
+        Self::protocol_request(&mut self.protocol, request, timeout_ms).await
+    }
+
+    /// Send a request through `protocol`, borrowing nothing else of the reader
+    async fn protocol_request<'p>(
+        protocol: &'p mut Protocol<I, T, MAX_FRAME_LEN>,
+        request: BorrowedRequest<'_>,
+        timeout_ms: u32,
+    ) -> Result<&'p [u8], Error<I::Error>> {
+        let (response, data) = protocol
+            .request(request.command as u8, request.data, timeout_ms)
             .await
             .map_err(Error::Protocol)?;
 
@@ -110,11 +421,12 @@ where
             return Err(Error::InvalidResponse);
         }
 
-        Ok(D::decode(data).map_err(|_| Error::Decoder)?)
+        Ok(data)
     }
 }
 
-#[derive(Clone, Debug, Format)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct FirmwareVersion {
     pub ic: u8,
     pub version: u8,
@@ -124,33 +436,6 @@ pub struct FirmwareVersion {
     pub supports_iso14443_b: bool,
 }
 
-pub enum DataReadResult<const N: usize> {
-    Ok([u8; N]),
-    Err,
-}
-
-impl<const N: usize> Decode for DataReadResult<N> {
-    type Error = ();
-    const LEN: usize = N + 1;
-
-    fn decode(data: &[u8]) -> Result<Self, Self::Error> {
-        trace!("DRR: len {} - expected: {}", data.len(), Self::LEN);
-        trace!("DRR: data {:#X}", data);
-
-        if data.len() != Self::LEN {
-            return Err(());
-        }
-
-        if data[0] != 0x00 {
-            return Ok(Self::Err);
-        }
-
-        let mut result = [0u8; N];
-        result.copy_from_slice(&data[1..]);
-        Ok(Self::Ok(result))
-    }
-}
-
 pub trait Decode: Sized {
     type Error;
     const LEN: usize;
@@ -182,6 +467,20 @@ impl<const N: usize> Decode for [u8; N] {
     }
 }
 
+/// Response starting with a status byte, followed by `D`
+impl<D: Decode> Decode for Result<D, Status> {
+    type Error = ();
+    const LEN: usize = D::LEN + 1;
+
+    fn decode(data: &[u8]) -> Result<Self, Self::Error> {
+        let (&status, data) = data.split_first().ok_or(())?;
+        match Status::check(status) {
+            Ok(()) => D::decode(data).map(Ok).map_err(|_| ()),
+            Err(status) => Ok(Err(status)),
+        }
+    }
+}
+
 impl Decode for FirmwareVersion {
     type Error = ();
 
@@ -203,36 +502,6 @@ impl Decode for FirmwareVersion {
     }
 }
 
-impl Decode for Option<CardUid> {
-    type Error = ();
-    const LEN: usize = 19;
-
-    fn decode(data: &[u8]) -> Result<Self, Self::Error> {
-        if data[0] != 1 {
-            // can only handle a single card
-            return Ok(None);
-        }
-        let len = data[5] as usize;
-        if len > 7 {
-            // UID too long
-            return Ok(None);
-        }
-
-        let mut uid = [0u8; 7];
-        uid[..len].copy_from_slice(&data[6..6 + len]);
-
-        Ok(Some(CardUid(uid)))
-    }
-}
-
-pub struct CardUid(pub [u8; 7]);
-
-impl Format for CardUid {
-    fn format(&self, fmt: Formatter) {
-        write!(fmt, "{:X}", self.0)
-    }
-}
-
 pub struct Request<const N: usize> {
     pub command: Command,
     pub data: [u8; N],
@@ -257,16 +526,45 @@ impl<const N: usize> Request<N> {
         Request::<3>::new(Command::SAMConfiguration, [mode, timeout, use_irq as u8])
     }
 
-    pub const fn ntag_read(page: u8) -> Request<3> {
+    pub const fn set_serial_baud_rate(rate: BaudRate) -> Request<1> {
+        Request::new(Command::SetSerialBaudRate, [rate as u8])
+    }
+
+    pub const fn ntag_read(tg: u8, page: u8) -> Request<3> {
+        Request::new(Command::InDataExchange, [tg, NTAGCommand::Read as u8, page])
+    }
+
+    pub const fn ntag_write(tg: u8, page: u8, data: [u8; 4]) -> Request<7> {
+        let [a, b, c, d] = data;
         Request::new(
             Command::InDataExchange,
-            [0x01 /* target (tg) */, NTAGCommand::Read as u8, page],
+            [tg, NTAGCommand::Write as u8, page, a, b, c, d],
         )
     }
 
-    pub const fn in_list_passive_target(card_type: CardType) -> Request<2> {
-        const MAX_CARDS: u8 = 0x01;
-        Request::new(Command::InListPassiveTarget, [MAX_CARDS, card_type as u8])
+    pub const fn ntag_pwd_auth(pwd: [u8; 4]) -> Request<5> {
+        let [a, b, c, d] = pwd;
+        Request::new(
+            Command::InCommunicateThru,
+            [NTAGCommand::PwdAuth as u8, a, b, c, d],
+        )
+    }
+
+    pub const fn ntag_compatibility_write(tg: u8, page: u8, data: [u8; 16]) -> Request<19> {
+        let mut request = [0u8; 19];
+        request[0] = tg;
+        request[1] = NTAGCommand::CompWrite as u8;
+        request[2] = page;
+        let mut i = 0;
+        while i < data.len() {
+            request[3 + i] = data[i];
+            i += 1;
+        }
+        Request::new(Command::InDataExchange, request)
+    }
+
+    pub const fn in_list_passive_target(max_tg: u8, card_type: CardType) -> Request<2> {
+        Request::new(Command::InListPassiveTarget, [max_tg, card_type as u8])
     }
 
     pub(crate) fn borrow(&self) -> BorrowedRequest<'_> {
@@ -0,0 +1,377 @@
+//! NTAG21x password protection and configuration pages
+//!
+//! The last four pages of an NTAG21x configure the tag: CFG0 (`MIRROR`, `MIRROR_PAGE`, `AUTH0`),
+//! CFG1 (`ACCESS`), `PWD` and `PACK`. All pages from `AUTH0` on are protected by the 32 bit
+//! password, for writing or also for reading.
+
+use crate::driver::protocol::{self, Interface, MAX_FRAME_DATA};
+use crate::driver::requests::NTAGCommand;
+use crate::driver::{CardKind, Error, ReadError, Reader, Request};
+use crate::originality::SIGNATURE_LEN;
+use crate::type2::{MemoryImage, DATA_PAGE, PAGE_SIZE};
+use embedded_hal_async::delay::DelayUs;
+
+/// `AUTH0` disabling the password protection
+pub const AUTH0_DISABLED: u8 = 0xFF;
+
+/// Address of the NFC counter for `READ_CNT`
+const NFC_COUNTER: u8 = 0x02;
+
+/// Most pages read by a single `FAST_READ`, limited by the frame size
+///
+/// The response frame also holds TFI, command code and status byte.
+const FAST_READ_PAGES: usize = (MAX_FRAME_DATA - 3) / PAGE_SIZE;
+
+/// First configuration page (CFG0) of an NTAG21x, `None` for other cards
+pub const fn config_page(kind: CardKind) -> Option<u8> {
+    match kind {
+        CardKind::Ntag213 => Some(0x29),
+        CardKind::Ntag215 => Some(0x83),
+        CardKind::Ntag216 => Some(0xE3),
+        _ => None,
+    }
+}
+
+/// Password and the acknowledge the tag answers a successful `PWD_AUTH` with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Password {
+    pub pwd: [u8; 4],
+    pub pack: [u8; 2],
+}
+
+/// What the password protects
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Protection {
+    /// Writing needs the password, reading is open
+    Write,
+    /// Reading and writing need the password
+    ReadWrite,
+}
+
+/// `ACCESS` byte of CFG1
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Access {
+    /// `PROT`: reading is protected too, not only writing
+    pub prot: bool,
+    /// `CFGLCK`: the configuration pages are locked for good
+    pub cfglck: bool,
+    /// `NFC_CNT_EN`: count the reads of the NFC counter
+    pub nfc_cnt_en: bool,
+    /// `NFC_CNT_PWD_PROT`: `READ_CNT` needs the password
+    pub nfc_cnt_pwd_prot: bool,
+    /// `AUTHLIM`: failed password attempts allowed, `0` for no limit
+    pub authlim: u8,
+}
+
+impl Access {
+    const PROT: u8 = 0x80;
+    const CFGLCK: u8 = 0x40;
+    const NFC_CNT_EN: u8 = 0x10;
+    const NFC_CNT_PWD_PROT: u8 = 0x08;
+    const AUTHLIM: u8 = 0x07;
+
+    pub const fn from_byte(access: u8) -> Self {
+        Self {
+            prot: access & Self::PROT != 0,
+            cfglck: access & Self::CFGLCK != 0,
+            nfc_cnt_en: access & Self::NFC_CNT_EN != 0,
+            nfc_cnt_pwd_prot: access & Self::NFC_CNT_PWD_PROT != 0,
+            authlim: access & Self::AUTHLIM,
+        }
+    }
+
+    pub const fn to_byte(self) -> u8 {
+        let mut access = self.authlim & Self::AUTHLIM;
+        if self.prot {
+            access |= Self::PROT;
+        }
+        if self.cfglck {
+            access |= Self::CFGLCK;
+        }
+        if self.nfc_cnt_en {
+            access |= Self::NFC_CNT_EN;
+        }
+        if self.nfc_cnt_pwd_prot {
+            access |= Self::NFC_CNT_PWD_PROT;
+        }
+        access
+    }
+}
+
+/// Configuration pages CFG0 and CFG1, RFU bytes are written as zero
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Config {
+    /// `MIRROR` byte: UID/counter mirror configuration and `STRG_MOD_EN`
+    pub mirror: u8,
+    pub mirror_page: u8,
+    /// First page protected by the password
+    pub auth0: u8,
+    pub access: Access,
+}
+
+impl Config {
+    pub const fn from_pages(cfg0: [u8; 4], cfg1: [u8; 4]) -> Self {
+        Self {
+            mirror: cfg0[0],
+            mirror_page: cfg0[2],
+            auth0: cfg0[3],
+            access: Access::from_byte(cfg1[0]),
+        }
+    }
+
+    pub const fn to_pages(&self) -> ([u8; 4], [u8; 4]) {
+        (
+            [self.mirror, 0x00, self.mirror_page, self.auth0],
+            [self.access.to_byte(), 0x00, 0x00, 0x00],
+        )
+    }
+
+    /// Whether any page is protected by the password
+    pub const fn is_protected(&self) -> bool {
+        self.auth0 != AUTH0_DISABLED
+    }
+}
+
+/// Error of [`Reader::authenticate_ntag`]
+#[derive(Debug)]
+pub enum AuthError<E> {
+    Reader(ReadError<E>),
+    /// The tag took the password, but answered with an unexpected PACK
+    PackMismatch([u8; 2]),
+}
+
+#[cfg(feature = "defmt")]
+impl<E> defmt::Format for AuthError<E> {
+    fn format(&self, fmt: defmt::Formatter) {
+        match self {
+            Self::Reader(err) => defmt::write!(fmt, "{}", err),
+            Self::PackMismatch(pack) => defmt::write!(fmt, "PACK mismatch: {:X}", pack),
+        }
+    }
+}
+
+impl<E> From<ReadError<E>> for AuthError<E> {
+    fn from(value: ReadError<E>) -> Self {
+        Self::Reader(value)
+    }
+}
+
+impl<I, T> Reader<I, T>
+where
+    I: Interface,
+    T: DelayUs,
+{
+    /// Send `PWD_AUTH` to the NTAG with target number `tg`, returning its PACK
+    ///
+    /// A wrong password is rejected by the tag, which reports as
+    /// [`Status::Timeout`](crate::driver::Status::Timeout) and counts towards `AUTHLIM`.
+    pub async fn ntag_pwd_auth(
+        &mut self,
+        tg: u8,
+        pwd: [u8; 4],
+    ) -> Result<[u8; 2], ReadError<I::Error>> {
+        self.ntag_command(tg, &Request::<0>::ntag_pwd_auth(pwd).data)
+            .await
+    }
+
+    /// Read the originality signature of the NTAG with target number `tg`
+    ///
+    /// See [`originality`](crate::originality) to verify it.
+    pub async fn read_signature(
+        &mut self,
+        tg: u8,
+    ) -> Result<[u8; SIGNATURE_LEN], ReadError<I::Error>> {
+        self.ntag_command(tg, &[NTAGCommand::ReadSig as u8, 0x00])
+            .await
+    }
+
+    /// Read the 24 bit NFC counter of the NTAG with target number `tg`
+    ///
+    /// The tag counts the first read of its memory after each activation, once enabled with
+    /// [`Self::enable_ntag_counter`].
+    pub async fn read_counter(&mut self, tg: u8) -> Result<u32, ReadError<I::Error>> {
+        let [low, mid, high] = self
+            .ntag_command(tg, &[NTAGCommand::ReadCnt as u8, NFC_COUNTER])
+            .await?;
+        Ok(u32::from_le_bytes([low, mid, high, 0]))
+    }
+
+    /// Read the pages `start` to `end` (both included) with `FAST_READ` into `buf`
+    ///
+    /// The range is split into as many commands as needed to fit into the frames. `buf` has to
+    /// hold 4 bytes per page.
+    pub async fn fast_read(
+        &mut self,
+        tg: u8,
+        start: u8,
+        end: u8,
+        buf: &mut [u8],
+    ) -> Result<(), ReadError<I::Error>> {
+        let pages = (end as usize + 1).saturating_sub(start as usize);
+        if buf.len() < pages * PAGE_SIZE {
+            return Err(Error::Protocol(protocol::Error::BufferUnderflow).into());
+        }
+
+        self.select(tg).await?;
+        let mut page = start as usize;
+        for chunk in buf[..pages * PAGE_SIZE].chunks_mut(FAST_READ_PAGES * PAGE_SIZE) {
+            let last = page + chunk.len() / PAGE_SIZE - 1;
+            debug!("Fast read: {} to {}", page, last);
+            let answer = self
+                .in_communicate_thru(&[NTAGCommand::FastRead as u8, page as u8, last as u8])
+                .await?;
+            if answer.len() != chunk.len() {
+                return Err(ReadError::Reader(Error::Decoder));
+            }
+            chunk.copy_from_slice(answer);
+            page = last + 1;
+        }
+        Ok(())
+    }
+
+    /// Read the whole user memory of the Type 2 Tag `kind` with target number `tg`
+    ///
+    /// `None` if `kind` isn't a Type 2 Tag, or its user memory is larger than
+    /// [`MemoryImage::MAX_LEN`].
+    pub async fn dump_type2(
+        &mut self,
+        tg: u8,
+        kind: CardKind,
+    ) -> Result<Option<MemoryImage>, ReadError<I::Error>> {
+        let Some(size) = kind.memory_size().filter(|_| kind.is_type2()) else {
+            return Ok(None);
+        };
+        let mut image = [0u8; MemoryImage::MAX_LEN];
+        let Some(buf) = image.get_mut(..size) else {
+            return Ok(None);
+        };
+
+        let last = DATA_PAGE as usize + size / PAGE_SIZE - 1;
+        if kind.supports_fast_read() {
+            self.fast_read(tg, DATA_PAGE, last as u8, buf).await?;
+        } else {
+            for (i, chunk) in buf.chunks_mut(16).enumerate() {
+                let read = self.read_ntag(tg, DATA_PAGE + (i * 4) as u8).await?;
+                chunk.copy_from_slice(&read[..chunk.len()]);
+            }
+        }
+        Ok(MemoryImage::from_bytes(buf))
+    }
+
+    /// Send an NTAG specific command to target `tg`, expecting an answer of `N` bytes
+    async fn ntag_command<const N: usize>(
+        &mut self,
+        tg: u8,
+        command: &[u8],
+    ) -> Result<[u8; N], ReadError<I::Error>> {
+        // like `GET_VERSION` in `classify`, passed on raw by `InCommunicateThru`, which talks to
+        // the selected target. Selecting it again would end a `PWD_AUTH`.
+        self.select(tg).await?;
+        let answer = self.in_communicate_thru(command).await?;
+        answer
+            .try_into()
+            .map_err(|_| ReadError::Reader(Error::Decoder))
+    }
+
+    /// Authenticate with `password`, checking the PACK to tell a genuine tag
+    pub async fn authenticate_ntag(
+        &mut self,
+        tg: u8,
+        password: &Password,
+    ) -> Result<(), AuthError<I::Error>> {
+        let pack = self.ntag_pwd_auth(tg, password.pwd).await?;
+        match pack == password.pack {
+            true => Ok(()),
+            false => Err(AuthError::PackMismatch(pack)),
+        }
+    }
+
+    /// Read the configuration pages of the NTAG `kind` with target number `tg`
+    ///
+    /// `None` if `kind` isn't an NTAG21x.
+    pub async fn read_ntag_config(
+        &mut self,
+        tg: u8,
+        kind: CardKind,
+    ) -> Result<Option<Config>, ReadError<I::Error>> {
+        let Some(page) = config_page(kind) else {
+            return Ok(None);
+        };
+        let read = self.read_ntag(tg, page).await?;
+        Ok(Some(Config::from_pages(
+            [read[0], read[1], read[2], read[3]],
+            [read[4], read[5], read[6], read[7]],
+        )))
+    }
+
+    /// Write the configuration pages of the NTAG `kind` with target number `tg`
+    ///
+    /// CFG1 is written first, so a lowered `AUTH0` protects a complete configuration. Once the
+    /// `AUTH0` covers the configuration pages, the tag needs to be authenticated for changes.
+    /// Returns `false` if `kind` isn't an NTAG21x.
+    pub async fn write_ntag_config(
+        &mut self,
+        tg: u8,
+        kind: CardKind,
+        config: &Config,
+    ) -> Result<bool, ReadError<I::Error>> {
+        let Some(page) = config_page(kind) else {
+            return Ok(false);
+        };
+        let (cfg0, cfg1) = config.to_pages();
+        self.write_ntag(tg, page + 1, cfg1).await?;
+        self.write_ntag(tg, page, cfg0).await?;
+        Ok(true)
+    }
+
+    /// Let the NTAG `kind` with target number `tg` count its reads, see [`Self::read_counter`]
+    ///
+    /// With `password_protected`, reading the counter needs the password. Returns `false` if
+    /// `kind` isn't an NTAG21x.
+    pub async fn enable_ntag_counter(
+        &mut self,
+        tg: u8,
+        kind: CardKind,
+        password_protected: bool,
+    ) -> Result<bool, ReadError<I::Error>> {
+        let Some(mut config) = self.read_ntag_config(tg, kind).await? else {
+            return Ok(false);
+        };
+        config.access.nfc_cnt_en = true;
+        config.access.nfc_cnt_pwd_prot = password_protected;
+        self.write_ntag_config(tg, kind, &config).await
+    }
+
+    /// Protect the pages from `auth0` on with `password`
+    ///
+    /// Password and PACK are written before the protection is enabled. Returns `false` if
+    /// `kind` isn't an NTAG21x.
+    pub async fn protect_ntag(
+        &mut self,
+        tg: u8,
+        kind: CardKind,
+        password: &Password,
+        auth0: u8,
+        protection: Protection,
+    ) -> Result<bool, ReadError<I::Error>> {
+        let Some(page) = config_page(kind) else {
+            return Ok(false);
+        };
+        let Some(mut config) = self.read_ntag_config(tg, kind).await? else {
+            return Ok(false);
+        };
+
+        self.write_ntag(tg, page + 2, password.pwd).await?;
+        let [pack0, pack1] = password.pack;
+        self.write_ntag(tg, page + 3, [pack0, pack1, 0x00, 0x00])
+            .await?;
+
+        config.auth0 = auth0;
+        config.access.prot = protection == Protection::ReadWrite;
+        self.write_ntag_config(tg, kind, &config).await
+    }
+}
@@ -0,0 +1,341 @@
+//! NTAG 424 DNA native commands and EV2 secure messaging
+//!
+//! The NTAG 424 DNA answers a subset of the DESFire native commands, wrapped the same way, see
+//! [`desfire`](super::desfire). Its files live in the NDEF Tag Application, which has to be
+//! selected first: the capability container, the NDEF file and a proprietary file.
+//!
+//! `AuthenticateEV2First` with one of the five AES keys establishes a session key for encryption
+//! and one for MACs. Both the MACs and the IVs of the session cover the transaction identifier
+//! chosen by the card and a counter of the commands, so nothing can be replayed or reordered.
+//! MACs are truncated to 8 bytes, see [`crypto::truncate_mac`].
+//!
+//! The random number of the reader has to come from a proper source of randomness, like the RNG
+//! of the MCU.
+
+use crate::crypto::{self, Block, Cipher, MAX_BLOCK_SIZE};
+use crate::driver::apdu::{Command, StatusWord};
+use crate::driver::desfire::{self, CommMode, DesFireError, Status};
+use crate::driver::protocol::Interface;
+use crate::driver::requests::DesFireCommand;
+use crate::driver::Reader;
+use crate::type4::NDEF_AID;
+use embedded_hal_async::delay::DelayUs;
+
+/// File number of the capability container
+pub const CC_FILE: u8 = 0x01;
+/// File number of the NDEF file
+pub const NDEF_FILE: u8 = 0x02;
+pub const PROPRIETARY_FILE: u8 = 0x03;
+
+/// Number of application keys
+pub const KEY_COUNT: u8 = 5;
+
+/// Bytes of the truncated MACs
+const MAC_LEN: usize = 8;
+/// Longest header of a command, the one of `ReadData`
+const MAX_HEADER_LEN: usize = 7;
+
+/// Labels of the session vectors, for the encryption and the MAC key
+const SV_ENC: [u8; 2] = [0xA5, 0x5A];
+const SV_MAC: [u8; 2] = [0x5A, 0xA5];
+/// Label of the IVs of answers
+const IV_ANSWER: [u8; 2] = [0x5A, 0xA5];
+
+const INS_SELECT: u8 = 0xA4;
+
+struct Session {
+    enc: Cipher,
+    mac: Cipher,
+    /// Transaction identifier
+    ti: [u8; 4],
+    /// Commands sent in the session
+    counter: u16,
+    key_no: u8,
+}
+
+/// An NTAG 424 DNA, and the session with it
+///
+/// Any error reported by the card ends the session.
+pub struct Ntag424<'r, I, T>
+where
+    I: Interface,
+    T: DelayUs,
+{
+    reader: &'r mut Reader<I, T>,
+    tg: u8,
+    session: Option<Session>,
+}
+
+impl<I, T> Reader<I, T>
+where
+    I: Interface,
+    T: DelayUs,
+{
+    /// Talk to the NTAG 424 DNA with target number `tg`
+    pub fn ntag424(&mut self, tg: u8) -> Ntag424<'_, I, T> {
+        Ntag424 {
+            reader: self,
+            tg,
+            session: None,
+        }
+    }
+}
+
+impl<'r, I, T> Ntag424<'r, I, T>
+where
+    I: Interface,
+    T: DelayUs,
+{
+    /// The key number of the current session
+    pub fn authenticated_key(&self) -> Option<u8> {
+        self.session.as_ref().map(|session| session.key_no)
+    }
+
+    /// Select the NDEF Tag Application, ending the session
+    pub async fn select_application(&mut self) -> Result<(), DesFireError<I::Error>> {
+        self.session = None;
+        let select = Command::new(0x00, INS_SELECT, 0x04, 0x0C).with_data(&NDEF_AID);
+        let mut buf = [0u8; 2];
+        match self.reader.transmit(self.tg, &select, &mut buf).await?.sw {
+            StatusWord::SUCCESS => Ok(()),
+            sw => Err(DesFireError::StatusWord(sw)),
+        }
+    }
+
+    /// Authenticate with `key_no`, starting a session
+    ///
+    /// `rnd_a` is the random number of the reader.
+    pub async fn authenticate_ev2_first(
+        &mut self,
+        key_no: u8,
+        key: &[u8; 16],
+        rnd_a: &[u8; 16],
+    ) -> Result<(), DesFireError<I::Error>> {
+        self.session = None;
+        let cipher = Cipher::aes(key);
+
+        // no capabilities of the reader
+        let mut buf = [0u8; 32 + 2];
+        let command = DesFireCommand::AuthenticateEv2First as u8;
+        let (len, status) = self.transmit(command, &[key_no, 0x00], &mut buf).await?;
+        match status {
+            Status::ADDITIONAL_FRAME => {}
+            Status::OK => return Err(DesFireError::InvalidResponse),
+            code => return Err(desfire::authentication_error(self.error(code))),
+        }
+        let mut rnd_b: [u8; 16] = buf[..len]
+            .try_into()
+            .map_err(|_| DesFireError::InvalidResponse)?;
+        cipher.decrypt_block(&mut rnd_b);
+
+        // RndA and RndB rotated left by a byte, unlike EV1 with a zero IV
+        let mut token = [0u8; 32];
+        token[..16].copy_from_slice(rnd_a);
+        token[16..].copy_from_slice(&rnd_b);
+        token[16..].rotate_left(1);
+        cipher.encrypt_cbc(&mut [0u8; MAX_BLOCK_SIZE], &mut token);
+
+        let command = DesFireCommand::AdditionalFrame as u8;
+        let (len, status) = self.transmit(command, &token, &mut buf).await?;
+        if status != Status::OK {
+            return Err(desfire::authentication_error(self.error(status)));
+        }
+        // TI, RndA rotated left, capabilities of the card and echoed ones of the reader
+        let answer: &mut [u8; 32] = (&mut buf[..len])
+            .try_into()
+            .map_err(|_| DesFireError::InvalidResponse)?;
+        cipher.decrypt_cbc(&mut [0u8; MAX_BLOCK_SIZE], answer);
+        let rnd_a_rotated = &mut answer[4..20];
+        rnd_a_rotated.rotate_right(1);
+        if rnd_a_rotated != rnd_a {
+            return Err(DesFireError::Authentication);
+        }
+
+        debug!("Authenticated with key {}", key_no);
+        let (enc, mac) = session_keys(key, rnd_a, &rnd_b);
+        self.session = Some(Session {
+            enc: Cipher::aes(&enc),
+            mac: Cipher::aes(&mac),
+            ti: answer[..4].try_into().unwrap(),
+            counter: 0,
+            key_no,
+        });
+        Ok(())
+    }
+
+    /// Read `len` bytes of a file, starting at `offset`
+    ///
+    /// A `len` of zero reads up to the end of the file. `comm` has to match the communication
+    /// settings of the file, a file with free read access is read in plain. `buf` needs room for
+    /// a block, the MAC and the status word more than the data.
+    pub async fn read_data<'b>(
+        &mut self,
+        file: u8,
+        offset: u32,
+        len: u32,
+        comm: CommMode,
+        buf: &'b mut [u8],
+    ) -> Result<&'b [u8], DesFireError<I::Error>> {
+        let offset = offset.to_le_bytes();
+        let length = len.to_le_bytes();
+        let header = [
+            file, offset[0], offset[1], offset[2], length[0], length[1], length[2],
+        ];
+        let data = self
+            .command(DesFireCommand::ReadDataEv2, &header, comm, buf)
+            .await?;
+        match len {
+            0 => Ok(data),
+            len if data.len() == len as usize => Ok(data),
+            _ => Err(DesFireError::InvalidResponse),
+        }
+    }
+
+    /// Read the UID in a session, the card only sends it encrypted
+    ///
+    /// With random IDs enabled, this is the only way to learn the UID.
+    pub async fn get_card_uid(&mut self) -> Result<[u8; 7], DesFireError<I::Error>> {
+        let mut buf = [0u8; 16 + MAC_LEN + 2];
+        let uid = self
+            .command(DesFireCommand::GetCardUid, &[], CommMode::Full, &mut buf)
+            .await?;
+        uid.try_into().map_err(|_| DesFireError::InvalidResponse)
+    }
+
+    /// Send a command with the secure messaging of the session, returning the plain answer
+    ///
+    /// The header is never encrypted, but MACed in [`CommMode::Mac`] and [`CommMode::Full`].
+    async fn command<'b>(
+        &mut self,
+        command: DesFireCommand,
+        header: &[u8],
+        comm: CommMode,
+        buf: &'b mut [u8],
+    ) -> Result<&'b [u8], DesFireError<I::Error>> {
+        let mut data = [0u8; MAX_HEADER_LEN + MAC_LEN];
+        data[..header.len()].copy_from_slice(header);
+        let mut len = header.len();
+        if let Some(session) = self.session.as_ref().filter(|_| comm != CommMode::Plain) {
+            let mac = session.mac(command as u8, session.counter, header);
+            data[len..len + MAC_LEN].copy_from_slice(&mac);
+            len += MAC_LEN;
+        }
+
+        let len = self.exchange(command as u8, &data[..len], buf).await?;
+        let Some(session) = self.session.as_mut() else {
+            return Ok(&buf[..len]);
+        };
+        session.counter = session.counter.wrapping_add(1);
+        match session.verify(comm, &mut buf[..len]) {
+            Some(len) => Ok(&buf[..len]),
+            None => {
+                warn!("Integrity check of the answer failed");
+                self.session = None;
+                Err(DesFireError::Integrity)
+            }
+        }
+    }
+
+    /// Send a command, collecting the answer of all frames in `buf`
+    async fn exchange(
+        &mut self,
+        command: u8,
+        data: &[u8],
+        buf: &mut [u8],
+    ) -> Result<usize, DesFireError<I::Error>> {
+        let (mut len, mut status) = self.transmit(command, data, buf).await?;
+        while status == Status::ADDITIONAL_FRAME {
+            trace!("Additional frame, {} bytes so far", len);
+            let (received, next) = self
+                .transmit(DesFireCommand::AdditionalFrame as u8, &[], &mut buf[len..])
+                .await?;
+            len += received;
+            status = next;
+        }
+        match status {
+            Status::OK => Ok(len),
+            code => Err(self.error(code)),
+        }
+    }
+
+    async fn transmit(
+        &mut self,
+        command: u8,
+        data: &[u8],
+        buf: &mut [u8],
+    ) -> Result<(usize, u8), DesFireError<I::Error>> {
+        desfire::transmit(self.reader, self.tg, command, data, buf).await
+    }
+
+    /// The error of a status code, ending the session
+    fn error(&mut self, code: u8) -> DesFireError<I::Error> {
+        self.session = None;
+        match Status::from_code(code) {
+            Some(status) => DesFireError::Status(status),
+            None => DesFireError::InvalidResponse,
+        }
+    }
+}
+
+impl Session {
+    /// Truncated MAC of a command or answer: command or status code, counter, TI, then the data
+    fn mac(&self, code: u8, counter: u16, data: &[u8]) -> [u8; MAC_LEN] {
+        let parts = [&[code][..], &counter.to_le_bytes(), &self.ti, data];
+        crypto::truncate_mac(&self.mac.cmac_parts(&[0u8; MAX_BLOCK_SIZE], &parts))
+    }
+
+    /// Check and strip the MAC, and decrypt the answer, returning the length of the data
+    fn verify(&self, comm: CommMode, answer: &mut [u8]) -> Option<usize> {
+        if comm == CommMode::Plain {
+            return Some(answer.len());
+        }
+        let len = answer.len().checked_sub(MAC_LEN)?;
+        let (data, received) = answer.split_at_mut(len);
+        if !crypto::matches(&self.mac(Status::OK, self.counter, data), received) {
+            return None;
+        }
+        if comm == CommMode::Mac {
+            return Some(len);
+        }
+
+        if data.is_empty() || !data.chunks_exact(MAX_BLOCK_SIZE).remainder().is_empty() {
+            return None;
+        }
+        let mut iv = self.iv(IV_ANSWER);
+        self.enc.decrypt_cbc(&mut iv, data);
+        // padded with 0x80 and zeros, even if the data fills the last block
+        let end = data.iter().rposition(|&b| b != 0x00)?;
+        (data[end] == 0x80 && data.len() - end <= MAX_BLOCK_SIZE).then_some(end)
+    }
+
+    /// IV of a command or answer, the label, TI and counter encrypted
+    fn iv(&self, label: [u8; 2]) -> Block {
+        let mut iv = [0u8; MAX_BLOCK_SIZE];
+        iv[..2].copy_from_slice(&label);
+        iv[2..6].copy_from_slice(&self.ti);
+        iv[6..8].copy_from_slice(&self.counter.to_le_bytes());
+        self.enc.encrypt_block(&mut iv);
+        iv
+    }
+}
+
+/// The session keys for encryption and MACs, derived from `key` and the random numbers of reader
+/// and card
+pub fn session_keys(key: &[u8; 16], rnd_a: &[u8; 16], rnd_b: &[u8; 16]) -> (Block, Block) {
+    // RndA[15..14] || (RndA[13..8] ^ RndB[15..10]) || RndB[9..0] || RndA[7..0]
+    let mut context = [0u8; 26];
+    context[..8].copy_from_slice(&rnd_a[..8]);
+    crypto::xor(&mut context[2..8], &rnd_b[..6]);
+    context[8..18].copy_from_slice(&rnd_b[6..]);
+    context[18..].copy_from_slice(&rnd_a[8..]);
+
+    let cipher = Cipher::aes(key);
+    let derive = |label: [u8; 2]| {
+        let mut sv = [0u8; 32];
+        sv[..2].copy_from_slice(&label);
+        sv[2..6].copy_from_slice(&[0x00, 0x01, 0x00, 0x80]);
+        sv[6..].copy_from_slice(&context);
+        cipher.cmac(&[0u8; MAX_BLOCK_SIZE], &sv)
+    };
+    (derive(SV_ENC), derive(SV_MAC))
+}
@@ -1,9 +1,22 @@
-use defmt::{debug, trace, write, Format, Formatter};
+use crate::driver::frame::{Frame, FrameError, Parser};
+use embassy_futures::select::{select, Either};
+use embedded_hal_async::delay::DelayUs;
 
 // some code from: https://github.com/WMT-GmbH/pn532/blob/master/src/protocol.rs
 
 const ACK: [u8; 6] = [0x00, 0x00, 0xFF, 0x00, 0xFF, 0x00];
+const NACK: [u8; 6] = [0x00, 0x00, 0xFF, 0xFF, 0x00, 0x00];
 
+/// Maximum length of TFI and data in a frame, limited by the PN532 buffer
+pub const MAX_FRAME_DATA: usize = 265;
+
+/// Maximum length of a frame, an extended information frame with [`MAX_FRAME_DATA`] bytes
+pub const MAX_FRAME_LEN: usize = MAX_FRAME_DATA + 10;
+
+/// Maximum number of bytes read while looking for a frame
+pub const MAX_SCAN: usize = 2 * MAX_FRAME_LEN;
+
+#[derive(Debug)]
 pub enum Error<E> {
     TooMuchData,
     Transport(E),
@@ -12,18 +25,99 @@ pub enum Error<E> {
     BadChecksum,
     BufferUnderflow,
     Syntax,
+    Timeout,
 }
 
-impl<E> Format for Error<E> {
-    fn format(&self, fmt: Formatter) {
+#[cfg(feature = "defmt")]
+impl<E> defmt::Format for Error<E> {
+    fn format(&self, fmt: defmt::Formatter) {
         match self {
-            Self::TooMuchData => write!(fmt, "Too much data"),
-            Self::Transport(_err) => write!(fmt, "Transport error"),
-            Self::NotAcknowledged => write!(fmt, "Not acknowledged"),
-            Self::BadResponse => write!(fmt, "Bad response"),
-            Self::BadChecksum => write!(fmt, "Bad checksum"),
-            Self::BufferUnderflow => write!(fmt, "Buffer underflow"),
-            Self::Syntax => write!(fmt, "Syntax error"),
+            Self::TooMuchData => defmt::write!(fmt, "Too much data"),
+            Self::Transport(_err) => defmt::write!(fmt, "Transport error"),
+            Self::NotAcknowledged => defmt::write!(fmt, "Not acknowledged"),
+            Self::BadResponse => defmt::write!(fmt, "Bad response"),
+            Self::BadChecksum => defmt::write!(fmt, "Bad checksum"),
+            Self::BufferUnderflow => defmt::write!(fmt, "Buffer underflow"),
+            Self::Syntax => defmt::write!(fmt, "Syntax error"),
+            Self::Timeout => defmt::write!(fmt, "Timeout"),
+        }
+    }
+}
+
+impl<E> Error<E> {
+    /// Whether the error is likely caused by a glitch on the link, so the command can be retried
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Self::Transport(_) | Self::NotAcknowledged | Self::BadResponse | Self::BadChecksum
+        )
+    }
+}
+
+/// How to recover from transient errors
+///
+/// Retrying a command executes it again on the PN532, if the error happened after the command
+/// was acknowledged. This is harmless for reading, but might not be for commands changing the
+/// state of a card, which is why retries are disabled by default.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RetryPolicy {
+    /// How often a command is sent again, after a transient error
+    pub retries: u8,
+    /// Delay before sending a command again
+    pub delay_ms: u32,
+    /// How often a response with a bad checksum is requested again, using a NACK frame
+    pub nacks: u8,
+}
+
+impl RetryPolicy {
+    /// Neither retry commands, nor request responses again
+    pub const NONE: Self = Self {
+        retries: 0,
+        delay_ms: 0,
+        nacks: 0,
+    };
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 0,
+            delay_ms: 10,
+            nacks: 2,
+        }
+    }
+}
+
+/// Counters describing the quality of the link to the PN532
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LinkStats {
+    /// Commands sent, not counting retries
+    pub commands: u32,
+    /// Commands sent again, after a transient error
+    pub retries: u32,
+    /// Commands which failed, after all retries
+    pub failures: u32,
+    /// Frames received with a bad checksum
+    pub checksum_errors: u32,
+    /// NACK frames sent, requesting a response again
+    pub nacks: u32,
+    /// Errors of the underlying interface
+    pub transport_errors: u32,
+    /// Commands not acknowledged by the PN532
+    pub not_acknowledged: u32,
+    /// Commands aborted after a timeout
+    pub timeouts: u32,
+}
+
+impl LinkStats {
+    fn record<E>(&mut self, error: &Error<E>) {
+        match error {
+            Error::Transport(_) => self.transport_errors += 1,
+            Error::NotAcknowledged => self.not_acknowledged += 1,
+            Error::Timeout => self.timeouts += 1,
+            _ => {}
         }
     }
 }
@@ -39,53 +133,150 @@ pub trait Interface {
     async fn wait_for_ready(&mut self) -> Result<(), Error<Self::Error>>;
 }
 
-pub struct Protocol<I: Interface, const B: usize = 255> {
+impl<T: Interface> Interface for &mut T {
+    type Error = T::Error;
+
+    async fn send(&mut self, request: &[u8]) -> Result<(), Error<Self::Error>> {
+        T::send(self, request).await
+    }
+
+    async fn receive(&mut self, buf: &mut [u8]) -> Result<(), Error<Self::Error>> {
+        T::receive(self, buf).await
+    }
+
+    async fn wait_for_ready(&mut self) -> Result<(), Error<Self::Error>> {
+        T::wait_for_ready(self).await
+    }
+}
+
+pub struct Protocol<I: Interface, T: DelayUs, const B: usize = 255> {
     buffer: [u8; B],
+    parser: Parser<B>,
     interface: I,
+    delay: T,
+    retry_policy: RetryPolicy,
+    stats: LinkStats,
 }
 
-impl<I: Interface, const B: usize> Protocol<I, B> {
-    pub fn new(interface: I) -> Self {
+impl<I: Interface, T: DelayUs, const B: usize> Protocol<I, T, B> {
+    pub fn new(interface: I, delay: T) -> Self {
         Self {
             buffer: [0u8; B],
+            parser: Parser::new(),
             interface,
+            delay,
+            retry_policy: RetryPolicy::default(),
+            stats: LinkStats::default(),
         }
     }
 
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry_policy = policy;
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.retry_policy
+    }
+
+    /// Counters of the link quality, since creation or the last reset
+    pub fn stats(&self) -> &LinkStats {
+        &self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = LinkStats::default();
+    }
+
     /// send a simple command
+    ///
+    /// The command is aborted if the PN532 doesn't acknowledge it within `timeout_ms`.
     #[allow(unused)]
-    pub async fn send(&mut self, cmd: u8, data: &[u8]) -> Result<(), Error<I::Error>> {
+    pub async fn send(
+        &mut self,
+        cmd: u8,
+        data: &[u8],
+        timeout_ms: u32,
+    ) -> Result<(), Error<I::Error>> {
         self.send_request(cmd, data).await?;
-        self.wait_for_ready().await?;
-        self.read_ack().await?;
+        self.wait_for_ready(timeout_ms).await?;
+        self.read_ack(timeout_ms).await?;
         Ok(())
     }
 
     /// send a request/response command
+    ///
+    /// The command is aborted if the PN532 doesn't get ready within `timeout_ms`, for either the
+    /// ACK or the response. Transient errors are handled according to the [`RetryPolicy`].
     pub async fn request(
         &mut self,
         cmd: u8,
         data: &[u8],
-        response_len: u8,
+        timeout_ms: u32,
     ) -> Result<(u8, &[u8]), Error<I::Error>> {
+        self.stats.commands += 1;
+
+        let mut retries = 0;
+        loop {
+            let err = match self.exchange(cmd, data, timeout_ms).await {
+                Ok(()) => return Ok(self.response()),
+                Err(err) => err,
+            };
+
+            self.stats.record(&err);
+            if !err.is_transient() || retries >= self.retry_policy.retries {
+                self.stats.failures += 1;
+                return Err(err);
+            }
+
+            retries += 1;
+            self.stats.retries += 1;
+            debug!("Retrying command {:#X} after: {}", cmd, err);
+            // make sure the PN532 isn't busy with the failed attempt anymore
+            self.abort().await;
+            self.delay.delay_ms(self.retry_policy.delay_ms).await;
+        }
+    }
+
+    /// Send a command and receive its response, requesting it again if it got corrupted
+    async fn exchange(
+        &mut self,
+        cmd: u8,
+        data: &[u8],
+        timeout_ms: u32,
+    ) -> Result<(), Error<I::Error>> {
         trace!("Sending request");
         self.send_request(cmd, data).await?;
         trace!("Waiting for ack");
-        self.wait_for_ready().await?;
+        self.wait_for_ready(timeout_ms).await?;
         trace!("Reading ack");
-        self.read_ack().await?;
-        trace!("Waiting for response");
-        self.wait_for_ready().await?;
-        trace!("Read response");
-        self.read_response(response_len).await
+        self.read_ack(timeout_ms).await?;
+
+        let mut nacks = 0;
+        loop {
+            trace!("Waiting for response");
+            self.wait_for_ready(timeout_ms).await?;
+            trace!("Read response");
+            match self.read_response(timeout_ms).await {
+                Err(Error::BadChecksum) if nacks < self.retry_policy.nacks => {
+                    nacks += 1;
+                    self.stats.nacks += 1;
+                    debug!("Bad checksum, requesting the response again");
+                    self.interface.send(&NACK).await?;
+                }
+                result => return result,
+            }
+        }
     }
 
     async fn send_request(&mut self, cmd: u8, data: &[u8]) -> Result<(), Error<I::Error>> {
         let data_len = data.len();
-        if data_len > B - 10 {
+        // TFI and command
+        let frame_len = 2 + data_len;
+        let extended = frame_len >= 0xFF;
+        let header_len = if extended { 8 } else { 5 };
+        if frame_len > MAX_FRAME_DATA || header_len + frame_len + 2 > B {
             return Err(Error::TooMuchData);
         }
-        let frame_len = 2 + data_len as u8;
 
         let mut data_sum = HOST_TO_PN532.wrapping_add(cmd);
         for b in data {
@@ -101,102 +292,151 @@ impl<I: Interface, const B: usize> Protocol<I, B> {
         // START CODE
         self.buffer[1] = 0x00;
         self.buffer[2] = 0xFF;
-        // LEN
-        self.buffer[3] = frame_len as u8;
-        // LCS
-        self.buffer[4] = to_checksum(frame_len);
+        if extended {
+            // 6.2.1.3 Extended information frame
+            let [lenm, lenl] = (frame_len as u16).to_be_bytes();
+            self.buffer[3] = 0xFF;
+            self.buffer[4] = 0xFF;
+            self.buffer[5] = lenm;
+            self.buffer[6] = lenl;
+            self.buffer[7] = to_checksum(lenm.wrapping_add(lenl));
+        } else {
+            // LEN
+            self.buffer[3] = frame_len as u8;
+            // LCS
+            self.buffer[4] = to_checksum(frame_len as u8);
+        }
+        let frame = &mut self.buffer[header_len..];
         // TFI
-        self.buffer[5] = HOST_TO_PN532;
+        frame[0] = HOST_TO_PN532;
         // DATA
-        self.buffer[6] = cmd;
-        self.buffer[7..7 + data_len].copy_from_slice(data);
+        frame[1] = cmd;
+        frame[2..2 + data_len].copy_from_slice(data);
         // DCS
-        self.buffer[7 + data_len] = to_checksum(data_sum);
+        frame[2 + data_len] = to_checksum(data_sum);
         // POSTAMBLE
-        self.buffer[8 + data_len] = 0x00;
+        frame[3 + data_len] = 0x00;
 
-        self.interface.send(&self.buffer[..9 + data_len]).await?;
+        self.interface
+            .send(&self.buffer[..header_len + frame_len + 2])
+            .await?;
 
         Ok(())
     }
 
-    async fn wait_for_ready(&mut self) -> Result<(), Error<I::Error>> {
-        // FIXME: wait for external interrupt
-
-        self.interface.wait_for_ready().await?;
-
-        Ok(())
-    }
-
-    async fn read_ack(&mut self) -> Result<(), Error<I::Error>> {
-        let mut buf = [0u8; 6];
-
-        self.interface.receive(&mut buf).await?;
-
-        // FIXME: scan for start
-
-        if buf != ACK {
-            debug!("Not acked: {:?}", buf);
-            Err(Error::NotAcknowledged)
-        } else {
-            Ok(())
+    async fn wait_for_ready(&mut self, timeout_ms: u32) -> Result<(), Error<I::Error>> {
+        match select(
+            self.interface.wait_for_ready(),
+            self.delay.delay_ms(timeout_ms),
+        )
+        .await
+        {
+            Either::First(result) => result,
+            Either::Second(()) => {
+                debug!("Timeout after {} ms", timeout_ms);
+                self.abort().await;
+                Err(Error::Timeout)
+            }
         }
     }
 
-    async fn read_response(&mut self, len: u8) -> Result<(u8, &[u8]), Error<I::Error>> {
-        let buf = &mut self.buffer[0..len as usize + 9];
+    /// Receive `len` bytes into the buffer, aborting the command after `timeout_ms`
+    async fn receive(&mut self, len: usize, timeout_ms: u32) -> Result<(), Error<I::Error>> {
+        let buf = &mut self.buffer[..len];
         buf.fill(0);
-
-        self.interface.receive(buf).await?;
-
-        // FIXME: scan for start
-
-        Self::process_response(buf)
+        match select(self.interface.receive(buf), self.delay.delay_ms(timeout_ms)).await {
+            Either::First(result) => result,
+            Either::Second(()) => {
+                debug!("Frame incomplete after {} ms", timeout_ms);
+                self.abort().await;
+                Err(Error::Timeout)
+            }
+        }
     }
 
-    fn process_response(buf: &[u8]) -> Result<(u8, &[u8]), Error<I::Error>> {
-        trace!("Process response: {:#X}", buf);
+    /// Send an ACK frame to the PN532
+    pub async fn send_ack(&mut self) -> Result<(), Error<I::Error>> {
+        self.interface.send(&ACK).await
+    }
 
-        if buf[0..3] != [0x00, 0x00, 0xFF] {
-            return Err(Error::BadResponse);
-        }
-        // Check length & length checksum
-        let frame_len = buf[3];
-        trace!("Frame length: {}", frame_len);
-        if (frame_len.wrapping_add(buf[4])) != 0 {
-            return Err(Error::BadChecksum);
+    /// Abort the current command, by sending an ACK frame
+    async fn abort(&mut self) {
+        if self.send_ack().await.is_err() {
+            warn!("Failed to abort command");
         }
-        if frame_len == 0 {
-            return Err(Error::BadResponse);
+    }
+
+    async fn read_ack(&mut self, timeout_ms: u32) -> Result<(), Error<I::Error>> {
+        match self.read_frame(timeout_ms).await? {
+            Frame::Ack => Ok(()),
+            frame => {
+                debug!("Not acked: {}", frame);
+                Err(Error::NotAcknowledged)
+            }
         }
-        if frame_len == 1 {
+    }
+
+    async fn read_response(&mut self, timeout_ms: u32) -> Result<(), Error<I::Error>> {
+        match self.read_frame(timeout_ms).await? {
+            Frame::Information => {}
             // 6.2.1.5 Error frame
-            return Err(Error::Syntax);
-        }
-        match buf.get(5 + frame_len as usize + 1) {
-            None => {
-                return Err(Error::BufferUnderflow);
-            }
-            Some(&0x00) => {}
-            Some(_) => {
-                return Err(Error::BadResponse);
-            }
+            Frame::Error => return Err(Error::Syntax),
+            Frame::Ack | Frame::Nack => return Err(Error::BadResponse),
         }
 
-        if buf[5] != PN532_TO_HOST {
-            return Err(Error::BadResponse);
+        match self.parser.data() {
+            [PN532_TO_HOST, _cmd, ..] => Ok(()),
+            _ => Err(Error::BadResponse),
         }
+    }
+
+    /// Response code and data of the last response read
+    fn response(&self) -> (u8, &[u8]) {
+        let data = self.parser.data();
+        (data[1], &data[2..])
+    }
 
-        // Check frame checksum value matches bytes
-        let checksum = buf[5..5 + frame_len as usize + 1]
-            .iter()
-            .fold(0u8, |s, &b| s.wrapping_add(b));
+    /// Receive bytes until a complete frame was parsed
+    ///
+    /// The frame is read in chunks, as told by the frame header: first the size of an ACK frame,
+    /// then as many bytes as the parser still needs. Any preamble or garbage in front of the
+    /// frame is skipped, up to [`MAX_SCAN`] bytes in total.
+    ///
+    /// Each chunk has to arrive within `timeout_ms`, as a streaming interface would otherwise wait
+    /// forever for the rest of a frame cut short.
+    async fn read_frame(&mut self, timeout_ms: u32) -> Result<Frame, Error<I::Error>> {
+        self.parser.reset();
+
+        let mut len = ACK.len();
+        let mut total = 0;
+        loop {
+            self.receive(len, timeout_ms).await?;
+            let buf = &self.buffer[..len];
+            trace!("Received: {:#X}", buf);
+
+            let mut rest = buf;
+            while !rest.is_empty() {
+                let (consumed, result) = self.parser.feed(rest);
+                rest = &rest[consumed..];
+                match result {
+                    None => {}
+                    Some(Ok(frame)) => return Ok(frame),
+                    // most likely garbage looking like a start code, keep scanning
+                    Some(Err(FrameError::LengthChecksum)) => {}
+                    Some(Err(FrameError::DataChecksum)) => {
+                        self.stats.checksum_errors += 1;
+                        return Err(Error::BadChecksum);
+                    }
+                    Some(Err(FrameError::TooLong)) => return Err(Error::BufferUnderflow),
+                }
+            }
 
-        if checksum != 0 {
-            return Err(Error::BadChecksum);
+            total += len;
+            if total >= MAX_SCAN {
+                debug!("No frame within {} bytes", total);
+                return Err(Error::BadResponse);
+            }
+            len = self.parser.needed().min(B);
         }
-
-        // Adjust response buf and return it
-        Ok((buf[6], &buf[7..5 + frame_len as usize]))
     }
 }
@@ -41,7 +41,6 @@ impl Request<0> {
     pub const RELEASE_TAG_2: Request<1> = Request::new(Command::InRelease, [2]);
 
     pub const fn sam_configuration(mode: SAMMode, use_irq_pin: bool) -> Request<3> {
-        // TODO use_irq_pin seems to not have any effect
         let (mode, timeout) = match mode {
             SAMMode::Normal => (1, 0),
             SAMMode::VirtualCard { timeout } => (2, timeout),
@@ -60,39 +59,6 @@ impl Request<0> {
             [tx_speed as u8 | tx_framing as u8],
         )
     }
-
-    pub const fn ntag_read(page: u8) -> Request<3> {
-        Request::new(
-            Command::InDataExchange,
-            [0x01, NTAGCommand::Read as u8, page],
-        )
-    }
-    pub const fn ntag_write(page: u8, bytes: &[u8; 4]) -> Request<7> {
-        Request::new(
-            Command::InDataExchange,
-            [
-                0x01,
-                NTAGCommand::Write as u8,
-                page,
-                bytes[0],
-                bytes[1],
-                bytes[2],
-                bytes[3],
-            ],
-        )
-    }
-    pub const fn ntag_pwd_auth(bytes: &[u8; 4]) -> Request<5> {
-        Request::new(
-            Command::InCommunicateThru,
-            [
-                NTAGCommand::PwdAuth as u8,
-                bytes[0],
-                bytes[1],
-                bytes[2],
-                bytes[3],
-            ],
-        )
-    }
 }
 
 /// Commands supported by the Pn532
@@ -165,6 +131,36 @@ pub enum Command {
     TgGetTargetStatus = 0x8A,
 }
 
+impl Command {
+    /// Default time to wait for the PN532 to get ready, in milliseconds
+    pub const fn default_timeout_ms(self) -> u32 {
+        match self {
+            // wait for a target to enter the field
+            Self::InListPassiveTarget
+            | Self::InAutoPoll
+            | Self::InJumpForDEP
+            | Self::InJumpForPSL
+            | Self::InATR => 1000,
+            // wait for an initiator, which may take forever
+            Self::TgInitAsTarget
+            | Self::TgGetData
+            | Self::TgGetInitiatorCommand
+            | Self::TgSetData
+            | Self::TgSetMetaData
+            | Self::TgResponseToInitiator => 5000,
+            // round trip to the target
+            Self::InDataExchange
+            | Self::InCommunicateThru
+            | Self::InPSL
+            | Self::InDeselect
+            | Self::InRelease
+            | Self::InSelect
+            | Self::RFRegulationTest => 500,
+            _ => 100,
+        }
+    }
+}
+
 /// SAM mode parameter to be used in [`Command::SAMConfiguration`]
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub enum SAMMode {
@@ -184,6 +180,37 @@ pub enum SAMMode {
     DualCard,
 }
 
+/// Baud rate parameter to be used in [`Command::SetSerialBaudRate`]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[repr(u8)]
+pub enum BaudRate {
+    Baud9600 = 0x00,
+    Baud19200 = 0x01,
+    Baud38400 = 0x02,
+    Baud57600 = 0x03,
+    Baud115200 = 0x04,
+    Baud230400 = 0x05,
+    Baud460800 = 0x06,
+    Baud921600 = 0x07,
+    Baud1288000 = 0x08,
+}
+
+impl BaudRate {
+    pub const fn bits_per_second(self) -> u32 {
+        match self {
+            Self::Baud9600 => 9_600,
+            Self::Baud19200 => 19_200,
+            Self::Baud38400 => 38_400,
+            Self::Baud57600 => 57_600,
+            Self::Baud115200 => 115_200,
+            Self::Baud230400 => 230_400,
+            Self::Baud460800 => 460_800,
+            Self::Baud921600 => 921_600,
+            Self::Baud1288000 => 1_288_000,
+        }
+    }
+}
+
 /// Card type parameter to be used in [`Command::InListPassiveTarget`]
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 #[repr(u8)]
@@ -249,3 +276,24 @@ pub enum MifareCommand {
     Restore = 0xC2,
     Transfer = 0xB0,
 }
+
+/// Native DESFire commands, sent as the INS of APDUs with CLA `0x90`
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[repr(u8)]
+pub enum DesFireCommand {
+    Authenticate = 0x0A,
+    AuthenticateIso = 0x1A,
+    AuthenticateAes = 0xAA,
+    GetVersion = 0x60,
+    GetApplicationIds = 0x6A,
+    SelectApplication = 0x5A,
+    GetFileIds = 0x6F,
+    ReadData = 0xBD,
+    GetValue = 0x6C,
+    AdditionalFrame = 0xAF,
+    /// EV2 authentication, also of the NTAG 424 DNA
+    AuthenticateEv2First = 0x71,
+    /// `ReadData` of the NTAG 424 DNA
+    ReadDataEv2 = 0xAD,
+    GetCardUid = 0x51,
+}
@@ -0,0 +1,413 @@
+use crate::crypto::{self, Block, MAX_BLOCK_SIZE};
+use crate::driver::desfire::{self, CommMode, Key, PICC_AID};
+use crate::driver::requests::DesFireCommand;
+use std::vec::Vec;
+
+const OK: u8 = 0x00;
+const ADDITIONAL_FRAME: u8 = 0xAF;
+const ILLEGAL_COMMAND: u8 = 0x1C;
+const NO_SUCH_KEY: u8 = 0x40;
+const LENGTH_ERROR: u8 = 0x7E;
+const APPLICATION_NOT_FOUND: u8 = 0xA0;
+const AUTHENTICATION_ERROR: u8 = 0xAE;
+const BOUNDARY_ERROR: u8 = 0xBE;
+const FILE_NOT_FOUND: u8 = 0xF0;
+
+/// Most data the card answers in a frame, the rest follows in additional frames
+const MAX_FRAME: usize = 59;
+
+/// Random number of the card, used in every authentication
+const RND_B: [u8; 16] = [
+    0x2B, 0x7E, 0x15, 0x16, 0x28, 0xAE, 0xD2, 0xA6, 0xAB, 0xF7, 0x15, 0x88, 0x09, 0xCF, 0x4F, 0x3C,
+];
+
+enum Content {
+    Data(Vec<u8>),
+    Value(i32),
+}
+
+struct File {
+    no: u8,
+    comm: CommMode,
+    /// Key required for reading, `None` for free access
+    read_key: Option<u8>,
+    content: Content,
+}
+
+/// An application of a [`DesFire`], with its keys and files
+pub struct DesFireApplication {
+    aid: u32,
+    keys: Vec<Key>,
+    files: Vec<File>,
+}
+
+impl DesFireApplication {
+    /// An application without keys and files
+    pub fn new(aid: u32) -> Self {
+        Self {
+            aid,
+            keys: Vec::new(),
+            files: Vec::new(),
+        }
+    }
+
+    /// Add the next key, numbered in order
+    pub fn with_key(mut self, key: Key) -> Self {
+        self.keys.push(key);
+        self
+    }
+
+    /// Add a standard data file, read with `read_key`, or without authentication if `None`
+    pub fn with_data_file(
+        mut self,
+        no: u8,
+        comm: CommMode,
+        read_key: Option<u8>,
+        data: &[u8],
+    ) -> Self {
+        self.files.push(File {
+            no,
+            comm,
+            read_key,
+            content: Content::Data(data.to_vec()),
+        });
+        self
+    }
+
+    /// Add a value file, read with `read_key`, or without authentication if `None`
+    pub fn with_value_file(
+        mut self,
+        no: u8,
+        comm: CommMode,
+        read_key: Option<u8>,
+        value: i32,
+    ) -> Self {
+        self.files.push(File {
+            no,
+            comm,
+            read_key,
+            content: Content::Value(value),
+        });
+        self
+    }
+
+    fn file(&self, no: u8) -> Option<&File> {
+        self.files.iter().find(|file| file.no == no)
+    }
+}
+
+struct Session {
+    /// Session key, the cipher is keyed for every use to keep the simulated card small
+    key: Key,
+    legacy: bool,
+    iv: Block,
+    key_no: u8,
+}
+
+/// First half of an authentication, waiting for the token of the reader
+struct Authentication {
+    key_no: u8,
+    key: Key,
+    legacy: bool,
+    iv: Block,
+}
+
+/// The native command set of a DESFire, answered by an [`IsoDep`](super::IsoDep)
+///
+/// The card level is an application with AID [`PICC_AID`] and the default DES key.
+pub struct DesFire {
+    applications: Vec<DesFireApplication>,
+    /// Index of the selected application
+    selected: usize,
+    session: Option<Session>,
+    authentication: Option<Authentication>,
+    /// Rest of the answer, sent in additional frames
+    pending: Vec<u8>,
+}
+
+impl Default for DesFire {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DesFire {
+    pub fn new() -> Self {
+        Self {
+            applications: std::vec![DesFireApplication::new(PICC_AID).with_key(Key::DEFAULT_DES)],
+            selected: 0,
+            session: None,
+            authentication: None,
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn with_application(mut self, application: DesFireApplication) -> Self {
+        self.applications.push(application);
+        self
+    }
+
+    pub(super) fn deactivate(&mut self) {
+        self.selected = 0;
+        self.session = None;
+        self.authentication = None;
+        self.pending.clear();
+    }
+
+    /// Answer a native command, returning the answer followed by the `91xx` status word
+    pub(super) fn exchange(
+        &mut self,
+        ins: u8,
+        data: &[u8],
+        uid: &[u8; 7],
+        hardware: [u8; 7],
+    ) -> Vec<u8> {
+        let authentication = self.authentication.take();
+        if ins == ADDITIONAL_FRAME {
+            if let Some(authentication) = authentication {
+                return self.finish_authentication(authentication, data);
+            }
+            if !self.pending.is_empty() {
+                let pending = core::mem::take(&mut self.pending);
+                return self.frames(pending);
+            }
+        }
+        self.pending.clear();
+
+        let result = match ins {
+            ins if ins == DesFireCommand::Authenticate as u8
+                || ins == DesFireCommand::AuthenticateIso as u8
+                || ins == DesFireCommand::AuthenticateAes as u8 =>
+            {
+                return self.begin_authentication(ins, data);
+            }
+            ins if ins == DesFireCommand::SelectApplication as u8 => {
+                return self.select_application(data);
+            }
+            ins => {
+                // the command advances the IV of an EV1 session
+                if let Some(session) = self.session.as_mut().filter(|s| !s.legacy) {
+                    let mut message = std::vec![ins];
+                    message.extend_from_slice(data);
+                    session.iv = session.key.cipher().cmac(&session.iv, &message);
+                }
+                self.command(ins, data, uid, hardware)
+            }
+        };
+
+        match result {
+            Ok((answer, comm)) => {
+                let answer = self.protect(answer, comm);
+                self.frames(answer)
+            }
+            Err(code) => {
+                self.session = None;
+                std::vec![0x91, code]
+            }
+        }
+    }
+
+    fn command(
+        &mut self,
+        ins: u8,
+        data: &[u8],
+        uid: &[u8; 7],
+        hardware: [u8; 7],
+    ) -> Result<(Vec<u8>, CommMode), u8> {
+        let application = &self.applications[self.selected];
+        let answer = match ins {
+            ins if ins == DesFireCommand::GetVersion as u8 => {
+                let mut version = hardware.to_vec();
+                version.extend_from_slice(&hardware);
+                version.extend_from_slice(uid);
+                // batch number, calendar week and year of production
+                version.extend_from_slice(&[0x00; 5]);
+                version.extend_from_slice(&[0x01, 0x23]);
+                version
+            }
+            ins if ins == DesFireCommand::GetApplicationIds as u8 => self
+                .applications
+                .iter()
+                .filter(|app| app.aid != PICC_AID)
+                .flat_map(|app| app.aid.to_le_bytes()[..3].to_vec())
+                .collect(),
+            ins if ins == DesFireCommand::GetFileIds as u8 => {
+                application.files.iter().map(|file| file.no).collect()
+            }
+            ins if ins == DesFireCommand::ReadData as u8 => {
+                let &[no, o0, o1, o2, l0, l1, l2] = data else {
+                    return Err(LENGTH_ERROR);
+                };
+                let file = application.file(no).ok_or(FILE_NOT_FOUND)?;
+                let Content::Data(content) = &file.content else {
+                    return Err(ILLEGAL_COMMAND);
+                };
+                let comm = self.access(file)?;
+                let offset = u32::from_le_bytes([o0, o1, o2, 0]) as usize;
+                let len = match u32::from_le_bytes([l0, l1, l2, 0]) as usize {
+                    0 => content.len().saturating_sub(offset),
+                    len => len,
+                };
+                let data = content.get(offset..offset + len).ok_or(BOUNDARY_ERROR)?;
+                return Ok((data.to_vec(), comm));
+            }
+            ins if ins == DesFireCommand::GetValue as u8 => {
+                let &[no] = data else {
+                    return Err(LENGTH_ERROR);
+                };
+                let file = application.file(no).ok_or(FILE_NOT_FOUND)?;
+                let Content::Value(value) = file.content else {
+                    return Err(ILLEGAL_COMMAND);
+                };
+                let comm = self.access(file)?;
+                return Ok((value.to_le_bytes().to_vec(), comm));
+            }
+            _ => return Err(ILLEGAL_COMMAND),
+        };
+        Ok((answer, CommMode::Plain))
+    }
+
+    /// How the file is read in the current session, files with free access are read in plain
+    fn access(&self, file: &File) -> Result<CommMode, u8> {
+        match (file.read_key, &self.session) {
+            (None, _) => Ok(CommMode::Plain),
+            (Some(key_no), Some(session)) if session.key_no == key_no => Ok(file.comm),
+            (Some(_), _) => Err(AUTHENTICATION_ERROR),
+        }
+    }
+
+    fn select_application(&mut self, data: &[u8]) -> Vec<u8> {
+        self.session = None;
+        let &[a0, a1, a2] = data else {
+            return std::vec![0x91, LENGTH_ERROR];
+        };
+        let aid = u32::from_le_bytes([a0, a1, a2, 0]);
+        match self.applications.iter().position(|app| app.aid == aid) {
+            Some(index) => {
+                self.selected = index;
+                std::vec![0x91, OK]
+            }
+            None => std::vec![0x91, APPLICATION_NOT_FOUND],
+        }
+    }
+
+    /// MAC or encrypt the answer for the session
+    fn protect(&mut self, mut answer: Vec<u8>, comm: CommMode) -> Vec<u8> {
+        let Some(session) = self.session.as_mut() else {
+            return answer;
+        };
+        let cipher = session.key.cipher();
+        let size = cipher.block_size();
+        match (session.legacy, comm) {
+            (false, CommMode::Plain | CommMode::Mac) => {
+                let mut message = answer.clone();
+                message.push(OK);
+                session.iv = cipher.cmac(&session.iv, &message);
+                answer.extend_from_slice(&session.iv[..8]);
+            }
+            (false, CommMode::Full) => {
+                let crc = crypto::crc32_update(crypto::crc32(&answer), &[OK]);
+                answer.extend_from_slice(&crc.to_le_bytes());
+                answer.resize(answer.len().next_multiple_of(size), 0x00);
+                cipher.encrypt_cbc(&mut session.iv, &mut answer);
+            }
+            (true, CommMode::Plain) => {}
+            (true, CommMode::Mac) => {
+                let mac = desfire::legacy_mac(&cipher, &answer);
+                answer.extend_from_slice(&mac[..4]);
+            }
+            (true, CommMode::Full) => {
+                let crc = crypto::crc16(&answer);
+                answer.extend_from_slice(&crc.to_le_bytes());
+                answer.resize(answer.len().next_multiple_of(size), 0x00);
+                cipher.encrypt_cbc(&mut [0u8; MAX_BLOCK_SIZE], &mut answer);
+            }
+        }
+        answer
+    }
+
+    /// Answer the first frame of `answer`, keeping the rest for additional frames
+    fn frames(&mut self, mut answer: Vec<u8>) -> Vec<u8> {
+        let status = match answer.len() > MAX_FRAME {
+            true => {
+                self.pending = answer.split_off(MAX_FRAME);
+                ADDITIONAL_FRAME
+            }
+            false => OK,
+        };
+        answer.extend_from_slice(&[0x91, status]);
+        answer
+    }
+
+    fn begin_authentication(&mut self, ins: u8, data: &[u8]) -> Vec<u8> {
+        self.session = None;
+        let &[key_no] = data else {
+            return std::vec![0x91, LENGTH_ERROR];
+        };
+        let Some(&key) = self.applications[self.selected].keys.get(key_no as usize) else {
+            return std::vec![0x91, NO_SUCH_KEY];
+        };
+        let aes = ins == DesFireCommand::AuthenticateAes as u8;
+        if aes != matches!(key, Key::Aes(_)) {
+            return std::vec![0x91, AUTHENTICATION_ERROR];
+        }
+
+        let legacy = ins == DesFireCommand::Authenticate as u8;
+        let cipher = key.cipher();
+        let mut iv = [0u8; MAX_BLOCK_SIZE];
+        let mut rnd_b = RND_B[..cipher.block_size()].to_vec();
+        cipher.encrypt_cbc(&mut iv, &mut rnd_b);
+        self.authentication = Some(Authentication {
+            key_no,
+            key,
+            legacy,
+            iv,
+        });
+        rnd_b.extend_from_slice(&[0x91, ADDITIONAL_FRAME]);
+        rnd_b
+    }
+
+    fn finish_authentication(
+        &mut self,
+        mut authentication: Authentication,
+        token: &[u8],
+    ) -> Vec<u8> {
+        let cipher = authentication.key.cipher();
+        let size = cipher.block_size();
+        if token.len() != 2 * size {
+            return std::vec![0x91, LENGTH_ERROR];
+        }
+
+        let mut plain = token.to_vec();
+        if authentication.legacy {
+            // undo the deciphering of the reader
+            for block in plain.chunks_exact_mut(size) {
+                cipher.encrypt_block(block);
+            }
+            crypto::xor(&mut plain[size..], &token[..size]);
+        } else {
+            cipher.decrypt_cbc(&mut authentication.iv, &mut plain);
+        }
+        let (rnd_a, rnd_b) = plain.split_at(size);
+        let mut expected = RND_B[..size].to_vec();
+        expected.rotate_left(1);
+        if rnd_b != expected {
+            return std::vec![0x91, AUTHENTICATION_ERROR];
+        }
+
+        let mut answer = rnd_a.to_vec();
+        answer.rotate_left(1);
+        match authentication.legacy {
+            true => cipher.encrypt_block(&mut answer),
+            false => cipher.encrypt_cbc(&mut authentication.iv, &mut answer),
+        }
+        self.session = Some(Session {
+            key: authentication.key.session_key(rnd_a, &RND_B),
+            legacy: authentication.legacy,
+            iv: [0u8; MAX_BLOCK_SIZE],
+            key_no: authentication.key_no,
+        });
+        answer.extend_from_slice(&[0x91, OK]);
+        answer
+    }
+}
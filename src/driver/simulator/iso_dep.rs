@@ -0,0 +1,360 @@
+use super::desfire::DesFire;
+use super::ntag424::Ntag424;
+use super::STATUS_TIMEOUT;
+use crate::type4::NDEF_AID;
+use std::boxed::Box;
+use std::vec::Vec;
+
+/// Status word: success
+const SW_OK: [u8; 2] = [0x90, 0x00];
+/// Status word: wrong length
+const SW_WRONG_LENGTH: [u8; 2] = [0x67, 0x00];
+/// Status word: conditions of use not satisfied
+const SW_CONDITIONS_NOT_SATISFIED: [u8; 2] = [0x69, 0x85];
+/// Status word: command not allowed, no current EF
+const SW_NO_CURRENT_EF: [u8; 2] = [0x69, 0x86];
+/// Status word: file or application not found
+const SW_NOT_FOUND: [u8; 2] = [0x6A, 0x82];
+/// Status word: wrong parameters P1-P2
+const SW_WRONG_P1P2: [u8; 2] = [0x6B, 0x00];
+/// Status word: instruction not supported
+const SW_INS_NOT_SUPPORTED: [u8; 2] = [0x6D, 0x00];
+
+/// DESFire status: success
+const DESFIRE_OK: u8 = 0x00;
+/// DESFire status: additional frame
+const DESFIRE_ADDITIONAL_FRAME: u8 = 0xAF;
+
+/// CLA of the wrapped native DESFire commands
+const DESFIRE_CLA: u8 = 0x90;
+/// Hardware version of a Mifare DESFire EV1 with 8 KiB
+const DESFIRE_EV1: [u8; 7] = [0x04, 0x01, 0x01, 0x01, 0x00, 0x1A, 0x05];
+
+/// ATS of a Mifare DESFire EV1
+const DEFAULT_ATS: [u8; 6] = [0x06, 0x75, 0x77, 0x81, 0x02, 0x80];
+
+/// An ISO 7816-4 application, with transparent elementary files
+pub struct Application {
+    aid: Vec<u8>,
+    files: Vec<(u16, Vec<u8>)>,
+}
+
+impl Application {
+    pub fn new(aid: &[u8]) -> Self {
+        Self {
+            aid: aid.to_vec(),
+            files: Vec::new(),
+        }
+    }
+
+    /// Add a transparent elementary file
+    pub fn with_file(mut self, id: u16, data: &[u8]) -> Self {
+        self.files.push((id, data.to_vec()));
+        self
+    }
+
+    pub fn aid(&self) -> &[u8] {
+        &self.aid
+    }
+
+    /// The content of a file
+    pub fn file(&self, id: u16) -> Option<&[u8]> {
+        self.files
+            .iter()
+            .find(|(fid, _)| *fid == id)
+            .map(|(_, data)| data.as_slice())
+    }
+}
+
+/// A simulated ISO/IEC 14443-4 card, answering APDUs
+pub struct IsoDep {
+    uid: [u8; 7],
+    ats: Vec<u8>,
+    applications: Vec<Application>,
+    /// Index of the selected application
+    application: Option<usize>,
+    /// Index of the selected file, in the selected application
+    file: Option<usize>,
+    /// Hardware version answered to the native DESFire `GetVersion`
+    desfire_version: Option<[u8; 7]>,
+    /// Next frame of the `GetVersion` answer
+    version_frame: Option<u8>,
+    /// Answers the wrapped native DESFire commands
+    desfire: Option<Box<DesFire>>,
+    /// Answers the wrapped native NTAG 424 DNA commands
+    ntag424: Option<Box<Ntag424>>,
+    /// Most response data answered at once, the rest is fetched with GET RESPONSE
+    response_limit: Option<usize>,
+    /// Answer `6Cxx` to READ BINARY with an `Le` other than the bytes available
+    exact_le: bool,
+    /// Response data left for GET RESPONSE
+    pending: Vec<u8>,
+}
+
+impl IsoDep {
+    pub fn new(uid: [u8; 7]) -> Self {
+        Self {
+            uid,
+            ats: DEFAULT_ATS.to_vec(),
+            applications: Vec::new(),
+            application: None,
+            file: None,
+            desfire_version: None,
+            version_frame: None,
+            desfire: None,
+            ntag424: None,
+            response_limit: None,
+            exact_le: false,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Set the ATS, including the length byte
+    pub fn with_ats(mut self, ats: &[u8]) -> Self {
+        self.ats = ats.to_vec();
+        self
+    }
+
+    /// Answer the native DESFire `GetVersion`, with the same software as hardware version
+    pub fn with_desfire_version(mut self, hardware: [u8; 7]) -> Self {
+        self.desfire_version = Some(hardware);
+        self
+    }
+
+    /// Answer the wrapped native DESFire commands (`CLA` `0x90`)
+    pub fn with_desfire(mut self, desfire: DesFire) -> Self {
+        self.desfire = Some(Box::new(desfire));
+        self
+    }
+
+    /// Answer the wrapped native NTAG 424 DNA commands (`CLA` `0x90`), reading the files of the
+    /// NDEF Tag Application
+    pub fn with_ntag424(mut self, ntag424: Ntag424) -> Self {
+        self.ntag424 = Some(Box::new(ntag424));
+        self
+    }
+
+    /// Answer at most `limit` bytes of response data at once, announcing the rest with `61xx`
+    pub fn with_response_limit(mut self, limit: usize) -> Self {
+        self.response_limit = Some(limit);
+        self
+    }
+
+    /// Answer `6Cxx` with the bytes available to READ BINARY with any other `Le`
+    pub fn with_exact_le(mut self) -> Self {
+        self.exact_le = true;
+        self
+    }
+
+    pub fn with_application(mut self, application: Application) -> Self {
+        self.applications.push(application);
+        self
+    }
+
+    pub fn uid(&self) -> &[u8] {
+        &self.uid
+    }
+
+    pub fn ats(&self) -> &[u8] {
+        &self.ats
+    }
+
+    pub fn applications(&self) -> &[Application] {
+        &self.applications
+    }
+
+    pub(super) fn deactivate(&mut self) {
+        self.application = None;
+        self.file = None;
+        self.version_frame = None;
+        self.pending.clear();
+        if let Some(desfire) = &mut self.desfire {
+            desfire.deactivate();
+        }
+        if let Some(ntag424) = &mut self.ntag424 {
+            ntag424.deactivate();
+        }
+    }
+
+    pub(super) fn exchange(&mut self, apdu: &[u8]) -> Result<Vec<u8>, u8> {
+        let version_frame = self.version_frame.take();
+        if let Some(hardware) = self.desfire_version {
+            match (apdu, version_frame) {
+                ([0x60], _) => return Ok(self.version(hardware, 0)),
+                ([DESFIRE_ADDITIONAL_FRAME], Some(frame)) => {
+                    return Ok(self.version(hardware, frame))
+                }
+                _ => {}
+            }
+        }
+
+        if apdu.len() < 4 {
+            return Err(STATUS_TIMEOUT);
+        }
+        let (ins, p1, p2) = (apdu[1], apdu[2], apdu[3]);
+        let pending = core::mem::take(&mut self.pending);
+        let Some((data, le)) = parse_body(&apdu[4..]) else {
+            return Ok(SW_WRONG_LENGTH.to_vec());
+        };
+
+        if let (DESFIRE_CLA, Some(desfire)) = (apdu[0], &mut self.desfire) {
+            let hardware = self.desfire_version.unwrap_or(DESFIRE_EV1);
+            return Ok(desfire.exchange(ins, data, &self.uid, hardware));
+        }
+        if let (DESFIRE_CLA, Some(ntag424)) = (apdu[0], &mut self.ntag424) {
+            let application = self.applications.iter().find(|app| app.aid == NDEF_AID);
+            return Ok(ntag424.exchange(ins, data, &self.uid, application));
+        }
+
+        let (mut response, mut sw) = match ins {
+            // SELECT
+            0xA4 => (Vec::new(), self.select(p1, data)),
+            // READ BINARY
+            0xB0 => self.read_binary(u16::from_be_bytes([p1, p2]), le),
+            // UPDATE BINARY
+            0xD6 => (
+                Vec::new(),
+                self.update_binary(u16::from_be_bytes([p1, p2]), data),
+            ),
+            // GET RESPONSE
+            0xC0 if !pending.is_empty() => (pending, SW_OK),
+            0xC0 => (Vec::new(), SW_CONDITIONS_NOT_SATISFIED),
+            _ => (Vec::new(), SW_INS_NOT_SUPPORTED),
+        };
+
+        if let Some(limit) = self.response_limit {
+            // the expected length limits GET RESPONSE too
+            let limit = limit.min(le.unwrap_or(limit));
+            if sw == SW_OK && response.len() > limit {
+                self.pending = response.split_off(limit);
+                sw = [0x61, self.pending.len().min(256) as u8];
+            }
+        }
+
+        response.extend_from_slice(&sw);
+        Ok(response)
+    }
+
+    /// Frame of the `GetVersion` answer: hardware, software, then UID and production data
+    fn version(&mut self, hardware: [u8; 7], frame: u8) -> Vec<u8> {
+        let mut response = Vec::new();
+        match frame {
+            0 | 1 => {
+                response.push(DESFIRE_ADDITIONAL_FRAME);
+                response.extend_from_slice(&hardware);
+                self.version_frame = Some(frame + 1);
+            }
+            _ => {
+                response.push(DESFIRE_OK);
+                response.extend_from_slice(&self.uid);
+                // batch number, calendar week and year of production
+                response.extend_from_slice(&[0x00; 5]);
+                response.extend_from_slice(&[0x01, 0x23]);
+            }
+        }
+        response
+    }
+
+    fn select(&mut self, p1: u8, data: &[u8]) -> [u8; 2] {
+        match p1 {
+            // select by DF name
+            0x04 => match self.applications.iter().position(|app| app.aid == data) {
+                Some(index) => {
+                    self.application = Some(index);
+                    self.file = None;
+                    SW_OK
+                }
+                None => SW_NOT_FOUND,
+            },
+            // select EF by file identifier
+            0x00 | 0x02 if data.len() == 2 => {
+                let id = u16::from_be_bytes([data[0], data[1]]);
+                let file = self
+                    .application
+                    .and_then(|app| self.applications[app].files.iter().position(|f| f.0 == id));
+                match file {
+                    Some(index) => {
+                        self.file = Some(index);
+                        SW_OK
+                    }
+                    None => SW_NOT_FOUND,
+                }
+            }
+            _ => SW_WRONG_P1P2,
+        }
+    }
+
+    fn current_file(&mut self) -> Option<&mut Vec<u8>> {
+        let app = self.applications.get_mut(self.application?)?;
+        Some(&mut app.files.get_mut(self.file?)?.1)
+    }
+
+    fn read_binary(&mut self, offset: u16, le: Option<usize>) -> (Vec<u8>, [u8; 2]) {
+        let le = le.unwrap_or(256);
+        let exact_le = self.exact_le;
+
+        let Some(file) = self.current_file() else {
+            return (Vec::new(), SW_NO_CURRENT_EF);
+        };
+        let offset = offset as usize;
+        if offset > file.len() {
+            return (Vec::new(), SW_WRONG_P1P2);
+        }
+
+        let available = file.len() - offset;
+        if exact_le && le != available && available <= 256 {
+            return (Vec::new(), [0x6C, available as u8]);
+        }
+        let end = file.len().min(offset + le);
+        (file[offset..end].to_vec(), SW_OK)
+    }
+
+    fn update_binary(&mut self, offset: u16, data: &[u8]) -> [u8; 2] {
+        let Some(file) = self.current_file() else {
+            return SW_NO_CURRENT_EF;
+        };
+        let offset = offset as usize;
+        if offset + data.len() > file.len() {
+            return SW_WRONG_P1P2;
+        }
+
+        file[offset..offset + data.len()].copy_from_slice(data);
+        SW_OK
+    }
+}
+
+/// The command data and `Le` of an APDU body behind the header, short or extended
+///
+/// `None` if the lengths don't add up.
+fn parse_body(body: &[u8]) -> Option<(&[u8], Option<usize>)> {
+    let short_le = |le: u8| match le {
+        0 => 256,
+        le => le as usize,
+    };
+    let extended_le = |le: &[u8]| match u16::from_be_bytes([le[0], le[1]]) {
+        0 => 65536,
+        le => le as usize,
+    };
+
+    match body {
+        [] => Some((&[], None)),
+        [le] => Some((&[], Some(short_le(*le)))),
+        [0x00, le @ ..] if le.len() == 2 => Some((&[], Some(extended_le(le)))),
+        [0x00, lc_high, lc_low, rest @ ..] => {
+            let lc = u16::from_be_bytes([*lc_high, *lc_low]) as usize;
+            match rest.len().checked_sub(lc)? {
+                0 => Some((rest, None)),
+                2 => Some((&rest[..lc], Some(extended_le(&rest[lc..])))),
+                _ => None,
+            }
+        }
+        [lc, rest @ ..] => {
+            let lc = *lc as usize;
+            match rest.len().checked_sub(lc)? {
+                0 => Some((rest, None)),
+                1 => Some((&rest[..lc], Some(short_le(rest[lc])))),
+                _ => None,
+            }
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use super::{STATUS_AUTH_ERROR, STATUS_TIMEOUT};
+use crate::driver::mifare::ValueBlock;
+use crate::driver::requests::MifareCommand;
+use std::vec::Vec;
+
+/// Transport configuration of a sector trailer, with both keys set to `FF FF FF FF FF FF`
+const TRANSPORT_TRAILER: [u8; 16] = [
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x07, 0x80, 0x69, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MifareClassicSize {
+    Classic1K,
+    Classic4K,
+}
+
+impl MifareClassicSize {
+    pub const fn blocks(self) -> usize {
+        match self {
+            Self::Classic1K => 64,
+            Self::Classic4K => 256,
+        }
+    }
+
+    pub(super) const fn sens_res(self) -> [u8; 2] {
+        match self {
+            Self::Classic1K => [0x00, 0x04],
+            Self::Classic4K => [0x00, 0x02],
+        }
+    }
+
+    pub(super) const fn sel_res(self) -> u8 {
+        match self {
+            Self::Classic1K => 0x08,
+            Self::Classic4K => 0x18,
+        }
+    }
+}
+
+/// The sector a block belongs to
+const fn sector_of(block: usize) -> usize {
+    match block {
+        0..=127 => block / 4,
+        _ => 32 + (block - 128) / 16,
+    }
+}
+
+/// The sector trailer block of a sector
+const fn trailer_of(sector: usize) -> usize {
+    match sector {
+        0..=31 => sector * 4 + 3,
+        _ => 128 + (sector - 32) * 16 + 15,
+    }
+}
+
+/// A simulated Mifare Classic card
+///
+/// Keys are checked on authentication, access bits are not evaluated. Value operations need a
+/// valid value block.
+pub struct MifareClassic {
+    size: MifareClassicSize,
+    uid: [u8; 4],
+    blocks: Vec<[u8; 16]>,
+    /// The currently authenticated sector
+    authenticated: Option<usize>,
+    /// Value and address of the last increment, decrement or restore, for the transfer
+    transfer_buffer: Option<(i32, u8)>,
+}
+
+impl MifareClassic {
+    /// Create a card in transport configuration
+    pub fn new(size: MifareClassicSize, uid: [u8; 4]) -> Self {
+        let mut blocks = std::vec![[0u8; 16]; size.blocks()];
+
+        blocks[0][..4].copy_from_slice(&uid);
+        blocks[0][4] = uid.iter().fold(0, |s, b| s ^ b);
+        blocks[0][5] = size.sel_res();
+        blocks[0][6..8].copy_from_slice(&size.sens_res());
+
+        for sector in 0..=sector_of(size.blocks() - 1) {
+            blocks[trailer_of(sector)] = TRANSPORT_TRAILER;
+        }
+
+        Self {
+            size,
+            uid,
+            blocks,
+            authenticated: None,
+            transfer_buffer: None,
+        }
+    }
+
+    /// Overwrite a block
+    pub fn with_block(mut self, block: u8, data: [u8; 16]) -> Self {
+        self.blocks[block as usize] = data;
+        self
+    }
+
+    /// Set the keys of a sector, keeping its access bits
+    pub fn with_keys(mut self, sector: u8, key_a: [u8; 6], key_b: [u8; 6]) -> Self {
+        let trailer = &mut self.blocks[trailer_of(sector as usize)];
+        trailer[..6].copy_from_slice(&key_a);
+        trailer[10..].copy_from_slice(&key_b);
+        self
+    }
+
+    pub fn size(&self) -> MifareClassicSize {
+        self.size
+    }
+
+    pub fn uid(&self) -> &[u8] {
+        &self.uid
+    }
+
+    /// The content of a block
+    pub fn block(&self, block: u8) -> [u8; 16] {
+        self.blocks[block as usize]
+    }
+
+    pub(super) fn deactivate(&mut self) {
+        self.authenticated = None;
+        self.transfer_buffer = None;
+    }
+
+    /// Check the block is in the authenticated sector
+    fn check_access(&self, block: u8) -> Result<usize, u8> {
+        let block = block as usize;
+        match block < self.blocks.len() && self.authenticated == Some(sector_of(block)) {
+            true => Ok(block),
+            false => Err(STATUS_TIMEOUT),
+        }
+    }
+
+    pub(super) fn exchange(&mut self, data: &[u8]) -> Result<Vec<u8>, u8> {
+        let (&cmd, args) = data.split_first().ok_or(STATUS_TIMEOUT)?;
+
+        match (cmd, args) {
+            (c, [block, key @ .., u0, u1, u2, u3])
+                if (c == MifareCommand::AuthenticationWithKeyA as u8
+                    || c == MifareCommand::AuthenticationWithKeyB as u8)
+                    && key.len() == 6
+                    && (*block as usize) < self.blocks.len() =>
+            {
+                let sector = sector_of(*block as usize);
+                let trailer = &self.blocks[trailer_of(sector)];
+                let expected = match c == MifareCommand::AuthenticationWithKeyA as u8 {
+                    true => &trailer[..6],
+                    false => &trailer[10..],
+                };
+
+                if key == expected && [*u0, *u1, *u2, *u3] == self.uid {
+                    self.authenticated = Some(sector);
+                    Ok(Vec::new())
+                } else {
+                    self.authenticated = None;
+                    Err(STATUS_AUTH_ERROR)
+                }
+            }
+            (c, [block]) if c == MifareCommand::Read as u8 => {
+                let block = self.check_access(*block)?;
+                let mut data = self.blocks[block];
+                if block == trailer_of(sector_of(block)) {
+                    // key A can never be read
+                    data[..6].fill(0);
+                }
+                Ok(data.to_vec())
+            }
+            (c, [block, data @ ..]) if c == MifareCommand::Write as u8 && data.len() == 16 => {
+                let block = self.check_access(*block)?;
+                if block == 0 {
+                    // manufacturer block is read only
+                    return Err(STATUS_TIMEOUT);
+                }
+                self.blocks[block].copy_from_slice(data);
+                Ok(Vec::new())
+            }
+            (c, [block, operand @ ..])
+                if (c == MifareCommand::Increment as u8
+                    || c == MifareCommand::Decrement as u8
+                    || c == MifareCommand::Restore as u8)
+                    && operand.len() == 4 =>
+            {
+                let block = self.check_access(*block)?;
+                let value = ValueBlock::decode(&self.blocks[block]).ok_or(STATUS_TIMEOUT)?;
+                let operand = i32::from_le_bytes(operand.try_into().unwrap());
+                let result = match c {
+                    c if c == MifareCommand::Increment as u8 => value.value.checked_add(operand),
+                    c if c == MifareCommand::Decrement as u8 => value.value.checked_sub(operand),
+                    _ => Some(value.value),
+                };
+                self.transfer_buffer = Some((result.ok_or(STATUS_TIMEOUT)?, value.address));
+                Ok(Vec::new())
+            }
+            (c, [block]) if c == MifareCommand::Transfer as u8 => {
+                let block = self.check_access(*block)?;
+                let (value, address) = self.transfer_buffer.take().ok_or(STATUS_TIMEOUT)?;
+                self.blocks[block] = ValueBlock { value, address }.encode();
+                Ok(Vec::new())
+            }
+            _ => Err(STATUS_TIMEOUT),
+        }
+    }
+}
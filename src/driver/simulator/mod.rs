@@ -0,0 +1,683 @@
+//! In-memory PN532 simulator
+//!
+//! [`Simulator`] implements [`Interface`] by parsing the host frames and answering them the way a
+//! PN532 would, including the ACK frames. The RF field is modeled as a list of [`Target`]s, which
+//! can be placed and removed at any time.
+//!
+//! Only the commands required by the [`Reader`](crate::driver::Reader) are supported, everything
+//! else is answered with a syntax error frame.
+//!
+//! Besides the frame based [`Interface`], the simulator can also be used as a byte stream, like the
+//! serial port of the HSU interface ([`Uart`](crate::driver::Uart)).
+
+mod desfire;
+mod iso_dep;
+mod mifare;
+mod ntag;
+mod ntag424;
+
+pub use desfire::{DesFire, DesFireApplication};
+pub use iso_dep::{Application, IsoDep};
+pub use mifare::{MifareClassic, MifareClassicSize};
+pub use ntag::{Ntag, NtagVariant};
+pub use ntag424::Ntag424;
+
+use crate::driver::protocol::{self, Interface};
+use crate::driver::requests::{CardType, Command};
+use crate::driver::Status;
+use embedded_hal_async::delay::DelayUs;
+use embedded_io::asynch::{Read, Write};
+use std::collections::VecDeque;
+use std::vec::Vec;
+
+const ACK: [u8; 6] = [0x00, 0x00, 0xFF, 0x00, 0xFF, 0x00];
+const SYNTAX_ERROR: [u8; 8] = [0x00, 0x00, 0xFF, 0x01, 0xFF, 0x7F, 0x81, 0x00];
+
+const HOST_TO_PN532: u8 = 0xD4;
+const PN532_TO_HOST: u8 = 0xD5;
+
+pub(crate) const STATUS_TIMEOUT: u8 = Status::Timeout.code();
+pub(crate) const STATUS_AUTH_ERROR: u8 = Status::MifareAuthentication.code();
+pub(crate) const STATUS_WRONG_CONTEXT: u8 = Status::WrongContext.code();
+
+/// The most targets the PN532 can handle at the same time
+const MAX_TARGETS: usize = 2;
+
+/// Longest data of `InDataExchange`, in both directions, longer data is chained
+const MAX_EXCHANGE_DATA: usize = 262;
+/// More information bit, in the target number byte and the status byte of `InDataExchange`
+const MORE_INFORMATION: u8 = 0x40;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// There is nothing to read, a real PN532 would never get ready
+    NotReady,
+    /// Transfer failure injected with [`Simulator::fail_receives`]
+    Link,
+}
+
+impl embedded_io::Error for Error {
+    fn kind(&self) -> embedded_io::ErrorKind {
+        embedded_io::ErrorKind::Other
+    }
+}
+
+/// A card in the field of the simulator
+pub enum Target {
+    Ntag(Ntag),
+    MifareClassic(MifareClassic),
+    IsoDep(IsoDep),
+}
+
+impl Target {
+    fn uid(&self) -> &[u8] {
+        match self {
+            Self::Ntag(tag) => tag.uid(),
+            Self::MifareClassic(card) => card.uid(),
+            Self::IsoDep(card) => card.uid(),
+        }
+    }
+
+    fn sens_res(&self) -> [u8; 2] {
+        match self {
+            Self::Ntag(_) => [0x00, 0x44],
+            Self::MifareClassic(card) => card.size().sens_res(),
+            Self::IsoDep(_) => [0x03, 0x44],
+        }
+    }
+
+    fn sel_res(&self) -> u8 {
+        match self {
+            Self::Ntag(_) => 0x00,
+            Self::MifareClassic(card) => card.size().sel_res(),
+            Self::IsoDep(_) => 0x20,
+        }
+    }
+
+    fn ats(&self) -> Option<&[u8]> {
+        match self {
+            Self::IsoDep(card) => Some(card.ats()),
+            _ => None,
+        }
+    }
+
+    /// Exchange data with the target, returning the answer or the PN532 status code
+    fn exchange(&mut self, data: &[u8]) -> Result<Vec<u8>, u8> {
+        match self {
+            Self::Ntag(tag) => tag.exchange(data),
+            Self::MifareClassic(card) => card.exchange(data),
+            Self::IsoDep(card) => card.exchange(data),
+        }
+    }
+
+    /// The target left the active state
+    fn deactivate(&mut self) {
+        match self {
+            Self::Ntag(tag) => tag.deactivate(),
+            Self::MifareClassic(card) => card.deactivate(),
+            Self::IsoDep(card) => card.deactivate(),
+        }
+    }
+}
+
+impl From<Ntag> for Target {
+    fn from(value: Ntag) -> Self {
+        Self::Ntag(value)
+    }
+}
+
+impl From<MifareClassic> for Target {
+    fn from(value: MifareClassic) -> Self {
+        Self::MifareClassic(value)
+    }
+}
+
+impl From<IsoDep> for Target {
+    fn from(value: IsoDep) -> Self {
+        Self::IsoDep(value)
+    }
+}
+
+/// Delay to be used with the simulator, which completes immediately
+///
+/// As the simulator answers right away, this only ends the waiting for a hanging simulator.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Delay;
+
+impl DelayUs for Delay {
+    async fn delay_us(&mut self, _us: u32) {}
+
+    async fn delay_ms(&mut self, _ms: u32) {}
+}
+
+pub struct Simulator {
+    firmware: [u8; 4],
+    /// Whether the simulator stopped answering
+    hanging: bool,
+    /// Number of commands aborted by the host
+    aborts: usize,
+    /// Whether a command has been received, and its response not been read yet
+    in_progress: bool,
+    field: Vec<Target>,
+    /// Indices into `field` of the activated targets, target number `n` is at position `n - 1`,
+    /// released targets are `None`
+    active: Vec<Option<usize>>,
+    /// Target number of the currently selected target
+    selected: Option<u8>,
+    /// Frames waiting to be read by the host
+    output: VecDeque<Vec<u8>>,
+    /// Last frame read by the host, sent again when receiving a NACK frame
+    last_output: Option<Vec<u8>>,
+    /// Number of responses still to be sent with a broken checksum
+    corrupt: usize,
+    /// Number of [`Interface::receive`] calls still to fail
+    failing: usize,
+    /// Number of NACK frames received
+    nacks: usize,
+    /// Rest of the frame being read by the host through [`Interface::receive`]
+    reading: Option<VecDeque<u8>>,
+    /// Bytes written by the host through the byte stream, not forming a complete frame yet
+    serial_input: Vec<u8>,
+    /// Bytes waiting to be read by the host through the byte stream
+    serial_output: VecDeque<u8>,
+    /// All commands received so far, with their data
+    commands: Vec<(u8, Vec<u8>)>,
+    /// Data of `InDataExchange` chained by the host, not sent to the target yet
+    chained_command: Vec<u8>,
+    /// Rest of the answer of the target, to be fetched by the host
+    chained_answer: Vec<u8>,
+}
+
+impl Default for Simulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Simulator {
+    /// Create a new simulator, reporting the firmware of a PN532 v1.6 and with an empty field
+    pub fn new() -> Self {
+        Self {
+            firmware: [0x32, 0x01, 0x06, 0x07],
+            hanging: false,
+            aborts: 0,
+            in_progress: false,
+            field: Vec::new(),
+            active: Vec::new(),
+            selected: None,
+            output: VecDeque::new(),
+            last_output: None,
+            corrupt: 0,
+            failing: 0,
+            nacks: 0,
+            reading: None,
+            serial_input: Vec::new(),
+            serial_output: VecDeque::new(),
+            commands: Vec::new(),
+            chained_command: Vec::new(),
+            chained_answer: Vec::new(),
+        }
+    }
+
+    /// Set the response to `GetFirmwareVersion`
+    pub fn with_firmware(mut self, ic: u8, version: u8, revision: u8, support: u8) -> Self {
+        self.firmware = [ic, version, revision, support];
+        self
+    }
+
+    /// Place a target in the field
+    pub fn with_target(mut self, target: impl Into<Target>) -> Self {
+        self.place(target);
+        self
+    }
+
+    /// Place a target in the field
+    pub fn place(&mut self, target: impl Into<Target>) {
+        self.field.push(target.into());
+    }
+
+    /// Remove all targets from the field, returning them
+    pub fn clear(&mut self) -> Vec<Target> {
+        // leaving the field powers the targets down
+        for target in &mut self.field {
+            target.deactivate();
+        }
+        self.active.clear();
+        self.selected = None;
+        core::mem::take(&mut self.field)
+    }
+
+    /// The targets currently in the field
+    pub fn field(&self) -> &[Target] {
+        &self.field
+    }
+
+    /// The targets currently in the field
+    pub fn field_mut(&mut self) -> &mut [Target] {
+        &mut self.field
+    }
+
+    /// Let the simulator stop answering commands, like a crashed or disconnected PN532
+    pub fn set_hanging(&mut self, hanging: bool) {
+        self.hanging = hanging;
+    }
+
+    /// Number of commands aborted by the host, using an ACK frame
+    pub fn aborts(&self) -> usize {
+        self.aborts
+    }
+
+    /// Send the next `count` responses with a broken checksum, a NACK frame gets the intact one
+    pub fn corrupt_responses(&mut self, count: usize) {
+        self.corrupt = count;
+    }
+
+    /// Let the next `count` reads through [`Interface::receive`] fail with [`Error::Link`]
+    pub fn fail_receives(&mut self, count: usize) {
+        self.failing = count;
+    }
+
+    /// Number of NACK frames received from the host
+    pub fn nacks(&self) -> usize {
+        self.nacks
+    }
+
+    /// All commands received so far, as command code and data
+    pub fn commands(&self) -> &[(u8, Vec<u8>)] {
+        &self.commands
+    }
+
+    fn target(&mut self, tg: u8) -> Option<&mut Target> {
+        let index = (*self.active.get((tg as usize).checked_sub(1)?)?)?;
+        self.field.get_mut(index)
+    }
+
+    fn process(&mut self, cmd: u8, data: &[u8]) -> Option<Vec<u8>> {
+        let response = match cmd {
+            c if c == Command::GetFirmwareVersion as u8 => self.firmware.to_vec(),
+            c if c == Command::SAMConfiguration as u8 => Vec::new(),
+            c if c == Command::SetSerialBaudRate as u8 => Vec::new(),
+            c if c == Command::InListPassiveTarget as u8 => {
+                self.in_list_passive_target(*data.first()?, *data.get(1)?)
+            }
+            c if c == Command::InDataExchange as u8 => {
+                let (&tg, data) = data.split_first()?;
+                self.in_data_exchange_chained(tg, data)
+            }
+            c if c == Command::InCommunicateThru as u8 => match self.selected {
+                Some(tg) => self.in_data_exchange(tg, data),
+                None => std::vec![STATUS_WRONG_CONTEXT],
+            },
+            c if c == Command::InSelect as u8 => {
+                let tg = *data.first()?;
+                match self.target(tg) {
+                    Some(target) => {
+                        // selecting reactivates the target, ending any authentication
+                        target.deactivate();
+                        self.selected = Some(tg);
+                        std::vec![0x00]
+                    }
+                    None => std::vec![STATUS_WRONG_CONTEXT],
+                }
+            }
+            c if c == Command::InDeselect as u8 || c == Command::InRelease as u8 => {
+                let tg = *data.first()?;
+                self.release(tg, c == Command::InRelease as u8)
+            }
+            _ => return None,
+        };
+        Some(response)
+    }
+
+    fn in_list_passive_target(&mut self, max_tg: u8, brty: u8) -> Vec<u8> {
+        for index in core::mem::take(&mut self.active).into_iter().flatten() {
+            self.field[index].deactivate();
+        }
+        self.selected = None;
+
+        if max_tg == 0 || max_tg as usize > MAX_TARGETS {
+            return std::vec![STATUS_WRONG_CONTEXT];
+        }
+
+        let mut response = std::vec![0x00];
+        if brty != CardType::IsoTypeA as u8 {
+            // only 106 kbps type A is simulated
+            return response;
+        }
+
+        for (index, target) in self.field.iter().enumerate().take(max_tg as usize) {
+            self.active.push(Some(index));
+            response.push(self.active.len() as u8);
+            response.extend_from_slice(&target.sens_res());
+            response.push(target.sel_res());
+            response.push(target.uid().len() as u8);
+            response.extend_from_slice(target.uid());
+            if let Some(ats) = target.ats() {
+                response.extend_from_slice(ats);
+            }
+        }
+        response[0] = self.active.len() as u8;
+        if !self.active.is_empty() {
+            self.selected = Some(1);
+        }
+
+        response
+    }
+
+    /// `InDataExchange`, with the data chained by the MI bit
+    fn in_data_exchange_chained(&mut self, tg: u8, data: &[u8]) -> Vec<u8> {
+        if tg & MORE_INFORMATION != 0 {
+            self.chained_command.extend_from_slice(data);
+            return std::vec![0x00];
+        }
+        // the host fetches the rest of the answer without sending any data
+        if data.is_empty() && !self.chained_answer.is_empty() {
+            let answer = core::mem::take(&mut self.chained_answer);
+            return self.chain_answer(answer);
+        }
+
+        let mut command = core::mem::take(&mut self.chained_command);
+        command.extend_from_slice(data);
+        self.chained_answer.clear();
+        let mut response = self.in_data_exchange(tg & 0x3F, &command);
+        match response[0] {
+            0x00 => {
+                let answer = response.split_off(1);
+                self.chain_answer(answer)
+            }
+            _ => response,
+        }
+    }
+
+    /// Respond with the start of `answer`, keeping the rest for the host to fetch
+    fn chain_answer(&mut self, mut answer: Vec<u8>) -> Vec<u8> {
+        if answer.len() > MAX_EXCHANGE_DATA {
+            self.chained_answer = answer.split_off(MAX_EXCHANGE_DATA);
+        }
+        let status = match self.chained_answer.is_empty() {
+            true => 0x00,
+            false => MORE_INFORMATION,
+        };
+        let mut response = std::vec![status];
+        response.extend(answer);
+        response
+    }
+
+    fn in_data_exchange(&mut self, tg: u8, data: &[u8]) -> Vec<u8> {
+        let Some(target) = self.target(tg) else {
+            return std::vec![STATUS_WRONG_CONTEXT];
+        };
+
+        let result = target.exchange(data);
+        self.selected = Some(tg);
+
+        match result {
+            Ok(answer) => {
+                let mut response = std::vec![0x00];
+                response.extend(answer);
+                response
+            }
+            Err(status) => std::vec![status],
+        }
+    }
+
+    fn release(&mut self, tg: u8, release: bool) -> Vec<u8> {
+        let numbers = match tg {
+            0 => 1..=self.active.len() as u8,
+            tg if self.target(tg).is_some() => tg..=tg,
+            _ => return std::vec![STATUS_WRONG_CONTEXT],
+        };
+
+        for tg in numbers {
+            if let Some(target) = self.target(tg) {
+                target.deactivate();
+            }
+            if release {
+                self.active[tg as usize - 1] = None;
+            }
+        }
+        self.selected = None;
+
+        std::vec![0x00]
+    }
+
+    /// Handle a frame sent by the host
+    fn receive_frame(&mut self, frame: &[u8]) {
+        let data = match parse_frame(frame) {
+            // a real PN532 silently drops broken frames
+            None => return,
+            Some(HostFrame::Ack) => {
+                // aborting the current command
+                if self.in_progress {
+                    self.aborts += 1;
+                }
+                self.in_progress = false;
+                self.output.clear();
+                self.reading = None;
+                return;
+            }
+            Some(HostFrame::Nack) => {
+                self.nacks += 1;
+                if let Some(frame) = self.last_output.take() {
+                    self.in_progress = true;
+                    self.output.push_front(frame);
+                }
+                return;
+            }
+            Some(HostFrame::Command(data)) => data,
+        };
+
+        self.in_progress = true;
+        if self.hanging {
+            return;
+        }
+
+        let (cmd, data) = (data[0], &data[1..]);
+        self.commands.push((cmd, data.to_vec()));
+        self.output.push_back(ACK.to_vec());
+
+        match self.process(cmd, data) {
+            Some(response) => self.output.push_back(build_frame(cmd + 1, &response)),
+            None => self.output.push_back(SYNTAX_ERROR.to_vec()),
+        }
+    }
+
+    /// Take the next frame to be read by the host
+    fn next_frame(&mut self) -> Option<Vec<u8>> {
+        let mut frame = self.output.pop_front()?;
+        if self.output.is_empty() {
+            self.in_progress = false;
+        }
+        self.last_output = Some(frame.clone());
+
+        if self.corrupt > 0 && frame != ACK {
+            self.corrupt -= 1;
+            // flip the DCS
+            let dcs = frame.len() - 2;
+            frame[dcs] ^= 0xFF;
+        }
+        Some(frame)
+    }
+}
+
+/// Position of `LEN` and length of TFI and data of the first frame in `data`
+///
+/// Returns `Some((start, None))` for ACK and NACK frames, and frames with a broken length.
+fn frame_header(data: &[u8]) -> Option<(usize, Option<usize>)> {
+    let start = data.windows(2).position(|w| w == [0x00, 0xFF])? + 2;
+
+    match (*data.get(start)?, *data.get(start + 1)?) {
+        // ACK and NACK frames
+        (0x00, 0xFF) | (0xFF, 0x00) => Some((start, None)),
+        // extended frame
+        (0xFF, 0xFF) => {
+            let [lenm, lenl, lcs] = [
+                *data.get(start + 2)?,
+                *data.get(start + 3)?,
+                *data.get(start + 4)?,
+            ];
+            let len = lenm.wrapping_add(lenl).wrapping_add(lcs) == 0;
+            Some((
+                start + 3,
+                len.then_some(u16::from_be_bytes([lenm, lenl]) as usize),
+            ))
+        }
+        (len, lcs) if len.wrapping_add(lcs) == 0 => Some((start, Some(len as usize))),
+        _ => Some((start, None)),
+    }
+}
+
+/// Length of the first frame in `data`, up to and including the postamble, if it is complete
+fn frame_len(data: &[u8]) -> Option<usize> {
+    match frame_header(data)? {
+        (start, Some(len)) => {
+            let end = start + 2 + len + 2;
+            (end <= data.len()).then_some(end)
+        }
+        // ACK and NACK frames, or a broken length, dropping everything up to here
+        (start, None) => Some((start + 3).min(data.len())),
+    }
+}
+
+/// Frame sent by the host
+enum HostFrame<'a> {
+    Ack,
+    Nack,
+    /// Command code and data
+    Command(&'a [u8]),
+}
+
+/// Parse a host to PN532 frame
+fn parse_frame(frame: &[u8]) -> Option<HostFrame<'_>> {
+    let (start, len) = frame_header(frame)?;
+    let Some(len) = len else {
+        return match frame[start..start + 2] {
+            [0x00, 0xFF] => Some(HostFrame::Ack),
+            [0xFF, 0x00] => Some(HostFrame::Nack),
+            _ => None,
+        };
+    };
+    if len < 2 {
+        return None;
+    }
+
+    let body = frame.get(start + 2..start + 2 + len + 1)?;
+    if body[0] != HOST_TO_PN532 || body.iter().fold(0u8, |s, b| s.wrapping_add(*b)) != 0 {
+        return None;
+    }
+
+    Some(HostFrame::Command(&body[1..len]))
+}
+
+/// Build a PN532 to host frame, an extended one if it doesn't fit into a normal frame
+fn build_frame(response: u8, data: &[u8]) -> Vec<u8> {
+    let len = data.len() + 2;
+    let sum = data
+        .iter()
+        .fold(PN532_TO_HOST.wrapping_add(response), |s, b| {
+            s.wrapping_add(*b)
+        });
+
+    let mut frame = std::vec![0x00, 0x00, 0xFF];
+    if len >= 0xFF {
+        let [lenm, lenl] = (len as u16).to_be_bytes();
+        frame.extend_from_slice(&[
+            0xFF,
+            0xFF,
+            lenm,
+            lenl,
+            lenm.wrapping_add(lenl).wrapping_neg(),
+        ]);
+    } else {
+        frame.extend_from_slice(&[len as u8, (len as u8).wrapping_neg()]);
+    }
+    frame.extend_from_slice(&[PN532_TO_HOST, response]);
+    frame.extend_from_slice(data);
+    frame.push(sum.wrapping_neg());
+    frame.push(0x00);
+    frame
+}
+
+impl Interface for Simulator {
+    type Error = Error;
+
+    async fn send(&mut self, request: &[u8]) -> Result<(), protocol::Error<Self::Error>> {
+        self.receive_frame(request);
+        Ok(())
+    }
+
+    async fn receive(&mut self, buf: &mut [u8]) -> Result<(), protocol::Error<Self::Error>> {
+        if self.failing > 0 {
+            self.failing -= 1;
+            return Err(protocol::Error::Transport(Error::Link));
+        }
+
+        let frame = match self.reading.take() {
+            Some(frame) => frame,
+            None => self
+                .next_frame()
+                .ok_or(protocol::Error::Transport(Error::NotReady))?
+                .into(),
+        };
+        let frame = self.reading.insert(frame);
+
+        // like on SPI, reading past the end of the frame yields zeros
+        buf.fill(0);
+        let len = frame.len().min(buf.len());
+        for (target, byte) in buf.iter_mut().zip(frame.drain(..len)) {
+            *target = byte;
+        }
+
+        Ok(())
+    }
+
+    async fn wait_for_ready(&mut self) -> Result<(), protocol::Error<Self::Error>> {
+        // the rest of a partially read frame is gone, once the next one gets ready
+        self.reading = None;
+
+        if self.hanging {
+            return core::future::pending().await;
+        }
+
+        match self.output.is_empty() {
+            true => Err(protocol::Error::Transport(Error::NotReady)),
+            false => Ok(()),
+        }
+    }
+}
+
+impl embedded_io::Io for Simulator {
+    type Error = Error;
+}
+
+impl Write for Simulator {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.serial_input.extend_from_slice(buf);
+
+        while let Some(len) = frame_len(&self.serial_input) {
+            let frame: Vec<u8> = self.serial_input.drain(..len).collect();
+            self.receive_frame(&frame);
+        }
+
+        Ok(buf.len())
+    }
+}
+
+impl Read for Simulator {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if self.serial_output.is_empty() {
+            if self.hanging {
+                return core::future::pending().await;
+            }
+            let frame = self.next_frame().ok_or(Error::NotReady)?;
+            self.serial_output.extend(frame);
+        }
+
+        let len = buf.len().min(self.serial_output.len());
+        for (target, byte) in buf.iter_mut().zip(self.serial_output.drain(..len)) {
+            *target = byte;
+        }
+
+        Ok(len)
+    }
+}
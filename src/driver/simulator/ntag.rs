@@ -0,0 +1,259 @@
+use super::STATUS_TIMEOUT;
+use crate::driver::requests::NTAGCommand;
+use std::vec::Vec;
+
+/// NTAG21x variants, differing in the memory size
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NtagVariant {
+    Ntag213,
+    Ntag215,
+    Ntag216,
+}
+
+impl NtagVariant {
+    /// Total number of pages, including UID and configuration pages
+    pub const fn pages(self) -> usize {
+        match self {
+            Self::Ntag213 => 45,
+            Self::Ntag215 => 135,
+            Self::Ntag216 => 231,
+        }
+    }
+
+    /// Data area size, as stored in the capability container (in multiples of 8 bytes)
+    const fn cc_size(self) -> u8 {
+        match self {
+            Self::Ntag213 => 0x12,
+            Self::Ntag215 => 0x3E,
+            Self::Ntag216 => 0x6D,
+        }
+    }
+
+    /// Response to `GET_VERSION`
+    pub const fn version(self) -> [u8; 8] {
+        let storage = match self {
+            Self::Ntag213 => 0x0F,
+            Self::Ntag215 => 0x11,
+            Self::Ntag216 => 0x13,
+        };
+        [0x00, 0x04, 0x04, 0x02, 0x01, 0x00, storage, 0x03]
+    }
+}
+
+/// A simulated NTAG21x
+pub struct Ntag {
+    variant: NtagVariant,
+    uid: [u8; 7],
+    pages: Vec<[u8; 4]>,
+    /// `PWD_AUTH` succeeded since the activation
+    authenticated: bool,
+    /// Failed `PWD_AUTH` attempts, compared against `AUTHLIM`
+    failed_auths: u8,
+    /// Originality signature, answered to `READ_SIG`
+    signature: [u8; 32],
+    /// NFC counter, 24 bit
+    counter: u32,
+    /// The NFC counter was incremented since the activation
+    counted: bool,
+    /// The NFC counter doesn't count, as on clones made from a dump
+    frozen: bool,
+}
+
+impl Ntag {
+    /// Create a tag in factory state: with a capability container and an empty NDEF message
+    pub fn new(variant: NtagVariant, uid: [u8; 7]) -> Self {
+        let mut pages = std::vec![[0u8; 4]; variant.pages()];
+
+        pages[0] = [uid[0], uid[1], uid[2], 0x88 ^ uid[0] ^ uid[1] ^ uid[2]];
+        pages[1] = [uid[3], uid[4], uid[5], uid[6]];
+        pages[2] = [uid[3] ^ uid[4] ^ uid[5] ^ uid[6], 0x48, 0x00, 0x00];
+        pages[3] = [0xE1, 0x10, variant.cc_size(), 0x00];
+        pages[4] = [0x03, 0x00, 0xFE, 0x00];
+
+        let cfg = variant.pages() - 4;
+        // CFG0: MIRROR, RFUI, MIRROR_PAGE, AUTH0
+        pages[cfg] = [0x04, 0x00, 0x00, 0xFF];
+        // PWD
+        pages[cfg + 2] = [0xFF; 4];
+
+        Self {
+            variant,
+            uid,
+            pages,
+            authenticated: false,
+            failed_auths: 0,
+            signature: [0u8; 32],
+            counter: 0,
+            counted: false,
+            frozen: false,
+        }
+    }
+
+    /// Set the originality signature, which is all zeros by default
+    pub fn with_signature(mut self, signature: [u8; 32]) -> Self {
+        self.signature = signature;
+        self
+    }
+
+    /// Set the NFC counter, which starts at zero by default
+    pub fn with_counter(mut self, counter: u32) -> Self {
+        self.counter = counter & 0xFF_FFFF;
+        self
+    }
+
+    /// Keep the NFC counter at its value, whether `NFC_CNT_EN` is set or not
+    pub fn with_frozen_counter(mut self) -> Self {
+        self.frozen = true;
+        self
+    }
+
+    /// Overwrite the memory starting at `page` with `data`
+    pub fn with_memory(mut self, page: u8, data: &[u8]) -> Self {
+        for (i, chunk) in data.chunks(4).enumerate() {
+            let page = &mut self.pages[page as usize + i];
+            page[..chunk.len()].copy_from_slice(chunk);
+        }
+        self
+    }
+
+    pub fn variant(&self) -> NtagVariant {
+        self.variant
+    }
+
+    pub fn uid(&self) -> &[u8] {
+        &self.uid
+    }
+
+    /// The content of a page
+    pub fn page(&self, page: u8) -> [u8; 4] {
+        self.pages[page as usize]
+    }
+
+    /// Failed password attempts counted so far
+    pub fn failed_auths(&self) -> u8 {
+        self.failed_auths
+    }
+
+    /// Current value of the NFC counter
+    pub fn counter(&self) -> u32 {
+        self.counter
+    }
+
+    pub(super) fn deactivate(&mut self) {
+        self.authenticated = false;
+        self.counted = false;
+    }
+
+    /// Count the first read since the activation, if `NFC_CNT_EN` is set
+    fn count_read(&mut self) {
+        let nfc_cnt_en = self.pages[self.config() + 1][0] & 0x10 != 0;
+        if nfc_cnt_en && !self.frozen && !self.counted && self.counter < 0xFF_FFFF {
+            self.counter += 1;
+        }
+        self.counted = true;
+    }
+
+    fn read_cnt(&self) -> Result<Vec<u8>, u8> {
+        let nfc_cnt_pwd_prot = self.pages[self.config() + 1][0] & 0x08 != 0;
+        if nfc_cnt_pwd_prot && !self.authenticated {
+            return Err(STATUS_TIMEOUT);
+        }
+        Ok(self.counter.to_le_bytes()[..3].to_vec())
+    }
+
+    /// Index of the CFG0 page
+    fn config(&self) -> usize {
+        self.pages.len() - 4
+    }
+
+    /// Whether `page` can be accessed without authentication
+    fn accessible(&self, page: usize, write: bool) -> bool {
+        let cfg = self.config();
+        let auth0 = self.pages[cfg][3] as usize;
+        let prot = self.pages[cfg + 1][0] & 0x80 != 0;
+        let cfglck = self.pages[cfg + 1][0] & 0x40 != 0;
+
+        if write && cfglck && (cfg..cfg + 2).contains(&page) {
+            return false;
+        }
+        self.authenticated || page < auth0 || (!write && !prot)
+    }
+
+    fn pwd_auth(&mut self, pwd: &[u8]) -> Result<Vec<u8>, u8> {
+        let cfg = self.config();
+        let authlim = self.pages[cfg + 1][0] & 0x07;
+        if authlim != 0 && self.failed_auths >= authlim {
+            return Err(STATUS_TIMEOUT);
+        }
+        if pwd != self.pages[cfg + 2] {
+            self.failed_auths += 1;
+            return Err(STATUS_TIMEOUT);
+        }
+
+        self.authenticated = true;
+        self.failed_auths = 0;
+        Ok(self.pages[cfg + 3][..2].to_vec())
+    }
+
+    /// Content of a page, as seen by the reader
+    fn read_page(&self, page: usize) -> [u8; 4] {
+        // PWD and PACK always read as zero
+        if page >= self.pages.len() - 2 {
+            [0u8; 4]
+        } else {
+            self.pages[page]
+        }
+    }
+
+    pub(super) fn exchange(&mut self, data: &[u8]) -> Result<Vec<u8>, u8> {
+        let (&cmd, args) = data.split_first().ok_or(STATUS_TIMEOUT)?;
+        let pages = self.pages.len();
+
+        match (cmd, args) {
+            (c, [page])
+                if c == NTAGCommand::Read as u8
+                    && (*page as usize) < pages
+                    && self.accessible(*page as usize, false) =>
+            {
+                self.count_read();
+                // reading past the end rolls over to page 0
+                Ok((0..4)
+                    .flat_map(|i| self.read_page((*page as usize + i) % pages))
+                    .collect())
+            }
+            (c, [page, data @ ..])
+                if (c == NTAGCommand::Write as u8 && data.len() == 4
+                    || c == NTAGCommand::CompWrite as u8 && data.len() == 16)
+                    && (3..pages).contains(&(*page as usize))
+                    && self.accessible(*page as usize, true) =>
+            {
+                // only the first 4 bytes of a compatibility write are written
+                let target = &mut self.pages[*page as usize];
+                for (target, data) in target.iter_mut().zip(data) {
+                    // the capability container is OTP, bits can only be set
+                    *target = match *page {
+                        3 => *target | *data,
+                        _ => *data,
+                    };
+                }
+                Ok(Vec::new())
+            }
+            (c, []) if c == NTAGCommand::GetVersion as u8 => Ok(self.variant.version().to_vec()),
+            (c, pwd) if c == NTAGCommand::PwdAuth as u8 && pwd.len() == 4 => self.pwd_auth(pwd),
+            (c, [0x00]) if c == NTAGCommand::ReadSig as u8 => Ok(self.signature.to_vec()),
+            (c, [0x02]) if c == NTAGCommand::ReadCnt as u8 => self.read_cnt(),
+            (c, [start, end])
+                if c == NTAGCommand::FastRead as u8
+                    && start <= end
+                    && (*end as usize) < pages
+                    && self.accessible(*start as usize, false) =>
+            {
+                self.count_read();
+                Ok((*start..=*end)
+                    .flat_map(|page| self.read_page(page as usize))
+                    .collect())
+            }
+            _ => Err(STATUS_TIMEOUT),
+        }
+    }
+}
@@ -0,0 +1,272 @@
+use super::iso_dep::Application;
+use crate::crypto::{self, Cipher, MAX_BLOCK_SIZE};
+use crate::driver::desfire::CommMode;
+use crate::driver::ntag424::{self, KEY_COUNT};
+use crate::driver::requests::DesFireCommand;
+use std::vec::Vec;
+
+const OK: u8 = 0x00;
+const ADDITIONAL_FRAME: u8 = 0xAF;
+const ILLEGAL_COMMAND: u8 = 0x1C;
+const INTEGRITY_ERROR: u8 = 0x1E;
+const NO_SUCH_KEY: u8 = 0x40;
+const LENGTH_ERROR: u8 = 0x7E;
+const AUTHENTICATION_ERROR: u8 = 0xAE;
+const BOUNDARY_ERROR: u8 = 0xBE;
+const FILE_NOT_FOUND: u8 = 0xF0;
+
+/// Most data the card answers in a frame, the rest follows in additional frames
+const MAX_FRAME: usize = 59;
+const MAC_LEN: usize = 8;
+
+/// Random number and transaction identifier of the card, the ones of NXP AN12196
+const RND_B: [u8; 16] = [
+    0xB9, 0xE2, 0xFC, 0x78, 0x9B, 0x64, 0xBF, 0x23, 0x7C, 0xCC, 0xAA, 0x20, 0xEC, 0x7E, 0x6E, 0x48,
+];
+const TI: [u8; 4] = [0x9D, 0x00, 0xC4, 0xDF];
+
+/// ISO file identifier of file number 0
+const FILE_ID_BASE: u16 = 0xE102;
+
+struct Session {
+    enc: [u8; 16],
+    mac: [u8; 16],
+    counter: u16,
+    key_no: u8,
+}
+
+impl Session {
+    fn mac(&self, code: u8, counter: u16, data: &[u8]) -> [u8; MAC_LEN] {
+        let parts = [&[code][..], &counter.to_le_bytes(), &TI, data];
+        let cmac = Cipher::aes(&self.mac).cmac_parts(&[0u8; MAX_BLOCK_SIZE], &parts);
+        crypto::truncate_mac(&cmac)
+    }
+}
+
+/// The native commands of an NTAG 424 DNA, answered by an [`IsoDep`](super::IsoDep)
+///
+/// The files are the ones of the NDEF Tag Application of the [`IsoDep`](super::IsoDep), file
+/// number 1 being `E103`. All keys are zero, and files can be read in plain, unless set up
+/// otherwise.
+pub struct Ntag424 {
+    keys: Vec<[u8; 16]>,
+    /// Communication settings and read key of the file numbers
+    access: Vec<(u8, CommMode, u8)>,
+    session: Option<Session>,
+    /// Key number of the authentication waiting for the token of the reader
+    authentication: Option<u8>,
+    /// Rest of the answer, sent in additional frames
+    pending: Vec<u8>,
+}
+
+impl Default for Ntag424 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Ntag424 {
+    pub fn new() -> Self {
+        Self {
+            keys: std::vec![[0x00; 16]; KEY_COUNT as usize],
+            access: Vec::new(),
+            session: None,
+            authentication: None,
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn with_key(mut self, key_no: u8, key: [u8; 16]) -> Self {
+        self.keys[key_no as usize] = key;
+        self
+    }
+
+    /// Require `read_key` to read file `no`, with the communication settings `comm`
+    pub fn with_file_access(mut self, no: u8, comm: CommMode, read_key: u8) -> Self {
+        self.access.push((no, comm, read_key));
+        self
+    }
+
+    pub(super) fn deactivate(&mut self) {
+        self.session = None;
+        self.authentication = None;
+        self.pending.clear();
+    }
+
+    /// Answer a native command, returning the answer followed by the `91xx` status word
+    pub(super) fn exchange(
+        &mut self,
+        ins: u8,
+        data: &[u8],
+        uid: &[u8; 7],
+        application: Option<&Application>,
+    ) -> Vec<u8> {
+        let authentication = self.authentication.take();
+        if ins == ADDITIONAL_FRAME {
+            if let Some(key_no) = authentication {
+                return self.finish_authentication(key_no, data);
+            }
+            if !self.pending.is_empty() {
+                let pending = core::mem::take(&mut self.pending);
+                return self.frames(pending);
+            }
+        }
+        self.pending.clear();
+
+        let result = match ins {
+            ins if ins == DesFireCommand::AuthenticateEv2First as u8 => {
+                return self.begin_authentication(data);
+            }
+            ins if ins == DesFireCommand::ReadDataEv2 as u8 => self.read_data(data, application),
+            ins if ins == DesFireCommand::GetCardUid as u8 => match self.session {
+                Some(_) => self
+                    .check_mac(ins, &[], data, CommMode::Full)
+                    .map(|_| (uid.to_vec(), CommMode::Full)),
+                None => Err(AUTHENTICATION_ERROR),
+            },
+            _ => Err(ILLEGAL_COMMAND),
+        };
+
+        match result {
+            Ok((answer, comm)) => {
+                let answer = self.protect(answer, comm);
+                self.frames(answer)
+            }
+            Err(code) => {
+                self.session = None;
+                std::vec![0x91, code]
+            }
+        }
+    }
+
+    fn read_data(
+        &mut self,
+        data: &[u8],
+        application: Option<&Application>,
+    ) -> Result<(Vec<u8>, CommMode), u8> {
+        let &[no, o0, o1, o2, l0, l1, l2, ref mac @ ..] = data else {
+            return Err(LENGTH_ERROR);
+        };
+        let file = application
+            .and_then(|app| app.file(FILE_ID_BASE + no as u16))
+            .ok_or(FILE_NOT_FOUND)?;
+        let comm = match self.access.iter().find(|(file, ..)| *file == no) {
+            None => CommMode::Plain,
+            Some(&(_, comm, key_no)) => match &self.session {
+                Some(session) if session.key_no == key_no => comm,
+                _ => return Err(AUTHENTICATION_ERROR),
+            },
+        };
+        self.check_mac(DesFireCommand::ReadDataEv2 as u8, &data[..7], mac, comm)?;
+
+        let offset = u32::from_le_bytes([o0, o1, o2, 0]) as usize;
+        let len = match u32::from_le_bytes([l0, l1, l2, 0]) as usize {
+            0 => file.len().saturating_sub(offset),
+            len => len,
+        };
+        let data = file.get(offset..offset + len).ok_or(BOUNDARY_ERROR)?;
+        Ok((data.to_vec(), comm))
+    }
+
+    /// Check the MAC following the header of a command, which is required by a session, unless in
+    /// plain
+    fn check_mac(&self, ins: u8, header: &[u8], mac: &[u8], comm: CommMode) -> Result<(), u8> {
+        match &self.session {
+            Some(session) if comm != CommMode::Plain => {
+                match session.mac(ins, session.counter, header) == mac {
+                    true => Ok(()),
+                    false => Err(INTEGRITY_ERROR),
+                }
+            }
+            Some(_) | None if mac.is_empty() => Ok(()),
+            _ => Err(LENGTH_ERROR),
+        }
+    }
+
+    /// MAC or encrypt the answer for the session, counting the command
+    fn protect(&mut self, mut answer: Vec<u8>, comm: CommMode) -> Vec<u8> {
+        let Some(session) = self.session.as_mut() else {
+            return answer;
+        };
+        session.counter = session.counter.wrapping_add(1);
+        if comm == CommMode::Full {
+            answer.push(0x80);
+            answer.resize(answer.len().next_multiple_of(16), 0x00);
+            let cipher = Cipher::aes(&session.enc);
+            let mut iv = [0u8; MAX_BLOCK_SIZE];
+            iv[..2].copy_from_slice(&[0x5A, 0xA5]);
+            iv[2..6].copy_from_slice(&TI);
+            iv[6..8].copy_from_slice(&session.counter.to_le_bytes());
+            cipher.encrypt_block(&mut iv);
+            cipher.encrypt_cbc(&mut iv, &mut answer);
+        }
+        if comm != CommMode::Plain {
+            let mac = session.mac(OK, session.counter, &answer);
+            answer.extend_from_slice(&mac);
+        }
+        answer
+    }
+
+    /// Answer the first frame of `answer`, keeping the rest for additional frames
+    fn frames(&mut self, mut answer: Vec<u8>) -> Vec<u8> {
+        let status = match answer.len() > MAX_FRAME {
+            true => {
+                self.pending = answer.split_off(MAX_FRAME);
+                ADDITIONAL_FRAME
+            }
+            false => OK,
+        };
+        answer.extend_from_slice(&[0x91, status]);
+        answer
+    }
+
+    fn begin_authentication(&mut self, data: &[u8]) -> Vec<u8> {
+        self.session = None;
+        // key number and capabilities of the reader
+        let Some(&key_no) = data.first() else {
+            return std::vec![0x91, LENGTH_ERROR];
+        };
+        let Some(key) = self.keys.get(key_no as usize) else {
+            return std::vec![0x91, NO_SUCH_KEY];
+        };
+
+        let mut rnd_b = RND_B;
+        Cipher::aes(key).encrypt_block(&mut rnd_b);
+        self.authentication = Some(key_no);
+        let mut answer = rnd_b.to_vec();
+        answer.extend_from_slice(&[0x91, ADDITIONAL_FRAME]);
+        answer
+    }
+
+    fn finish_authentication(&mut self, key_no: u8, token: &[u8]) -> Vec<u8> {
+        let key = &self.keys[key_no as usize];
+        let cipher = Cipher::aes(key);
+        let Ok(mut plain): Result<[u8; 32], _> = token.try_into() else {
+            return std::vec![0x91, LENGTH_ERROR];
+        };
+        cipher.decrypt_cbc(&mut [0u8; MAX_BLOCK_SIZE], &mut plain);
+        let (rnd_a, rnd_b) = plain.split_at_mut(16);
+        rnd_b.rotate_right(1);
+        if rnd_b != RND_B {
+            return std::vec![0x91, AUTHENTICATION_ERROR];
+        }
+
+        // TI, RndA rotated left, no capabilities
+        let mut answer = [0u8; 32];
+        answer[..4].copy_from_slice(&TI);
+        answer[4..20].copy_from_slice(rnd_a);
+        answer[4..20].rotate_left(1);
+        cipher.encrypt_cbc(&mut [0u8; MAX_BLOCK_SIZE], &mut answer);
+
+        let (enc, mac) = ntag424::session_keys(key, &rnd_a.try_into().unwrap(), &RND_B);
+        self.session = Some(Session {
+            enc,
+            mac,
+            counter: 0,
+            key_no,
+        });
+        let mut answer = answer.to_vec();
+        answer.extend_from_slice(&[0x91, OK]);
+        answer
+    }
+}
@@ -1,5 +1,6 @@
-use crate::driver::{protocol::Error, Interface};
-use defmt::trace;
+use crate::driver::protocol::{Error, MAX_FRAME_LEN};
+use crate::driver::{Interface, Irq, NoIrq};
+use embassy_futures::yield_now;
 use embedded_hal_async::spi::Operation;
 
 // some code from: https://github.com/WMT-GmbH/pn532/blob/master/src/spi.rs
@@ -14,25 +15,60 @@ pub const PN532_SPI_DATAREAD: u8 = as_lsb(0x03);
 /// ready indicator
 pub const PN532_SPI_READY: u8 = as_lsb(0x01);
 
-pub struct Spi<I>(pub I)
+/// SPI interface, with an optional IRQ line
+pub struct Spi<I, Q = NoIrq>
 where
-    I: embedded_hal_async::spi::SpiDevice;
+    I: embedded_hal_async::spi::SpiDevice,
+    Q: Irq,
+{
+    device: I,
+    irq: Q,
+    /// Whether the next read continues a frame which has been partially read already
+    continued: bool,
+}
+
+impl<I> Spi<I>
+where
+    I: embedded_hal_async::spi::SpiDevice,
+{
+    /// Create an interface without IRQ line, polling for readiness
+    pub fn new(device: I) -> Self {
+        Self::with_irq(device, NoIrq)
+    }
+}
+
+impl<I, Q> Spi<I, Q>
+where
+    I: embedded_hal_async::spi::SpiDevice,
+    Q: Irq,
+{
+    /// Create an interface waiting for the IRQ line before polling the status
+    pub fn with_irq(device: I, irq: Q) -> Self {
+        Self {
+            device,
+            irq,
+            continued: false,
+        }
+    }
+}
 
-impl<I> Interface for Spi<I>
+impl<I, Q> Interface for Spi<I, Q>
 where
     I: embedded_hal_async::spi::SpiDevice,
+    Q: Irq,
 {
     type Error = I::Error;
 
     async fn send(&mut self, request: &[u8]) -> Result<(), Error<Self::Error>> {
         let len = request.len();
-        let mut buf = [0u8; 255];
+        let mut buf = [0u8; MAX_FRAME_LEN + 1];
         buf[0] = PN532_SPI_DATAWRITE;
         buf[1..len + 1].copy_from_slice(request);
 
         trace!("Request: {}", &buf[0..len + 1]);
 
-        self.0
+        self.continued = false;
+        self.device
             //    .write_transaction(&[&[PN532_SPI_DATAWRITE], request])
             .write(&buf[0..len + 1])
             .await
@@ -42,19 +78,38 @@ where
     }
 
     async fn receive(&mut self, buf: &mut [u8]) -> Result<(), Error<Self::Error>> {
-        self.0
-            .transaction(&mut [
-                Operation::Write(&[PN532_SPI_DATAREAD]),
-                Operation::Read(buf),
-            ])
-            .await
-            .map_err(Error::Transport)?;
+        if buf.is_empty() {
+            return Ok(());
+        }
+
+        if self.continued {
+            // the PN532 already shifts out the next byte of the frame while receiving DATAREAD,
+            // so a continued read has to be full duplex to not lose it
+            let (first, rest) = buf.split_at_mut(1);
+            self.device
+                .transaction(&mut [
+                    Operation::Transfer(first, &[PN532_SPI_DATAREAD]),
+                    Operation::Read(rest),
+                ])
+                .await
+                .map_err(Error::Transport)?;
+        } else {
+            self.device
+                .transaction(&mut [
+                    Operation::Write(&[PN532_SPI_DATAREAD]),
+                    Operation::Read(buf),
+                ])
+                .await
+                .map_err(Error::Transport)?;
+        }
+        self.continued = true;
 
         Ok(())
     }
 
     async fn wait_for_ready(&mut self) -> Result<(), Error<Self::Error>> {
-        // FIXME: implement external interrupt
+        self.continued = false;
+        self.irq.wait_for_ready().await;
 
         let mut buf = [0u8; 1];
 
@@ -69,7 +124,7 @@ where
                 .await
                 .map_err(Error::Transport)?;
              */
-            self.0
+            self.device
                 .transaction(&mut [
                     Operation::Write(&[PN532_SPI_STATREAD]),
                     Operation::Read(&mut buf),
@@ -85,6 +140,9 @@ where
             //debug!("State: {}", buf);
 
             // Timer::after(Duration::from_millis(100)).await;
+
+            // give a pending timeout the chance to cancel us
+            yield_now().await;
         }
 
         trace!("Ready after {0} checks", cnt);
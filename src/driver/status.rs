@@ -0,0 +1,142 @@
+//! Application level status byte (UM0701-02, 7.1 Error handling)
+
+/// Error code of the status byte, which starts the response of many commands, like
+/// `InDataExchange`, `InCommunicateThru`, `InSelect` or `InRelease`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Status {
+    /// The target has not answered
+    Timeout,
+    /// CRC error detected by the CIU
+    Crc,
+    /// Parity error detected by the CIU
+    Parity,
+    /// Erroneous bit count during anti-collision or select
+    BitCount,
+    /// Framing error during a Mifare operation
+    Framing,
+    /// Abnormal bit collision during bitwise anti-collision at 106 kbps
+    Collision,
+    /// Communication buffer size insufficient
+    BufferSize,
+    /// RF buffer overflow detected by the CIU
+    RfBufferOverflow,
+    /// The RF field has not been switched on in time by the counterpart (active mode)
+    RfFieldNotOn,
+    /// RF protocol error
+    RfProtocol,
+    /// Overheating, the antenna drivers have been switched off
+    Temperature,
+    /// Internal buffer overflow
+    InternalBufferOverflow,
+    /// Invalid parameter
+    InvalidParameter,
+    /// DEP protocol: the command received from the initiator is not supported
+    DepUnsupportedCommand,
+    /// The data format doesn't match the specification (DEP, Mifare or ISO/IEC 14443-4)
+    DataFormat,
+    /// Mifare authentication error
+    MifareAuthentication,
+    /// ISO/IEC 14443-3: wrong UID check byte
+    UidCheckByte,
+    /// DEP protocol: invalid device state
+    DepInvalidState,
+    /// Operation not allowed in this configuration
+    NotAllowed,
+    /// Command not acceptable in the current context, e.g. an unknown target number
+    WrongContext,
+    /// The PN532 configured as target has been released by its initiator
+    Released,
+    /// The ID of the card doesn't match, the card has been exchanged
+    CardExchanged,
+    /// The card activated before has disappeared
+    CardDisappeared,
+    /// DEP: mismatch between the NFCID3 of initiator and target
+    Nfcid3Mismatch,
+    /// Over-current event detected
+    OverCurrent,
+    /// NAD missing in a DEP frame
+    NadMissing,
+    /// Error code not listed in the datasheet
+    Unknown(u8),
+}
+
+impl Status {
+    /// Mask of the error code in the status byte, the upper bits are the MI and NAD flags
+    pub const MASK: u8 = 0x3F;
+
+    /// Decode a status byte, `Ok` if it reports success
+    pub const fn check(status: u8) -> Result<(), Self> {
+        match Self::from_code(status & Self::MASK) {
+            None => Ok(()),
+            Some(status) => Err(status),
+        }
+    }
+
+    /// Error of an error code, `None` for success (`0x00`)
+    pub const fn from_code(code: u8) -> Option<Self> {
+        Some(match code {
+            0x00 => return None,
+            0x01 => Self::Timeout,
+            0x02 => Self::Crc,
+            0x03 => Self::Parity,
+            0x04 => Self::BitCount,
+            0x05 => Self::Framing,
+            0x06 => Self::Collision,
+            0x07 => Self::BufferSize,
+            0x09 => Self::RfBufferOverflow,
+            0x0A => Self::RfFieldNotOn,
+            0x0B => Self::RfProtocol,
+            0x0D => Self::Temperature,
+            0x0E => Self::InternalBufferOverflow,
+            0x10 => Self::InvalidParameter,
+            0x12 => Self::DepUnsupportedCommand,
+            0x13 => Self::DataFormat,
+            0x14 => Self::MifareAuthentication,
+            0x23 => Self::UidCheckByte,
+            0x25 => Self::DepInvalidState,
+            0x26 => Self::NotAllowed,
+            0x27 => Self::WrongContext,
+            0x29 => Self::Released,
+            0x2A => Self::CardExchanged,
+            0x2B => Self::CardDisappeared,
+            0x2C => Self::Nfcid3Mismatch,
+            0x2D => Self::OverCurrent,
+            0x2E => Self::NadMissing,
+            code => Self::Unknown(code),
+        })
+    }
+
+    /// The error code of the status
+    pub const fn code(&self) -> u8 {
+        match self {
+            Self::Timeout => 0x01,
+            Self::Crc => 0x02,
+            Self::Parity => 0x03,
+            Self::BitCount => 0x04,
+            Self::Framing => 0x05,
+            Self::Collision => 0x06,
+            Self::BufferSize => 0x07,
+            Self::RfBufferOverflow => 0x09,
+            Self::RfFieldNotOn => 0x0A,
+            Self::RfProtocol => 0x0B,
+            Self::Temperature => 0x0D,
+            Self::InternalBufferOverflow => 0x0E,
+            Self::InvalidParameter => 0x10,
+            Self::DepUnsupportedCommand => 0x12,
+            Self::DataFormat => 0x13,
+            Self::MifareAuthentication => 0x14,
+            Self::UidCheckByte => 0x23,
+            Self::DepInvalidState => 0x25,
+            Self::NotAllowed => 0x26,
+            Self::WrongContext => 0x27,
+            Self::Released => 0x29,
+            Self::CardExchanged => 0x2A,
+            Self::CardDisappeared => 0x2B,
+            Self::Nfcid3Mismatch => 0x2C,
+            Self::OverCurrent => 0x2D,
+            Self::NadMissing => 0x2E,
+            Self::Unknown(code) => *code,
+        }
+    }
+}
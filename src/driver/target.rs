@@ -0,0 +1,343 @@
+//! Targets found by `InListPassiveTarget`
+
+use crate::driver::requests::CardType;
+use core::fmt;
+use core::hash::{Hash, Hasher};
+
+/// Longest ATS stored, longer ones are rejected
+pub const MAX_ATS_LEN: usize = 64;
+
+/// Longest ATTRIB_RES stored, longer ones are rejected
+pub const MAX_ATTRIB_RES_LEN: usize = 32;
+
+/// Up to `N` bytes, stored inline
+#[derive(Clone, Copy)]
+pub struct Bytes<const N: usize> {
+    data: [u8; N],
+    len: u8,
+}
+
+impl<const N: usize> Bytes<N> {
+    /// Copy `bytes`, `None` if there are more than `N` of them
+    pub fn new(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() > N || bytes.len() > u8::MAX as usize {
+            return None;
+        }
+        let mut data = [0u8; N];
+        data[..bytes.len()].copy_from_slice(bytes);
+        Some(Self {
+            data,
+            len: bytes.len() as u8,
+        })
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data[..self.len as usize]
+    }
+}
+
+impl<const N: usize> core::ops::Deref for Bytes<N> {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        self.as_bytes()
+    }
+}
+
+impl<const N: usize> PartialEq for Bytes<N> {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl<const N: usize> Eq for Bytes<N> {}
+
+impl<const N: usize> Hash for Bytes<N> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_bytes().hash(state)
+    }
+}
+
+impl<const N: usize> fmt::Debug for Bytes<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02X?}", self.as_bytes())
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<const N: usize> defmt::Format for Bytes<N> {
+    fn format(&self, fmt: defmt::Formatter) {
+        defmt::write!(fmt, "{:X}", self.as_bytes())
+    }
+}
+
+/// Unique identifier of a card: 4, 7 or 10 bytes for ISO/IEC 14443 type A, the PUPI for type B,
+/// the IDm for FeliCa and the JEWELID for Jewel
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CardUid(Bytes<{ CardUid::MAX_LEN }>);
+
+impl CardUid {
+    pub const MAX_LEN: usize = 10;
+
+    /// `None` if `uid` is longer than [`Self::MAX_LEN`]
+    pub fn new(uid: &[u8]) -> Option<Self> {
+        Bytes::new(uid).map(Self)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.as_bytes()
+    }
+}
+
+impl AsRef<[u8]> for CardUid {
+    fn as_ref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl fmt::Debug for CardUid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CardUid({:?})", self.0)
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for CardUid {
+    fn format(&self, fmt: defmt::Formatter) {
+        defmt::write!(fmt, "{:X}", self.as_bytes())
+    }
+}
+
+/// 106 kbps type A target (ISO/IEC 14443 type A)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct IsoTypeATarget {
+    /// Logical target number assigned by the PN532
+    pub tg: u8,
+    /// SENS_RES, as sent by the PN532 (most significant byte first)
+    pub atqa: [u8; 2],
+    /// SEL_RES
+    pub sak: u8,
+    pub uid: CardUid,
+    /// Answer to select of ISO/IEC 14443-4 compliant targets, including the length byte
+    pub ats: Option<Bytes<MAX_ATS_LEN>>,
+}
+
+impl IsoTypeATarget {
+    pub fn atqa(&self) -> u16 {
+        u16::from_be_bytes(self.atqa)
+    }
+
+    /// Whether the target supports ISO/IEC 14443-4
+    pub fn supports_iso14443_4(&self) -> bool {
+        self.sak & 0x20 != 0
+    }
+}
+
+/// 106 kbps type B target (ISO/IEC 14443-3B)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct IsoTypeBTarget {
+    /// Logical target number assigned by the PN532
+    pub tg: u8,
+    pub atqb: [u8; 12],
+    pub attrib_res: Bytes<MAX_ATTRIB_RES_LEN>,
+}
+
+impl IsoTypeBTarget {
+    /// Pseudo-unique PICC identifier
+    pub fn pupi(&self) -> [u8; 4] {
+        [self.atqb[1], self.atqb[2], self.atqb[3], self.atqb[4]]
+    }
+}
+
+/// FeliCa target, polled at 212 or 424 kbps
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FeliCaTarget {
+    /// Logical target number assigned by the PN532
+    pub tg: u8,
+    /// Manufacture ID, NFCID2
+    pub idm: [u8; 8],
+    /// Manufacture parameter
+    pub pmm: [u8; 8],
+    /// Only present if requested when polling
+    pub system_code: Option<[u8; 2]>,
+}
+
+/// Innovision Jewel target
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct JewelTarget {
+    /// Logical target number assigned by the PN532
+    pub tg: u8,
+    pub sens_res: [u8; 2],
+    pub jewel_id: [u8; 4],
+}
+
+/// Target data of a single target, as returned by `InListPassiveTarget`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TargetInfo {
+    IsoTypeA(IsoTypeATarget),
+    IsoTypeB(IsoTypeBTarget),
+    FeliCa(FeliCaTarget),
+    Jewel(JewelTarget),
+}
+
+impl TargetInfo {
+    /// Parse the data of the first target in `data`, returning it and the remaining data
+    ///
+    /// The format depends on the `card_type` the targets have been listed for.
+    pub fn parse(card_type: CardType, data: &[u8]) -> Option<(Self, &[u8])> {
+        let (&tg, data) = data.split_first()?;
+
+        match card_type {
+            CardType::IsoTypeA => {
+                let (atqa, data) = take::<2>(data)?;
+                let (&[sak], data) = take::<1>(data)?;
+                let (&len, data) = data.split_first()?;
+                let (uid, mut data) = split(data, len as usize)?;
+
+                let mut ats = None;
+                if sak & 0x20 != 0 && !data.is_empty() {
+                    // the length byte is part of the ATS
+                    let (bytes, rest) = split(data, data[0] as usize)?;
+                    ats = Some(Bytes::new(bytes)?);
+                    data = rest;
+                }
+
+                let target = IsoTypeATarget {
+                    tg,
+                    atqa: *atqa,
+                    sak,
+                    uid: CardUid::new(uid)?,
+                    ats,
+                };
+                Some((Self::IsoTypeA(target), data))
+            }
+            CardType::IsoTypeB => {
+                let (atqb, data) = take::<12>(data)?;
+                let (&len, data) = data.split_first()?;
+                let (attrib_res, data) = split(data, len as usize)?;
+
+                let target = IsoTypeBTarget {
+                    tg,
+                    atqb: *atqb,
+                    attrib_res: Bytes::new(attrib_res)?,
+                };
+                Some((Self::IsoTypeB(target), data))
+            }
+            CardType::FeliCa212kbps | CardType::FeliCa424kbps => {
+                // POL_RES length includes the length byte itself
+                let (&len, data) = data.split_first()?;
+                let (pol_res, data) = split(data, (len as usize).checked_sub(1)?)?;
+                let (&[0x01], pol_res) = take::<1>(pol_res)? else {
+                    return None;
+                };
+                let (idm, pol_res) = take::<8>(pol_res)?;
+                let (pmm, pol_res) = take::<8>(pol_res)?;
+                let system_code = take::<2>(pol_res).map(|(code, _)| *code);
+
+                let target = FeliCaTarget {
+                    tg,
+                    idm: *idm,
+                    pmm: *pmm,
+                    system_code,
+                };
+                Some((Self::FeliCa(target), data))
+            }
+            CardType::Jewel => {
+                let (sens_res, data) = take::<2>(data)?;
+                let (jewel_id, data) = take::<4>(data)?;
+
+                let target = JewelTarget {
+                    tg,
+                    sens_res: *sens_res,
+                    jewel_id: *jewel_id,
+                };
+                Some((Self::Jewel(target), data))
+            }
+        }
+    }
+
+    /// Logical target number assigned by the PN532
+    pub fn tg(&self) -> u8 {
+        match self {
+            Self::IsoTypeA(target) => target.tg,
+            Self::IsoTypeB(target) => target.tg,
+            Self::FeliCa(target) => target.tg,
+            Self::Jewel(target) => target.tg,
+        }
+    }
+
+    /// Identifier of the target
+    pub fn uid(&self) -> CardUid {
+        // all of them fit into a `CardUid`
+        let uid = match self {
+            Self::IsoTypeA(target) => return target.uid,
+            Self::IsoTypeB(target) => CardUid::new(&target.pupi()),
+            Self::FeliCa(target) => CardUid::new(&target.idm),
+            Self::Jewel(target) => CardUid::new(&target.jewel_id),
+        };
+        uid.unwrap()
+    }
+}
+
+fn split(data: &[u8], len: usize) -> Option<(&[u8], &[u8])> {
+    (len <= data.len()).then(|| data.split_at(len))
+}
+
+fn take<const N: usize>(data: &[u8]) -> Option<(&[u8; N], &[u8])> {
+    let (head, rest) = split(data, N)?;
+    Some((head.try_into().ok()?, rest))
+}
+
+/// The most targets the PN532 handles at the same time
+pub const MAX_TARGETS: usize = 2;
+
+/// Targets found by a single `InListPassiveTarget`, in the order of their target numbers
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Targets {
+    targets: [Option<TargetInfo>; MAX_TARGETS],
+}
+
+impl Targets {
+    /// Parse the response of `InListPassiveTarget`, starting with the number of targets
+    pub fn parse(card_type: CardType, data: &[u8]) -> Option<Self> {
+        let (&count, mut data) = data.split_first()?;
+        if count as usize > MAX_TARGETS {
+            return None;
+        }
+
+        let mut targets = Self::default();
+        for slot in targets.targets.iter_mut().take(count as usize) {
+            let (target, rest) = TargetInfo::parse(card_type, data)?;
+            *slot = Some(target);
+            data = rest;
+        }
+        Some(targets)
+    }
+
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.targets[0].is_none()
+    }
+
+    pub fn first(&self) -> Option<&TargetInfo> {
+        self.targets[0].as_ref()
+    }
+
+    /// The target with target number `tg`
+    pub fn get(&self, tg: u8) -> Option<&TargetInfo> {
+        self.iter().find(|target| target.tg() == tg)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &TargetInfo> {
+        self.targets.iter().flatten()
+    }
+}
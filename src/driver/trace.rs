@@ -0,0 +1,187 @@
+//! Frame level record and replay of [`Interface`] exchanges
+//!
+//! The [`Recorder`] wraps an interface and logs every exchange into a buffer. [`Replay`] serves
+//! such a trace back to the [`Protocol`](super::protocol::Protocol), and panics as soon as the host
+//! diverges from the recorded exchange.
+//!
+//! A trace is a sequence of entries `kind len_lo len_hi data...`. `kind` is one of [`SEND`],
+//! [`RECEIVE`] and [`READY`], with [`FAILED`] set if the operation returned an error. Send entries
+//! carry the frame written by the host, receive entries the bytes read from the PN532, ready
+//! entries no data.
+
+use crate::driver::protocol::{Error, Interface};
+
+pub const SEND: u8 = 0x01;
+pub const RECEIVE: u8 = 0x02;
+pub const READY: u8 = 0x03;
+/// Flag marking a failed operation
+pub const FAILED: u8 = 0x80;
+
+const HEADER_LEN: usize = 3;
+
+/// Records all exchanges with the wrapped interface
+pub struct Recorder<'t, I: Interface> {
+    interface: I,
+    trace: &'t mut [u8],
+    len: usize,
+    truncated: bool,
+}
+
+impl<'t, I: Interface> Recorder<'t, I> {
+    pub fn new(interface: I, trace: &'t mut [u8]) -> Self {
+        Self {
+            interface,
+            trace,
+            len: 0,
+            truncated: false,
+        }
+    }
+
+    /// The trace recorded so far
+    pub fn trace(&self) -> &[u8] {
+        &self.trace[..self.len]
+    }
+
+    /// Whether entries got dropped, because the trace buffer was full
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    pub fn into_inner(self) -> I {
+        self.interface
+    }
+
+    fn record(&mut self, kind: u8, data: &[u8], ok: bool) {
+        if self.truncated {
+            return;
+        }
+
+        let end = self.len + HEADER_LEN + data.len();
+        if end > self.trace.len() || data.len() > u16::MAX as usize {
+            warn!("Trace buffer full, stop recording");
+            self.truncated = true;
+            return;
+        }
+
+        let kind = match ok {
+            true => kind,
+            false => kind | FAILED,
+        };
+        let [lo, hi] = (data.len() as u16).to_le_bytes();
+        self.trace[self.len..self.len + HEADER_LEN].copy_from_slice(&[kind, lo, hi]);
+        self.trace[self.len + HEADER_LEN..end].copy_from_slice(data);
+        self.len = end;
+    }
+}
+
+impl<I: Interface> Interface for Recorder<'_, I> {
+    type Error = I::Error;
+
+    async fn send(&mut self, request: &[u8]) -> Result<(), Error<Self::Error>> {
+        let result = self.interface.send(request).await;
+        self.record(SEND, request, result.is_ok());
+        result
+    }
+
+    async fn receive(&mut self, buf: &mut [u8]) -> Result<(), Error<Self::Error>> {
+        let result = self.interface.receive(buf).await;
+        match result {
+            Ok(()) => self.record(RECEIVE, buf, true),
+            Err(_) => self.record(RECEIVE, &[], false),
+        }
+        result
+    }
+
+    async fn wait_for_ready(&mut self) -> Result<(), Error<Self::Error>> {
+        let result = self.interface.wait_for_ready().await;
+        self.record(READY, &[], result.is_ok());
+        result
+    }
+}
+
+/// Transport error of a [`Replay`], returned where the recorded operation failed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Failed;
+
+/// Serves a recorded trace back to the host
+pub struct Replay<'t> {
+    trace: &'t [u8],
+    position: usize,
+}
+
+impl<'t> Replay<'t> {
+    pub fn new(trace: &'t [u8]) -> Self {
+        Self { trace, position: 0 }
+    }
+
+    /// Whether the whole trace has been replayed
+    pub fn is_done(&self) -> bool {
+        self.position >= self.trace.len()
+    }
+
+    /// Assert that the whole trace has been replayed
+    pub fn done(&self) {
+        assert!(
+            self.is_done(),
+            "trace not fully replayed, stopped at offset {} of {}",
+            self.position,
+            self.trace.len()
+        );
+    }
+
+    fn next(&mut self, kind: u8) -> Result<&'t [u8], Error<Failed>> {
+        let position = self.position;
+        let Some(&[recorded, lo, hi]) = self.trace.get(position..position + HEADER_LEN) else {
+            panic!("trace exhausted at offset {position}, expected entry of kind {kind:#04X}");
+        };
+        if recorded & !FAILED != kind {
+            panic!("trace diverged at offset {position}: recorded kind {recorded:#04X}, got {kind:#04X}");
+        }
+
+        let start = position + HEADER_LEN;
+        let end = start + u16::from_le_bytes([lo, hi]) as usize;
+        let Some(data) = self.trace.get(start..end) else {
+            panic!("trace truncated at offset {position}");
+        };
+        self.position = end;
+
+        match recorded & FAILED {
+            0 => Ok(data),
+            _ => Err(Error::Transport(Failed)),
+        }
+    }
+}
+
+impl Interface for Replay<'_> {
+    type Error = Failed;
+
+    async fn send(&mut self, request: &[u8]) -> Result<(), Error<Self::Error>> {
+        let position = self.position;
+        let recorded = self.next(SEND)?;
+        if recorded != request {
+            panic!(
+                "trace diverged at offset {position}: recorded send {recorded:02X?}, got {request:02X?}"
+            );
+        }
+        Ok(())
+    }
+
+    async fn receive(&mut self, buf: &mut [u8]) -> Result<(), Error<Self::Error>> {
+        let position = self.position;
+        let recorded = self.next(RECEIVE)?;
+        if recorded.len() != buf.len() {
+            panic!(
+                "trace diverged at offset {position}: recorded receive of {} bytes, got {}",
+                recorded.len(),
+                buf.len()
+            );
+        }
+        buf.copy_from_slice(recorded);
+        Ok(())
+    }
+
+    async fn wait_for_ready(&mut self) -> Result<(), Error<Self::Error>> {
+        self.next(READY)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,119 @@
+use crate::driver::requests::Command;
+use crate::driver::{protocol::Error, Interface};
+use embedded_io::asynch::{Read, Write};
+
+/// Wakeup preamble, waking up the PN532 from power down, must precede the first frame
+const WAKEUP: [u8; 16] = [
+    0x55, 0x55, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+#[derive(Debug)]
+pub enum UartError<E> {
+    Serial(E),
+    /// The serial port got closed
+    Closed,
+}
+
+/// High speed UART (HSU) interface
+///
+/// There is no status byte in HSU mode, readiness is signaled by the first byte of a frame
+/// arriving. The protocol reads frames exactly up to their postamble, as reading any further
+/// would block.
+pub struct Uart<S>
+where
+    S: Read + Write,
+{
+    serial: S,
+    /// Whether the PN532 needs to be woken up before the next frame
+    asleep: bool,
+    /// The byte which signaled readiness
+    peeked: Option<u8>,
+}
+
+impl<S> Uart<S>
+where
+    S: Read + Write,
+{
+    /// Create a new interface, assuming the PN532 still needs to be woken up
+    pub fn new(serial: S) -> Self {
+        Self {
+            serial,
+            asleep: true,
+            peeked: None,
+        }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.serial
+    }
+
+    async fn read_byte(&mut self) -> Result<u8, Error<UartError<S::Error>>> {
+        if let Some(byte) = self.peeked.take() {
+            return Ok(byte);
+        }
+
+        let mut buf = [0u8; 1];
+        match self.serial.read(&mut buf).await {
+            Ok(0) => Err(Error::Transport(UartError::Closed)),
+            Ok(_) => Ok(buf[0]),
+            Err(err) => Err(Error::Transport(UartError::Serial(err))),
+        }
+    }
+
+    async fn write_all(&mut self, data: &[u8]) -> Result<(), Error<UartError<S::Error>>> {
+        self.serial
+            .write_all(data)
+            .await
+            .map_err(|err| Error::Transport(UartError::Serial(err)))
+    }
+}
+
+impl<S> Interface for Uart<S>
+where
+    S: Read + Write,
+{
+    type Error = UartError<S::Error>;
+
+    async fn send(&mut self, request: &[u8]) -> Result<(), Error<Self::Error>> {
+        if self.asleep {
+            trace!("Sending wakeup preamble");
+            self.write_all(&WAKEUP).await?;
+            self.asleep = false;
+        }
+
+        self.write_all(request).await?;
+        self.serial
+            .flush()
+            .await
+            .map_err(|err| Error::Transport(UartError::Serial(err)))?;
+
+        // the command follows the TFI, after a normal or an extended header
+        let cmd = match request.get(3..5) {
+            Some([0xFF, 0xFF]) => request.get(9),
+            _ => request.get(6),
+        };
+        if cmd == Some(&(Command::PowerDown as u8)) {
+            // need to wake it up again, before the next command
+            self.asleep = true;
+        }
+
+        Ok(())
+    }
+
+    async fn receive(&mut self, buf: &mut [u8]) -> Result<(), Error<Self::Error>> {
+        for target in buf.iter_mut() {
+            *target = self.read_byte().await?;
+        }
+
+        Ok(())
+    }
+
+    async fn wait_for_ready(&mut self) -> Result<(), Error<Self::Error>> {
+        if self.peeked.is_none() {
+            let byte = self.read_byte().await?;
+            self.peeked = Some(byte);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,19 @@
+#![cfg_attr(not(test), no_std)]
+#![feature(async_fn_in_trait)]
+#![allow(incomplete_features)]
+
+#[cfg(feature = "simulator")]
+extern crate std;
+
+mod fmt;
+
+pub mod crypto;
+pub mod driver;
+pub mod mad;
+pub mod ndef;
+pub mod originality;
+pub mod reader;
+pub mod replay;
+pub mod sun;
+pub mod type2;
+pub mod type4;
@@ -0,0 +1,320 @@
+//! Mifare Application Directory (NXP AN10787) and NDEF on Mifare Classic
+//!
+//! The MAD in sector 0 (and sector 16 for MAD v2 on the 4K) assigns an application ID to every
+//! other sector. Sectors with the NFC Forum AID hold the NDEF data, a TLV area spanning their
+//! data blocks in order, like the data area of a Type 2 Tag. MAD and NDEF sectors are read with
+//! public keys A.
+
+use crate::driver::mifare::{self, ClassicError, Key, KeyType, BLOCK_SIZE};
+use crate::driver::protocol::Interface;
+use crate::driver::{CardKind, CardUid, Reader};
+use embedded_hal_async::delay::DelayUs;
+
+/// Public key A of the MAD sectors
+pub const MAD_KEY_A: [u8; 6] = [0xA0, 0xA1, 0xA2, 0xA3, 0xA4, 0xA5];
+/// Public key A of the NDEF sectors
+pub const NDEF_KEY_A: [u8; 6] = [0xD3, 0xF7, 0xD3, 0xF7, 0xD3, 0xF7];
+
+/// Application ID of NFC Forum sectors holding NDEF data
+pub const NDEF_AID: u16 = 0xE103;
+/// Application ID of free sectors
+pub const FREE_AID: u16 = 0x0000;
+
+/// Sector of MAD v2, only on the 4K
+const MAD2_SECTOR: u8 = 16;
+/// Sectors covered by MAD v1 and v2
+const MAX_SECTORS: usize = 40;
+
+/// General purpose byte: the MAD is available
+const GPB_DA: u8 = 0x80;
+/// General purpose byte: version of the MAD
+const GPB_ADV: u8 = 0x03;
+
+const CRC_PRESET: u8 = 0xC7;
+const CRC_POLYNOMIAL: u8 = 0x1D;
+
+const TLV_NULL: u8 = 0x00;
+const TLV_NDEF_MESSAGE: u8 = 0x03;
+const TLV_TERMINATOR: u8 = 0xFE;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The CRC of the MAD sector doesn't match its content
+    Crc {
+        sector: u8,
+        expected: u8,
+        actual: u8,
+    },
+    /// MAD version other than 1 or 2
+    UnsupportedVersion(u8),
+}
+
+/// Error of [`read_mad`] and [`read_ndef`]
+#[derive(Debug)]
+pub enum ReadError<E> {
+    Classic(ClassicError<E>),
+    Mad(Error),
+}
+
+#[cfg(feature = "defmt")]
+impl<E> defmt::Format for ReadError<E> {
+    fn format(&self, fmt: defmt::Formatter) {
+        match self {
+            Self::Classic(err) => defmt::write!(fmt, "{}", err),
+            Self::Mad(err) => defmt::write!(fmt, "MAD error: {}", err),
+        }
+    }
+}
+
+impl<E> From<ClassicError<E>> for ReadError<E> {
+    fn from(value: ClassicError<E>) -> Self {
+        Self::Classic(value)
+    }
+}
+
+impl<E> From<crate::driver::ReadError<E>> for ReadError<E> {
+    fn from(value: crate::driver::ReadError<E>) -> Self {
+        Self::Classic(value.into())
+    }
+}
+
+impl<E> From<Error> for ReadError<E> {
+    fn from(value: Error) -> Self {
+        Self::Mad(value)
+    }
+}
+
+/// CRC-8 of a MAD sector, over the info byte and the application IDs
+pub fn crc(data: &[u8]) -> u8 {
+    data.iter().fold(CRC_PRESET, |crc, &byte| {
+        (0..8).fold(crc ^ byte, |crc, _| match crc & 0x80 {
+            0 => crc << 1,
+            _ => crc << 1 ^ CRC_POLYNOMIAL,
+        })
+    })
+}
+
+/// The application IDs of the sectors, as listed in MAD v1 and v2
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Mad {
+    /// Application IDs by sector, the MAD sectors themselves are [`FREE_AID`]
+    aids: [u16; MAX_SECTORS],
+    /// Sectors listed, 16 for MAD v1 and 40 for MAD v2
+    sectors: u8,
+    /// Info byte of MAD v1, pointing to the card publisher sector
+    info: u8,
+}
+
+impl Mad {
+    /// A MAD v1 with all sectors free
+    pub const fn new() -> Self {
+        Self {
+            aids: [FREE_AID; MAX_SECTORS],
+            sectors: 16,
+            info: 0,
+        }
+    }
+
+    /// Parse blocks 1 and 2 of sector 0
+    pub fn parse(sector0: &[u8; 2 * BLOCK_SIZE]) -> Result<Self, Error> {
+        let mut mad = Self::new();
+        mad.info = sector0[1] & 0x3F;
+        mad.parse_sector(0, sector0, 1)?;
+        Ok(mad)
+    }
+
+    /// Add the MAD v2 of sector 16, blocks 64 to 66
+    pub fn parse_v2(&mut self, sector16: &[u8; 3 * BLOCK_SIZE]) -> Result<(), Error> {
+        self.sectors = MAX_SECTORS as u8;
+        self.parse_sector(MAD2_SECTOR, sector16, MAD2_SECTOR as usize + 1)
+    }
+
+    fn parse_sector(&mut self, sector: u8, data: &[u8], first: usize) -> Result<(), Error> {
+        let actual = crc(&data[1..]);
+        if data[0] != actual {
+            return Err(Error::Crc {
+                sector,
+                expected: data[0],
+                actual,
+            });
+        }
+        for (i, aid) in data[2..].chunks(2).enumerate() {
+            self.aids[first + i] = u16::from_le_bytes([aid[0], aid[1]]);
+        }
+        Ok(())
+    }
+
+    /// Encode blocks 1 and 2 of sector 0
+    pub fn encode(&self) -> [u8; 2 * BLOCK_SIZE] {
+        let mut data = [0u8; 2 * BLOCK_SIZE];
+        data[1] = self.info;
+        self.encode_sector(&mut data, 1);
+        data
+    }
+
+    /// Encode blocks 64 to 66 of sector 16, the info byte is left zero
+    pub fn encode_v2(&self) -> [u8; 3 * BLOCK_SIZE] {
+        let mut data = [0u8; 3 * BLOCK_SIZE];
+        self.encode_sector(&mut data, MAD2_SECTOR as usize + 1);
+        data
+    }
+
+    fn encode_sector(&self, data: &mut [u8], first: usize) {
+        for (i, aid) in data[2..].chunks_mut(2).enumerate() {
+            aid.copy_from_slice(&self.aids[first + i].to_le_bytes());
+        }
+        data[0] = crc(&data[1..]);
+    }
+
+    /// Number of sectors listed, including the MAD sectors
+    pub fn sectors(&self) -> u8 {
+        self.sectors
+    }
+
+    /// Application ID of `sector`, `None` if not listed
+    pub fn aid(&self, sector: u8) -> Option<u16> {
+        match sector < self.sectors && sector != 0 && sector != MAD2_SECTOR {
+            true => Some(self.aids[sector as usize]),
+            false => None,
+        }
+    }
+
+    /// Assign `aid` to `sector`, turning the MAD into v2 for sectors above 15
+    ///
+    /// `false` if `sector` is a MAD sector or doesn't exist.
+    pub fn set_aid(&mut self, sector: u8, aid: u16) -> bool {
+        if sector == 0 || sector == MAD2_SECTOR || sector as usize >= MAX_SECTORS {
+            return false;
+        }
+        if sector >= MAD2_SECTOR {
+            self.sectors = MAX_SECTORS as u8;
+        }
+        self.aids[sector as usize] = aid;
+        true
+    }
+
+    /// The sectors assigned to `aid`, in order
+    pub fn sectors_of(&self, aid: u16) -> impl Iterator<Item = u8> + '_ {
+        (1..self.sectors).filter(move |&sector| self.aid(sector) == Some(aid))
+    }
+}
+
+impl Default for Mad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The NDEF message TLV in a TLV area, including type and length
+///
+/// `None` if there is none, or it isn't complete yet.
+pub fn ndef_tlv(area: &[u8]) -> Option<&[u8]> {
+    let mut offset = 0;
+    loop {
+        let tag = *area.get(offset)?;
+        match tag {
+            TLV_NULL => offset += 1,
+            TLV_TERMINATOR => return None,
+            _ => {
+                let (header, len) = match *area.get(offset + 1)? {
+                    0xFF => {
+                        let len = area.get(offset + 2..offset + 4)?;
+                        (4, u16::from_be_bytes([len[0], len[1]]) as usize)
+                    }
+                    len => (2, len as usize),
+                };
+                let tlv = area.get(offset..offset + header + len)?;
+                if tag == TLV_NDEF_MESSAGE {
+                    return Some(tlv);
+                }
+                offset += tlv.len();
+            }
+        }
+    }
+}
+
+/// Read the MAD of a Mifare Classic, `None` if the card has none
+///
+/// Reading the MAD authenticates its sectors, a card without a MAD rejects the key and has to be
+/// activated again.
+pub async fn read_mad<I: Interface, T: DelayUs>(
+    reader: &mut Reader<I, T>,
+    tg: u8,
+    uid: &CardUid,
+    kind: CardKind,
+) -> Result<Option<Mad>, ReadError<I::Error>> {
+    let key = Key {
+        key_type: KeyType::A,
+        key: MAD_KEY_A,
+    };
+    match reader.mifare_authenticate(tg, 0, &key, uid).await {
+        Ok(()) => {}
+        Err(ClassicError::Authentication(_)) => return Ok(None),
+        Err(err) => return Err(err.into()),
+    }
+
+    let trailer = reader.mifare_read_trailer(tg, 0).await?;
+    if trailer.gpb & GPB_DA == 0 {
+        return Ok(None);
+    }
+    let version = trailer.gpb & GPB_ADV;
+    if !(1..=2).contains(&version) {
+        return Err(Error::UnsupportedVersion(version).into());
+    }
+
+    let mut sector0 = [0u8; 2 * BLOCK_SIZE];
+    for (i, block) in sector0.chunks_mut(BLOCK_SIZE).enumerate() {
+        block.copy_from_slice(&reader.mifare_read(tg, 1 + i as u8).await?);
+    }
+    let mut mad = Mad::parse(&sector0)?;
+    debug!("MAD v{}: {}", version, mad);
+
+    if version == 2 && kind == CardKind::MifareClassic4K {
+        let first = mifare::first_block(MAD2_SECTOR);
+        reader.mifare_authenticate(tg, first, &key, uid).await?;
+        let mut sector16 = [0u8; 3 * BLOCK_SIZE];
+        for (i, block) in sector16.chunks_mut(BLOCK_SIZE).enumerate() {
+            block.copy_from_slice(&reader.mifare_read(tg, first + i as u8).await?);
+        }
+        mad.parse_v2(&sector16)?;
+    }
+    Ok(Some(mad))
+}
+
+/// Read the NDEF sectors listed in `mad` into `buf`, returning the NDEF message TLV
+///
+/// Reading stops once the message is complete, or `buf` is full. `None` if there is no message,
+/// or it doesn't fit into `buf`.
+pub async fn read_ndef<'b, I: Interface, T: DelayUs>(
+    reader: &mut Reader<I, T>,
+    tg: u8,
+    uid: &CardUid,
+    mad: &Mad,
+    buf: &'b mut [u8],
+) -> Result<Option<&'b [u8]>, ReadError<I::Error>> {
+    let key = Key {
+        key_type: KeyType::A,
+        key: NDEF_KEY_A,
+    };
+
+    let mut len = 0;
+    for sector in mad.sectors_of(NDEF_AID) {
+        let first = mifare::first_block(sector);
+        reader.mifare_authenticate(tg, first, &key, uid).await?;
+        for block in first..mifare::trailer_block(sector) {
+            let Some(chunk) = buf.get_mut(len..len + BLOCK_SIZE) else {
+                return Ok(None);
+            };
+            debug!("Read block: {}", block);
+            chunk.copy_from_slice(&reader.mifare_read(tg, block).await?);
+            len += BLOCK_SIZE;
+        }
+        if ndef_tlv(&buf[..len]).is_some() {
+            break;
+        }
+    }
+    let buf: &'b [u8] = buf;
+    Ok(ndef_tlv(&buf[..len]))
+}
@@ -4,22 +4,19 @@
 #![feature(async_fn_in_trait)]
 #![allow(incomplete_features)]
 
-use crate::driver::protocol::Interface;
-use crate::driver::requests::{CardType, SAMMode};
-use crate::driver::Reader;
-use defmt::{write, *};
+use defmt::*;
 use embassy_executor::Spawner;
 use embassy_stm32::gpio::{Level, Output, Speed};
 use embassy_stm32::spi::{BitOrder, Config, Spi};
 use embassy_stm32::time::Hertz;
-use embassy_time::{Duration, Timer};
+use embassy_time::{Delay, Duration, Timer};
 use embedded_hal_async::spi::ExclusiveDevice;
+use vat_card_reader::driver::requests::{CardType, SAMMode};
+use vat_card_reader::driver::{self, Reader};
+use vat_card_reader::reader::{check_counter, is_original, read_key};
+use vat_card_reader::replay::CounterStore;
 use {defmt_rtt as _, panic_probe as _};
 
-mod driver;
-mod ndef;
-mod reader;
-
 #[embassy_executor::main]
 async fn main(_spawner: Spawner) {
     let p = embassy_stm32::init(Default::default());
@@ -99,122 +96,85 @@ async fn main(_spawner: Spawner) {
     Timer::after(Duration::from_millis(100)).await;
 
     let device = ExclusiveDevice::new(spi, cs);
-    let mut reader = Reader::new(driver::Spi(device));
+    let mut reader = Reader::new(driver::Spi::new(device), Delay);
+    // with the IRQ line connected, the PN532 must be configured to drive it
+    // let irq = ExtiInput::new(Input::new(p.PA9, Pull::Up), p.EXTI9);
+    // let mut reader = Reader::new(driver::Spi::with_irq(device, irq), Delay);
+    let use_irq = false;
 
     let response = unwrap!(reader.get_firmware_version().await);
     info!("Firmware: {}", response);
 
-    unwrap!(reader.sam_configuration(SAMMode::Normal, false).await);
+    unwrap!(reader.sam_configuration(SAMMode::Normal, use_irq).await);
+
+    let mut counters = CounterStore::<32>::new();
 
     loop {
-        let result = reader.read_passive_target(CardType::IsoTypeA).await;
-        if let Ok(Some(card)) = result {
+        let Ok(targets) = reader.list_passive_targets(CardType::IsoTypeA).await else {
+            continue;
+        };
+        if targets.len() > 1 {
+            // don't let a genuine card vouch for another one held together with it
+            warn!("Rejecting {} cards held together", targets.len());
+            Timer::after(Duration::from_secs(2)).await;
+            continue;
+        }
+
+        if let Some(card) = targets.first() {
             info!("Card: {}", card);
 
-            let mut buf = [0u8; 1024];
-            match read_key(&mut buf, &mut reader).await {
-                Ok(key) => {
-                    info!("Key: {}", key);
+            let kind = match reader.classify(card).await {
+                Ok(kind) => kind,
+                Err(err) => {
+                    info!("Classification failed: {}", err);
+                    continue;
                 }
+            };
+            info!("Kind: {}", kind);
+
+            match is_original(&mut reader, card, kind).await {
+                Ok(Some(true)) => {}
+                Ok(None) => info!("No originality signature to check"),
+                Ok(Some(false)) => {
+                    warn!("Rejecting NTAG without a valid NXP originality signature");
+                    Timer::after(Duration::from_secs(2)).await;
+                    continue;
+                }
+                Err(err) => {
+                    info!("Signature check failed: {}", err);
+                    Timer::after(Duration::from_secs(2)).await;
+                    continue;
+                }
+            }
+
+            let mut buf = [0u8; 1024];
+            let key = match read_key(&mut buf, &mut reader, card, kind).await {
+                Ok(key) => key,
                 Err(err) => {
                     info!("Key read failed: {}", err);
+                    Timer::after(Duration::from_secs(2)).await;
+                    continue;
+                }
+            };
+
+            // the key has been read, so the tag counted this activation
+            match check_counter(&mut reader, card, kind, &mut counters).await {
+                Ok(Some(check)) if check.is_suspicious() => {
+                    warn!("Rejecting card with NFC counter not advanced: {}", check);
+                    Timer::after(Duration::from_secs(2)).await;
+                    continue;
+                }
+                Ok(Some(check)) => info!("Counter: {}", check),
+                Ok(None) => info!("No NFC counter to check"),
+                Err(err) => {
+                    info!("Counter check failed: {}", err);
+                    Timer::after(Duration::from_secs(2)).await;
+                    continue;
                 }
             }
+            info!("Key: {}", key);
 
             Timer::after(Duration::from_secs(2)).await;
         }
     }
 }
-
-pub struct Key<'d>(&'d str);
-
-impl Format for Key<'_> {
-    fn format(&self, fmt: Formatter) {
-        defmt::write!(fmt, "{}", self.0);
-    }
-}
-
-pub enum ReadKeyError<I: Interface> {
-    Io(driver::ReadError<I::Error>),
-    Ndef(ndef::Error),
-}
-
-impl<I: Interface> Format for ReadKeyError<I> {
-    fn format(&self, fmt: Formatter) {
-        match self {
-            Self::Io(err) => write!(fmt, "I/O error: {}", err),
-            Self::Ndef(err) => write!(fmt, "NDEF error: {}", err),
-        }
-    }
-}
-
-impl<I: Interface> From<driver::ReadError<I::Error>> for ReadKeyError<I> {
-    fn from(value: driver::ReadError<I::Error>) -> Self {
-        Self::Io(value)
-    }
-}
-
-impl<I: Interface> From<ndef::Error> for ReadKeyError<I> {
-    fn from(value: ndef::Error) -> Self {
-        Self::Ndef(value)
-    }
-}
-
-async fn read_key<'d, const N: usize, I: Interface>(
-    buf: &'d mut [u8; N],
-    reader: &mut Reader<I>,
-) -> Result<Option<Key<'d>>, ReadKeyError<I>> {
-    let read = reader.read_ntag(0).await?;
-
-    trace!("Read 0: {:X}", read[0..4]);
-    trace!("Read 1: {:X}", read[4..8]);
-    trace!("Read 2: {:X}", read[8..12]);
-    trace!("Read 3: {:X}", read[12..16]);
-
-    let max = read[12 + 2] as u16 * 8;
-    info!(
-        "Max size: {} ({} pages, {} chunks)",
-        max,
-        max / 4,
-        max / (4 * 4)
-    );
-
-    if (max as usize) < N {
-        // let mut buf = [0u8; 1024];
-
-        let max_p = max / 4;
-        let mut p = 4u8; // start page
-        let mut i = 0;
-
-        while (p as u16) <= max_p {
-            debug!("Read page starting: {}", p);
-            let read = reader.read_ntag(p).await?;
-
-            buf[i..i + 16].copy_from_slice(&read);
-
-            // advance index by 16 bytes
-            i += 16;
-
-            // advance by 4 pages (4 bytes each)
-            p += 4;
-        }
-
-        let data = &buf[0..max as usize];
-
-        info!("NDEF: {:02X}", data);
-
-        for record in ndef::Reader::new(&data) {
-            let record = record?;
-            info!("{:X}", record);
-            if let ndef::Record::MimeMedia {
-                r#type: "text/card",
-                value,
-            } = record
-            {
-                return Ok(Some(Key(value)));
-            }
-        }
-    }
-
-    Ok(None)
-}
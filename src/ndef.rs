@@ -1,7 +1,7 @@
 use core::str::from_utf8;
-use defmt::{debug, trace, write, Format, Formatter};
 
-#[derive(Format)]
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Record<'d> {
     // Empty (0x00)
     Empty,
@@ -19,11 +19,21 @@ pub enum Record<'d> {
 
 pub struct Reader<'d> {
     data: &'d [u8],
+    /// Whether the message is wrapped in a TLV, like in a Type 2 Tag
+    tlv: bool,
 }
 
 impl<'d> Reader<'d> {
     pub fn new(data: &'d [u8]) -> Self {
-        Self { data }
+        Self { data, tlv: true }
+    }
+
+    /// Read a bare NDEF message, like the content of a Type 4 Tag NDEF file behind `NLEN`
+    pub fn from_message(message: &'d [u8]) -> Self {
+        Self {
+            data: message,
+            tlv: false,
+        }
     }
 }
 
@@ -34,6 +44,7 @@ impl<'d> IntoIterator for Reader<'d> {
     fn into_iter(self) -> Self::IntoIter {
         ReaderIter {
             data: &self.data,
+            tlv: self.tlv,
             position: 0,
             state: IterState::Fresh,
         }
@@ -48,16 +59,14 @@ enum IterState {
 
 pub struct ReaderIter<'d> {
     data: &'d [u8],
+    tlv: bool,
     position: usize,
     state: IterState,
 }
 
 impl<'d> ReaderIter<'d> {
     fn is_unformatted(&self) -> bool {
-        return self.data[0] == 0xFF
-            && self.data[1] == 0xFF
-            && self.data[2] == 0xFF
-            && self.data[3] == 0xFF;
+        self.data.starts_with(&[0xFF; 4])
     }
 
     fn try_next(&mut self) -> Result<Option<Record<'d>>, Error> {
@@ -70,26 +79,52 @@ impl<'d> ReaderIter<'d> {
                 return Ok(None);
             }
             IterState::Reading => {}
+            IterState::Fresh if !self.tlv => {
+                if self.data.is_empty() {
+                    return Err(Error::UnderflowHeader);
+                }
+                self.state = IterState::Reading;
+            }
             IterState::Fresh => {
                 if self.is_unformatted() {
                     // we keep the state in order to run into this again, and again, …
                     return Err(Error::NotFormatted);
                 }
-                if self.data.len() < 8 {
-                    return Err(Error::UnderflowHeader);
+                // the NDEF TLV, at the start or behind the lock control TLV
+                let start = match self.data {
+                    [0x03, ..] => 0,
+                    [_, _, _, _, _, 0x03, ..] => 5,
+                    _ => 0,
+                };
+                let (position, len) = match self.data.get(start + 1..).unwrap_or_default() {
+                    // three byte length format
+                    [0xFF, high, low, ..] => {
+                        (start + 4, u16::from_be_bytes([*high, *low]) as usize)
+                    }
+                    [len, ..] if *len != 0xFF => (start + 2, *len as usize),
+                    _ => return Err(Error::UnderflowHeader),
+                };
+                debug!("Message len: {}, start: {}", len, position);
+                if self.data.len() - position < len {
+                    return Err(Error::UnderflowPayload);
                 }
 
-                if self.data[0] == 0x03 {
-                    debug!("Message len: {}, start: 2", self.data[1]);
-                    self.position = 2;
-                } else if self.data[5] == 0x03 {
-                    debug!("Message len: {}, start: 7", self.data[6]);
-                    self.position = 7;
+                // the records end with the TLV
+                self.data = &self.data[..position + len];
+                self.position = position;
+                if len == 0 {
+                    self.state = IterState::Complete;
+                    return Ok(None);
                 }
                 self.state = IterState::Reading;
             }
         };
 
+        if self.position >= self.data.len() {
+            // the last record didn't end the message
+            return Err(Error::UnderflowHeader);
+        }
+
         trace!("flags - tnf: {0=0..3}, il: {0=3..4}, sr: {0=4..5}, chunk: {0=5..6}, me: {0=6..7}, mb: {0=7..8}", self.data[self.position]);
         let flags = RecordHeaderFlags(self.data[self.position]);
         debug!("flags: {}", flags);
@@ -97,9 +132,6 @@ impl<'d> ReaderIter<'d> {
         let mut header_len = 1 // flags
             + 1; // type length
 
-        if !flags.is_short_record() {
-            header_len += 1;
-        }
         match flags.is_short_record() {
             true => header_len += 1,
             false => header_len += 4,
@@ -192,12 +224,81 @@ impl<'d> Iterator for ReaderIter<'d> {
     }
 }
 
-#[derive(Format)]
+/// Encodes records into an NDEF message
+pub struct Writer<'b> {
+    buf: &'b mut [u8],
+    len: usize,
+    /// Position of the header of the last record
+    last: Option<usize>,
+}
+
+impl<'b> Writer<'b> {
+    pub fn new(buf: &'b mut [u8]) -> Self {
+        Self {
+            buf,
+            len: 0,
+            last: None,
+        }
+    }
+
+    /// Append a record, it becomes the end of the message
+    ///
+    /// Only empty and mime media records can be encoded.
+    pub fn push(&mut self, record: &Record) -> Result<(), Error> {
+        let (tnf, r#type, payload) = match record {
+            Record::Empty => (0x00, &[][..], &[][..]),
+            Record::MimeMedia { r#type, value } => (0x02, r#type.as_bytes(), value.as_bytes()),
+            _ => return Err(Error::Unsupported),
+        };
+        let short = payload.len() <= u8::MAX as usize;
+        let header_len = if short { 3 } else { 6 };
+
+        let total_len = header_len + r#type.len() + payload.len();
+        if r#type.len() > u8::MAX as usize || self.buf.len() - self.len < total_len {
+            return Err(Error::Overflow);
+        }
+
+        let mut flags = tnf | RecordHeaderFlags::MESSAGE_END;
+        if short {
+            flags |= RecordHeaderFlags::SHORT_RECORD;
+        }
+        match self.last {
+            Some(last) => self.buf[last] &= !RecordHeaderFlags::MESSAGE_END,
+            None => flags |= RecordHeaderFlags::MESSAGE_BEGIN,
+        }
+
+        let record = &mut self.buf[self.len..self.len + total_len];
+        record[0] = flags;
+        record[1] = r#type.len() as u8;
+        match short {
+            true => record[2] = payload.len() as u8,
+            false => record[2..6].copy_from_slice(&(payload.len() as u32).to_be_bytes()),
+        }
+        record[header_len..header_len + r#type.len()].copy_from_slice(r#type);
+        record[header_len + r#type.len()..].copy_from_slice(payload);
+
+        self.last = Some(self.len);
+        self.len += total_len;
+        Ok(())
+    }
+
+    /// The message encoded so far
+    pub fn finish(self) -> &'b [u8] {
+        &self.buf[..self.len]
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Error {
     NotFormatted,
     UnderflowHeader,
     UnderflowPayload,
     Utf8,
+    /// The record can't be encoded
+    Unsupported,
+    /// The buffer is too small for the message
+    Overflow,
 }
 
 impl From<core::str::Utf8Error> for Error {
@@ -208,9 +309,10 @@ impl From<core::str::Utf8Error> for Error {
 
 pub struct RecordHeaderFlags(u8);
 
-impl Format for RecordHeaderFlags {
-    fn format(&self, fmt: Formatter) {
-        write!(
+#[cfg(feature = "defmt")]
+impl defmt::Format for RecordHeaderFlags {
+    fn format(&self, fmt: defmt::Formatter) {
+        defmt::write!(
             fmt,
             "Flags(tnf: {:x}, mb: {}, me: {}, chunk: {}, shortRecord: {}, idLength: {})",
             self.tnf(),
@@ -224,6 +326,10 @@ impl Format for RecordHeaderFlags {
 }
 
 impl RecordHeaderFlags {
+    const SHORT_RECORD: u8 = 0b0001_0000;
+    const MESSAGE_END: u8 = 0b0100_0000;
+    const MESSAGE_BEGIN: u8 = 0b1000_0000;
+
     pub fn tnf(&self) -> u8 {
         self.0 & 0b0000_0111
     }
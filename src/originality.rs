@@ -0,0 +1,252 @@
+//! Offline verification of the NXP originality signature
+//!
+//! NXP signs the UID of every NTAG21x with ECDSA on the curve secp128r1 (SEC 2), the signature
+//! is read with `READ_SIG`. The UID is signed as is, without hashing it first. A valid signature
+//! proves that NXP issued the UID, a tag copying both UID and signature of a genuine one still
+//! passes.
+//!
+//! The arithmetic is done on plain `u128`s, as all values fit. Multiplying by shifting and adding
+//! is slow, but good enough for a single verification per card.
+
+/// Public key of NXP for the NTAG21x originality signature, SEC 1 encoded (uncompressed)
+pub const NTAG21X_PUBLIC_KEY: [u8; 33] = [
+    0x04, 0x49, 0x4E, 0x1A, 0x38, 0x6D, 0x3D, 0x3C, 0xFE, 0x3D, 0xC1, 0x0E, 0x5D, 0xE6, 0x8A, 0x49,
+    0x9B, 0x1C, 0x20, 0x2D, 0xB5, 0xB1, 0x32, 0x39, 0x3E, 0x89, 0xED, 0x19, 0xFE, 0x5B, 0xE8, 0xBC,
+    0x61,
+];
+
+/// Length of a signature, `r` followed by `s`, both big endian
+pub const SIGNATURE_LEN: usize = 32;
+
+/// Field prime: 2^128 - 2^97 - 1
+const P: Modulus = Modulus(0xFFFFFFFD_FFFFFFFF_FFFFFFFF_FFFFFFFF);
+/// Order of the base point
+const N: Modulus = Modulus(0xFFFFFFFE_00000000_75A30D1B_9038A115);
+/// Curve coefficient `b`, `a` is -3
+const B: u128 = 0xE87579C1_1079F43D_D824993C_2CEE5ED3;
+/// Base point
+const G: Affine = Affine {
+    x: 0x161FF752_8B899B2D_0C28607C_A52C5B86,
+    y: 0xCF5AC839_5BAFEB13_C02DA292_DDED7A83,
+};
+
+/// Public key, a point on secp128r1
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PublicKey(Affine);
+
+impl PublicKey {
+    /// Decode an uncompressed SEC 1 point, `None` if it isn't on the curve
+    pub fn from_sec1(bytes: &[u8; 33]) -> Option<Self> {
+        let (&[0x04], coordinates) = bytes.split_at(1) else {
+            return None;
+        };
+        let (x, y) = coordinates.split_at(16);
+        let point = Affine {
+            x: u128::from_be_bytes(x.try_into().ok()?),
+            y: u128::from_be_bytes(y.try_into().ok()?),
+        };
+        point.is_on_curve().then_some(Self(point))
+    }
+
+    /// Check the ECDSA `signature` of `message`, which is used as is, not hashed
+    ///
+    /// Messages longer than 16 bytes are truncated, like a hash would be.
+    pub fn verify(&self, message: &[u8], signature: &[u8; SIGNATURE_LEN]) -> bool {
+        let r = u128::from_be_bytes(signature[..16].try_into().unwrap());
+        let s = u128::from_be_bytes(signature[16..].try_into().unwrap());
+        if r == 0 || r >= N.0 || s == 0 || s >= N.0 {
+            return false;
+        }
+
+        let e = N.reduce(
+            message
+                .iter()
+                .take(16)
+                .fold(0u128, |e, &byte| e << 8 | byte as u128),
+        );
+        let w = N.inv(s);
+        let u1 = N.mul(e, w);
+        let u2 = N.mul(r, w);
+
+        let point = match multiply(u2, self.0).to_affine() {
+            Some(point) => multiply(u1, G).add(point),
+            None => multiply(u1, G),
+        };
+        match point.to_affine() {
+            Some(point) => N.reduce(point.x) == r,
+            None => false,
+        }
+    }
+}
+
+/// Check the originality signature of an NTAG21x with NXP's public key
+pub fn verify_ntag21x(uid: &[u8], signature: &[u8; SIGNATURE_LEN]) -> bool {
+    match PublicKey::from_sec1(&NTAG21X_PUBLIC_KEY) {
+        Some(key) => key.verify(uid, signature),
+        None => false,
+    }
+}
+
+/// Arithmetic modulo a prime
+#[derive(Clone, Copy)]
+struct Modulus(u128);
+
+impl Modulus {
+    /// Reduce a value below twice the modulus
+    fn reduce(self, a: u128) -> u128 {
+        match a >= self.0 {
+            true => a - self.0,
+            false => a,
+        }
+    }
+
+    fn add(self, a: u128, b: u128) -> u128 {
+        let (sum, carry) = a.overflowing_add(b);
+        match carry || sum >= self.0 {
+            true => sum.wrapping_sub(self.0),
+            false => sum,
+        }
+    }
+
+    fn sub(self, a: u128, b: u128) -> u128 {
+        match a >= b {
+            true => a - b,
+            false => self.0 - (b - a),
+        }
+    }
+
+    /// Shift and add, the product doesn't fit into a `u128`
+    fn mul(self, a: u128, b: u128) -> u128 {
+        let mut product = 0;
+        for bit in (0..128).rev() {
+            product = self.add(product, product);
+            if b >> bit & 1 == 1 {
+                product = self.add(product, a);
+            }
+        }
+        product
+    }
+
+    fn pow(self, a: u128, exponent: u128) -> u128 {
+        let mut result = 1;
+        for bit in (0..128).rev() {
+            result = self.mul(result, result);
+            if exponent >> bit & 1 == 1 {
+                result = self.mul(result, a);
+            }
+        }
+        result
+    }
+
+    /// Inverse by Fermat's little theorem, `a` must not be zero
+    fn inv(self, a: u128) -> u128 {
+        self.pow(a, self.0 - 2)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Affine {
+    x: u128,
+    y: u128,
+}
+
+impl Affine {
+    fn is_on_curve(&self) -> bool {
+        if self.x >= P.0 || self.y >= P.0 {
+            return false;
+        }
+        // y^2 = x^3 - 3x + b
+        let x3 = P.mul(P.mul(self.x, self.x), self.x);
+        let rhs = P.add(P.sub(x3, P.mul(3, self.x)), B);
+        P.mul(self.y, self.y) == rhs
+    }
+}
+
+/// Point in Jacobian coordinates, `z == 0` is the point at infinity
+#[derive(Clone, Copy)]
+struct Jacobian {
+    x: u128,
+    y: u128,
+    z: u128,
+}
+
+impl Jacobian {
+    const INFINITY: Self = Self { x: 1, y: 1, z: 0 };
+
+    fn to_affine(self) -> Option<Affine> {
+        if self.z == 0 {
+            return None;
+        }
+        let z = P.inv(self.z);
+        let z2 = P.mul(z, z);
+        Some(Affine {
+            x: P.mul(self.x, z2),
+            y: P.mul(self.y, P.mul(z2, z)),
+        })
+    }
+
+    /// dbl-2001-b, for `a = -3`
+    fn double(self) -> Self {
+        if self.z == 0 || self.y == 0 {
+            return Self::INFINITY;
+        }
+        let delta = P.mul(self.z, self.z);
+        let gamma = P.mul(self.y, self.y);
+        let beta = P.mul(self.x, gamma);
+        let alpha = P.mul(3, P.mul(P.sub(self.x, delta), P.add(self.x, delta)));
+
+        let x = P.sub(P.mul(alpha, alpha), P.mul(8, beta));
+        let yz = P.add(self.y, self.z);
+        let z = P.sub(P.sub(P.mul(yz, yz), gamma), delta);
+        let y = P.sub(
+            P.mul(alpha, P.sub(P.mul(4, beta), x)),
+            P.mul(8, P.mul(gamma, gamma)),
+        );
+        Self { x, y, z }
+    }
+
+    /// Mixed addition of an affine point
+    fn add(self, other: Affine) -> Self {
+        if self.z == 0 {
+            return Self {
+                x: other.x,
+                y: other.y,
+                z: 1,
+            };
+        }
+        let z2 = P.mul(self.z, self.z);
+        let u = P.mul(other.x, z2);
+        let s = P.mul(other.y, P.mul(self.z, z2));
+        let h = P.sub(u, self.x);
+        let r = P.sub(s, self.y);
+        if h == 0 {
+            return match r {
+                0 => self.double(),
+                _ => Self::INFINITY,
+            };
+        }
+
+        let hh = P.mul(h, h);
+        let hhh = P.mul(h, hh);
+        let v = P.mul(self.x, hh);
+        let x = P.sub(P.sub(P.mul(r, r), hhh), P.add(v, v));
+        let y = P.sub(P.mul(r, P.sub(v, x)), P.mul(self.y, hhh));
+        Self {
+            x,
+            y,
+            z: P.mul(self.z, h),
+        }
+    }
+}
+
+/// `k * point`, double and add
+fn multiply(k: u128, point: Affine) -> Jacobian {
+    let mut result = Jacobian::INFINITY;
+    for bit in (0..128).rev() {
+        result = result.double();
+        if k >> bit & 1 == 1 {
+            result = result.add(point);
+        }
+    }
+    result
+}
@@ -1,6 +1,384 @@
-/*
-pub async fn read_memory<const N: usize, I: Interface>(reader: &mut Reader<I>) -> Result<[u8; N]> {
-    reader.read_ntag(0);
-    Ok()
+use crate::driver::apdu::ApduError;
+use crate::driver::mifare::ClassicError;
+use crate::driver::ntag::{AuthError, Password};
+use crate::driver::{self, protocol::Interface, CardKind, Reader, TargetInfo};
+use crate::mad;
+use crate::ndef;
+use crate::originality;
+use crate::replay::{CounterCheck, CounterStore};
+use crate::type2::{self, CapabilityContainer, NdefLayout};
+use crate::type4;
+use embedded_hal_async::delay::DelayUs;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Key<'d>(pub &'d str);
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Key<'_> {
+    fn format(&self, fmt: defmt::Formatter) {
+        defmt::write!(fmt, "{}", self.0);
+    }
+}
+
+pub enum ReadKeyError<I: Interface> {
+    Io(driver::ReadError<I::Error>),
+    Ndef(ndef::Error),
+    /// Keys are only read from NFC Forum Type 2 and Type 4 Tags, and Mifare Classic
+    Unsupported(CardKind),
+    /// The tag took the password, but answered with an unexpected PACK
+    PackMismatch([u8; 2]),
+    Classic(ClassicError<I::Error>),
+    Mad(mad::Error),
+    Apdu(ApduError<I::Error>),
+    Type4(type4::Error),
+}
+
+#[cfg(feature = "defmt")]
+impl<I: Interface> defmt::Format for ReadKeyError<I> {
+    fn format(&self, fmt: defmt::Formatter) {
+        match self {
+            Self::Io(err) => defmt::write!(fmt, "I/O error: {}", err),
+            Self::Ndef(err) => defmt::write!(fmt, "NDEF error: {}", err),
+            Self::Unsupported(kind) => defmt::write!(fmt, "Unsupported card: {}", kind),
+            Self::PackMismatch(pack) => defmt::write!(fmt, "PACK mismatch: {:X}", pack),
+            Self::Classic(err) => defmt::write!(fmt, "Mifare Classic error: {}", err),
+            Self::Mad(err) => defmt::write!(fmt, "MAD error: {}", err),
+            Self::Apdu(err) => defmt::write!(fmt, "APDU error: {}", err),
+            Self::Type4(err) => defmt::write!(fmt, "Type 4 Tag error: {}", err),
+        }
+    }
+}
+
+impl<I: Interface> From<driver::ReadError<I::Error>> for ReadKeyError<I> {
+    fn from(value: driver::ReadError<I::Error>) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl<I: Interface> From<AuthError<I::Error>> for ReadKeyError<I> {
+    fn from(value: AuthError<I::Error>) -> Self {
+        match value {
+            AuthError::Reader(err) => Self::Io(err),
+            AuthError::PackMismatch(pack) => Self::PackMismatch(pack),
+        }
+    }
+}
+
+impl<I: Interface> From<mad::ReadError<I::Error>> for ReadKeyError<I> {
+    fn from(value: mad::ReadError<I::Error>) -> Self {
+        match value {
+            mad::ReadError::Classic(ClassicError::Reader(err)) => Self::Io(err),
+            mad::ReadError::Classic(err) => Self::Classic(err),
+            mad::ReadError::Mad(err) => Self::Mad(err),
+        }
+    }
+}
+
+impl<I: Interface> From<type4::ReadError<I::Error>> for ReadKeyError<I> {
+    fn from(value: type4::ReadError<I::Error>) -> Self {
+        match value {
+            type4::ReadError::Apdu(ApduError::Reader(err)) => Self::Io(err),
+            type4::ReadError::Apdu(err) => Self::Apdu(err),
+            type4::ReadError::Tag(err) => Self::Type4(err),
+        }
+    }
+}
+
+impl<I: Interface> From<ndef::Error> for ReadKeyError<I> {
+    fn from(value: ndef::Error) -> Self {
+        Self::Ndef(value)
+    }
+}
+
+pub enum WriteKeyError<I: Interface> {
+    Io(driver::ReadError<I::Error>),
+    Ndef(ndef::Error),
+    Tag(type2::Error),
+    /// Keys are only written to NFC Forum Type 2 Tags
+    Unsupported(CardKind),
+}
+
+#[cfg(feature = "defmt")]
+impl<I: Interface> defmt::Format for WriteKeyError<I> {
+    fn format(&self, fmt: defmt::Formatter) {
+        match self {
+            Self::Io(err) => defmt::write!(fmt, "I/O error: {}", err),
+            Self::Ndef(err) => defmt::write!(fmt, "NDEF error: {}", err),
+            Self::Tag(err) => defmt::write!(fmt, "Tag error: {}", err),
+            Self::Unsupported(kind) => defmt::write!(fmt, "Unsupported card: {}", kind),
+        }
+    }
+}
+
+impl<I: Interface> From<driver::ReadError<I::Error>> for WriteKeyError<I> {
+    fn from(value: driver::ReadError<I::Error>) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl<I: Interface> From<ndef::Error> for WriteKeyError<I> {
+    fn from(value: ndef::Error) -> Self {
+        Self::Ndef(value)
+    }
+}
+
+impl<I: Interface> From<type2::Error> for WriteKeyError<I> {
+    fn from(value: type2::Error) -> Self {
+        Self::Tag(value)
+    }
+}
+
+/// Read the key from a card, `kind` being what [`Reader::classify`] made of it
+pub async fn read_key<'d, const N: usize, I: Interface, T: DelayUs>(
+    buf: &'d mut [u8; N],
+    reader: &mut Reader<I, T>,
+    target: &TargetInfo,
+    kind: CardKind,
+) -> Result<Option<Key<'d>>, ReadKeyError<I>> {
+    read_protected_key(buf, reader, target, kind, None).await
+}
+
+/// Read the key from a tag protected by `password`, see [`Reader::protect_ntag`]
+///
+/// The tag is authenticated before reading, if `password` is given.
+pub async fn read_protected_key<'d, const N: usize, I: Interface, T: DelayUs>(
+    buf: &'d mut [u8; N],
+    reader: &mut Reader<I, T>,
+    target: &TargetInfo,
+    kind: CardKind,
+    password: Option<&Password>,
+) -> Result<Option<Key<'d>>, ReadKeyError<I>> {
+    if matches!(
+        kind,
+        CardKind::MifareMini | CardKind::MifareClassic1K | CardKind::MifareClassic4K
+    ) {
+        return read_classic_key(buf, reader, target, kind).await;
+    }
+    if kind.is_iso_dep() {
+        return read_type4_key(buf, reader, target).await;
+    }
+    // the NDEF message is read page by page
+    let Some(size) = kind.memory_size().filter(|_| kind.is_type2()) else {
+        return Err(ReadKeyError::Unsupported(kind));
+    };
+
+    let tg = target.tg();
+    if let Some(password) = password {
+        reader.authenticate_ntag(tg, password).await?;
+    }
+    let read = reader.read_ntag(tg, 0).await?;
+
+    trace!("Read 0: {:X}", read[0..4]);
+    trace!("Read 1: {:X}", read[4..8]);
+    trace!("Read 2: {:X}", read[8..12]);
+    trace!("Read 3: {:X}", read[12..16]);
+
+    // the capability container might announce more than the card has
+    let max = (read[12 + 2] as u16 * 8).min(size as u16);
+    info!(
+        "Max size: {} ({} pages, {} chunks)",
+        max,
+        max / 4,
+        max / (4 * 4)
+    );
+
+    if max > 0 && (max as usize) < N {
+        if kind.supports_fast_read() {
+            let last = type2::DATA_PAGE + (max / 4) as u8 - 1;
+            reader
+                .fast_read(tg, type2::DATA_PAGE, last, &mut buf[..max as usize])
+                .await?;
+        } else {
+            let max_p = max / 4;
+            let mut p = 4u8; // start page
+            let mut i = 0;
+
+            while (p as u16) <= max_p {
+                debug!("Read page starting: {}", p);
+                let read = reader.read_ntag(tg, p).await?;
+
+                buf[i..i + 16].copy_from_slice(&read);
+
+                // advance index by 16 bytes
+                i += 16;
+
+                // advance by 4 pages (4 bytes each)
+                p += 4;
+            }
+        }
+
+        let data = &buf[0..max as usize];
+
+        info!("NDEF: {:02X}", data);
+
+        for record in ndef::Reader::new(&data) {
+            let record = record?;
+            info!("{:X}", record);
+            if let ndef::Record::MimeMedia {
+                r#type: "text/card",
+                value,
+            } = record
+            {
+                return Ok(Some(Key(value)));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Read the key from the NDEF sectors of a Mifare Classic, found through its MAD
+async fn read_classic_key<'d, const N: usize, I: Interface, T: DelayUs>(
+    buf: &'d mut [u8; N],
+    reader: &mut Reader<I, T>,
+    target: &TargetInfo,
+    kind: CardKind,
+) -> Result<Option<Key<'d>>, ReadKeyError<I>> {
+    let tg = target.tg();
+    let uid = target.uid();
+    let Some(mad) = mad::read_mad(reader, tg, &uid, kind).await? else {
+        return Ok(None);
+    };
+    let Some(tlv) = mad::read_ndef(reader, tg, &uid, &mad, buf).await? else {
+        return Ok(None);
+    };
+
+    info!("NDEF: {:02X}", tlv);
+    for record in ndef::Reader::new(tlv) {
+        let record = record?;
+        info!("{:X}", record);
+        if let ndef::Record::MimeMedia {
+            r#type: "text/card",
+            value,
+        } = record
+        {
+            return Ok(Some(Key(value)));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Read the key from the NDEF file of a Type 4 Tag
+async fn read_type4_key<'d, const N: usize, I: Interface, T: DelayUs>(
+    buf: &'d mut [u8; N],
+    reader: &mut Reader<I, T>,
+    target: &TargetInfo,
+) -> Result<Option<Key<'d>>, ReadKeyError<I>> {
+    let Some(message) = type4::read_ndef(reader, target.tg(), buf).await? else {
+        return Ok(None);
+    };
+
+    info!("NDEF: {:02X}", message);
+    for record in ndef::Reader::from_message(message) {
+        let record = record?;
+        info!("{:X}", record);
+        if let ndef::Record::MimeMedia {
+            r#type: "text/card",
+            value,
+        } = record
+        {
+            return Ok(Some(Key(value)));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Provision a card with `key`, replacing its NDEF message by a single `text/card` record
+///
+/// `buf` has to hold the encoded message, `kind` is what [`Reader::classify`] made of the card.
+pub async fn write_key<const N: usize, I: Interface, T: DelayUs>(
+    buf: &mut [u8; N],
+    reader: &mut Reader<I, T>,
+    target: &TargetInfo,
+    kind: CardKind,
+    key: Key<'_>,
+) -> Result<(), WriteKeyError<I>> {
+    let mut writer = ndef::Writer::new(buf);
+    writer.push(&ndef::Record::MimeMedia {
+        r#type: "text/card",
+        value: key.0,
+    })?;
+    write_ndef(reader, target, kind, writer.finish()).await
+}
+
+/// Write the NDEF `message` to a NFC Forum Type 2 Tag, `kind` being what [`Reader::classify`]
+/// made of it
+///
+/// The capability container has to grant write access, lock and memory control TLVs in front of
+/// the current message are kept.
+pub async fn write_ndef<I: Interface, T: DelayUs>(
+    reader: &mut Reader<I, T>,
+    target: &TargetInfo,
+    kind: CardKind,
+    message: &[u8],
+) -> Result<(), WriteKeyError<I>> {
+    let Some(size) = kind.memory_size().filter(|_| kind.is_type2()) else {
+        return Err(WriteKeyError::Unsupported(kind));
+    };
+
+    let tg = target.tg();
+    // capability container and the start of the data area
+    let read = reader.read_ntag(tg, type2::CC_PAGE).await?;
+    let cc = CapabilityContainer::parse([read[0], read[1], read[2], read[3]])?;
+    debug!("CC: {}", cc);
+    if !cc.is_writable() {
+        return Err(type2::Error::ReadOnly.into());
+    }
+
+    let layout = NdefLayout::new(&read[4..], message, cc.size.min(size))?;
+    for index in layout.pages() {
+        let page = type2::DATA_PAGE + index as u8;
+        debug!("Write page: {}", page);
+        reader.write_ntag(tg, page, layout.page(index)).await?;
+    }
+
+    Ok(())
+}
+
+/// Check the NXP originality signature of an NTAG21x, to reject clones before trusting its key
+///
+/// `None` for any card which isn't an NTAG21x, as there is no signature to check.
+pub async fn is_original<I: Interface, T: DelayUs>(
+    reader: &mut Reader<I, T>,
+    target: &TargetInfo,
+    kind: CardKind,
+) -> Result<Option<bool>, driver::ReadError<I::Error>> {
+    if !matches!(
+        kind,
+        CardKind::Ntag213 | CardKind::Ntag215 | CardKind::Ntag216
+    ) {
+        return Ok(None);
+    }
+
+    let signature = reader.read_signature(target.tg()).await?;
+    debug!("Signature: {:02X}", signature);
+    Ok(Some(originality::verify_ntag21x(
+        target.uid().as_bytes(),
+        &signature,
+    )))
+}
+
+/// Check the NFC counter of an NTAG21x against the last one seen, to reject clones
+///
+/// Call this after reading the key, which made the tag count the activation. `None` for any card
+/// which isn't an NTAG21x, or doesn't count its reads as `NFC_CNT_EN` is clear, the factory
+/// default, see [`Reader::enable_ntag_counter`].
+pub async fn check_counter<const N: usize, I: Interface, T: DelayUs>(
+    reader: &mut Reader<I, T>,
+    target: &TargetInfo,
+    kind: CardKind,
+    store: &mut CounterStore<N>,
+) -> Result<Option<CounterCheck>, driver::ReadError<I::Error>> {
+    let Some(config) = reader.read_ntag_config(target.tg(), kind).await? else {
+        return Ok(None);
+    };
+    if !config.access.nfc_cnt_en {
+        debug!("NFC counter disabled");
+        return Ok(None);
+    }
+    let counter = reader.read_counter(target.tg()).await?;
+    debug!("NFC counter: {}", counter);
+    Ok(Some(store.check(target.uid(), counter)))
 }
- */
@@ -0,0 +1,108 @@
+//! Detection of cloned NTAG21x by their NFC counter
+//!
+//! Once enabled, an NTAG21x increments its 24 bit NFC counter on the first read after every
+//! activation, see [`Reader::enable_ntag_counter`](crate::driver::Reader::enable_ntag_counter).
+//! The counter can't be written, so a clone made from a dump of a genuine card keeps showing
+//! the counter it was made with, while the genuine card moves on. Remembering the last counter
+//! seen per UID flags whichever of the two shows up second with a lower counter. As the card is
+//! read on every tap, a genuine card never shows the same counter twice, so neither does a clone
+//! get away with the counter of the last tap.
+
+use crate::driver::CardUid;
+
+/// Outcome of [`CounterStore::check`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CounterCheck {
+    /// The card wasn't seen before, or has been forgotten
+    New,
+    /// The counter went up since the card was last seen
+    Advanced { last: u32 },
+    /// The counter didn't change, although the card was read since it was last seen
+    ///
+    /// A genuine card with the counter enabled counts every activation it is read in, this is a
+    /// clone with a frozen counter.
+    Unchanged,
+    /// The counter went backwards, one of the cards seen with this UID is a clone
+    RolledBack { last: u32 },
+}
+
+impl CounterCheck {
+    pub fn is_rollback(&self) -> bool {
+        matches!(self, Self::RolledBack { .. })
+    }
+
+    /// Whether the counter didn't go up, so the card is likely a clone
+    pub fn is_suspicious(&self) -> bool {
+        matches!(self, Self::Unchanged | Self::RolledBack { .. })
+    }
+}
+
+/// The last NFC counter of up to `N` cards
+///
+/// When full, the card seen least recently is forgotten.
+#[derive(Clone, Debug)]
+pub struct CounterStore<const N: usize> {
+    /// Most recently seen first
+    entries: [Option<(CardUid, u32)>; N],
+}
+
+impl<const N: usize> CounterStore<N> {
+    pub const fn new() -> Self {
+        Self { entries: [None; N] }
+    }
+
+    /// The last counter seen of the card `uid`
+    pub fn last(&self, uid: &CardUid) -> Option<u32> {
+        self.iter()
+            .find(|(entry, _)| entry == uid)
+            .map(|(_, counter)| *counter)
+    }
+
+    /// Compare `counter` with the last one seen of `uid`, and remember the higher of both
+    ///
+    /// A rolled back counter doesn't replace the stored one, a clone keeps being flagged.
+    pub fn check(&mut self, uid: CardUid, counter: u32) -> CounterCheck {
+        let position = self.entries.iter().position(|entry| match entry {
+            Some((entry, _)) => *entry == uid,
+            None => true,
+        });
+        // forget the least recently seen card if full
+        let position = position.unwrap_or(N.saturating_sub(1));
+        let last = match self.entries.get(position).copied().flatten() {
+            Some((entry, last)) if entry == uid => Some(last),
+            _ => None,
+        };
+
+        let (check, counter) = match last {
+            None => (CounterCheck::New, counter),
+            Some(last) if counter > last => (CounterCheck::Advanced { last }, counter),
+            Some(last) if counter == last => (CounterCheck::Unchanged, counter),
+            Some(last) => (CounterCheck::RolledBack { last }, last),
+        };
+
+        if N > 0 {
+            self.entries[..=position].rotate_right(1);
+            self.entries[0] = Some((uid, counter));
+        }
+        check
+    }
+
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &(CardUid, u32)> {
+        self.entries.iter().flatten()
+    }
+}
+
+impl<const N: usize> Default for CounterStore<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
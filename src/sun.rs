@@ -0,0 +1,159 @@
+//! Secure Unique NFC (SUN) messages of the NTAG 424 DNA
+//!
+//! With Secure Dynamic Messaging (SDM) enabled, the card mirrors into its NDEF file on every read:
+//! the PICC data, its UID and read counter encrypted with the SDM meta read key, and the SDM MAC
+//! over a part of the file, keyed with a session key derived from the SDM file read key, the UID
+//! and the counter. Both are mirrored as ASCII hex, so they fit into a URL or a text record.
+//!
+//! A matching MAC proves the message comes from a card holding the key, without talking to the
+//! card or keeping anything per card. The counter goes up with every read, so a replayed message
+//! shows a counter seen before, see [`CounterStore`](crate::replay::CounterStore).
+
+use crate::crypto::{self, Block, Cipher, MAX_BLOCK_SIZE};
+
+/// Bytes of the encrypted PICC data
+pub const PICC_DATA_LEN: usize = 16;
+/// Bytes of the SDM MAC
+pub const MAC_LEN: usize = 8;
+
+/// Length of `NLEN`, in front of the message in the NDEF file
+const NLEN_LEN: usize = 2;
+
+/// PICC data tag: UID mirrored
+const TAG_UID: u8 = 0x80;
+/// PICC data tag: read counter mirrored
+const TAG_COUNTER: u8 = 0x40;
+/// PICC data tag: reserved bits
+const TAG_RFU: u8 = 0x30;
+const UID_LEN: u8 = 7;
+
+/// Label of the session vector of the SDM MAC key
+const SV_MAC: [u8; 2] = [0x3C, 0xC3];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// A mirror is out of the message, or isn't hex
+    InvalidMirror,
+    /// The PICC data doesn't decrypt to a valid tag, the meta read key is wrong
+    InvalidPiccData,
+    /// The SDM MAC doesn't match, the message is forged or the file read key is wrong
+    Mac,
+}
+
+/// The keys of the SDM file settings
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Keys {
+    /// Key of the PICC data
+    pub meta_read: [u8; 16],
+    /// Key of the SDM MAC
+    pub file_read: [u8; 16],
+}
+
+/// Where the card mirrors into the NDEF file, the offsets of the SDM file settings
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Layout {
+    /// Offset of the encrypted PICC data
+    pub picc_data: usize,
+    /// Offset of the data covered by the SDM MAC, which ends at the MAC
+    pub mac_input: usize,
+    /// Offset of the SDM MAC
+    pub mac: usize,
+}
+
+/// The decrypted PICC data
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PiccData {
+    /// The UID, if mirrored
+    pub uid: Option<[u8; 7]>,
+    /// The SDM read counter, if mirrored
+    pub read_counter: Option<u32>,
+}
+
+impl PiccData {
+    /// Decrypt the PICC data: a tag, the UID and the counter, followed by random padding
+    pub fn decrypt(key: &[u8; 16], encrypted: &[u8; PICC_DATA_LEN]) -> Result<Self, Error> {
+        let mut data = *encrypted;
+        Cipher::aes(key).decrypt_block(&mut data);
+
+        let tag = data[0];
+        let uid_len = match tag & TAG_UID {
+            0 => 0,
+            _ => UID_LEN,
+        };
+        if tag & TAG_RFU != 0 || tag & 0x0F != uid_len {
+            return Err(Error::InvalidPiccData);
+        }
+        let uid: Option<[u8; 7]> = (uid_len > 0).then(|| data[1..8].try_into().unwrap());
+        let counter = &data[1 + uid_len as usize..];
+        let read_counter = (tag & TAG_COUNTER != 0)
+            .then(|| u32::from_le_bytes([counter[0], counter[1], counter[2], 0]));
+        Ok(Self { uid, read_counter })
+    }
+
+    /// The session key of the SDM MAC, derived from the SDM file read key
+    pub fn mac_key(&self, key: &[u8; 16]) -> Block {
+        let mut sv = [0u8; MAX_BLOCK_SIZE];
+        sv[..2].copy_from_slice(&SV_MAC);
+        sv[2..6].copy_from_slice(&[0x00, 0x01, 0x00, 0x80]);
+        let mut len = 6;
+        if let Some(uid) = self.uid {
+            sv[len..len + 7].copy_from_slice(&uid);
+            len += 7;
+        }
+        if let Some(counter) = self.read_counter {
+            sv[len..len + 3].copy_from_slice(&counter.to_le_bytes()[..3]);
+        }
+        Cipher::aes(key).cmac(&[0u8; MAX_BLOCK_SIZE], &sv)
+    }
+
+    /// The SDM MAC over `input`
+    pub fn mac(&self, key: &[u8; 16], input: &[u8]) -> [u8; MAC_LEN] {
+        let cipher = Cipher::aes(&self.mac_key(key));
+        crypto::truncate_mac(&cipher.cmac(&[0u8; MAX_BLOCK_SIZE], input))
+    }
+}
+
+/// Verify the SUN message in an NDEF message, as returned by
+/// [`type4::read_ndef`](crate::type4::read_ndef), returning the PICC data
+///
+/// The offsets of `layout` are the ones configured on the card, which include `NLEN`.
+pub fn verify(message: &[u8], layout: &Layout, keys: &Keys) -> Result<PiccData, Error> {
+    let offset = |offset: usize| offset.checked_sub(NLEN_LEN).ok_or(Error::InvalidMirror);
+    let (picc_data, mac_input, mac) = (
+        offset(layout.picc_data)?,
+        offset(layout.mac_input)?,
+        offset(layout.mac)?,
+    );
+
+    let encrypted = decode_hex::<PICC_DATA_LEN>(message.get(picc_data..))?;
+    let received = decode_hex::<MAC_LEN>(message.get(mac..))?;
+    let input = message.get(mac_input..mac).ok_or(Error::InvalidMirror)?;
+
+    let picc = PiccData::decrypt(&keys.meta_read, &encrypted)?;
+    debug!("PICC data: {}", picc);
+    match crypto::matches(&picc.mac(&keys.file_read, input), &received) {
+        true => Ok(picc),
+        false => Err(Error::Mac),
+    }
+}
+
+/// Decode `N` bytes of ASCII hex at the start of `hex`
+fn decode_hex<const N: usize>(hex: Option<&[u8]>) -> Result<[u8; N], Error> {
+    let hex = hex
+        .and_then(|hex| hex.get(..2 * N))
+        .ok_or(Error::InvalidMirror)?;
+    let digit = |c: u8| match c {
+        b'0'..=b'9' => Ok(c - b'0'),
+        b'A'..=b'F' => Ok(c - b'A' + 10),
+        b'a'..=b'f' => Ok(c - b'a' + 10),
+        _ => Err(Error::InvalidMirror),
+    };
+    let mut bytes = [0u8; N];
+    for (byte, pair) in bytes.iter_mut().zip(hex.chunks_exact(2)) {
+        *byte = digit(pair[0])? << 4 | digit(pair[1])?;
+    }
+    Ok(bytes)
+}
@@ -0,0 +1,252 @@
+//! NFC Forum Type 2 Tag memory layout (NTAG21x, Mifare Ultralight)
+//!
+//! The memory is organized in pages of 4 bytes. Page 3 holds the capability container, the data
+//! area starts at page 4 with a list of TLV blocks, one of them holding the NDEF message.
+
+use crate::ndef;
+use core::fmt;
+
+/// Page of the capability container
+pub const CC_PAGE: u8 = 3;
+/// First page of the data area
+pub const DATA_PAGE: u8 = 4;
+/// Bytes in a page
+pub const PAGE_SIZE: usize = 4;
+
+/// Magic number of the capability container, the tag holds NDEF data
+const NDEF_MAGIC: u8 = 0xE1;
+/// Major version of the mapping document supported
+const VERSION_MAJOR: u8 = 1;
+
+const TLV_NULL: u8 = 0x00;
+const TLV_LOCK_CONTROL: u8 = 0x01;
+const TLV_MEMORY_CONTROL: u8 = 0x02;
+const TLV_NDEF_MESSAGE: u8 = 0x03;
+const TLV_TERMINATOR: u8 = 0xFE;
+
+/// Longest start of the data area searched for control TLVs
+const MAX_HEAD: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The capability container is missing, the tag isn't formatted for NDEF
+    NotFormatted,
+    /// Version of the capability container not supported
+    UnsupportedVersion(u8),
+    /// The tag doesn't grant write access
+    ReadOnly,
+    /// The message doesn't fit into the data area
+    TooLarge,
+}
+
+/// Capability container (page 3)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CapabilityContainer {
+    /// Version of the mapping document, major version in the upper nibble
+    pub version: u8,
+    /// Size of the data area in bytes
+    pub size: usize,
+    /// Read access condition, `0x0` grants access without any security
+    pub read_access: u8,
+    /// Write access condition, `0x0` grants access without any security, `0xF` none at all
+    pub write_access: u8,
+}
+
+impl CapabilityContainer {
+    pub fn parse(cc: [u8; 4]) -> Result<Self, Error> {
+        let [magic, version, size, access] = cc;
+        if magic != NDEF_MAGIC {
+            return Err(Error::NotFormatted);
+        }
+        if version >> 4 != VERSION_MAJOR {
+            return Err(Error::UnsupportedVersion(version));
+        }
+
+        Ok(Self {
+            version,
+            size: size as usize * 8,
+            read_access: access >> 4,
+            write_access: access & 0x0F,
+        })
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.write_access == 0x0
+    }
+}
+
+/// Position of an NDEF message TLV in the data area, followed by a terminator TLV
+///
+/// Lock and memory control TLVs at the start of the data area are kept, the message goes right
+/// behind them. The pages are zero padded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NdefLayout<'m> {
+    /// Start of the data area in front of the message TLV, unchanged
+    head: [u8; MAX_HEAD],
+    /// Offset of the message TLV in the data area
+    start: usize,
+    /// Type and length of the message TLV
+    header: [u8; 4],
+    header_len: usize,
+    message: &'m [u8],
+}
+
+impl<'m> NdefLayout<'m> {
+    /// Lay out `message` in a data area of `size` bytes, which currently starts with `data`
+    pub fn new(data: &[u8], message: &'m [u8], size: usize) -> Result<Self, Error> {
+        let start = skip_control_tlvs(data).ok_or(Error::NotFormatted)?;
+
+        let mut header = [TLV_NDEF_MESSAGE, 0, 0, 0];
+        let header_len = match message.len() {
+            len if len < 0xFF => {
+                header[1] = len as u8;
+                2
+            }
+            len if len <= 0xFFFE => {
+                header[1] = 0xFF;
+                header[2..4].copy_from_slice(&(len as u16).to_be_bytes());
+                4
+            }
+            _ => return Err(Error::TooLarge),
+        };
+
+        let mut head = [0u8; MAX_HEAD];
+        head[..start].copy_from_slice(&data[..start]);
+
+        let layout = Self {
+            head,
+            start,
+            header,
+            header_len,
+            message,
+        };
+        match layout.end() <= size {
+            true => Ok(layout),
+            false => Err(Error::TooLarge),
+        }
+    }
+
+    /// The pages to write, relative to [`DATA_PAGE`]
+    pub fn pages(&self) -> core::ops::Range<usize> {
+        // the terminator TLV makes `end` at least 1
+        self.start / PAGE_SIZE..(self.end() - 1) / PAGE_SIZE + 1
+    }
+
+    /// Content of the page `index`, relative to [`DATA_PAGE`]
+    pub fn page(&self, index: usize) -> [u8; PAGE_SIZE] {
+        let mut page = [0u8; PAGE_SIZE];
+        for (i, byte) in page.iter_mut().enumerate() {
+            *byte = self.byte(index * PAGE_SIZE + i);
+        }
+        page
+    }
+
+    fn byte(&self, offset: usize) -> u8 {
+        if offset < self.start {
+            return self.head[offset];
+        }
+        let offset = offset - self.start;
+        if offset < self.header_len {
+            return self.header[offset];
+        }
+        match self.message.get(offset - self.header_len) {
+            Some(&byte) => byte,
+            None if offset == self.header_len + self.message.len() => TLV_TERMINATOR,
+            None => 0x00,
+        }
+    }
+
+    /// End of the terminator TLV in the data area
+    fn end(&self) -> usize {
+        self.start + self.header_len + self.message.len() + 1
+    }
+}
+
+/// Offset of the first TLV which isn't a NULL, lock control or memory control TLV
+fn skip_control_tlvs(data: &[u8]) -> Option<usize> {
+    let data = &data[..data.len().min(MAX_HEAD)];
+    let mut offset = 0;
+    // end of the last control TLV
+    let mut end = 0;
+    while let Some(&tag) = data.get(offset) {
+        match tag {
+            TLV_NULL => offset += 1,
+            TLV_LOCK_CONTROL | TLV_MEMORY_CONTROL => {
+                offset += 2 + *data.get(offset + 1)? as usize;
+                end = offset;
+            }
+            _ => return Some(offset),
+        }
+    }
+    // nothing but padding behind the control TLVs
+    (end <= data.len()).then_some(end)
+}
+
+/// User memory of a Type 2 Tag, starting at [`DATA_PAGE`]
+///
+/// The bytes are stored as is in the `test/*.dump` files.
+#[derive(Clone, PartialEq, Eq)]
+pub struct MemoryImage {
+    data: [u8; Self::MAX_LEN],
+    len: usize,
+}
+
+impl MemoryImage {
+    /// The largest user memory supported, the one of the NTAG216
+    pub const MAX_LEN: usize = 888;
+
+    /// Load a dump, `None` if it is too long or doesn't consist of whole pages
+    pub fn from_bytes(dump: &[u8]) -> Option<Self> {
+        if dump.len() > Self::MAX_LEN || !dump.chunks_exact(PAGE_SIZE).remainder().is_empty() {
+            return None;
+        }
+        let mut data = [0u8; Self::MAX_LEN];
+        data[..dump.len()].copy_from_slice(dump);
+        Some(Self {
+            data,
+            len: dump.len(),
+        })
+    }
+
+    /// The dump, to be saved
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Content of `page`, counted from the start of the tag memory
+    pub fn page(&self, page: u8) -> Option<[u8; PAGE_SIZE]> {
+        let offset = (page.checked_sub(DATA_PAGE)? as usize) * PAGE_SIZE;
+        self.as_bytes()
+            .get(offset..offset + PAGE_SIZE)?
+            .try_into()
+            .ok()
+    }
+
+    /// Records of the NDEF message
+    pub fn ndef(&self) -> ndef::Reader<'_> {
+        ndef::Reader::new(self.as_bytes())
+    }
+}
+
+impl fmt::Debug for MemoryImage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "MemoryImage({:02X?})", self.as_bytes())
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for MemoryImage {
+    fn format(&self, fmt: defmt::Formatter) {
+        defmt::write!(fmt, "MemoryImage({:X})", self.as_bytes())
+    }
+}
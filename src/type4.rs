@@ -0,0 +1,236 @@
+//! NFC Forum Type 4 Tag NDEF mapping (ISO/IEC 14443-4 cards, like DESFire or NTAG 424 DNA)
+//!
+//! The NDEF data lives in the files of the NDEF Tag Application. Its capability container file
+//! lists the NDEF file and the largest data the card exchanges in a single APDU. The NDEF file
+//! starts with the length of the message (`NLEN`), followed by the bare message.
+
+use crate::driver::apdu::{ApduError, Command, StatusWord, MAX_SHORT_LE};
+use crate::driver::protocol::Interface;
+use crate::driver::{ReadError as DriverError, Reader};
+use embedded_hal_async::delay::DelayUs;
+
+/// Name of the NDEF Tag Application
+pub const NDEF_AID: [u8; 7] = [0xD2, 0x76, 0x00, 0x00, 0x85, 0x01, 0x01];
+/// File identifier of the capability container
+pub const CC_FILE: u16 = 0xE103;
+
+/// Length of the capability container with a single NDEF file control TLV
+pub const CC_LEN: usize = 15;
+/// Major version of the mapping document supported
+const VERSION_MAJOR: u8 = 2;
+/// Smallest `MLe` allowed
+const MIN_MLE: u16 = 0x000F;
+
+const TLV_NDEF_FILE_CONTROL: u8 = 0x04;
+
+const CLA: u8 = 0x00;
+const INS_SELECT: u8 = 0xA4;
+const INS_READ_BINARY: u8 = 0xB0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The NDEF Tag Application has no capability container
+    NotFormatted,
+    /// Version of the capability container not supported
+    UnsupportedVersion(u8),
+    /// The capability container doesn't describe an NDEF file
+    InvalidCapabilityContainer,
+    /// The NDEF file can't be read without security
+    ReadProtected,
+    /// `NLEN` exceeds the size of the NDEF file, or the card ran out of data
+    InvalidLength,
+    /// The card refused a command
+    Status(StatusWord),
+}
+
+/// Error of [`read_ndef`]
+#[derive(Debug)]
+pub enum ReadError<E> {
+    Apdu(ApduError<E>),
+    Tag(Error),
+}
+
+#[cfg(feature = "defmt")]
+impl<E> defmt::Format for ReadError<E> {
+    fn format(&self, fmt: defmt::Formatter) {
+        match self {
+            Self::Apdu(err) => defmt::write!(fmt, "{}", err),
+            Self::Tag(err) => defmt::write!(fmt, "Type 4 Tag error: {}", err),
+        }
+    }
+}
+
+impl<E> From<ApduError<E>> for ReadError<E> {
+    fn from(value: ApduError<E>) -> Self {
+        Self::Apdu(value)
+    }
+}
+
+impl<E> From<DriverError<E>> for ReadError<E> {
+    fn from(value: DriverError<E>) -> Self {
+        Self::Apdu(value.into())
+    }
+}
+
+impl<E> From<Error> for ReadError<E> {
+    fn from(value: Error) -> Self {
+        Self::Tag(value)
+    }
+}
+
+/// Capability container file, with the NDEF file control TLV
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CapabilityContainer {
+    /// Version of the mapping document, major version in the upper nibble
+    pub version: u8,
+    /// Most data read with a single READ BINARY
+    pub mle: u16,
+    /// Most data written with a single UPDATE BINARY
+    pub mlc: u16,
+    /// File identifier of the NDEF file
+    pub ndef_file: u16,
+    /// Size of the NDEF file in bytes, including `NLEN`
+    pub ndef_size: u16,
+    /// Read access condition, `0x00` grants access without any security
+    pub read_access: u8,
+    /// Write access condition, `0x00` grants access without any security, `0xFF` none at all
+    pub write_access: u8,
+}
+
+impl CapabilityContainer {
+    pub fn parse(cc: &[u8]) -> Result<Self, Error> {
+        let &[_, _, version, mle_high, mle_low, mlc_high, mlc_low, tlv, len, ref file @ ..] = cc
+        else {
+            return Err(Error::InvalidCapabilityContainer);
+        };
+        if version >> 4 != VERSION_MAJOR {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        let &[id_high, id_low, size_high, size_low, read_access, write_access, ..] = file else {
+            return Err(Error::InvalidCapabilityContainer);
+        };
+        if tlv != TLV_NDEF_FILE_CONTROL || len != 6 {
+            return Err(Error::InvalidCapabilityContainer);
+        }
+
+        let mle = u16::from_be_bytes([mle_high, mle_low]);
+        if mle < MIN_MLE {
+            return Err(Error::InvalidCapabilityContainer);
+        }
+        Ok(Self {
+            version,
+            mle,
+            mlc: u16::from_be_bytes([mlc_high, mlc_low]),
+            ndef_file: u16::from_be_bytes([id_high, id_low]),
+            ndef_size: u16::from_be_bytes([size_high, size_low]),
+            read_access,
+            write_access,
+        })
+    }
+
+    pub fn is_readable(&self) -> bool {
+        self.read_access == 0x00
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.write_access == 0x00
+    }
+}
+
+/// Read the NDEF message of the Type 4 Tag with target number `tg` into `buf`
+///
+/// `None` if the card has no NDEF Tag Application, the NDEF file is empty, or the message and the
+/// status word don't fit into `buf`. The message is bare, without a TLV, see
+/// [`ndef::Reader::from_message`](crate::ndef::Reader::from_message).
+pub async fn read_ndef<'b, I: Interface, T: DelayUs>(
+    reader: &mut Reader<I, T>,
+    tg: u8,
+    buf: &'b mut [u8],
+) -> Result<Option<&'b [u8]>, ReadError<I::Error>> {
+    let select = Command::new(CLA, INS_SELECT, 0x04, 0x00)
+        .with_data(&NDEF_AID)
+        .with_le(MAX_SHORT_LE);
+    let response = reader.transmit(tg, &select, buf).await?;
+    match response.sw {
+        StatusWord::SUCCESS => {}
+        StatusWord::NOT_FOUND => return Ok(None),
+        sw => return Err(Error::Status(sw).into()),
+    }
+
+    if !select_file(reader, tg, CC_FILE, buf).await? {
+        return Err(Error::NotFormatted.into());
+    }
+    let cc = read_binary(reader, tg, 0, CC_LEN, buf).await?;
+    let cc = CapabilityContainer::parse(cc)?;
+    debug!("CC: {}", cc);
+    if !cc.is_readable() {
+        return Err(Error::ReadProtected.into());
+    }
+
+    if !select_file(reader, tg, cc.ndef_file, buf).await? {
+        return Err(Error::InvalidCapabilityContainer.into());
+    }
+    let nlen = read_binary(reader, tg, 0, 2, buf).await?;
+    let &[high, low] = nlen else {
+        return Err(Error::InvalidLength.into());
+    };
+    let nlen = u16::from_be_bytes([high, low]) as usize;
+    info!("Message len: {}", nlen);
+    if nlen + 2 > cc.ndef_size as usize {
+        return Err(Error::InvalidLength.into());
+    }
+    // every response ends with the status word
+    if nlen == 0 || nlen + 2 > buf.len() {
+        return Ok(None);
+    }
+
+    // the message is read in chunks of MLe, which short APDUs limit to 256 bytes
+    let chunk = (cc.mle as usize).min(MAX_SHORT_LE);
+    let mut len = 0;
+    while len < nlen {
+        let le = chunk.min(nlen - len);
+        debug!("Read {} bytes at {}", le, 2 + len);
+        let received = read_binary(reader, tg, 2 + len, le, &mut buf[len..]).await?;
+        if received.is_empty() {
+            return Err(Error::InvalidLength.into());
+        }
+        len += received.len();
+    }
+    let buf: &'b [u8] = buf;
+    Ok(Some(&buf[..nlen]))
+}
+
+/// Select an elementary file of the NDEF Tag Application, `false` if it doesn't exist
+async fn select_file<I: Interface, T: DelayUs>(
+    reader: &mut Reader<I, T>,
+    tg: u8,
+    file: u16,
+    buf: &mut [u8],
+) -> Result<bool, ReadError<I::Error>> {
+    let id = file.to_be_bytes();
+    let select = Command::new(CLA, INS_SELECT, 0x00, 0x0C).with_data(&id);
+    match reader.transmit(tg, &select, buf).await?.sw {
+        StatusWord::SUCCESS => Ok(true),
+        StatusWord::NOT_FOUND => Ok(false),
+        sw => Err(Error::Status(sw).into()),
+    }
+}
+
+/// Read up to `le` bytes of the selected file, starting at `offset`
+async fn read_binary<'b, I: Interface, T: DelayUs>(
+    reader: &mut Reader<I, T>,
+    tg: u8,
+    offset: usize,
+    le: usize,
+    buf: &'b mut [u8],
+) -> Result<&'b [u8], ReadError<I::Error>> {
+    let [p1, p2] = (offset as u16).to_be_bytes();
+    let read = Command::new(CLA, INS_READ_BINARY, p1, p2).with_le(le);
+    reader
+        .transmit(tg, &read, buf)
+        .await?
+        .check()
+        .map_err(|sw| Error::Status(sw).into())
+}
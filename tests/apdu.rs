@@ -0,0 +1,216 @@
+#[cfg(feature = "simulator")]
+mod common;
+
+use vat_card_reader::driver::apdu::{Command, EncodeError, Response, StatusWord};
+
+#[test]
+fn encode_short() {
+    let mut buf = [0u8; 16];
+
+    // case 1, header only
+    let command = Command::new(0x00, 0x70, 0x00, 0x00);
+    assert_eq!(command.encode(&mut buf).unwrap(), [0x00, 0x70, 0x00, 0x00]);
+
+    // case 2, Le of 256 encoded as zero
+    let command = Command::new(0x00, 0xB0, 0x00, 0x02).with_le(256);
+    assert!(!command.is_extended());
+    assert_eq!(
+        command.encode(&mut buf).unwrap(),
+        [0x00, 0xB0, 0x00, 0x02, 0x00]
+    );
+
+    // case 3, select the NDEF application
+    let aid = [0xD2, 0x76, 0x00, 0x00, 0x85, 0x01, 0x01];
+    let command = Command::new(0x00, 0xA4, 0x04, 0x00).with_data(&aid);
+    assert_eq!(
+        command.encode(&mut buf).unwrap(),
+        [0x00, 0xA4, 0x04, 0x00, 0x07, 0xD2, 0x76, 0x00, 0x00, 0x85, 0x01, 0x01]
+    );
+
+    // case 4
+    let command = command.with_le(256);
+    assert_eq!(command.encoded_len(), 13);
+    assert_eq!(command.encode(&mut buf).unwrap()[12], 0x00);
+
+    assert_eq!(
+        command.encode(&mut buf[..12]),
+        Err(EncodeError::BufferTooSmall)
+    );
+    assert_eq!(
+        Command::new(0x00, 0xB0, 0x00, 0x00)
+            .with_le(0)
+            .encode(&mut buf),
+        Err(EncodeError::InvalidLength)
+    );
+}
+
+#[test]
+fn encode_extended() {
+    let mut buf = [0u8; 512];
+
+    // case 2, Le of 65536 encoded as zero
+    let command = Command::new(0x00, 0xB0, 0x00, 0x00).with_le(65536);
+    assert!(command.is_extended());
+    assert_eq!(
+        command.encode(&mut buf).unwrap(),
+        [0x00, 0xB0, 0x00, 0x00, 0x00, 0x00, 0x00]
+    );
+    let command = Command::new(0x00, 0xB0, 0x00, 0x00).with_le(257);
+    assert_eq!(
+        command.encode(&mut buf).unwrap(),
+        [0x00, 0xB0, 0x00, 0x00, 0x00, 0x01, 0x01]
+    );
+
+    // case 3
+    let data = [0xAB; 300];
+    let command = Command::new(0x00, 0xD6, 0x00, 0x00).with_data(&data);
+    let apdu = command.encode(&mut buf).unwrap();
+    assert_eq!(apdu.len(), 4 + 3 + 300);
+    assert_eq!(apdu[4..7], [0x00, 0x01, 0x2C]);
+    assert_eq!(apdu[7..], data);
+
+    // case 4, a short Le turns extended along with the data
+    let apdu = command.with_le(16).encode(&mut buf).unwrap();
+    assert_eq!(apdu.len(), 4 + 3 + 300 + 2);
+    assert_eq!(apdu[307..], [0x00, 0x10]);
+}
+
+#[test]
+fn response() {
+    let response = Response::parse(&[0x01, 0x02, 0x90, 0x00]).unwrap();
+    assert_eq!(response.data, [0x01, 0x02]);
+    assert!(response.sw.is_success());
+    assert_eq!(response.check(), Ok(&[0x01, 0x02][..]));
+
+    let response = Response::parse(&[0x6A, 0x82]).unwrap();
+    assert_eq!(response.sw, StatusWord::NOT_FOUND);
+    assert_eq!(response.sw.sw1(), 0x6A);
+    assert_eq!(response.sw.sw2(), 0x82);
+    assert_eq!(response.check(), Err(StatusWord::NOT_FOUND));
+
+    assert_eq!(Response::parse(&[0x90]), None);
+
+    assert_eq!(StatusWord(0x6110).bytes_available(), Some(0x10));
+    assert_eq!(StatusWord(0x6100).bytes_available(), Some(256));
+    assert_eq!(StatusWord(0x6C05).exact_le(), Some(5));
+    assert_eq!(StatusWord::SUCCESS.bytes_available(), None);
+    assert_eq!(StatusWord::SUCCESS.exact_le(), None);
+}
+
+/// ISO-DEP cards in the simulator
+#[cfg(feature = "simulator")]
+mod simulator {
+    use super::common::UID;
+    use embassy_futures::block_on;
+    use vat_card_reader::driver::apdu::{ApduError, Command, StatusWord};
+    use vat_card_reader::driver::protocol;
+    use vat_card_reader::driver::requests::CardType;
+    use vat_card_reader::driver::simulator::{Application, Delay, IsoDep, Simulator};
+    use vat_card_reader::driver::{Error, ReadError, Reader};
+
+    /// Activate the ISO-DEP card and select its file, returning the target number
+    fn select_file(reader: &mut Reader<&mut Simulator, Delay>) -> u8 {
+        let target = block_on(reader.read_passive_target(CardType::IsoTypeA))
+            .unwrap()
+            .unwrap();
+        let select = Command::new(0x00, 0xA4, 0x04, 0x00).with_data(&[0xF0, 0x01, 0x02]);
+        let mut buf = [0u8; 16];
+        let response = block_on(reader.transmit(target.tg(), &select, &mut buf)).unwrap();
+        assert_eq!(response.check(), Ok(&[][..]));
+        let select = Command::new(0x00, 0xA4, 0x00, 0x0C).with_data(&[0x01, 0x01]);
+        let response = block_on(reader.transmit(target.tg(), &select, &mut buf)).unwrap();
+        assert_eq!(response.sw, StatusWord::SUCCESS);
+        target.tg()
+    }
+
+    #[test]
+    fn apdu_chaining() {
+        let file: Vec<u8> = (0..600).map(|i| i as u8).collect();
+        let card = IsoDep::new(UID)
+            .with_application(Application::new(&[0xF0, 0x01, 0x02]).with_file(0x0101, &file));
+        let mut simulator = Simulator::new().with_target(card);
+        let mut reader = Reader::new(&mut simulator, Delay);
+        let tg = select_file(&mut reader);
+        let mut buf = [0u8; 1024];
+
+        // the answer takes three frames
+        let read = Command::new(0x00, 0xB0, 0x00, 0x00).with_le(600);
+        let response = block_on(reader.transmit(tg, &read, &mut buf)).unwrap();
+        assert_eq!(response.check(), Ok(&file[..]));
+
+        // and so does the command
+        let data = [0xA5; 520];
+        let update = Command::new(0x00, 0xD6, 0x00, 0x10).with_data(&data);
+        let response = block_on(reader.transmit(tg, &update, &mut buf)).unwrap();
+        assert_eq!(response.sw, StatusWord::SUCCESS);
+        let response = block_on(reader.transmit(tg, &read, &mut buf)).unwrap();
+        assert_eq!(response.data[..0x10], file[..0x10]);
+        assert_eq!(response.data[0x10..0x10 + 520], data);
+
+        // too long for the buffer
+        assert!(matches!(
+            block_on(reader.transmit(tg, &read, &mut buf[..300])),
+            Err(ApduError::Reader(ReadError::Reader(Error::Protocol(
+                protocol::Error::BufferUnderflow
+            ))))
+        ));
+
+        let exchanges = simulator
+            .commands()
+            .iter()
+            .filter(|(command, _)| *command == 0x40)
+            .count();
+        assert_eq!(exchanges, 2 + 3 + 3 + 3 + 2);
+    }
+
+    #[test]
+    fn apdu_get_response() {
+        let file: Vec<u8> = (0..200).map(|i| i as u8).collect();
+        let card = IsoDep::new(UID)
+            .with_response_limit(64)
+            .with_application(Application::new(&[0xF0, 0x01, 0x02]).with_file(0x0101, &file));
+        let mut simulator = Simulator::new().with_target(card);
+        let mut reader = Reader::new(&mut simulator, Delay);
+        let tg = select_file(&mut reader);
+        let mut buf = [0u8; 256];
+
+        let read = Command::new(0x00, 0xB0, 0x00, 0x00).with_le(256);
+        let response = block_on(reader.transmit(tg, &read, &mut buf)).unwrap();
+        assert_eq!(response.check(), Ok(&file[..]));
+
+        // nothing left to fetch
+        let get_response = Command::new(0x00, 0xC0, 0x00, 0x00).with_le(16);
+        let response = block_on(reader.transmit(tg, &get_response, &mut buf)).unwrap();
+        assert_eq!(response.sw, StatusWord::CONDITIONS_NOT_SATISFIED);
+
+        let get_responses = simulator
+            .commands()
+            .iter()
+            .filter(|(_, data)| data.get(2) == Some(&0xC0))
+            .count();
+        assert_eq!(get_responses, 3 + 1);
+    }
+
+    #[test]
+    fn apdu_exact_le() {
+        let card = IsoDep::new(UID)
+            .with_exact_le()
+            .with_application(Application::new(&[0xF0, 0x01, 0x02]).with_file(0x0101, b"hello"));
+        let mut simulator = Simulator::new().with_target(card);
+        let mut reader = Reader::new(&mut simulator, Delay);
+        let tg = select_file(&mut reader);
+        let mut buf = [0u8; 256];
+
+        let read = Command::new(0x00, 0xB0, 0x00, 0x00).with_le(256);
+        let response = block_on(reader.transmit(tg, &read, &mut buf)).unwrap();
+        assert_eq!(response.check(), Ok(&b"hello"[..]));
+
+        let reads: Vec<u8> = simulator
+            .commands()
+            .iter()
+            .filter(|(_, data)| data.get(2) == Some(&0xB0))
+            .map(|(_, data)| data[5])
+            .collect();
+        assert_eq!(reads, [0x00, 0x05]);
+    }
+}
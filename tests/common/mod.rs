@@ -0,0 +1,24 @@
+//! Cards and helpers shared by the tests
+#![allow(dead_code)]
+
+#[cfg(feature = "simulator")]
+mod simulator;
+
+#[cfg(feature = "simulator")]
+#[allow(unused_imports)]
+pub use simulator::{activate, ndef_application};
+
+pub const NDEF1: &[u8] = include_bytes!("../../test/ndef1.dump");
+pub const UID: [u8; 7] = [0x04, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66];
+/// Random number of the reader in the DESFire and NTAG 424 DNA authentications
+pub const RND_A: [u8; 16] = [
+    0x13, 0xC5, 0xDB, 0x8A, 0x59, 0x30, 0x43, 0x9F, 0xC3, 0xD4, 0x5A, 0xA1, 0x7A, 0x49, 0x05, 0xFE,
+];
+
+/// Parse a hex string, ignoring spaces
+pub fn hex(s: &str) -> Vec<u8> {
+    let s: Vec<u8> = s.bytes().filter(|b| *b != b' ').collect();
+    s.chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16).unwrap())
+        .collect()
+}
@@ -0,0 +1,37 @@
+//! Helpers for the tests running against the simulator
+
+use embassy_futures::block_on;
+use vat_card_reader::driver::protocol::Interface;
+use vat_card_reader::driver::requests::CardType;
+use vat_card_reader::driver::simulator::{Application, Delay, Error};
+use vat_card_reader::driver::{CardKind, Reader, TargetInfo};
+use vat_card_reader::type4;
+
+/// Activate the card in the field and classify it, like the firmware does
+pub fn activate<I>(reader: &mut Reader<I, Delay>) -> (TargetInfo, CardKind)
+where
+    I: Interface<Error = Error>,
+{
+    let target = block_on(reader.read_passive_target(CardType::IsoTypeA))
+        .unwrap()
+        .unwrap();
+    let kind = block_on(reader.classify(&target)).unwrap();
+    (target, kind)
+}
+
+/// The NDEF Tag Application holding `message`, read in chunks of up to `mle` bytes
+pub fn ndef_application(message: &[u8], mle: u16) -> Application {
+    let mut file = (message.len() as u16).to_be_bytes().to_vec();
+    file.extend_from_slice(message);
+    file.resize(file.len() + 32, 0x00);
+
+    let mut cc = vec![0x00, 0x0F, 0x20];
+    cc.extend_from_slice(&mle.to_be_bytes());
+    cc.extend_from_slice(&[0x00, 0x3B, 0x04, 0x06, 0xE1, 0x04]);
+    cc.extend_from_slice(&(file.len() as u16).to_be_bytes());
+    cc.extend_from_slice(&[0x00, 0x00]);
+
+    Application::new(&type4::NDEF_AID)
+        .with_file(type4::CC_FILE, &cc)
+        .with_file(0xE104, &file)
+}
@@ -0,0 +1,115 @@
+mod common;
+
+use common::hex;
+use vat_card_reader::crypto::{crc16, crc32, Cipher};
+use vat_card_reader::driver::desfire::Key;
+
+#[test]
+fn aes_cmac() {
+    // NIST SP 800-38B, D.1
+    let key = hex("2b7e1516 28aed2a6 abf71588 09cf4f3c");
+    let cipher = Cipher::aes(key[..].try_into().unwrap());
+    let message = hex(
+        "6bc1bee2 2e409f96 e93d7e11 7393172a ae2d8a57 1e03ac9c 9eb76fac 45af8e51 \
+         30c81c46 a35ce411 e5fbc119 1a0a52ef f69f2445 df4f9b17 ad2b417b e66c3710",
+    );
+    let iv = [0u8; 16];
+    assert_eq!(
+        cipher.cmac(&iv, &[])[..],
+        hex("bb1d6929 e9593728 7fa37d12 9b756746")
+    );
+    assert_eq!(
+        cipher.cmac(&iv, &message[..16])[..],
+        hex("070a16b4 6b4d4144 f79bdd9d d04a287c")
+    );
+    assert_eq!(
+        cipher.cmac(&iv, &message[..40])[..],
+        hex("dfa66747 de9ae630 30ca3261 1497c827")
+    );
+    assert_eq!(
+        cipher.cmac(&iv, &message)[..],
+        hex("51f0bebf 7e3b9d92 fc497417 79363cfe")
+    );
+}
+
+#[test]
+fn tdes_cmac() {
+    // NIST SP 800-38B, D.4, two key TDEA
+    let key = hex("4cf15134 a2850dd5 8a3d10ba 80570d38");
+    let cipher = Cipher::tdes(key[..].try_into().unwrap());
+    let message = hex("6bc1bee2 2e409f96 e93d7e11 7393172a ae2d8a57 1e03ac9c 9eb76fac 45af8e51");
+    let iv = [0u8; 16];
+    assert_eq!(cipher.cmac(&iv, &[])[..8], hex("bd2ebf9a 3ba00361"));
+    assert_eq!(
+        cipher.cmac(&iv, &message[..8])[..8],
+        hex("4ff2ab81 3c53ce83")
+    );
+    assert_eq!(
+        cipher.cmac(&iv, &message[..20])[..8],
+        hex("62dd1b47 1902bd4e")
+    );
+    assert_eq!(cipher.cmac(&iv, &message)[..8], hex("31b1e431 dabc4eb8"));
+}
+
+#[test]
+fn cbc() {
+    // NIST SP 800-38A, F.2.1 and F.2.2
+    let key = hex("2b7e1516 28aed2a6 abf71588 09cf4f3c");
+    let cipher = Cipher::aes(key[..].try_into().unwrap());
+    let plain = hex("6bc1bee2 2e409f96 e93d7e11 7393172a ae2d8a57 1e03ac9c 9eb76fac 45af8e51");
+    let encrypted = hex("7649abac 8119b246 cee98e9b 12e9197d 5086cb9b 507219ee 95db113a 917678b2");
+
+    let mut iv: [u8; 16] = hex("00010203 04050607 08090a0b 0c0d0e0f")
+        .try_into()
+        .unwrap();
+    let mut data = plain.clone();
+    cipher.encrypt_cbc(&mut iv, &mut data);
+    assert_eq!(data, encrypted);
+    assert_eq!(iv[..], encrypted[16..]);
+
+    let mut iv: [u8; 16] = hex("00010203 04050607 08090a0b 0c0d0e0f")
+        .try_into()
+        .unwrap();
+    cipher.decrypt_cbc(&mut iv, &mut data);
+    assert_eq!(data, plain);
+    assert_eq!(iv[..], encrypted[16..]);
+}
+
+#[test]
+fn checksums() {
+    // JAMCRC and CRC-16/ISO-IEC-14443-3-A check values
+    assert_eq!(crc32(b"123456789"), 0x340B_C6D9);
+    assert_eq!(crc16(b"123456789"), 0xBF05);
+}
+
+#[test]
+fn session_keys() {
+    let rnd_a: Vec<u8> = (0x00..0x10).collect();
+    let rnd_b: Vec<u8> = (0x10..0x20).collect();
+
+    let aes = Key::Aes([0x00; 16]).session_key(&rnd_a, &rnd_b);
+    assert_eq!(
+        aes,
+        Key::Aes(
+            hex("00010203 10111213 0c0d0e0f 1c1d1e1f")
+                .try_into()
+                .unwrap()
+        )
+    );
+    let des = Key::DEFAULT_DES.session_key(&rnd_a, &rnd_b);
+    assert_eq!(des, Key::Des(hex("00010203 10111213").try_into().unwrap()));
+    let tdes = hex("01234567 89abcdef fedcba98 76543210");
+    let tdes = Key::Tdes(tdes.try_into().unwrap()).session_key(&rnd_a, &rnd_b);
+    assert_eq!(
+        tdes,
+        Key::Tdes(
+            hex("00010203 10111213 04050607 14151617")
+                .try_into()
+                .unwrap()
+        )
+    );
+
+    // halves differing in the parity bits only act as single DES
+    let des = hex("00112233 44556677 01102332 45546776");
+    assert!(Key::Tdes(des.try_into().unwrap()).is_single_des());
+}
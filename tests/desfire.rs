@@ -0,0 +1,209 @@
+mod common;
+
+use common::{RND_A, UID};
+use embassy_futures::block_on;
+use vat_card_reader::driver::desfire::{
+    CommMode, DesFireError, Key as DesFireKey, Status as DesFireStatus,
+};
+use vat_card_reader::driver::requests::CardType;
+use vat_card_reader::driver::simulator::{Delay, DesFire, DesFireApplication, IsoDep, Simulator};
+use vat_card_reader::driver::{CardKind, DesFireVersion, Reader};
+
+const DESFIRE_AES: DesFireKey = DesFireKey::Aes([
+    0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF,
+]);
+const DESFIRE_TDES: DesFireKey = DesFireKey::Tdes([
+    0x01, 0x23, 0x45, 0x67, 0x89, 0xAB, 0xCD, 0xEF, 0xFE, 0xDC, 0xBA, 0x98, 0x76, 0x54, 0x32, 0x10,
+]);
+const DESFIRE_DES: DesFireKey = DesFireKey::Des([0x10, 0x32, 0x54, 0x76, 0x98, 0xBA, 0xDC, 0xFE]);
+
+/// A DESFire with an application of an AES, a 2K3DES and a DES key, and files of all
+/// communication settings
+fn desfire() -> IsoDep {
+    let file: Vec<u8> = (0..100).collect();
+    let application = DesFireApplication::new(0x563412)
+        .with_key(DESFIRE_AES)
+        .with_key(DESFIRE_TDES)
+        .with_key(DESFIRE_DES)
+        .with_data_file(0, CommMode::Plain, None, b"free")
+        .with_data_file(1, CommMode::Mac, Some(0), &file)
+        .with_data_file(2, CommMode::Full, Some(0), &file)
+        .with_data_file(3, CommMode::Mac, Some(1), &file)
+        .with_data_file(4, CommMode::Full, Some(1), &file)
+        .with_data_file(5, CommMode::Mac, Some(2), &file)
+        .with_data_file(6, CommMode::Full, Some(2), &file)
+        .with_value_file(7, CommMode::Full, Some(0), -42);
+    IsoDep::new(UID).with_desfire(
+        DesFire::new()
+            .with_application(DesFireApplication::new(0x000102))
+            .with_application(application),
+    )
+}
+
+#[test]
+fn desfire_commands() {
+    let mut simulator = Simulator::new().with_target(desfire());
+    let mut reader = Reader::new(&mut simulator, Delay);
+    let target = block_on(reader.read_passive_target(CardType::IsoTypeA))
+        .unwrap()
+        .unwrap();
+    let mut desfire = reader.desfire(target.tg());
+
+    let version = block_on(desfire.get_version()).unwrap();
+    assert_eq!(version.uid, UID);
+    assert!(matches!(
+        version.kind(),
+        Some(CardKind::DesFire {
+            version: DesFireVersion::Ev1,
+            ..
+        })
+    ));
+
+    let mut aids = [0u32; 4];
+    let count = block_on(desfire.get_application_ids(&mut aids)).unwrap();
+    assert_eq!(aids[..count], [0x000102, 0x563412]);
+    assert!(matches!(
+        block_on(desfire.select_application(0x123456)),
+        Err(DesFireError::Status(DesFireStatus::ApplicationNotFound))
+    ));
+
+    block_on(desfire.select_application(0x563412)).unwrap();
+    let mut ids = [0u8; 8];
+    let count = block_on(desfire.get_file_ids(&mut ids)).unwrap();
+    assert_eq!(ids[..count], [0, 1, 2, 3, 4, 5, 6, 7]);
+    let mut buf = [0u8; 128];
+    let data = block_on(desfire.read_data(0, 0, 0, CommMode::Plain, &mut buf)).unwrap();
+    assert_eq!(data, b"free");
+    let data = block_on(desfire.read_data(0, 1, 2, CommMode::Plain, &mut buf)).unwrap();
+    assert_eq!(data, b"re");
+    assert!(matches!(
+        block_on(desfire.read_data(0, 2, 4, CommMode::Plain, &mut buf)),
+        Err(DesFireError::Status(DesFireStatus::BoundaryError))
+    ));
+    assert!(matches!(
+        block_on(desfire.read_data(1, 0, 0, CommMode::Mac, &mut buf)),
+        Err(DesFireError::Status(DesFireStatus::AuthenticationError))
+    ));
+    assert!(matches!(
+        block_on(desfire.read_data(9, 0, 0, CommMode::Plain, &mut buf)),
+        Err(DesFireError::Status(DesFireStatus::FileNotFound))
+    ));
+}
+
+#[test]
+fn desfire_secure_messaging() {
+    let file: Vec<u8> = (0..100).collect();
+    let sessions = [
+        (0, DESFIRE_AES, 1, 2),
+        (1, DESFIRE_TDES, 3, 4),
+        (2, DESFIRE_DES, 5, 6),
+    ];
+    for (key_no, key, mac_file, full_file) in sessions {
+        let mut simulator = Simulator::new().with_target(desfire());
+        let mut reader = Reader::new(&mut simulator, Delay);
+        let target = block_on(reader.read_passive_target(CardType::IsoTypeA))
+            .unwrap()
+            .unwrap();
+        let mut desfire = reader.desfire(target.tg());
+        block_on(desfire.select_application(0x563412)).unwrap();
+        block_on(desfire.authenticate(key_no, &key, &RND_A)).unwrap();
+        assert_eq!(desfire.authenticated_key(), Some(key_no));
+
+        // every answer is MACed in the session, the whole file takes two frames
+        let mut buf = [0u8; 256];
+        let data = block_on(desfire.read_data(0, 0, 0, CommMode::Plain, &mut buf)).unwrap();
+        assert_eq!(data, b"free");
+        let data = block_on(desfire.read_data(mac_file, 0, 0, CommMode::Mac, &mut buf)).unwrap();
+        assert_eq!(data, file);
+        let data = block_on(desfire.read_data(full_file, 0, 0, CommMode::Full, &mut buf)).unwrap();
+        assert_eq!(data, file);
+        let data = block_on(desfire.read_data(full_file, 10, 5, CommMode::Full, &mut buf)).unwrap();
+        assert_eq!(data, &file[10..15]);
+        let mut ids = [0u8; 8];
+        assert_eq!(block_on(desfire.get_file_ids(&mut ids)).unwrap(), 8);
+
+        // the MAC doesn't decrypt
+        assert!(matches!(
+            block_on(desfire.read_data(mac_file, 0, 0, CommMode::Full, &mut buf)),
+            Err(DesFireError::Integrity)
+        ));
+        assert_eq!(desfire.authenticated_key(), None);
+    }
+
+    let mut simulator = Simulator::new().with_target(desfire());
+    let mut reader = Reader::new(&mut simulator, Delay);
+    let target = block_on(reader.read_passive_target(CardType::IsoTypeA))
+        .unwrap()
+        .unwrap();
+    let mut desfire = reader.desfire(target.tg());
+    block_on(desfire.select_application(0x563412)).unwrap();
+    block_on(desfire.authenticate(0, &DESFIRE_AES, &RND_A)).unwrap();
+    assert_eq!(block_on(desfire.get_value(7, CommMode::Full)).unwrap(), -42);
+
+    // selecting ends the session
+    block_on(desfire.select_application(0x563412)).unwrap();
+    assert_eq!(desfire.authenticated_key(), None);
+    assert!(matches!(
+        block_on(desfire.get_value(7, CommMode::Full)),
+        Err(DesFireError::Status(DesFireStatus::AuthenticationError))
+    ));
+}
+
+#[test]
+fn desfire_legacy_authentication() {
+    let file: Vec<u8> = (0..100).collect();
+    let sessions = [(1, DESFIRE_TDES, 3, 4), (2, DESFIRE_DES, 5, 6)];
+    for (key_no, key, mac_file, full_file) in sessions {
+        let mut simulator = Simulator::new().with_target(desfire());
+        let mut reader = Reader::new(&mut simulator, Delay);
+        let target = block_on(reader.read_passive_target(CardType::IsoTypeA))
+            .unwrap()
+            .unwrap();
+        let mut desfire = reader.desfire(target.tg());
+        block_on(desfire.select_application(0x563412)).unwrap();
+        let rnd_a = RND_A[..8].try_into().unwrap();
+        block_on(desfire.authenticate_legacy(key_no, &key, &rnd_a)).unwrap();
+
+        let mut buf = [0u8; 256];
+        let data = block_on(desfire.read_data(0, 0, 0, CommMode::Plain, &mut buf)).unwrap();
+        assert_eq!(data, b"free");
+        let data = block_on(desfire.read_data(mac_file, 0, 0, CommMode::Mac, &mut buf)).unwrap();
+        assert_eq!(data, file);
+        let data = block_on(desfire.read_data(full_file, 0, 0, CommMode::Full, &mut buf)).unwrap();
+        assert_eq!(data, file);
+    }
+}
+
+#[test]
+fn desfire_wrong_key() {
+    let mut simulator = Simulator::new().with_target(desfire());
+    let mut reader = Reader::new(&mut simulator, Delay);
+    let target = block_on(reader.read_passive_target(CardType::IsoTypeA))
+        .unwrap()
+        .unwrap();
+    let mut desfire = reader.desfire(target.tg());
+
+    // the card level has the default key
+    block_on(desfire.authenticate(0, &DesFireKey::DEFAULT_DES, &RND_A)).unwrap();
+    block_on(desfire.select_application(0x563412)).unwrap();
+
+    let wrong = DesFireKey::Aes([0x00; 16]);
+    assert!(matches!(
+        block_on(desfire.authenticate(0, &wrong, &RND_A)),
+        Err(DesFireError::Authentication)
+    ));
+    // the key is a DES key
+    assert!(matches!(
+        block_on(desfire.authenticate(1, &wrong, &RND_A)),
+        Err(DesFireError::Authentication)
+    ));
+    assert!(matches!(
+        block_on(desfire.authenticate(3, &DESFIRE_AES, &RND_A)),
+        Err(DesFireError::Status(DesFireStatus::NoSuchKey))
+    ));
+    assert!(matches!(
+        block_on(desfire.authenticate_legacy(0, &DESFIRE_AES, &[0x00; 8])),
+        Err(DesFireError::UnsupportedKey)
+    ));
+    assert_eq!(desfire.authenticated_key(), None);
+}
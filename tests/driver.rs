@@ -0,0 +1,329 @@
+#![feature(async_fn_in_trait)]
+#![allow(incomplete_features)]
+
+use embassy_futures::block_on;
+use embedded_hal_async::delay::DelayUs;
+use vat_card_reader::driver::ntag::{Access, Config};
+use vat_card_reader::driver::protocol::{self, Interface};
+use vat_card_reader::driver::requests::CardType;
+use vat_card_reader::driver::{
+    CardUid, Error, FeliCaTarget, FirmwareVersion, IsoTypeATarget, JewelTarget, Reader,
+    RetryPolicy, Status, TargetInfo,
+};
+
+const ACK: [u8; 6] = [0x00, 0x00, 0xFF, 0x00, 0xFF, 0x00];
+
+/// Build a PN532 to host frame
+fn frame(data: &[u8]) -> Vec<u8> {
+    let len = data.len() as u8 + 1;
+    let sum = data.iter().fold(0xD5u8, |s, b| s.wrapping_add(*b));
+    let mut frame = vec![0x00, 0x00, 0xFF, len, len.wrapping_neg(), 0xD5];
+    frame.extend_from_slice(data);
+    frame.push(sum.wrapping_neg());
+    frame.push(0x00);
+    frame
+}
+
+struct NoDelay;
+
+impl DelayUs for NoDelay {
+    async fn delay_us(&mut self, _us: u32) {}
+
+    async fn delay_ms(&mut self, _ms: u32) {}
+}
+
+/// Interface replying with an ACK followed by a fixed response
+///
+/// Each reply can be read in several chunks, until the next `wait_for_ready`.
+struct Scripted {
+    sent: Vec<Vec<u8>>,
+    replies: Vec<Vec<u8>>,
+    reading: Option<Vec<u8>>,
+}
+
+impl Scripted {
+    fn new(response: Vec<u8>) -> Self {
+        Self::with_replies(ACK.to_vec(), response)
+    }
+
+    fn with_replies(ack: Vec<u8>, response: Vec<u8>) -> Self {
+        Self {
+            sent: Vec::new(),
+            replies: vec![response, ack],
+            reading: None,
+        }
+    }
+}
+
+impl Interface for Scripted {
+    type Error = ();
+
+    async fn send(&mut self, request: &[u8]) -> Result<(), protocol::Error<Self::Error>> {
+        self.sent.push(request.to_vec());
+        Ok(())
+    }
+
+    async fn receive(&mut self, buf: &mut [u8]) -> Result<(), protocol::Error<Self::Error>> {
+        let reply = match self.reading.take() {
+            Some(reply) => reply,
+            None => self.replies.pop().ok_or(protocol::Error::Transport(()))?,
+        };
+        let len = reply.len().min(buf.len());
+        buf.fill(0);
+        buf[..len].copy_from_slice(&reply[..len]);
+        self.reading = Some(reply[len..].to_vec());
+        Ok(())
+    }
+
+    async fn wait_for_ready(&mut self) -> Result<(), protocol::Error<Self::Error>> {
+        self.reading = None;
+        Ok(())
+    }
+}
+
+#[test]
+fn firmware_version() {
+    let mut reader = Reader::new(
+        Scripted::new(frame(&[0x03, 0x32, 0x01, 0x06, 0x07])),
+        NoDelay,
+    );
+
+    let version = block_on(reader.get_firmware_version()).unwrap();
+    assert_eq!(
+        version,
+        FirmwareVersion {
+            ic: 0x32,
+            version: 1,
+            revision: 6,
+            supports_iso18092: true,
+            supports_iso14443_a: true,
+            supports_iso14443_b: true,
+        }
+    );
+}
+
+#[test]
+fn request_frame() {
+    let mut interface = Scripted::new(frame(&[0x15]));
+    let mut protocol = protocol::Protocol::<_, _, 64>::new(&mut interface, NoDelay);
+    block_on(protocol.request(0x14, &[0x01, 0x00, 0x00], 100)).unwrap();
+
+    assert_eq!(
+        interface.sent,
+        [vec![
+            0x00, 0x00, 0xFF, 0x05, 0xFB, 0xD4, 0x14, 0x01, 0x00, 0x00, 0x17, 0x00
+        ]]
+    );
+}
+
+#[test]
+fn passive_target() {
+    let mut reader = Reader::new(
+        Scripted::new(frame(&[
+            0x4B, 0x01, 0x01, 0x00, 0x44, 0x00, 0x07, 0x04, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66,
+        ])),
+        NoDelay,
+    );
+
+    let target = block_on(reader.read_passive_target(CardType::IsoTypeA)).unwrap();
+    assert_eq!(
+        target,
+        Some(TargetInfo::IsoTypeA(IsoTypeATarget {
+            tg: 1,
+            atqa: [0x00, 0x44],
+            sak: 0x00,
+            uid: CardUid::new(&[0x04, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66]).unwrap(),
+            ats: None,
+        }))
+    );
+}
+
+#[test]
+fn bad_checksum() {
+    let mut response = frame(&[0x03, 0x32, 0x01, 0x06, 0x07]);
+    response[10] ^= 0xFF;
+    let mut reader =
+        Reader::new(Scripted::new(response), NoDelay).with_retry_policy(RetryPolicy::NONE);
+
+    assert!(matches!(
+        block_on(reader.get_firmware_version()),
+        Err(Error::Protocol(protocol::Error::BadChecksum))
+    ));
+}
+
+#[test]
+fn wrong_response_code() {
+    let mut reader = Reader::new(
+        Scripted::new(frame(&[0x05, 0x32, 0x01, 0x06, 0x07])),
+        NoDelay,
+    );
+
+    assert!(matches!(
+        block_on(reader.get_firmware_version()),
+        Err(Error::InvalidResponse)
+    ));
+}
+
+#[test]
+fn garbage_before_response() {
+    let mut response = vec![0x00, 0xFF, 0x12, 0x00, 0x00];
+    response.extend_from_slice(&frame(&[0x03, 0x32, 0x01, 0x06, 0x07]));
+    let mut reader = Reader::new(
+        Scripted::with_replies([&[0x00, 0x55], &ACK[..]].concat(), response),
+        NoDelay,
+    );
+
+    let version = block_on(reader.get_firmware_version()).unwrap();
+    assert_eq!(version.ic, 0x32);
+}
+
+#[test]
+fn extended_frames() {
+    let data: Vec<u8> = (0..260).map(|i| i as u8).collect();
+
+    let mut response = vec![
+        0x00, 0x00, 0xFF, 0xFF, 0xFF, 0x01, 0x07, 0xF8, 0xD5, 0x41, 0x00,
+    ];
+    response.extend_from_slice(&data);
+    let sum = response[8..].iter().fold(0u8, |s, b| s.wrapping_add(*b));
+    response.extend_from_slice(&[sum.wrapping_neg(), 0x00]);
+
+    let mut interface = Scripted::new(response);
+    let mut protocol =
+        protocol::Protocol::<_, _, { protocol::MAX_FRAME_LEN }>::new(&mut interface, NoDelay);
+    let (cmd, received) = block_on(protocol.request(0x40, &data, 100)).unwrap();
+    assert_eq!(cmd, 0x41);
+    assert_eq!(received[0], 0x00);
+    assert_eq!(&received[1..], &data[..]);
+
+    let sent = &interface.sent[0];
+    // LEN = TFI, command and data
+    assert_eq!(sent[..8], [0x00, 0x00, 0xFF, 0xFF, 0xFF, 0x01, 0x06, 0xF9]);
+    assert_eq!(sent[8..10], [0xD4, 0x40]);
+    assert_eq!(sent[10..270], data[..]);
+    assert_eq!(sent.len(), 272);
+    assert_eq!(
+        sent.iter().skip(8).fold(0u8, |s, b| s.wrapping_add(*b)),
+        0x00
+    );
+}
+
+#[test]
+fn status_byte() {
+    assert_eq!(Status::check(0x00), Ok(()));
+    // MI and NAD flags are ignored
+    assert_eq!(Status::check(0xC0), Ok(()));
+    assert_eq!(Status::check(0x41), Err(Status::Timeout));
+    assert_eq!(Status::check(0x14), Err(Status::MifareAuthentication));
+    assert_eq!(Status::check(0x3F), Err(Status::Unknown(0x3F)));
+
+    for code in 1..=Status::MASK {
+        assert_eq!(Status::from_code(code).unwrap().code(), code);
+    }
+}
+
+#[test]
+fn target_info() {
+    // DESFire with a 7 byte UID and ATS, followed by a second target
+    let data = [
+        0x01, 0x03, 0x44, 0x20, 0x07, 0x04, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x06, 0x75, 0x77,
+        0x81, 0x02, 0x80, 0x02,
+    ];
+    let (target, rest) = TargetInfo::parse(CardType::IsoTypeA, &data).unwrap();
+    let TargetInfo::IsoTypeA(a) = target else {
+        panic!("not type A: {target:?}");
+    };
+    assert_eq!(a.atqa(), 0x0344);
+    assert!(a.supports_iso14443_4());
+    assert_eq!(
+        a.ats.unwrap().as_bytes(),
+        [0x06, 0x75, 0x77, 0x81, 0x02, 0x80]
+    );
+    assert_eq!(rest, [0x02]);
+
+    // 10 byte UID
+    let data = [
+        0x01, 0x00, 0x44, 0x00, 0x0A, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A,
+    ];
+    let (target, _) = TargetInfo::parse(CardType::IsoTypeA, &data).unwrap();
+    assert_eq!(target.uid().as_bytes().len(), 10);
+
+    // truncated UID
+    assert!(TargetInfo::parse(CardType::IsoTypeA, &data[..10]).is_none());
+
+    let mut data = vec![0x01, 0x14, 0x01];
+    data.extend_from_slice(&[0x01, 0x2E, 0x3D, 0x4C, 0x5B, 0x6A, 0x79, 0x88]);
+    data.extend_from_slice(&[0x03, 0x01, 0x4B, 0x02, 0x4F, 0x49, 0x8A, 0xFF]);
+    data.extend_from_slice(&[0x88, 0xB4]);
+    let (target, _) = TargetInfo::parse(CardType::FeliCa212kbps, &data).unwrap();
+    assert_eq!(
+        target,
+        TargetInfo::FeliCa(FeliCaTarget {
+            tg: 1,
+            idm: [0x01, 0x2E, 0x3D, 0x4C, 0x5B, 0x6A, 0x79, 0x88],
+            pmm: [0x03, 0x01, 0x4B, 0x02, 0x4F, 0x49, 0x8A, 0xFF],
+            system_code: Some([0x88, 0xB4]),
+        })
+    );
+    assert_eq!(target.uid().as_bytes(), &data[3..11]);
+
+    let data = [0x01, 0x0C, 0x00, 0xB0, 0x1D, 0x02, 0x03];
+    let (target, _) = TargetInfo::parse(CardType::Jewel, &data).unwrap();
+    assert_eq!(
+        target,
+        TargetInfo::Jewel(JewelTarget {
+            tg: 1,
+            sens_res: [0x0C, 0x00],
+            jewel_id: [0xB0, 0x1D, 0x02, 0x03],
+        })
+    );
+
+    let mut data = vec![0x01, 0x50, 0x92, 0x03, 0x84, 0x59, 0x00, 0x00, 0x00, 0x00];
+    data.extend_from_slice(&[0x00, 0x71, 0x71, 0x01, 0x00]);
+    let (target, _) = TargetInfo::parse(CardType::IsoTypeB, &data).unwrap();
+    assert_eq!(target.uid().as_bytes(), [0x92, 0x03, 0x84, 0x59]);
+}
+
+#[test]
+fn card_uid() {
+    use std::collections::HashSet;
+
+    let short = CardUid::new(&[0x01, 0x02, 0x03, 0x04]).unwrap();
+    let long = CardUid::new(&[0x01, 0x02, 0x03, 0x04, 0x00, 0x00, 0x00]).unwrap();
+    assert_ne!(short, long);
+    assert!(CardUid::new(&[0; 11]).is_none());
+
+    let set: HashSet<_> = [short, long, short].into_iter().collect();
+    assert_eq!(set.len(), 2);
+    assert_eq!(format!("{short:?}"), "CardUid([01, 02, 03, 04])");
+}
+
+#[test]
+fn ntag_config() {
+    let config = Config::from_pages([0x04, 0x00, 0x00, 0xFF], [0x00, 0x00, 0x00, 0x00]);
+    assert!(!config.is_protected());
+    assert_eq!(config.access, Access::default());
+
+    let access = Access::from_byte(0xC5);
+    assert_eq!(
+        access,
+        Access {
+            prot: true,
+            cfglck: true,
+            nfc_cnt_en: false,
+            nfc_cnt_pwd_prot: false,
+            authlim: 5,
+        }
+    );
+    assert_eq!(access.to_byte(), 0xC5);
+
+    let config = Config {
+        auth0: 0x10,
+        access,
+        ..config
+    };
+    assert_eq!(
+        config.to_pages(),
+        ([0x04, 0x00, 0x00, 0x10], [0xC5, 0x00, 0x00, 0x00])
+    );
+}
@@ -0,0 +1,167 @@
+use vat_card_reader::driver::frame::{Frame, FrameError, Parser};
+
+const ACK: [u8; 6] = [0x00, 0x00, 0xFF, 0x00, 0xFF, 0x00];
+const NACK: [u8; 6] = [0x00, 0x00, 0xFF, 0xFF, 0x00, 0x00];
+
+/// Build a PN532 to host frame
+fn frame(data: &[u8]) -> Vec<u8> {
+    let len = data.len() as u8 + 1;
+    let sum = data.iter().fold(0xD5u8, |s, b| s.wrapping_add(*b));
+    let mut frame = vec![0x00, 0x00, 0xFF, len, len.wrapping_neg(), 0xD5];
+    frame.extend_from_slice(data);
+    frame.push(sum.wrapping_neg());
+    frame.push(0x00);
+    frame
+}
+
+/// Feed all bytes, collecting every outcome
+fn parse_all<const N: usize>(
+    parser: &mut Parser<N>,
+    mut bytes: &[u8],
+) -> Vec<Result<Frame, FrameError>> {
+    let mut results = Vec::new();
+    while !bytes.is_empty() {
+        let (consumed, result) = parser.feed(bytes);
+        bytes = &bytes[consumed..];
+        results.extend(result);
+    }
+    results
+}
+
+#[test]
+fn ack_and_nack() {
+    let mut parser = Parser::<16>::new();
+    assert_eq!(parse_all(&mut parser, &ACK), [Ok(Frame::Ack)]);
+    assert_eq!(parse_all(&mut parser, &NACK), [Ok(Frame::Nack)]);
+}
+
+#[test]
+fn information_frame() {
+    let mut parser = Parser::<16>::new();
+    assert_eq!(
+        parse_all(&mut parser, &frame(&[0x03, 0x32, 0x01, 0x06, 0x07])),
+        [Ok(Frame::Information)]
+    );
+    assert_eq!(parser.data(), [0xD5, 0x03, 0x32, 0x01, 0x06, 0x07]);
+}
+
+#[test]
+fn error_frame() {
+    let mut parser = Parser::<16>::new();
+    assert_eq!(
+        parse_all(
+            &mut parser,
+            &[0x00, 0x00, 0xFF, 0x01, 0xFF, 0x7F, 0x81, 0x00]
+        ),
+        [Ok(Frame::Error)]
+    );
+}
+
+#[test]
+fn skips_preamble() {
+    let mut bytes = vec![0x00; 7];
+    bytes.extend_from_slice(&[0xFF, 0x12, 0x00, 0x34, 0x00]);
+    bytes.extend_from_slice(&frame(&[0x41, 0x00]));
+
+    let mut parser = Parser::<16>::new();
+    let results = parse_all(&mut parser, &bytes);
+    assert_eq!(results.last(), Some(&Ok(Frame::Information)));
+    assert_eq!(parser.data(), [0xD5, 0x41, 0x00]);
+}
+
+#[test]
+fn resync_after_corrupted_frame() {
+    let mut broken = frame(&[0x41, 0x00, 0x01]);
+    broken[7] ^= 0x10;
+    let mut bytes = broken;
+    bytes.extend_from_slice(&ACK);
+    bytes.extend_from_slice(&frame(&[0x41, 0x00]));
+
+    let mut parser = Parser::<16>::new();
+    assert_eq!(
+        parse_all(&mut parser, &bytes),
+        [
+            Err(FrameError::DataChecksum),
+            Ok(Frame::Ack),
+            Ok(Frame::Information)
+        ]
+    );
+    assert_eq!(parser.data(), [0xD5, 0x41, 0x00]);
+}
+
+#[test]
+fn too_long() {
+    let mut bytes = frame(&[0x00; 20]);
+    bytes.extend_from_slice(&frame(&[0x41, 0x00]));
+
+    let mut parser = Parser::<16>::new();
+    let results = parse_all(&mut parser, &bytes);
+    assert_eq!(results.first(), Some(&Err(FrameError::TooLong)));
+    assert_eq!(results.last(), Some(&Ok(Frame::Information)));
+}
+
+#[test]
+fn extended_frame() {
+    let data: Vec<u8> = (0..299).map(|i| i as u8).collect();
+    let sum = data.iter().fold(0xD5u8, |s, b| s.wrapping_add(*b));
+    let mut bytes = vec![0x00, 0x00, 0xFF, 0xFF, 0xFF, 0x01, 0x2C, 0xD3, 0xD5];
+    bytes.extend_from_slice(&data);
+    bytes.extend_from_slice(&[sum.wrapping_neg(), 0x00]);
+
+    let mut parser = Parser::<300>::new();
+    assert_eq!(parse_all(&mut parser, &bytes), [Ok(Frame::Information)]);
+    assert_eq!(parser.data()[0], 0xD5);
+    assert_eq!(parser.data()[1..], data[..]);
+
+    // broken length checksum
+    bytes[7] ^= 0x01;
+    assert_eq!(
+        parse_all(&mut parser, &bytes).first(),
+        Some(&Err(FrameError::LengthChecksum))
+    );
+}
+
+#[test]
+fn needed_bytes() {
+    for bytes in [
+        ACK.to_vec(),
+        frame(&[0x03, 0x32, 0x01, 0x06, 0x07]),
+        frame(&[0x00; 200]),
+    ] {
+        let mut parser = Parser::<300>::new();
+
+        // never asks for more than the rest of the frame, up to the postamble
+        for (n, &b) in bytes.iter().enumerate() {
+            assert!(parser.needed() <= bytes.len() - n);
+            if parser.push(b).is_some() {
+                break;
+            }
+        }
+    }
+}
+
+#[test]
+fn random_noise() {
+    let mut state = 0x2545_F491_4F6C_DD1Du64;
+    let mut parser = Parser::<32>::new();
+
+    for _ in 0..100_000 {
+        // xorshift, biased towards start code bytes
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        let byte = match state % 4 {
+            0 => 0x00,
+            1 => 0xFF,
+            _ => (state >> 32) as u8,
+        };
+
+        assert!(parser.needed() > 0);
+        if let Some(Ok(Frame::Ack | Frame::Nack)) = parser.push(byte) {
+            assert!(parser.data().is_empty());
+        }
+    }
+
+    parser.reset();
+    assert_eq!(parse_all(&mut parser, &ACK), [Ok(Frame::Ack)]);
+}
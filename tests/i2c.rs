@@ -0,0 +1,98 @@
+#![feature(async_fn_in_trait)]
+#![allow(incomplete_features)]
+
+use core::convert::Infallible;
+use embassy_futures::block_on;
+use embedded_hal_async::i2c::{ErrorType, I2c as Bus, Operation};
+use embedded_io::asynch::{Read, Write};
+use vat_card_reader::driver::simulator::{Delay, Simulator};
+use vat_card_reader::driver::{I2c, Reader};
+
+/// I2C bus in front of the simulator, capturing the length of the frames read
+///
+/// Like the PN532, every read starts with the status byte, followed by the frame from its start.
+struct Tap {
+    simulator: Simulator,
+    /// Frame ready to be read, and whether it has been read completely
+    frame: Option<(Vec<u8>, bool)>,
+    reads: Vec<usize>,
+}
+
+impl Tap {
+    fn new(simulator: Simulator) -> Self {
+        Self {
+            simulator,
+            frame: None,
+            reads: Vec::new(),
+        }
+    }
+
+    /// Status byte, making the next frame of the simulator ready once the last one was read
+    async fn status(&mut self) -> u8 {
+        if matches!(self.frame, None | Some((_, true))) {
+            let mut buf = [0u8; 300];
+            self.frame = match self.simulator.read(&mut buf).await {
+                Ok(len) => Some((buf[..len].to_vec(), false)),
+                Err(_) => None,
+            };
+        }
+        self.frame.is_some() as u8
+    }
+}
+
+impl ErrorType for Tap {
+    type Error = Infallible;
+}
+
+impl Bus for Tap {
+    async fn read(&mut self, _address: u8, read: &mut [u8]) -> Result<(), Self::Error> {
+        read.fill(0);
+        read[0] = self.status().await;
+        Ok(())
+    }
+
+    async fn write(&mut self, _address: u8, write: &[u8]) -> Result<(), Self::Error> {
+        self.frame = None;
+        self.simulator.write(write).await.unwrap();
+        Ok(())
+    }
+
+    async fn write_read(
+        &mut self,
+        address: u8,
+        write: &[u8],
+        read: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        self.write(address, write).await?;
+        self.read(address, read).await
+    }
+
+    async fn transaction(
+        &mut self,
+        _address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        let [Operation::Read(status), Operation::Read(buf)] = operations else {
+            panic!("Unexpected transaction");
+        };
+        status[0] = self.status().await;
+        buf.fill(0);
+        if let Some((frame, read)) = &mut self.frame {
+            let len = frame.len().min(buf.len());
+            buf[..len].copy_from_slice(&frame[..len]);
+            *read = len == frame.len();
+        }
+        self.reads.push(buf.len());
+        Ok(())
+    }
+}
+
+#[test]
+fn read_announced_length() {
+    let mut tap = Tap::new(Simulator::new());
+    let mut reader = Reader::new(I2c::new(&mut tap), Delay);
+
+    block_on(reader.get_firmware_version()).unwrap();
+    // the ACK fits into the header, the response is read again with its length
+    assert_eq!(tap.reads, [8, 8, 13]);
+}
@@ -0,0 +1,183 @@
+#[cfg(feature = "simulator")]
+mod common;
+
+use vat_card_reader::mad::{self, Error, Mad};
+
+/// Sector 0 of a 1K formatted for NDEF by common tools: all sectors are NDEF sectors
+const NDEF_SECTOR0: [u8; 32] = [
+    0x14, 0x01, 0x03, 0xE1, 0x03, 0xE1, 0x03, 0xE1, 0x03, 0xE1, 0x03, 0xE1, 0x03, 0xE1, 0x03, 0xE1,
+    0x03, 0xE1, 0x03, 0xE1, 0x03, 0xE1, 0x03, 0xE1, 0x03, 0xE1, 0x03, 0xE1, 0x03, 0xE1, 0x03, 0xE1,
+];
+
+#[test]
+fn parse_mad() {
+    let mad = Mad::parse(&NDEF_SECTOR0).unwrap();
+    assert_eq!(mad.sectors(), 16);
+    assert_eq!(mad.aid(0), None);
+    assert_eq!(mad.aid(1), Some(mad::NDEF_AID));
+    assert_eq!(mad.sectors_of(mad::NDEF_AID).count(), 15);
+    assert_eq!(mad.encode(), NDEF_SECTOR0);
+
+    let mut broken = NDEF_SECTOR0;
+    broken[5] = 0x00;
+    assert!(matches!(
+        Mad::parse(&broken),
+        Err(Error::Crc {
+            sector: 0,
+            expected: 0x14,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn mad_v2() {
+    let mut mad = Mad::new();
+    assert!(!mad.set_aid(0, mad::NDEF_AID));
+    assert!(!mad.set_aid(16, mad::NDEF_AID));
+    assert!(mad.set_aid(2, mad::NDEF_AID));
+    assert!(mad.set_aid(39, mad::NDEF_AID));
+    assert_eq!(mad.sectors(), 40);
+
+    let mut parsed = Mad::parse(&mad.encode()).unwrap();
+    assert_eq!(parsed.sectors_of(mad::NDEF_AID).collect::<Vec<_>>(), [2]);
+    parsed.parse_v2(&mad.encode_v2()).unwrap();
+    assert_eq!(parsed, mad);
+    assert_eq!(
+        parsed.sectors_of(mad::NDEF_AID).collect::<Vec<_>>(),
+        [2, 39]
+    );
+}
+
+#[test]
+fn ndef_tlv() {
+    assert_eq!(
+        mad::ndef_tlv(&[0x00, 0x03, 0x02, 0xAA, 0xBB, 0xFE]),
+        Some(&[0x03, 0x02, 0xAA, 0xBB][..])
+    );
+    // behind a proprietary TLV
+    assert_eq!(
+        mad::ndef_tlv(&[0xFD, 0x01, 0x00, 0x03, 0x00]),
+        Some(&[0x03, 0x00][..])
+    );
+    // not complete
+    assert_eq!(mad::ndef_tlv(&[0x03, 0x05, 0xAA]), None);
+    assert_eq!(mad::ndef_tlv(&[0x03, 0xFF, 0x01]), None);
+    assert_eq!(mad::ndef_tlv(&[0xFE, 0x03, 0x00]), None);
+}
+
+/// Mifare Classic formatted for NDEF in the simulator
+#[cfg(feature = "simulator")]
+mod simulator {
+    use super::common::{activate, NDEF1};
+    use embassy_futures::block_on;
+    use vat_card_reader::driver::mifare;
+    use vat_card_reader::driver::simulator::{Delay, MifareClassic, MifareClassicSize, Simulator};
+    use vat_card_reader::driver::Reader;
+    use vat_card_reader::mad::{self, Mad};
+    use vat_card_reader::ndef;
+    use vat_card_reader::reader::{read_key, Key, ReadKeyError};
+
+    /// A Mifare Classic formatted for NDEF, holding the TLV area `tlvs` in `sectors`
+    fn ndef_classic(size: MifareClassicSize, sectors: &[u8], tlvs: &[u8]) -> MifareClassic {
+        let mut mad = Mad::new();
+        for &sector in sectors {
+            mad.set_aid(sector, mad::NDEF_AID);
+        }
+        let sector0 = mad.encode();
+        let mad_v2 = mad.sectors() > 16;
+
+        let mut card = MifareClassic::new(size, [0xDE, 0xAD, 0xBE, 0xEF])
+            .with_block(1, sector0[..16].try_into().unwrap())
+            .with_block(2, sector0[16..].try_into().unwrap())
+            .with_keys(0, mad::MAD_KEY_A, [0xFF; 6]);
+        // GPB: MAD available, multi application card, MAD v1 or v2
+        let mut trailer = card.block(3);
+        trailer[9] = if mad_v2 { 0xC2 } else { 0xC1 };
+        card = card.with_block(3, trailer);
+        if mad_v2 {
+            let sector16 = mad.encode_v2();
+            for (i, block) in sector16.chunks(16).enumerate() {
+                card = card.with_block(64 + i as u8, block.try_into().unwrap());
+            }
+            card = card.with_keys(16, mad::MAD_KEY_A, [0xFF; 6]);
+        }
+
+        let mut area = tlvs.to_vec();
+        for &sector in sectors {
+            card = card.with_keys(sector, mad::NDEF_KEY_A, [0xFF; 6]);
+            let first = mifare::first_block(sector);
+            for block in first..mifare::trailer_block(sector) {
+                let mut data = [0u8; 16];
+                let len = area.len().min(16);
+                data[..len].copy_from_slice(&area[..len]);
+                area.drain(..len);
+                card = card.with_block(block, data);
+            }
+        }
+        card
+    }
+
+    #[test]
+    fn read_key_from_mifare_classic() {
+        // the NDEF TLV of the dump, behind its lock control TLV
+        let tlvs = &NDEF1[5..];
+        let cards = [
+            ndef_classic(MifareClassicSize::Classic1K, &[1, 2], tlvs),
+            // listed in the MAD v2 of the 4K
+            ndef_classic(MifareClassicSize::Classic4K, &[17], tlvs),
+        ];
+        for card in cards {
+            let mut reader = Reader::new(Simulator::new().with_target(card), Delay);
+            let (target, kind) = activate(&mut reader);
+
+            let mut buf = [0u8; 1024];
+            let key = block_on(read_key(&mut buf, &mut reader, &target, kind));
+            assert!(matches!(key, Ok(Some(Key("43211234")))));
+        }
+    }
+
+    #[test]
+    fn read_key_from_short_tlvs() {
+        let mut buf = [0u8; 1024];
+        // an empty message, and a single empty record
+        for tlvs in [
+            &[0x03, 0x00, 0xFE][..],
+            &[0x03, 0x03, 0xD0, 0x00, 0x00, 0xFE],
+        ] {
+            let card = ndef_classic(MifareClassicSize::Classic1K, &[1], tlvs);
+            let mut reader = Reader::new(Simulator::new().with_target(card), Delay);
+            let (target, kind) = activate(&mut reader);
+            assert!(matches!(
+                block_on(read_key(&mut buf, &mut reader, &target, kind)),
+                Ok(None)
+            ));
+        }
+
+        // the record doesn't end the message
+        let tlvs = [0x03, 0x06, 0x11, 0x01, 0x02, 0x54, 0x41, 0x42, 0xFE];
+        let card = ndef_classic(MifareClassicSize::Classic1K, &[1], &tlvs);
+        let mut reader = Reader::new(Simulator::new().with_target(card), Delay);
+        let (target, kind) = activate(&mut reader);
+        assert!(matches!(
+            block_on(read_key(&mut buf, &mut reader, &target, kind)),
+            Err(ReadKeyError::Ndef(ndef::Error::UnderflowHeader))
+        ));
+    }
+
+    #[test]
+    fn mad_crc_error() {
+        let mut card = ndef_classic(MifareClassicSize::Classic1K, &[1], &NDEF1[5..]);
+        let mut block = card.block(1);
+        block[0] ^= 0xFF;
+        card = card.with_block(1, block);
+
+        let mut reader = Reader::new(Simulator::new().with_target(card), Delay);
+        let (target, kind) = activate(&mut reader);
+        let mut buf = [0u8; 1024];
+        assert!(matches!(
+            block_on(read_key(&mut buf, &mut reader, &target, kind)),
+            Err(ReadKeyError::Mad(mad::Error::Crc { sector: 0, .. }))
+        ));
+    }
+}
@@ -0,0 +1,242 @@
+use vat_card_reader::driver::mifare::{
+    self, AccessBits, AccessCondition, BlockAccess, KeyType, Permission, SectorTrailer,
+    TrailerAccess, ValueBlock,
+};
+
+#[test]
+fn value_block() {
+    // example of the MF1S50 datasheet, value 100 with address 0x11
+    let block = [
+        0x64, 0x00, 0x00, 0x00, 0x9B, 0xFF, 0xFF, 0xFF, 0x64, 0x00, 0x00, 0x00, 0x11, 0xEE, 0x11,
+        0xEE,
+    ];
+    let value = ValueBlock {
+        value: 100,
+        address: 0x11,
+    };
+    assert_eq!(ValueBlock::decode(&block), Some(value));
+    assert_eq!(value.encode(), block);
+
+    let negative = ValueBlock {
+        value: -1,
+        address: 0x00,
+    };
+    assert_eq!(ValueBlock::decode(&negative.encode()), Some(negative));
+
+    let mut broken = block;
+    broken[4] ^= 0x01;
+    assert_eq!(ValueBlock::decode(&broken), None);
+    let mut broken = block;
+    broken[15] = 0x11;
+    assert_eq!(ValueBlock::decode(&broken), None);
+}
+
+#[test]
+fn sectors() {
+    assert_eq!(mifare::sector_of(0), 0);
+    assert_eq!(mifare::sector_of(7), 1);
+    assert_eq!(mifare::sector_of(127), 31);
+    assert_eq!(mifare::sector_of(128), 32);
+    assert_eq!(mifare::sector_of(255), 39);
+
+    assert_eq!(mifare::trailer_block(1), 7);
+    assert_eq!(mifare::trailer_block(32), 143);
+    assert_eq!(mifare::first_block(39), 240);
+    assert_eq!(mifare::trailer_block(39), 255);
+}
+
+#[test]
+fn access_bits() {
+    assert_eq!(
+        AccessBits::decode([0xFF, 0x07, 0x80]),
+        Some(AccessBits::TRANSPORT)
+    );
+    assert_eq!(AccessBits::TRANSPORT.encode(), [0xFF, 0x07, 0x80]);
+
+    // data blocks written with key B only, trailer managed by key B
+    let bits = AccessBits::decode([0x78, 0x77, 0x88]).unwrap();
+    assert_eq!(bits.conditions[0], AccessCondition::from_bits(0b100));
+    assert_eq!(bits.conditions[3], AccessCondition::from_bits(0b011));
+    assert_eq!(bits.encode(), [0x78, 0x77, 0x88]);
+
+    // the inverted copy doesn't match
+    assert_eq!(AccessBits::decode([0xFF, 0x07, 0x81]), None);
+    assert_eq!(AccessBits::decode([0x00, 0x00, 0x00]), None);
+}
+
+#[test]
+fn permissions() {
+    let bits = AccessBits::decode([0x78, 0x77, 0x88]).unwrap();
+    let BlockAccess::Data(data) = bits.access(5) else {
+        panic!("not a data block");
+    };
+    assert_eq!(data.read, Permission::KeyAOrB);
+    assert_eq!(data.write, Permission::KeyB);
+    assert!(!data.write.allows(KeyType::A));
+    assert_eq!(data.increment, Permission::Never);
+    assert_eq!(
+        bits.access(7),
+        BlockAccess::Trailer(TrailerAccess {
+            key_a_write: Permission::KeyB,
+            access_bits_read: Permission::KeyAOrB,
+            access_bits_write: Permission::KeyB,
+            key_b_read: Permission::Never,
+            key_b_write: Permission::KeyB,
+        })
+    );
+
+    // key B is readable in transport configuration, so it can't authenticate
+    assert!(AccessBits::TRANSPORT.key_b_readable());
+    let BlockAccess::Data(data) = AccessBits::TRANSPORT.access(0) else {
+        panic!("not a data block");
+    };
+    assert_eq!(data.write, Permission::KeyA);
+
+    // groups of 5 blocks in the large sectors of the 4K
+    assert_eq!(mifare::group_of(128 + 4), 0);
+    assert_eq!(mifare::group_of(128 + 14), 2);
+    assert_eq!(mifare::group_of(128 + 15), 3);
+}
+
+#[test]
+fn sector_trailer() {
+    let block = [
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x78, 0x77, 0x88, 0x69, 0xB0, 0xB1, 0xB2, 0xB3, 0xB4,
+        0xB5,
+    ];
+    let trailer = SectorTrailer::decode(&block).unwrap();
+    assert_eq!(trailer.gpb, 0x69);
+    assert_eq!(trailer.key_b, [0xB0, 0xB1, 0xB2, 0xB3, 0xB4, 0xB5]);
+    assert_eq!(trailer.encode(), block);
+
+    let mut broken = block;
+    broken[7] = 0x78;
+    assert_eq!(SectorTrailer::decode(&broken), None);
+}
+
+/// Mifare Classic in the simulator
+#[cfg(feature = "simulator")]
+mod simulator {
+    use embassy_futures::block_on;
+    use vat_card_reader::driver::mifare::{
+        self, AccessBits, ClassicError, Key as ClassicKey, KeyType, ValueBlock,
+    };
+    use vat_card_reader::driver::requests::CardType;
+    use vat_card_reader::driver::simulator::{
+        Delay, MifareClassic, MifareClassicSize, Simulator, Target,
+    };
+    use vat_card_reader::driver::{ReadError, Reader, Status};
+
+    #[test]
+    fn mifare_classic_blocks() {
+        let uid = [0xDE, 0xAD, 0xBE, 0xEF];
+        let key_b = ClassicKey {
+            key_type: KeyType::B,
+            key: [0xB0, 0xB1, 0xB2, 0xB3, 0xB4, 0xB5],
+        };
+        let card = MifareClassic::new(MifareClassicSize::Classic1K, uid)
+            .with_keys(1, [0xFF; 6], key_b.key);
+        let mut reader = Reader::new(Simulator::new().with_target(card), Delay);
+        let target = block_on(reader.read_passive_target(CardType::IsoTypeA))
+            .unwrap()
+            .unwrap();
+        let uid = target.uid();
+
+        let wrong = ClassicKey {
+            key_type: KeyType::B,
+            key: [0xFF; 6],
+        };
+        assert!(matches!(
+            block_on(reader.mifare_authenticate(1, 4, &wrong, &uid)),
+            Err(ClassicError::Authentication(KeyType::B))
+        ));
+        // not authenticated any more
+        assert!(matches!(
+            block_on(reader.mifare_read(1, 4)),
+            Err(ReadError::Status(Status::Timeout))
+        ));
+
+        block_on(reader.mifare_authenticate(1, 4, &key_b, &uid)).unwrap();
+        block_on(reader.mifare_write(1, 5, &[0x42; 16])).unwrap();
+        assert_eq!(block_on(reader.mifare_read(1, 5)).unwrap(), [0x42; 16]);
+        // key A reads as zeros
+        assert_eq!(
+            block_on(reader.mifare_read(1, mifare::trailer_block(1))).unwrap()[..6],
+            [0x00; 6]
+        );
+
+        assert!(matches!(
+            block_on(reader.mifare_read_value(1, 5)),
+            Err(ClassicError::InvalidValueBlock)
+        ));
+        let value = ValueBlock {
+            value: 100,
+            address: 6,
+        };
+        block_on(reader.mifare_write_value(1, 5, &value)).unwrap();
+        block_on(reader.mifare_decrement(1, 5, 30)).unwrap();
+        // the block only changes on transfer
+        assert_eq!(block_on(reader.mifare_read_value(1, 5)).unwrap(), value);
+        block_on(reader.mifare_transfer(1, 5)).unwrap();
+        block_on(reader.mifare_increment(1, 5, 5)).unwrap();
+        block_on(reader.mifare_transfer(1, 5)).unwrap();
+        assert_eq!(
+            block_on(reader.mifare_read_value(1, 5)).unwrap(),
+            ValueBlock {
+                value: 75,
+                address: 6
+            }
+        );
+
+        // backup into the block the address points to
+        block_on(reader.mifare_restore(1, 5)).unwrap();
+        block_on(reader.mifare_transfer(1, 6)).unwrap();
+        assert_eq!(block_on(reader.mifare_read_value(1, 6)).unwrap().value, 75);
+
+        // an overflowing value is rejected
+        block_on(reader.mifare_write_value(
+            1,
+            5,
+            &ValueBlock {
+                value: i32::MAX,
+                address: 6,
+            },
+        ))
+        .unwrap();
+        assert!(block_on(reader.mifare_increment(1, 5, 1)).is_err());
+    }
+
+    #[test]
+    fn mifare_classic_trailer() {
+        let uid = [0xDE, 0xAD, 0xBE, 0xEF];
+        let card = MifareClassic::new(MifareClassicSize::Classic1K, uid);
+        let mut simulator = Simulator::new().with_target(card);
+        let mut reader = Reader::new(&mut simulator, Delay);
+        let target = block_on(reader.read_passive_target(CardType::IsoTypeA))
+            .unwrap()
+            .unwrap();
+
+        block_on(reader.mifare_authenticate(1, 4, &ClassicKey::DEFAULT_A, &target.uid())).unwrap();
+        let mut trailer = block_on(reader.mifare_read_trailer(1, 1)).unwrap();
+        assert_eq!(trailer.access_bits, AccessBits::TRANSPORT);
+        assert_eq!(trailer.key_a, [0x00; 6]);
+
+        // inconsistent access bits are never written
+        let mut block = trailer.encode();
+        block[8] ^= 0x01;
+        assert!(matches!(
+            block_on(reader.mifare_write(1, 7, &block)),
+            Err(ClassicError::InvalidAccessBits)
+        ));
+
+        trailer.key_a = [0xFF; 6];
+        trailer.key_b = [0xB0, 0xB1, 0xB2, 0xB3, 0xB4, 0xB5];
+        trailer.access_bits = AccessBits::decode([0x78, 0x77, 0x88]).unwrap();
+        block_on(reader.mifare_write_trailer(1, 1, &trailer)).unwrap();
+
+        let Target::MifareClassic(card) = &simulator.field()[0] else {
+            panic!("not a Mifare Classic");
+        };
+        assert_eq!(card.block(7)[6..10], [0x78, 0x77, 0x88, 0x69]);
+    }
+}
@@ -0,0 +1,161 @@
+use vat_card_reader::ndef::{Error, Reader, Record, Writer};
+
+const NDEF1: &[u8] = include_bytes!("../test/ndef1.dump");
+
+#[test]
+fn read_mime_record() {
+    let records = Reader::new(NDEF1)
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+
+    assert_eq!(
+        records,
+        [Record::MimeMedia {
+            r#type: "text/card",
+            value: "43211234",
+        }]
+    );
+}
+
+#[test]
+fn unformatted() {
+    let data = [0xFFu8; 16];
+    let mut iter = Reader::new(&data).into_iter();
+    assert_eq!(iter.next(), Some(Err(Error::NotFormatted)));
+}
+
+#[test]
+fn truncated_payload() {
+    let mut iter = Reader::new(&NDEF1[..20]).into_iter();
+    assert_eq!(iter.next(), Some(Err(Error::UnderflowPayload)));
+}
+
+#[test]
+fn short_tlvs() {
+    // an empty message
+    assert_eq!(Reader::new(&[0x03, 0x00, 0xFE]).into_iter().next(), None);
+
+    let records = Reader::new(&[0x03, 0x03, 0xD0, 0x00, 0x00, 0xFE])
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(records, [Record::Empty]);
+
+    // the record doesn't end the message, which ends with the TLV
+    let data = [0x03, 0x06, 0x11, 0x01, 0x02, 0x54, 0x41, 0x42, 0xFE];
+    let mut iter = Reader::new(&data).into_iter();
+    assert!(matches!(iter.next(), Some(Ok(Record::Unexpected(_)))));
+    assert_eq!(iter.next(), Some(Err(Error::UnderflowHeader)));
+}
+
+#[test]
+fn message_without_end() {
+    // a single record, without the message end flag
+    let mut iter = Reader::from_message(&[0x92, 0x01, 0x01, b'a', b'b']).into_iter();
+    assert_eq!(
+        iter.next(),
+        Some(Ok(Record::MimeMedia {
+            r#type: "a",
+            value: "b",
+        }))
+    );
+    assert_eq!(iter.next(), Some(Err(Error::UnderflowHeader)));
+}
+
+#[test]
+fn write_mime_record() {
+    let mut buf = [0u8; 32];
+    let mut writer = Writer::new(&mut buf);
+    writer
+        .push(&Record::MimeMedia {
+            r#type: "text/card",
+            value: "43211234",
+        })
+        .unwrap();
+    // the same message as in the dump, behind the lock control TLV and NDEF TLV header
+    assert_eq!(writer.finish(), &NDEF1[7..7 + 0x14]);
+}
+
+#[test]
+fn write_long_records() {
+    let value = "0123456789".repeat(30);
+    let mut buf = [0u8; 400];
+    let mut writer = Writer::new(&mut buf);
+    writer.push(&Record::Empty).unwrap();
+    writer
+        .push(&Record::MimeMedia {
+            r#type: "text/card",
+            value: &value,
+        })
+        .unwrap();
+    let message = writer.finish();
+    assert_eq!(message.len(), 3 + 6 + 9 + 300);
+    // only the first record begins the message, only the last one ends it
+    assert_eq!(message[..3], [0x90, 0x00, 0x00]);
+    // the payload length takes four bytes
+    assert_eq!(message[3..9], [0x42, 0x09, 0x00, 0x00, 0x01, 0x2C]);
+    assert_eq!(&message[9..18], b"text/card");
+    assert_eq!(&message[18..], value.as_bytes());
+
+    let mut buf = [0u8; 16];
+    let mut writer = Writer::new(&mut buf);
+    assert_eq!(
+        writer.push(&Record::MimeMedia {
+            r#type: "text/card",
+            value: &value,
+        }),
+        Err(Error::Overflow)
+    );
+}
+
+#[test]
+fn read_three_byte_tlv_length() {
+    let mut data = vec![0x03, 0xFF, 0x00, 0x14];
+    data.extend_from_slice(&NDEF1[7..7 + 0x14]);
+    data.push(0xFE);
+
+    let records = Reader::new(&data)
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(
+        records,
+        [Record::MimeMedia {
+            r#type: "text/card",
+            value: "43211234",
+        }]
+    );
+}
+
+#[test]
+fn read_long_record() {
+    let value = "0123456789".repeat(30);
+    let mut buf = [0u8; 400];
+    let mut writer = Writer::new(&mut buf[4..]);
+    writer.push(&Record::Empty).unwrap();
+    writer
+        .push(&Record::MimeMedia {
+            r#type: "text/card",
+            value: &value,
+        })
+        .unwrap();
+    let len = writer.finish().len();
+    buf[..4].copy_from_slice(&[0x03, 0xFF, 0x01, 0x3E]);
+
+    // the payload length of a record which isn't short takes four bytes
+    let records = Reader::new(&buf[..4 + len])
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(
+        records,
+        [
+            Record::Empty,
+            Record::MimeMedia {
+                r#type: "text/card",
+                value: &value,
+            }
+        ]
+    );
+}
@@ -0,0 +1,219 @@
+mod common;
+
+use common::hex;
+use vat_card_reader::crypto::Cipher;
+use vat_card_reader::driver::ntag424::session_keys;
+use vat_card_reader::sun::{self, Error, Keys, Layout, PiccData};
+
+/// The SUN message of NXP AN12196, with the PICC data and an SDM MAC over nothing
+const MESSAGE: &str =
+    "https://choose.url.com/ntag424?e=EF963FF7828658A599F3041510671E88&c=94EED9EE65337086";
+const KEYS: Keys = Keys {
+    meta_read: [0x00; 16],
+    file_read: [0x00; 16],
+};
+
+/// The layout of [`MESSAGE`] in an NDEF file, where it starts at `start`
+fn layout(start: usize) -> Layout {
+    let mac = start + MESSAGE.find("c=").unwrap() + 2;
+    Layout {
+        picc_data: start + MESSAGE.find("e=").unwrap() + 2,
+        mac_input: mac,
+        mac,
+    }
+}
+
+#[test]
+fn ev2_session_keys() {
+    // NXP AN12196, AuthenticateEV2First with key 0
+    let key = [0x00; 16];
+    let rnd_a = hex("13C5DB8A5930439FC3DEF9A4C675360F").try_into().unwrap();
+    let mut rnd_b: [u8; 16] = hex("A04C124213C186F22399D33AC2A30215").try_into().unwrap();
+    Cipher::aes(&key).decrypt_block(&mut rnd_b);
+    assert_eq!(rnd_b[..], hex("B9E2FC789B64BF237CCCAA20EC7E6E48"));
+
+    let (enc, mac) = session_keys(&key, &rnd_a, &rnd_b);
+    assert_eq!(enc[..], hex("1309C877509E5A215007FF0ED19CA564"));
+    assert_eq!(mac[..], hex("4C6626F5E72EA694202139295C7A7FC7"));
+}
+
+#[test]
+fn picc_data() {
+    let encrypted = hex("EF963FF7828658A599F3041510671E88").try_into().unwrap();
+    let picc = PiccData::decrypt(&KEYS.meta_read, &encrypted).unwrap();
+    assert_eq!(picc.uid.unwrap()[..], hex("04DE5F1EACC040"));
+    assert_eq!(picc.read_counter, Some(61));
+
+    assert_eq!(
+        picc.mac_key(&KEYS.file_read)[..],
+        hex("3FB5F6E3A807A03D5E3570ACE393776F")
+    );
+    assert_eq!(picc.mac(&KEYS.file_read, &[])[..], hex("94EED9EE65337086"));
+
+    // a wrong key doesn't decrypt to a valid tag
+    assert_eq!(
+        PiccData::decrypt(&[0x01; 16], &encrypted),
+        Err(Error::InvalidPiccData)
+    );
+}
+
+#[test]
+fn verify() {
+    // the message of an NDEF file, without NLEN
+    let message = MESSAGE.as_bytes();
+    let picc = sun::verify(message, &layout(2), &KEYS).unwrap();
+    assert_eq!(picc.read_counter, Some(61));
+
+    let forged = MESSAGE.replace("c=94", "c=95");
+    assert_eq!(
+        sun::verify(forged.as_bytes(), &layout(2), &KEYS),
+        Err(Error::Mac)
+    );
+    let keys = Keys {
+        file_read: [0x01; 16],
+        ..KEYS
+    };
+    assert_eq!(sun::verify(message, &layout(2), &keys), Err(Error::Mac));
+
+    // the MAC covers the data in front of it
+    let mut layout = layout(2);
+    layout.mac_input = layout.picc_data;
+    assert_eq!(sun::verify(message, &layout, &KEYS), Err(Error::Mac));
+
+    let invalid = MESSAGE.replace("e=EF", "e=XF");
+    assert_eq!(
+        sun::verify(invalid.as_bytes(), &layout, &KEYS),
+        Err(Error::InvalidMirror)
+    );
+    layout.mac = message.len();
+    assert_eq!(
+        sun::verify(message, &layout, &KEYS),
+        Err(Error::InvalidMirror)
+    );
+}
+
+/// NTAG 424 DNA in the simulator
+#[cfg(feature = "simulator")]
+mod simulator {
+    use super::common::{ndef_application, RND_A, UID};
+    use embassy_futures::block_on;
+    use vat_card_reader::driver::desfire::{CommMode, DesFireError, Status as DesFireStatus};
+    use vat_card_reader::driver::ntag424;
+    use vat_card_reader::driver::requests::CardType;
+    use vat_card_reader::driver::simulator::{Delay, IsoDep, Ntag424, Simulator};
+    use vat_card_reader::driver::{CardUid, Reader};
+    use vat_card_reader::replay::{CounterCheck, CounterStore};
+    use vat_card_reader::{sun, type4};
+
+    const NTAG424_KEY: [u8; 16] = [
+        0x2B, 0x7E, 0x15, 0x16, 0x28, 0xAE, 0xD2, 0xA6, 0xAB, 0xF7, 0x15, 0x88, 0x09, 0xCF, 0x4F,
+        0x3C,
+    ];
+
+    /// An NTAG 424 DNA holding `message`, with a proprietary file read with key 2
+    fn ntag424(message: &[u8]) -> IsoDep {
+        let proprietary: Vec<u8> = (0..128).collect();
+        IsoDep::new(UID)
+            .with_ats(&[0x06, 0x77, 0x77, 0x71, 0x02, 0x80])
+            .with_application(ndef_application(message, 0x00FF).with_file(0xE105, &proprietary))
+            .with_ntag424(Ntag424::new().with_key(2, NTAG424_KEY).with_file_access(
+                ntag424::PROPRIETARY_FILE,
+                CommMode::Full,
+                2,
+            ))
+    }
+
+    #[test]
+    fn ntag424_secure_messaging() {
+        let file: Vec<u8> = (0..128).collect();
+        let mut simulator = Simulator::new().with_target(ntag424(b""));
+        let mut reader = Reader::new(&mut simulator, Delay);
+        let target = block_on(reader.read_passive_target(CardType::IsoTypeA))
+            .unwrap()
+            .unwrap();
+        let mut ntag = reader.ntag424(target.tg());
+        block_on(ntag.select_application()).unwrap();
+
+        // the capability container can be read without authentication
+        let mut buf = [0u8; 256];
+        let cc =
+            block_on(ntag.read_data(ntag424::CC_FILE, 0, 15, CommMode::Plain, &mut buf)).unwrap();
+        assert_eq!(cc[..3], [0x00, 0x0F, 0x20]);
+        assert!(matches!(
+            block_on(ntag.read_data(ntag424::PROPRIETARY_FILE, 0, 0, CommMode::Full, &mut buf)),
+            Err(DesFireError::Status(DesFireStatus::AuthenticationError))
+        ));
+        assert!(matches!(
+            block_on(ntag.get_card_uid()),
+            Err(DesFireError::Status(DesFireStatus::AuthenticationError))
+        ));
+
+        assert!(matches!(
+            block_on(ntag.authenticate_ev2_first(2, &[0x00; 16], &RND_A)),
+            Err(DesFireError::Authentication)
+        ));
+        block_on(ntag.authenticate_ev2_first(2, &NTAG424_KEY, &RND_A)).unwrap();
+        assert_eq!(ntag.authenticated_key(), Some(2));
+
+        // every command counts, whether MACed or not
+        for _ in 0..2 {
+            let data = block_on(ntag.read_data(ntag424::CC_FILE, 0, 3, CommMode::Plain, &mut buf));
+            assert_eq!(data.unwrap(), [0x00, 0x0F, 0x20]);
+            let data = ntag.read_data(ntag424::PROPRIETARY_FILE, 0, 0, CommMode::Full, &mut buf);
+            assert_eq!(block_on(data).unwrap(), file);
+            let data = ntag.read_data(ntag424::PROPRIETARY_FILE, 16, 16, CommMode::Full, &mut buf);
+            assert_eq!(block_on(data).unwrap(), &file[16..32]);
+            assert_eq!(block_on(ntag.get_card_uid()).unwrap(), UID);
+        }
+
+        // the command has to be MACed
+        assert!(matches!(
+            block_on(ntag.read_data(ntag424::PROPRIETARY_FILE, 0, 0, CommMode::Plain, &mut buf)),
+            Err(DesFireError::Status(DesFireStatus::IntegrityError))
+        ));
+        assert_eq!(ntag.authenticated_key(), None);
+        assert!(matches!(
+            block_on(ntag.authenticate_ev2_first(5, &NTAG424_KEY, &RND_A)),
+            Err(DesFireError::Status(DesFireStatus::NoSuchKey))
+        ));
+    }
+
+    #[test]
+    fn ntag424_sun_message() {
+        // the message of NXP AN12196, in a URI record
+        let url = b"choose.url.com/ntag424?e=EF963FF7828658A599F3041510671E88&c=94EED9EE65337086";
+        let mut message = vec![0xD1, 0x01, url.len() as u8 + 1, b'U', 0x04];
+        message.extend_from_slice(url);
+        let offset = |param: &[u8]| {
+            let start = url.windows(2).position(|w| w == param).unwrap();
+            // NLEN, record header and URI prefix
+            2 + 5 + start + 2
+        };
+        let layout = sun::Layout {
+            picc_data: offset(b"e="),
+            mac_input: offset(b"c="),
+            mac: offset(b"c="),
+        };
+        let keys = sun::Keys {
+            meta_read: [0x00; 16],
+            file_read: [0x00; 16],
+        };
+
+        let mut reader = Reader::new(Simulator::new().with_target(ntag424(&message)), Delay);
+        let target = block_on(reader.read_passive_target(CardType::IsoTypeA))
+            .unwrap()
+            .unwrap();
+        let mut buf = [0u8; 256];
+        let message = block_on(type4::read_ndef(&mut reader, target.tg(), &mut buf))
+            .unwrap()
+            .unwrap();
+        let picc = sun::verify(message, &layout, &keys).unwrap();
+        assert_eq!(picc.read_counter, Some(61));
+
+        // a replayed message shows the same counter
+        let uid = CardUid::new(&picc.uid.unwrap()).unwrap();
+        let mut counters = CounterStore::<4>::new();
+        assert_eq!(counters.check(uid, 61), CounterCheck::New);
+        assert_eq!(counters.check(uid, 61), CounterCheck::Unchanged);
+    }
+}
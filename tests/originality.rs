@@ -0,0 +1,114 @@
+#[cfg(feature = "simulator")]
+mod common;
+
+use vat_card_reader::originality::{verify_ntag21x, PublicKey, NTAG21X_PUBLIC_KEY};
+
+/// Public key of the test vectors, generated for a private key of
+/// `0123456789ABCDEF0123456789ABCDEF`
+const TEST_KEY: [u8; 33] = [
+    0x04, 0x1B, 0xB9, 0x27, 0x3D, 0x32, 0xCF, 0xCD, 0x5B, 0xB0, 0x97, 0x50, 0xDD, 0x50, 0xAF, 0x91,
+    0xB3, 0xCB, 0xC8, 0xEC, 0x11, 0x84, 0x2E, 0xCA, 0x82, 0x18, 0x34, 0x75, 0xB8, 0x45, 0x44, 0x41,
+    0xA5,
+];
+
+/// UIDs and their signatures made with the test key
+const VECTORS: [([u8; 7], [u8; 32]); 2] = [
+    (
+        [0x04, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66],
+        [
+            0x15, 0xE2, 0x8D, 0x49, 0x50, 0x2E, 0x1E, 0x0F, 0x79, 0x8F, 0x3B, 0x72, 0x52, 0xF8,
+            0xCB, 0x6A, 0x93, 0x52, 0x6C, 0x71, 0xA9, 0x10, 0xE1, 0xF1, 0x0E, 0x71, 0x55, 0xF0,
+            0xE4, 0xA7, 0xF0, 0x21,
+        ],
+    ),
+    (
+        [0x04, 0xE1, 0x0A, 0x1A, 0x3B, 0x5C, 0x80],
+        [
+            0xD7, 0x8C, 0xBD, 0x65, 0xD5, 0x14, 0x71, 0x20, 0x7D, 0x59, 0xFC, 0xA6, 0x69, 0xF4,
+            0x20, 0x43, 0xB8, 0xEE, 0xE7, 0xBE, 0xE5, 0xA4, 0xB7, 0x9B, 0x53, 0xB0, 0x9D, 0xCE,
+            0x0C, 0xB8, 0xA8, 0xB3,
+        ],
+    ),
+];
+
+#[test]
+fn public_keys() {
+    assert!(PublicKey::from_sec1(&NTAG21X_PUBLIC_KEY).is_some());
+    assert!(PublicKey::from_sec1(&TEST_KEY).is_some());
+
+    // not on the curve
+    let mut key = TEST_KEY;
+    key[32] ^= 0x01;
+    assert!(PublicKey::from_sec1(&key).is_none());
+    // compressed points aren't supported
+    let mut key = TEST_KEY;
+    key[0] = 0x03;
+    assert!(PublicKey::from_sec1(&key).is_none());
+}
+
+#[test]
+fn valid_signatures() {
+    let key = PublicKey::from_sec1(&TEST_KEY).unwrap();
+    for (uid, signature) in VECTORS {
+        assert!(key.verify(&uid, &signature));
+    }
+}
+
+#[test]
+fn invalid_signatures() {
+    let key = PublicKey::from_sec1(&TEST_KEY).unwrap();
+    let (uid, signature) = VECTORS[0];
+
+    // signature of another UID
+    assert!(!key.verify(&VECTORS[1].0, &signature));
+
+    let mut uid2 = uid;
+    uid2[6] ^= 0x01;
+    assert!(!key.verify(&uid2, &signature));
+
+    let mut broken = signature;
+    broken[31] ^= 0x01;
+    assert!(!key.verify(&uid, &broken));
+
+    assert!(!key.verify(&uid, &[0x00; 32]));
+    assert!(!key.verify(&uid, &[0xFF; 32]));
+
+    // signed by the test key, not by NXP
+    assert!(!verify_ntag21x(&uid, &signature));
+}
+
+/// Signatures read from the simulator
+#[cfg(feature = "simulator")]
+mod simulator {
+    use super::common::{activate, UID};
+    use embassy_futures::block_on;
+    use vat_card_reader::driver::simulator::{
+        Delay, MifareClassic, MifareClassicSize, Ntag, NtagVariant, Simulator,
+    };
+    use vat_card_reader::driver::Reader;
+    use vat_card_reader::reader::is_original;
+
+    #[test]
+    fn originality_signature() {
+        let signature = [0x5A; 32];
+        let tag = Ntag::new(NtagVariant::Ntag216, UID).with_signature(signature);
+        let mut reader = Reader::new(Simulator::new().with_target(tag), Delay);
+        let (target, kind) = activate(&mut reader);
+
+        assert_eq!(block_on(reader.read_signature(1)).unwrap(), signature);
+        // not signed by NXP
+        assert_eq!(
+            block_on(is_original(&mut reader, &target, kind)).unwrap(),
+            Some(false)
+        );
+
+        let card = MifareClassic::new(MifareClassicSize::Classic1K, [0xDE, 0xAD, 0xBE, 0xEF]);
+        let mut reader = Reader::new(Simulator::new().with_target(card), Delay);
+        let (target, kind) = activate(&mut reader);
+        // nothing to check, rather than a clone
+        assert_eq!(
+            block_on(is_original(&mut reader, &target, kind)).unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,146 @@
+#[cfg(feature = "simulator")]
+mod common;
+
+use vat_card_reader::driver::CardUid;
+use vat_card_reader::replay::{CounterCheck, CounterStore};
+
+fn uid(n: u8) -> CardUid {
+    CardUid::new(&[0x04, n, 0x00, 0x00, 0x00, 0x00, 0x00]).unwrap()
+}
+
+#[test]
+fn counter_rollback() {
+    let mut store = CounterStore::<2>::new();
+    assert!(store.is_empty());
+
+    assert_eq!(store.check(uid(1), 5), CounterCheck::New);
+    assert_eq!(store.check(uid(1), 7), CounterCheck::Advanced { last: 5 });
+    assert_eq!(store.check(uid(1), 7), CounterCheck::Unchanged);
+    assert_eq!(store.check(uid(1), 6), CounterCheck::RolledBack { last: 7 });
+    assert!(store.check(uid(1), 6).is_rollback());
+    // the highest counter is kept
+    assert_eq!(store.last(&uid(1)), Some(7));
+    assert_eq!(store.check(uid(1), 8), CounterCheck::Advanced { last: 7 });
+    // a card read on every tap can't show the same counter twice
+    assert!(store.check(uid(1), 8).is_suspicious());
+    assert!(store.check(uid(1), 7).is_suspicious());
+    assert!(!store.check(uid(1), 9).is_suspicious());
+    assert_eq!(store.len(), 1);
+}
+
+#[test]
+fn forget_least_recently_seen() {
+    let mut store = CounterStore::<2>::new();
+    store.check(uid(1), 1);
+    store.check(uid(2), 2);
+    store.check(uid(1), 3);
+    assert_eq!(store.check(uid(3), 4), CounterCheck::New);
+
+    assert_eq!(store.len(), 2);
+    assert_eq!(store.last(&uid(1)), Some(3));
+    assert_eq!(store.last(&uid(2)), None);
+    assert_eq!(store.last(&uid(3)), Some(4));
+    assert_eq!(store.check(uid(2), 1), CounterCheck::New);
+}
+
+/// NFC counter of an NTAG in the simulator
+#[cfg(feature = "simulator")]
+mod simulator {
+    use super::common::{activate, NDEF1, UID};
+    use embassy_futures::block_on;
+    use vat_card_reader::driver::requests::CardType;
+    use vat_card_reader::driver::simulator::{Delay, Ntag, NtagVariant, Simulator};
+    use vat_card_reader::driver::{CardKind, ReadError, Reader, Status};
+    use vat_card_reader::reader::{check_counter, read_key};
+    use vat_card_reader::replay::{CounterCheck, CounterStore};
+
+    /// Activate the tag, read its key and check its counter
+    fn check_tag_counter(
+        simulator: &mut Simulator,
+        store: &mut CounterStore<4>,
+    ) -> Option<CounterCheck> {
+        let mut reader = Reader::new(simulator, Delay);
+        let (target, kind) = activate(&mut reader);
+        let mut buf = [0u8; 1024];
+        assert!(block_on(read_key(&mut buf, &mut reader, &target, kind)).is_ok());
+        block_on(check_counter(&mut reader, &target, kind, store)).unwrap()
+    }
+
+    /// A clone of the NTAG213 from a dump with `counter`, its `ACCESS` enabling the counter
+    fn clone(counter: u32) -> Ntag {
+        Ntag::new(NtagVariant::Ntag213, UID)
+            .with_memory(4, NDEF1)
+            .with_memory(0x2A, &[0x10, 0x00, 0x00, 0x00])
+            .with_counter(counter)
+            .with_frozen_counter()
+    }
+
+    #[test]
+    fn nfc_counter() {
+        let tag = Ntag::new(NtagVariant::Ntag213, UID).with_memory(4, NDEF1);
+        let mut simulator = Simulator::new().with_target(tag);
+        let mut store = CounterStore::new();
+
+        // disabled by default, so not checked
+        for _ in 0..2 {
+            assert_eq!(check_tag_counter(&mut simulator, &mut store), None);
+        }
+        {
+            let mut reader = Reader::new(&mut simulator, Delay);
+            block_on(reader.read_passive_target(CardType::IsoTypeA)).unwrap();
+            block_on(reader.read_ntag(1, 4)).unwrap();
+            assert_eq!(block_on(reader.read_counter(1)).unwrap(), 0);
+            assert!(block_on(reader.enable_ntag_counter(1, CardKind::Ntag213, false)).unwrap());
+        }
+
+        // only the first read of an activation counts
+        assert_eq!(
+            check_tag_counter(&mut simulator, &mut store),
+            Some(CounterCheck::New)
+        );
+        assert_eq!(
+            check_tag_counter(&mut simulator, &mut store),
+            Some(CounterCheck::Advanced { last: 1 })
+        );
+
+        // a clone made from a dump of the first activation
+        let genuine = simulator.clear();
+        simulator.place(clone(1));
+        for _ in 0..2 {
+            assert_eq!(
+                check_tag_counter(&mut simulator, &mut store),
+                Some(CounterCheck::RolledBack { last: 2 })
+            );
+        }
+
+        simulator.clear();
+        for target in genuine {
+            simulator.place(target);
+        }
+        assert_eq!(
+            check_tag_counter(&mut simulator, &mut store),
+            Some(CounterCheck::Advanced { last: 2 })
+        );
+
+        // a clone made from a dump of the last activation, its counter frozen
+        let genuine = simulator.clear();
+        simulator.place(clone(3));
+        let check = check_tag_counter(&mut simulator, &mut store).unwrap();
+        assert_eq!(check, CounterCheck::Unchanged);
+        assert!(check.is_suspicious());
+
+        simulator.clear();
+        for target in genuine {
+            simulator.place(target);
+        }
+
+        // protected by the password
+        let mut reader = Reader::new(&mut simulator, Delay);
+        block_on(reader.read_passive_target(CardType::IsoTypeA)).unwrap();
+        assert!(block_on(reader.enable_ntag_counter(1, CardKind::Ntag213, true)).unwrap());
+        assert!(matches!(
+            block_on(reader.read_counter(1)),
+            Err(ReadError::Status(Status::Timeout))
+        ));
+    }
+}
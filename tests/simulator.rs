@@ -0,0 +1,377 @@
+mod common;
+
+use common::{activate, NDEF1, UID};
+use embassy_futures::block_on;
+use vat_card_reader::driver::protocol::{self, Protocol};
+use vat_card_reader::driver::requests::{CardType, SAMMode};
+use vat_card_reader::driver::simulator::{
+    Application, Delay, IsoDep, MifareClassic, MifareClassicSize, Ntag, NtagVariant, Simulator,
+    Target,
+};
+use vat_card_reader::driver::{
+    CardKind, CardUid, DesFireVersion, Error, LinkStats, ReadError, Reader, RetryPolicy, Status,
+};
+use vat_card_reader::reader::{read_key, Key};
+
+#[test]
+fn firmware_version() {
+    let mut reader = Reader::new(Simulator::new().with_firmware(0x32, 1, 4, 0x07), Delay);
+
+    let version = block_on(reader.get_firmware_version()).unwrap();
+    assert_eq!(
+        (version.ic, version.version, version.revision),
+        (0x32, 1, 4)
+    );
+    block_on(reader.sam_configuration(SAMMode::Normal, false)).unwrap();
+}
+
+#[test]
+fn empty_field() {
+    let mut reader = Reader::new(Simulator::new(), Delay);
+
+    assert_eq!(
+        block_on(reader.read_passive_target(CardType::IsoTypeA)).unwrap(),
+        None
+    );
+}
+
+#[test]
+fn read_key_from_ntag() {
+    let tag = Ntag::new(NtagVariant::Ntag215, UID).with_memory(4, NDEF1);
+    let mut reader = Reader::new(Simulator::new().with_target(tag), Delay);
+
+    let (target, kind) = activate(&mut reader);
+    assert_eq!(target.uid(), CardUid::new(&UID).unwrap());
+
+    let mut buf = [0u8; 1024];
+    let key = block_on(read_key(&mut buf, &mut reader, &target, kind))
+        .ok()
+        .unwrap();
+    assert_eq!(key, Some(Key("43211234")));
+}
+
+#[test]
+fn read_key_from_empty_ntag() {
+    let mut reader = Reader::new(
+        Simulator::new().with_target(Ntag::new(NtagVariant::Ntag213, UID)),
+        Delay,
+    );
+    let (target, kind) = activate(&mut reader);
+
+    let mut buf = [0u8; 1024];
+    let key = block_on(read_key(&mut buf, &mut reader, &target, kind))
+        .ok()
+        .unwrap();
+    assert_eq!(key, None);
+}
+
+#[test]
+fn mifare_classic_authentication() {
+    let uid = [0xDE, 0xAD, 0xBE, 0xEF];
+    let key = [0xA0, 0xA1, 0xA2, 0xA3, 0xA4, 0xA5];
+    let card = MifareClassic::new(MifareClassicSize::Classic1K, uid)
+        .with_keys(1, key, [0xFF; 6])
+        .with_block(4, [0x42; 16]);
+    let mut simulator = Simulator::new().with_target(card);
+    let mut protocol = Protocol::<_, _, 64>::new(&mut simulator, Delay);
+
+    let (_, data) = block_on(protocol.request(0x4A, &[0x01, 0x00], 100)).unwrap();
+    assert_eq!(
+        data,
+        [0x01, 0x01, 0x00, 0x04, 0x08, 0x04, 0xDE, 0xAD, 0xBE, 0xEF]
+    );
+
+    // wrong key
+    let mut auth = [
+        0x01, 0x60, 0x04, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0,
+    ];
+    auth[9..].copy_from_slice(&uid);
+    let (_, data) = block_on(protocol.request(0x40, &auth, 100)).unwrap();
+    assert_eq!(data, [0x14]);
+
+    auth[3..9].copy_from_slice(&key);
+    let (_, data) = block_on(protocol.request(0x40, &auth, 100)).unwrap();
+    assert_eq!(data, [0x00]);
+
+    let (_, data) = block_on(protocol.request(0x40, &[0x01, 0x30, 0x04], 100)).unwrap();
+    assert_eq!(data[0], 0x00);
+    assert_eq!(data[1..], [0x42; 16]);
+
+    // outside of the authenticated sector
+    let (_, data) = block_on(protocol.request(0x40, &[0x01, 0x30, 0x08], 100)).unwrap();
+    assert_eq!(data, [0x01]);
+}
+
+#[test]
+fn data_exchange_status() {
+    let uid = [0xDE, 0xAD, 0xBE, 0xEF];
+    let card = MifareClassic::new(MifareClassicSize::Classic1K, uid).with_block(4, [0x42; 16]);
+    let mut simulator = Simulator::new().with_target(card);
+    let mut reader = Reader::new(&mut simulator, Delay);
+
+    block_on(reader.read_passive_target(CardType::IsoTypeA)).unwrap();
+
+    // not authenticated
+    assert!(matches!(
+        block_on(reader.read_ntag(1, 4)),
+        Err(ReadError::Status(Status::Timeout))
+    ));
+
+    let mut auth = [0x60, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0, 0, 0, 0];
+    auth[8..].copy_from_slice(&uid);
+    assert!(matches!(
+        block_on(reader.in_data_exchange(1, &auth)),
+        Err(ReadError::Status(Status::MifareAuthentication))
+    ));
+
+    auth[2..8].fill(0xFF);
+    assert_eq!(block_on(reader.in_data_exchange(1, &auth)).unwrap(), []);
+    assert_eq!(
+        block_on(reader.in_data_exchange(1, &[0x30, 0x04])).unwrap(),
+        [0x42; 16]
+    );
+
+    // unknown target
+    assert!(matches!(
+        block_on(reader.in_data_exchange(2, &[0x30, 0x04])),
+        Err(ReadError::Status(Status::WrongContext))
+    ));
+}
+
+#[test]
+fn two_targets() {
+    let first = Ntag::new(NtagVariant::Ntag213, UID).with_memory(4, &[0x11; 4]);
+    let second = Ntag::new(
+        NtagVariant::Ntag213,
+        [0x04, 0x99, 0x88, 0x77, 0x66, 0x55, 0x44],
+    )
+    .with_memory(4, &[0x22; 4]);
+    let mut simulator = Simulator::new().with_target(first).with_target(second);
+    let mut reader = Reader::new(&mut simulator, Delay);
+
+    let targets = block_on(reader.list_passive_targets(CardType::IsoTypeA)).unwrap();
+    assert_eq!(targets.len(), 2);
+    assert_eq!(
+        targets.get(2).unwrap().uid().as_bytes(),
+        [0x04, 0x99, 0x88, 0x77, 0x66, 0x55, 0x44]
+    );
+
+    assert_eq!(block_on(reader.read_ntag(1, 4)).unwrap()[..4], [0x11; 4]);
+    assert_eq!(block_on(reader.read_ntag(2, 4)).unwrap()[..4], [0x22; 4]);
+
+    block_on(reader.in_select(2)).unwrap();
+    block_on(reader.in_deselect(0)).unwrap();
+
+    block_on(reader.in_release(1)).unwrap();
+    assert!(matches!(
+        block_on(reader.read_ntag(1, 4)),
+        Err(ReadError::Status(Status::WrongContext))
+    ));
+    assert!(matches!(
+        block_on(reader.in_select(1)),
+        Err(ReadError::Status(Status::WrongContext))
+    ));
+    assert_eq!(block_on(reader.read_ntag(2, 4)).unwrap()[..4], [0x22; 4]);
+
+    // a single target is listed at most by read_passive_target
+    let target = block_on(reader.read_passive_target(CardType::IsoTypeA)).unwrap();
+    assert_eq!(target.unwrap().uid().as_bytes(), UID);
+}
+
+#[test]
+fn iso_dep_select_and_read() {
+    let card = IsoDep::new(UID)
+        .with_application(Application::new(&[0xF0, 0x01, 0x02]).with_file(0x0101, b"hello"));
+    let mut simulator = Simulator::new().with_target(card);
+    let mut protocol = Protocol::<_, _, 64>::new(&mut simulator, Delay);
+
+    let (_, data) = block_on(protocol.request(0x4A, &[0x01, 0x00], 100)).unwrap();
+    assert_eq!(data[..5], [0x01, 0x01, 0x03, 0x44, 0x20]);
+
+    let select = [0x01, 0x00, 0xA4, 0x04, 0x00, 0x03, 0xF0, 0x01, 0x02, 0x00];
+    let (_, data) = block_on(protocol.request(0x40, &select, 100)).unwrap();
+    assert_eq!(data, [0x00, 0x90, 0x00]);
+
+    let select = [0x01, 0x00, 0xA4, 0x00, 0x0C, 0x02, 0x01, 0x01];
+    let (_, data) = block_on(protocol.request(0x40, &select, 100)).unwrap();
+    assert_eq!(data, [0x00, 0x90, 0x00]);
+
+    let read = [0x01, 0x00, 0xB0, 0x00, 0x01, 0x00];
+    let (_, data) = block_on(protocol.request(0x40, &read, 100)).unwrap();
+    assert_eq!(data, b"\x00ello\x90\x00");
+}
+
+#[test]
+fn iso_dep_extended_response() {
+    let file: Vec<u8> = (0..300).map(|i| i as u8).collect();
+    let card = IsoDep::new(UID)
+        .with_application(Application::new(&[0xF0, 0x01, 0x02]).with_file(0x0101, &file));
+    let mut simulator = Simulator::new().with_target(card);
+    let mut protocol = Protocol::<_, _, { protocol::MAX_FRAME_LEN }>::new(&mut simulator, Delay);
+
+    block_on(protocol.request(0x4A, &[0x01, 0x00], 100)).unwrap();
+    let select = [0x01, 0x00, 0xA4, 0x04, 0x00, 0x03, 0xF0, 0x01, 0x02, 0x00];
+    block_on(protocol.request(0x40, &select, 100)).unwrap();
+    let select = [0x01, 0x00, 0xA4, 0x00, 0x0C, 0x02, 0x01, 0x01];
+    block_on(protocol.request(0x40, &select, 100)).unwrap();
+
+    // Le = 0, reading 256 bytes
+    let read = [0x01, 0x00, 0xB0, 0x00, 0x00, 0x00];
+    let (_, data) = block_on(protocol.request(0x40, &read, 100)).unwrap();
+    assert_eq!(data.len(), 1 + 256 + 2);
+    assert_eq!(data[1..257], file[..256]);
+    assert_eq!(data[257..], [0x90, 0x00]);
+}
+
+#[test]
+fn unknown_command() {
+    let mut simulator = Simulator::new();
+    let mut protocol = Protocol::<_, _, 64>::new(&mut simulator, Delay);
+
+    assert!(matches!(
+        block_on(protocol.request(0x00, &[0x00], 100)),
+        Err(protocol::Error::Syntax)
+    ));
+}
+
+#[test]
+fn timeout_aborts_command() {
+    let mut simulator = Simulator::new();
+    simulator.set_hanging(true);
+
+    let mut reader = Reader::new(&mut simulator, Delay);
+    assert!(matches!(
+        block_on(reader.get_firmware_version()),
+        Err(Error::Protocol(protocol::Error::Timeout))
+    ));
+    assert_eq!(simulator.aborts(), 1);
+
+    // the next command works again, once the PN532 recovered
+    simulator.set_hanging(false);
+    let mut reader = Reader::new(&mut simulator, Delay);
+    block_on(reader.get_firmware_version()).unwrap();
+}
+
+#[test]
+fn nack_on_bad_checksum() {
+    let mut simulator = Simulator::new();
+    simulator.corrupt_responses(2);
+
+    let mut reader = Reader::new(&mut simulator, Delay);
+    block_on(reader.get_firmware_version()).unwrap();
+    assert_eq!(
+        reader.link_stats(),
+        LinkStats {
+            commands: 1,
+            checksum_errors: 2,
+            nacks: 2,
+            ..LinkStats::default()
+        }
+    );
+    assert_eq!(simulator.nacks(), 2);
+
+    // gives up once the NACKs are used up
+    simulator.corrupt_responses(3);
+    let mut reader = Reader::new(&mut simulator, Delay);
+    assert!(matches!(
+        block_on(reader.get_firmware_version()),
+        Err(Error::Protocol(protocol::Error::BadChecksum))
+    ));
+    assert_eq!(reader.link_stats().failures, 1);
+}
+
+#[test]
+fn retry_transient_errors() {
+    let mut simulator = Simulator::new();
+    simulator.fail_receives(2);
+
+    let policy = RetryPolicy {
+        retries: 2,
+        ..RetryPolicy::default()
+    };
+    let mut reader = Reader::new(&mut simulator, Delay).with_retry_policy(policy);
+    block_on(reader.get_firmware_version()).unwrap();
+    assert_eq!(
+        reader.link_stats(),
+        LinkStats {
+            commands: 1,
+            retries: 2,
+            transport_errors: 2,
+            ..LinkStats::default()
+        }
+    );
+    // every failed attempt got aborted
+    assert_eq!(simulator.aborts(), 2);
+    assert_eq!(simulator.commands().len(), 3);
+
+    // not retried by default
+    simulator.fail_receives(1);
+    let mut reader = Reader::new(&mut simulator, Delay);
+    assert!(matches!(
+        block_on(reader.get_firmware_version()),
+        Err(Error::Protocol(protocol::Error::Transport(_)))
+    ));
+    block_on(reader.get_firmware_version()).unwrap();
+    assert_eq!(reader.link_stats().commands, 2);
+    assert_eq!(reader.link_stats().failures, 1);
+}
+
+#[test]
+fn classify_cards() {
+    let classify =
+        |card: Target| activate(&mut Reader::new(Simulator::new().with_target(card), Delay)).1;
+
+    let kind = classify(Ntag::new(NtagVariant::Ntag213, UID).into());
+    assert_eq!(kind, CardKind::Ntag213);
+    assert_eq!(kind.memory_size(), Some(144));
+    assert_eq!(
+        classify(Ntag::new(NtagVariant::Ntag216, UID).into()).memory_size(),
+        Some(888)
+    );
+
+    let uid = [0xDE, 0xAD, 0xBE, 0xEF];
+    assert_eq!(
+        classify(MifareClassic::new(MifareClassicSize::Classic1K, uid).into()),
+        CardKind::MifareClassic1K
+    );
+    assert_eq!(
+        classify(MifareClassic::new(MifareClassicSize::Classic4K, uid).into()),
+        CardKind::MifareClassic4K
+    );
+
+    // DESFire EV2 with 8 kB
+    let card = IsoDep::new(UID).with_desfire_version([0x04, 0x01, 0x01, 0x12, 0x00, 0x1A, 0x05]);
+    let kind = classify(card.into());
+    assert_eq!(
+        kind,
+        CardKind::DesFire {
+            version: DesFireVersion::Ev2,
+            memory: 8192
+        }
+    );
+    assert!(!kind.is_type2());
+
+    assert_eq!(classify(IsoDep::new(UID).into()), CardKind::Iso14443_4);
+}
+
+#[test]
+fn read_key_from_other_cards() {
+    // without a MAD
+    let card = MifareClassic::new(MifareClassicSize::Classic1K, [0xDE, 0xAD, 0xBE, 0xEF]);
+    let mut reader = Reader::new(Simulator::new().with_target(card), Delay);
+    let (target, kind) = activate(&mut reader);
+
+    let mut buf = [0u8; 1024];
+    assert!(matches!(
+        block_on(read_key(&mut buf, &mut reader, &target, kind)),
+        Ok(None)
+    ));
+
+    let mut reader = Reader::new(Simulator::new().with_target(IsoDep::new(UID)), Delay);
+    let (target, kind) = activate(&mut reader);
+    // without the NDEF Tag Application
+    assert!(matches!(
+        block_on(read_key(&mut buf, &mut reader, &target, kind)),
+        Ok(None)
+    ));
+}
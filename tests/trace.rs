@@ -0,0 +1,94 @@
+use embassy_futures::block_on;
+use vat_card_reader::driver::protocol::{Error, Interface};
+use vat_card_reader::driver::requests::CardType;
+use vat_card_reader::driver::simulator::{Delay, Ntag, NtagVariant, Simulator};
+use vat_card_reader::driver::trace::{Failed, Recorder, Replay};
+use vat_card_reader::driver::Reader;
+use vat_card_reader::reader::{read_key, Key};
+
+const NDEF1: &[u8] = include_bytes!("../test/ndef1.dump");
+const UID: [u8; 7] = [0x04, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66];
+
+fn simulator() -> Simulator {
+    Simulator::new().with_target(Ntag::new(NtagVariant::Ntag213, UID).with_memory(4, NDEF1))
+}
+
+#[test]
+fn record_and_replay() {
+    let mut buf = [0u8; 4096];
+    let mut recorder = Recorder::new(simulator(), &mut buf);
+
+    let mut reader = Reader::new(&mut recorder, Delay);
+    block_on(reader.get_firmware_version()).unwrap();
+    let target = block_on(reader.read_passive_target(CardType::IsoTypeA))
+        .unwrap()
+        .unwrap();
+    let kind = block_on(reader.classify(&target)).unwrap();
+    let mut data = [0u8; 1024];
+    let key = block_on(read_key(&mut data, &mut reader, &target, kind))
+        .ok()
+        .unwrap();
+    assert_eq!(key, Some(Key("43211234")));
+    assert!(!recorder.is_truncated());
+
+    let mut replay = Replay::new(recorder.trace());
+    let mut reader = Reader::new(&mut replay, Delay);
+    block_on(reader.get_firmware_version()).unwrap();
+    let target = block_on(reader.read_passive_target(CardType::IsoTypeA))
+        .unwrap()
+        .unwrap();
+    let kind = block_on(reader.classify(&target)).unwrap();
+    let mut data = [0u8; 1024];
+    let key = block_on(read_key(&mut data, &mut reader, &target, kind))
+        .ok()
+        .unwrap();
+    assert_eq!(key, Some(Key("43211234")));
+    replay.done();
+}
+
+#[test]
+#[should_panic(expected = "trace diverged at offset 0")]
+fn divergence() {
+    let mut buf = [0u8; 256];
+    let mut recorder = Recorder::new(simulator(), &mut buf);
+    block_on(Reader::new(&mut recorder, Delay).get_firmware_version()).unwrap();
+
+    let mut reader = Reader::new(Replay::new(recorder.trace()), Delay);
+    let _ = block_on(reader.read_passive_target(CardType::IsoTypeA));
+}
+
+#[test]
+#[should_panic(expected = "trace not fully replayed")]
+fn incomplete_replay() {
+    let mut buf = [0u8; 256];
+    let mut recorder = Recorder::new(simulator(), &mut buf);
+    block_on(Reader::new(&mut recorder, Delay).get_firmware_version()).unwrap();
+
+    Replay::new(recorder.trace()).done();
+}
+
+#[test]
+fn failed_operation() {
+    let mut buf = [0u8; 256];
+    let mut recorder = Recorder::new(Simulator::new(), &mut buf);
+    assert!(block_on(recorder.wait_for_ready()).is_err());
+    assert_eq!(recorder.trace(), [0x83, 0x00, 0x00]);
+
+    let mut replay = Replay::new(recorder.trace());
+    assert!(matches!(
+        block_on(replay.wait_for_ready()),
+        Err(Error::Transport(Failed))
+    ));
+    replay.done();
+}
+
+#[test]
+fn truncated() {
+    let mut buf = [0u8; 16];
+    let mut recorder = Recorder::new(simulator(), &mut buf);
+    block_on(Reader::new(&mut recorder, Delay).get_firmware_version()).unwrap();
+
+    assert!(recorder.is_truncated());
+    // only the complete entries are kept
+    assert_eq!(recorder.trace().len(), 15);
+}
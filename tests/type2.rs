@@ -0,0 +1,309 @@
+#[cfg(feature = "simulator")]
+mod common;
+
+use vat_card_reader::type2::{CapabilityContainer, Error, MemoryImage, NdefLayout};
+
+const NDEF1: &[u8] = include_bytes!("../test/ndef1.dump");
+
+#[test]
+fn capability_container() {
+    let cc = CapabilityContainer::parse([0xE1, 0x10, 0x12, 0x00]).unwrap();
+    assert_eq!(cc.size, 144);
+    assert!(cc.is_writable());
+    assert!(!CapabilityContainer::parse([0xE1, 0x10, 0x12, 0x0F])
+        .unwrap()
+        .is_writable());
+
+    assert_eq!(
+        CapabilityContainer::parse([0x00; 4]),
+        Err(Error::NotFormatted)
+    );
+    assert_eq!(
+        CapabilityContainer::parse([0xE1, 0x20, 0x12, 0x00]),
+        Err(Error::UnsupportedVersion(0x20))
+    );
+}
+
+#[test]
+fn layout_behind_lock_control() {
+    let message = &NDEF1[7..7 + 0x14];
+    let layout = NdefLayout::new(&NDEF1[..12], message, 144).unwrap();
+
+    // the lock control TLV in the first page is kept
+    assert_eq!(layout.pages(), 1..7);
+    let mut data = [0u8; 32];
+    for index in layout.pages() {
+        data[index * 4..index * 4 + 4].copy_from_slice(&layout.page(index));
+    }
+    assert_eq!(data[4..], NDEF1[4..32]);
+    assert_eq!(layout.page(1), [0x34, 0x03, 0x14, 0xD2]);
+}
+
+#[test]
+fn layout_of_empty_tag() {
+    let layout = NdefLayout::new(&[0x03, 0x00, 0xFE, 0x00], &[0xD0, 0x00, 0x00], 48).unwrap();
+    assert_eq!(layout.pages(), 0..2);
+    assert_eq!(layout.page(0), [0x03, 0x03, 0xD0, 0x00]);
+    assert_eq!(layout.page(1), [0x00, 0xFE, 0x00, 0x00]);
+
+    // unformatted data area, only NULL TLVs
+    assert!(NdefLayout::new(&[0x00; 12], &[0xD0, 0x00, 0x00], 48).is_ok());
+}
+
+#[test]
+fn long_messages() {
+    let message = [0x42; 300];
+    let layout = NdefLayout::new(&[0x03, 0x00, 0xFE, 0x00], &message, 504).unwrap();
+    assert_eq!(layout.page(0), [0x03, 0xFF, 0x01, 0x2C]);
+    // TLV header, message and terminator
+    assert_eq!(layout.pages(), 0..77);
+    assert_eq!(layout.page(75), [0x42; 4]);
+    assert_eq!(layout.page(76), [0xFE, 0x00, 0x00, 0x00]);
+
+    assert_eq!(
+        NdefLayout::new(&[0x03, 0x00, 0xFE, 0x00], &message, 144),
+        Err(Error::TooLarge)
+    );
+}
+
+#[test]
+fn memory_image() {
+    let image = MemoryImage::from_bytes(NDEF1).unwrap();
+    assert_eq!(image.as_bytes(), NDEF1);
+    assert_eq!(image.len(), 144);
+    assert_eq!(image.page(4), Some(NDEF1[..4].try_into().unwrap()));
+    assert_eq!(image.page(3), None);
+    assert_eq!(image.page(40), None);
+
+    // whole pages only
+    assert!(MemoryImage::from_bytes(&NDEF1[..10]).is_none());
+    assert!(MemoryImage::from_bytes(&[0; 892]).is_none());
+}
+
+/// NTAG21x in the simulator
+#[cfg(feature = "simulator")]
+mod simulator {
+    use super::common::{activate, NDEF1, UID};
+    use embassy_futures::block_on;
+    use vat_card_reader::driver::ntag::{Access, AuthError, Password, Protection};
+    use vat_card_reader::driver::protocol;
+    use vat_card_reader::driver::requests::CardType;
+    use vat_card_reader::driver::simulator::{Delay, Ntag, NtagVariant, Simulator};
+    use vat_card_reader::driver::{CardKind, Error, ReadError, Reader, Status};
+    use vat_card_reader::reader::{
+        read_key, read_protected_key, write_key, Key, ReadKeyError, WriteKeyError,
+    };
+    use vat_card_reader::type2;
+
+    #[test]
+    fn write_and_read_key() {
+        for tag in [
+            Ntag::new(NtagVariant::Ntag213, UID),
+            Ntag::new(NtagVariant::Ntag213, UID).with_memory(4, NDEF1),
+        ] {
+            let mut simulator = Simulator::new().with_target(tag);
+            let mut reader = Reader::new(&mut simulator, Delay);
+            let (target, kind) = activate(&mut reader);
+
+            let mut buf = [0u8; 64];
+            block_on(write_key(
+                &mut buf,
+                &mut reader,
+                &target,
+                kind,
+                Key("12345678"),
+            ))
+            .ok()
+            .unwrap();
+
+            let mut buf = [0u8; 1024];
+            let key = block_on(read_key(&mut buf, &mut reader, &target, kind))
+                .ok()
+                .unwrap();
+            assert_eq!(key, Some(Key("12345678")));
+        }
+    }
+
+    #[test]
+    fn write_read_only_tag() {
+        let tag = Ntag::new(NtagVariant::Ntag213, UID).with_memory(3, &[0xE1, 0x10, 0x12, 0x0F]);
+        let mut reader = Reader::new(Simulator::new().with_target(tag), Delay);
+        let (target, kind) = activate(&mut reader);
+
+        let mut buf = [0u8; 64];
+        assert!(matches!(
+            block_on(write_key(
+                &mut buf,
+                &mut reader,
+                &target,
+                kind,
+                Key("12345678")
+            )),
+            Err(WriteKeyError::Tag(type2::Error::ReadOnly))
+        ));
+        // keys are only written to Type 2 Tags
+        assert!(matches!(
+            block_on(write_key(
+                &mut buf,
+                &mut reader,
+                &target,
+                CardKind::MifareClassic1K,
+                Key("12345678")
+            )),
+            Err(WriteKeyError::Unsupported(CardKind::MifareClassic1K))
+        ));
+    }
+
+    #[test]
+    fn compatibility_write() {
+        let mut simulator = Simulator::new().with_target(Ntag::new(NtagVariant::Ntag213, UID));
+        let mut reader = Reader::new(&mut simulator, Delay);
+        block_on(reader.read_passive_target(CardType::IsoTypeA)).unwrap();
+
+        let mut data = [0xEE; 16];
+        data[..4].copy_from_slice(&[0x01, 0x02, 0x03, 0x04]);
+        block_on(reader.compatibility_write_ntag(1, 5, data)).unwrap();
+        block_on(reader.write_ntag(1, 6, [0x05, 0x06, 0x07, 0x08])).unwrap();
+
+        let read = block_on(reader.read_ntag(1, 4)).unwrap();
+        assert_eq!(
+            read[4..12],
+            [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08]
+        );
+        assert_eq!(read[12..], [0x00; 4]);
+
+        // the UID can't be written
+        assert!(matches!(
+            block_on(reader.write_ntag(1, 0, [0x00; 4])),
+            Err(ReadError::Status(Status::Timeout))
+        ));
+    }
+
+    const PASSWORD: Password = Password {
+        pwd: [0x12, 0x34, 0x56, 0x78],
+        pack: [0xAB, 0xCD],
+    };
+
+    #[test]
+    fn password_protection() {
+        let tag = Ntag::new(NtagVariant::Ntag213, UID).with_memory(4, NDEF1);
+        let mut simulator = Simulator::new().with_target(tag);
+        let mut reader = Reader::new(&mut simulator, Delay);
+        let (_, kind) = activate(&mut reader);
+
+        let config = block_on(reader.read_ntag_config(1, kind)).unwrap().unwrap();
+        assert!(!config.is_protected());
+        assert!(
+            block_on(reader.protect_ntag(1, kind, &PASSWORD, 4, Protection::ReadWrite)).unwrap()
+        );
+
+        // protected from the next activation on
+        let (target, kind) = activate(&mut reader);
+        let mut buf = [0u8; 1024];
+        assert!(matches!(
+            block_on(read_key(&mut buf, &mut reader, &target, kind)),
+            Err(ReadKeyError::Io(ReadError::Status(Status::Timeout)))
+        ));
+
+        let key = block_on(read_protected_key(
+            &mut buf,
+            &mut reader,
+            &target,
+            kind,
+            Some(&PASSWORD),
+        ))
+        .ok()
+        .unwrap();
+        assert_eq!(key, Some(Key("43211234")));
+
+        let config = block_on(reader.read_ntag_config(1, kind)).unwrap().unwrap();
+        assert_eq!(config.auth0, 4);
+        assert!(config.access.prot);
+
+        // a tag answering with another PACK isn't trusted
+        let password = Password {
+            pack: [0x00, 0x00],
+            ..PASSWORD
+        };
+        assert!(matches!(
+            block_on(reader.authenticate_ntag(1, &password)),
+            Err(AuthError::PackMismatch([0xAB, 0xCD]))
+        ));
+    }
+
+    #[test]
+    fn password_attempts_limit() {
+        let mut simulator = Simulator::new().with_target(Ntag::new(NtagVariant::Ntag215, UID));
+        let mut reader = Reader::new(&mut simulator, Delay);
+        block_on(reader.read_passive_target(CardType::IsoTypeA)).unwrap();
+        let kind = CardKind::Ntag215;
+
+        let mut config = block_on(reader.read_ntag_config(1, kind)).unwrap().unwrap();
+        config.access = Access {
+            authlim: 2,
+            ..config.access
+        };
+        block_on(reader.write_ntag_config(1, kind, &config)).unwrap();
+        assert!(
+            block_on(reader.protect_ntag(1, kind, &PASSWORD, 0x10, Protection::Write)).unwrap()
+        );
+
+        // reading is still open, writing is not
+        block_on(reader.read_ntag(1, 0x10)).unwrap();
+        assert!(matches!(
+            block_on(reader.write_ntag(1, 0x10, [0x00; 4])),
+            Err(ReadError::Status(Status::Timeout))
+        ));
+
+        let wrong = [0x00; 4];
+        for _ in 0..2 {
+            assert!(matches!(
+                block_on(reader.ntag_pwd_auth(1, wrong)),
+                Err(ReadError::Status(Status::Timeout))
+            ));
+        }
+        // the correct password is rejected as well after too many attempts
+        assert!(matches!(
+            block_on(reader.ntag_pwd_auth(1, PASSWORD.pwd)),
+            Err(ReadError::Status(Status::Timeout))
+        ));
+    }
+
+    #[test]
+    fn fast_read_and_dump() {
+        let memory: Vec<u8> = (0..888).map(|i| i as u8).collect();
+        let tag = Ntag::new(NtagVariant::Ntag216, UID).with_memory(4, &memory);
+        let mut reader = Reader::new(Simulator::new().with_target(tag), Delay);
+        let target = block_on(reader.read_passive_target(CardType::IsoTypeA))
+            .unwrap()
+            .unwrap();
+
+        // more pages than fit into a single frame
+        let mut buf = [0u8; 888];
+        block_on(reader.fast_read(1, 4, 225, &mut buf)).unwrap();
+        assert_eq!(buf[..], memory[..]);
+        assert!(matches!(
+            block_on(reader.fast_read(1, 4, 225, &mut buf[..400])),
+            Err(ReadError::Reader(Error::Protocol(
+                protocol::Error::BufferUnderflow
+            )))
+        ));
+
+        let kind = block_on(reader.classify(&target)).unwrap();
+        let image = block_on(reader.dump_type2(1, kind)).unwrap().unwrap();
+        assert_eq!(image.as_bytes(), &memory[..]);
+        assert_eq!(image.page(5), Some([4, 5, 6, 7]));
+
+        // cards without FAST_READ are dumped with READ
+        let tag = Ntag::new(NtagVariant::Ntag213, UID).with_memory(4, NDEF1);
+        let mut reader = Reader::new(Simulator::new().with_target(tag), Delay);
+        block_on(reader.read_passive_target(CardType::IsoTypeA)).unwrap();
+        let image = block_on(reader.dump_type2(1, CardKind::UltralightC))
+            .unwrap()
+            .unwrap();
+        assert_eq!(image, type2::MemoryImage::from_bytes(NDEF1).unwrap());
+
+        let none = block_on(reader.dump_type2(1, CardKind::MifareClassic1K)).unwrap();
+        assert!(none.is_none());
+    }
+}
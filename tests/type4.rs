@@ -0,0 +1,166 @@
+#[cfg(feature = "simulator")]
+mod common;
+
+use vat_card_reader::ndef::{Reader, Record};
+use vat_card_reader::type4::{CapabilityContainer, Error};
+
+const NDEF1: &[u8] = include_bytes!("../test/ndef1.dump");
+
+#[test]
+fn capability_container() {
+    // capability container of an NTAG 424 DNA as shipped
+    let cc = [
+        0x00, 0x17, 0x20, 0x01, 0x00, 0x00, 0xFF, 0x04, 0x06, 0xE1, 0x04, 0x01, 0x00, 0x00, 0x00,
+    ];
+    let cc = CapabilityContainer::parse(&cc).unwrap();
+    assert_eq!(
+        cc,
+        CapabilityContainer {
+            version: 0x20,
+            mle: 0x0100,
+            mlc: 0x00FF,
+            ndef_file: 0xE104,
+            ndef_size: 256,
+            read_access: 0x00,
+            write_access: 0x00,
+        }
+    );
+    assert!(cc.is_readable());
+    assert!(cc.is_writable());
+
+    let cc = [
+        0x00, 0x0F, 0x30, 0x00, 0x3B, 0x00, 0x34, 0x04, 0x06, 0xE1, 0x04, 0x00, 0x32, 0x00, 0x00,
+    ];
+    assert_eq!(
+        CapabilityContainer::parse(&cc),
+        Err(Error::UnsupportedVersion(0x30))
+    );
+
+    // MLe below the minimum
+    let cc = [
+        0x00, 0x0F, 0x20, 0x00, 0x0E, 0x00, 0x34, 0x04, 0x06, 0xE1, 0x04, 0x00, 0x32, 0x00, 0x00,
+    ];
+    assert_eq!(
+        CapabilityContainer::parse(&cc),
+        Err(Error::InvalidCapabilityContainer)
+    );
+    assert_eq!(
+        CapabilityContainer::parse(&cc[..12]),
+        Err(Error::InvalidCapabilityContainer)
+    );
+}
+
+#[test]
+fn bare_message() {
+    let message = &NDEF1[7..7 + 0x14];
+    let records: Vec<_> = Reader::from_message(message).into_iter().collect();
+    assert_eq!(
+        records,
+        [Ok(Record::MimeMedia {
+            r#type: "text/card",
+            value: "43211234",
+        })]
+    );
+
+    assert!(Reader::from_message(&[])
+        .into_iter()
+        .next()
+        .unwrap()
+        .is_err());
+}
+
+/// Type 4 Tags in the simulator
+#[cfg(feature = "simulator")]
+mod simulator {
+    use super::common::{activate, ndef_application, NDEF1, UID};
+    use embassy_futures::block_on;
+    use vat_card_reader::driver::simulator::{Application, Delay, IsoDep, Simulator};
+    use vat_card_reader::driver::Reader;
+    use vat_card_reader::reader::{read_key, Key, ReadKeyError};
+    use vat_card_reader::{ndef, type4};
+
+    /// A Type 4 Tag holding `message`, read in chunks of up to `mle` bytes
+    fn ndef_type4(card: IsoDep, message: &[u8], mle: u16) -> IsoDep {
+        card.with_application(ndef_application(message, mle))
+    }
+
+    #[test]
+    fn read_key_from_type4() {
+        let message = &NDEF1[7..7 + 0x14];
+        let cards = [
+            ndef_type4(IsoDep::new(UID), message, 0x00FF),
+            // the message takes two READ BINARY
+            ndef_type4(IsoDep::new(UID), message, 0x000F),
+            ndef_type4(
+                IsoDep::new(UID).with_desfire_version([0x04, 0x01, 0x01, 0x12, 0x00, 0x1A, 0x05]),
+                message,
+                0x003B,
+            ),
+        ];
+        for card in cards {
+            let mut reader = Reader::new(Simulator::new().with_target(card), Delay);
+            let (target, kind) = activate(&mut reader);
+
+            let mut buf = [0u8; 1024];
+            let key = block_on(read_key(&mut buf, &mut reader, &target, kind));
+            assert!(matches!(key, Ok(Some(Key("43211234")))));
+        }
+
+        // longer than a short APDU answers
+        let value = "0123456789".repeat(40);
+        let mut buf = [0u8; 512];
+        let mut writer = ndef::Writer::new(&mut buf);
+        writer
+            .push(&ndef::Record::MimeMedia {
+                r#type: "text/card",
+                value: &value,
+            })
+            .unwrap();
+        let card = ndef_type4(IsoDep::new(UID), writer.finish(), 0xFFFF);
+        let mut reader = Reader::new(Simulator::new().with_target(card), Delay);
+        let (target, kind) = activate(&mut reader);
+        let mut buf = [0u8; 1024];
+        let key = block_on(read_key(&mut buf, &mut reader, &target, kind));
+        assert!(matches!(key, Ok(Some(Key(key))) if key == value));
+
+        // the message doesn't fit
+        let mut buf = [0u8; 128];
+        let key = block_on(read_key(&mut buf, &mut reader, &target, kind));
+        assert!(matches!(key, Ok(None)));
+    }
+
+    #[test]
+    fn read_key_without_message_end() {
+        let message = [0x92, 0x01, 0x01, b'a', b'b'];
+        let card = ndef_type4(IsoDep::new(UID), &message, 0x00FF);
+        let mut reader = Reader::new(Simulator::new().with_target(card), Delay);
+        let (target, kind) = activate(&mut reader);
+        let mut buf = [0u8; 1024];
+        assert!(matches!(
+            block_on(read_key(&mut buf, &mut reader, &target, kind)),
+            Err(ReadKeyError::Ndef(ndef::Error::UnderflowHeader))
+        ));
+    }
+
+    #[test]
+    fn type4_read_protected() {
+        // proprietary read access condition
+        let cc = [
+            0x00, 0x0F, 0x20, 0x00, 0xFF, 0x00, 0xFF, 0x04, 0x06, 0xE1, 0x04, 0x00, 0x80, 0x80,
+            0x00,
+        ];
+        let card = IsoDep::new(UID).with_application(
+            Application::new(&type4::NDEF_AID)
+                .with_file(type4::CC_FILE, &cc)
+                .with_file(0xE104, &[0x00, 0x00]),
+        );
+
+        let mut reader = Reader::new(Simulator::new().with_target(card), Delay);
+        let (target, kind) = activate(&mut reader);
+        let mut buf = [0u8; 1024];
+        assert!(matches!(
+            block_on(read_key(&mut buf, &mut reader, &target, kind)),
+            Err(ReadKeyError::Type4(type4::Error::ReadProtected))
+        ));
+    }
+}
@@ -0,0 +1,142 @@
+#![feature(async_fn_in_trait)]
+#![allow(incomplete_features)]
+
+use embassy_futures::block_on;
+use embedded_io::asynch::{Read, Write};
+use vat_card_reader::driver::protocol;
+use vat_card_reader::driver::requests::{BaudRate, CardType};
+use vat_card_reader::driver::simulator::{self, Delay, Ntag, NtagVariant, Simulator};
+use vat_card_reader::driver::{Error, Reader, Uart};
+use vat_card_reader::reader::{read_key, Key};
+
+const NDEF1: &[u8] = include_bytes!("../test/ndef1.dump");
+const UID: [u8; 7] = [0x04, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66];
+
+/// Serial port in front of the simulator, capturing the written bytes
+struct Tap {
+    simulator: Simulator,
+    written: Vec<u8>,
+    /// Noise to be read before the next byte from the simulator
+    noise: Vec<u8>,
+    /// Bytes passed on from the simulator before the line goes silent
+    cut_after: Option<usize>,
+}
+
+impl Tap {
+    fn new(simulator: Simulator) -> Self {
+        Self {
+            simulator,
+            written: Vec::new(),
+            noise: Vec::new(),
+            cut_after: None,
+        }
+    }
+}
+
+impl embedded_io::Io for Tap {
+    type Error = simulator::Error;
+}
+
+impl Write for Tap {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.written.extend_from_slice(buf);
+        self.simulator.write(buf).await
+    }
+}
+
+impl Read for Tap {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if !self.noise.is_empty() {
+            buf[0] = self.noise.remove(0);
+            return Ok(1);
+        }
+        match &mut self.cut_after {
+            Some(0) => core::future::pending().await,
+            Some(left) => {
+                let len = buf.len().min(*left);
+                let len = self.simulator.read(&mut buf[..len]).await?;
+                *left -= len;
+                Ok(len)
+            }
+            None => self.simulator.read(buf).await,
+        }
+    }
+}
+
+#[test]
+fn read_key_over_uart() {
+    let tag = Ntag::new(NtagVariant::Ntag216, UID).with_memory(4, NDEF1);
+    let mut reader = Reader::new(Uart::new(Simulator::new().with_target(tag)), Delay);
+
+    block_on(reader.get_firmware_version()).unwrap();
+    let target = block_on(reader.read_passive_target(CardType::IsoTypeA))
+        .unwrap()
+        .unwrap();
+    let kind = block_on(reader.classify(&target)).unwrap();
+    let mut buf = [0u8; 1024];
+    let key = block_on(read_key(&mut buf, &mut reader, &target, kind))
+        .ok()
+        .unwrap();
+    assert_eq!(key, Some(Key("43211234")));
+}
+
+#[test]
+fn wakeup_preamble() {
+    let mut tap = Tap::new(Simulator::new());
+    let mut reader = Reader::new(Uart::new(&mut tap), Delay);
+    block_on(reader.get_firmware_version()).unwrap();
+    block_on(reader.get_firmware_version()).unwrap();
+
+    // only before the first frame
+    assert_eq!(tap.written[..2], [0x55, 0x55]);
+    assert_eq!(tap.written.iter().filter(|b| **b == 0x55).count(), 2);
+    assert_eq!(tap.simulator.commands().len(), 2);
+}
+
+#[test]
+fn skip_noise() {
+    let mut tap = Tap::new(Simulator::new());
+    tap.noise = vec![0x12, 0xFF, 0x00, 0x00, 0x34];
+    let mut reader = Reader::new(Uart::new(&mut tap), Delay);
+
+    block_on(reader.get_firmware_version()).unwrap();
+}
+
+#[test]
+fn set_baud_rate() {
+    let mut simulator = Simulator::new();
+    let mut reader = Reader::new(Uart::new(&mut simulator), Delay);
+
+    block_on(reader.set_serial_baud_rate(BaudRate::Baud921600)).unwrap();
+    assert_eq!(simulator.commands(), [(0x10, vec![0x07])]);
+    // acknowledging the response is not an abort
+    assert_eq!(simulator.aborts(), 0);
+}
+
+#[test]
+fn timeout() {
+    let mut simulator = Simulator::new();
+    simulator.set_hanging(true);
+    let mut reader = Reader::new(Uart::new(&mut simulator), Delay);
+
+    assert!(matches!(
+        block_on(reader.get_firmware_version()),
+        Err(Error::Protocol(protocol::Error::Timeout))
+    ));
+    assert_eq!(simulator.aborts(), 1);
+}
+
+#[test]
+fn truncated_frame() {
+    let mut tap = Tap::new(Simulator::new());
+    // the ACK and the start of the response
+    tap.cut_after = Some(6 + 8);
+    let mut reader = Reader::new(Uart::new(&mut tap), Delay);
+
+    assert!(matches!(
+        block_on(reader.get_firmware_version()),
+        Err(Error::Protocol(protocol::Error::Timeout))
+    ));
+    // aborted with an ACK
+    assert!(tap.written.ends_with(&[0x00, 0x00, 0xFF, 0x00, 0xFF, 0x00]));
+}